@@ -1,15 +1,31 @@
 use bootloader::DiskImageBuilder;
 use std::{env, path::PathBuf};
 
+#[path = "src/fat_image.rs"]
+mod fat_image;
+#[path = "src/initrd.rs"]
+mod initrd;
+
 fn main() {
     // set by cargo for the kernel artifact dependency
     let kernel_path = env::var("CARGO_BIN_FILE_JO12BAR_OS_KERNEL").unwrap();
-    let disk_builder = DiskImageBuilder::new(PathBuf::from(kernel_path));
+    let mut disk_builder = DiskImageBuilder::new(PathBuf::from(&kernel_path));
 
     // specify output paths
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let uefi_path = out_dir.join("jo12bar_os-uefi.img");
     let bios_path = out_dir.join("jo12bar_os-bios.img");
+    let initrd_path = out_dir.join("initrd.cpio");
+
+    // pack the initial ramdisk, including the FAT image for the kernel's FAT demo
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let initrd_dir = manifest_dir.join("initrd");
+    let fat_dir = manifest_dir.join("fat");
+    println!("cargo:rerun-if-changed={}", initrd_dir.display());
+    println!("cargo:rerun-if-changed={}", fat_dir.display());
+    let fat_image = fat_image::build(&fat_dir).unwrap();
+    initrd::pack(&initrd_dir, &[("fat.img", &fat_image)], &initrd_path).unwrap();
+    disk_builder.set_ramdisk(initrd_path);
 
     // create the disk images
     disk_builder.create_uefi_image(&uefi_path).unwrap();
@@ -18,4 +34,9 @@ fn main() {
     // pass the disk image paths via environment variables
     println!("cargo:rustc-env=UEFI_IMAGE={}", uefi_path.display());
     println!("cargo:rustc-env=BIOS_IMAGE={}", bios_path.display());
+    // used to symbolize addresses in the kernel's serial output
+    println!("cargo:rustc-env=KERNEL_ELF={kernel_path}");
+    // used to pack the initial ramdisk again if the kernel gets a command line
+    println!("cargo:rustc-env=INITRD_DIR={}", initrd_dir.display());
+    println!("cargo:rustc-env=FAT_DIR={}", fat_dir.display());
 }
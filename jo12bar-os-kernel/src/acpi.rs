@@ -0,0 +1,167 @@
+//! Finding ACPI tables.
+//!
+//! The bootloader passes the address of the RSDP, which points to the XSDT
+//! (or the RSDT of ACPI 1.0), which in turn lists the physical addresses of
+//! all other tables. Tables are only looked up, e.g. the MCFG by
+//! [`pci`][crate::pci], and never modified.
+//!
+//! ACPI tables live in memory that the frame allocator never hands out, so
+//! they are accessed through the mapping of the complete physical memory.
+
+use core::slice;
+
+use log::{debug, warn};
+use thiserror::Error;
+use x86_64::PhysAddr;
+
+use crate::{mem, prelude::*};
+
+/// The root tables, once [`init`] was called.
+static ROOT: SpinOnce<RootTable> = SpinOnce::new();
+
+/// The size of the header every table except the RSDP starts with.
+pub const HEADER_LEN: usize = 36;
+
+/// Errors returned when looking up ACPI tables.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum AcpiError {
+    #[error("the bootloader did not find the RSDP")]
+    NoRsdp,
+    #[error("the RSDP is invalid")]
+    InvalidRsdp,
+    #[error("table {0} is invalid")]
+    InvalidTable(&'static str),
+    #[error("table {0} does not exist")]
+    NotFound(&'static str),
+}
+
+/// The XSDT or RSDT.
+#[derive(Debug, Clone, Copy)]
+struct RootTable {
+    table: &'static [u8],
+    /// The size of the table addresses: 8 for the XSDT, 4 for the RSDT.
+    entry_len: usize,
+}
+
+/// Find the root table using the RSDP passed by the bootloader.
+///
+/// # Safety
+/// Must only be called once, by the bootstrap processor after memory was
+/// initialized, and `boot_info.rsdp_addr` must be valid if it is set.
+pub unsafe fn init(boot_info: &bootloader_api::BootInfo) {
+    let Some(rsdp_addr) = boot_info.rsdp_addr.into_option() else {
+        warn!("No ACPI tables: {}", AcpiError::NoRsdp);
+        return;
+    };
+
+    // Safety: The caller guarantees that the RSDP address is valid.
+    match unsafe { parse_rsdp(PhysAddr::new(rsdp_addr)) } {
+        Ok(root) => {
+            debug!(
+                "ACPI {} at {:p}, {} tables",
+                if root.entry_len == 8 { "XSDT" } else { "RSDT" },
+                root.table,
+                (root.table.len() - HEADER_LEN) / root.entry_len
+            );
+            if ROOT.set(root).is_err() {
+                panic!("acpi::init() should be called only once");
+            }
+        }
+        Err(e) => warn!("No ACPI tables: {e}"),
+    }
+}
+
+/// Returns the table with the 4-letter `signature`, including its header.
+pub fn find_table(signature: &'static str) -> Result<&'static [u8], AcpiError> {
+    let root = ROOT.get().ok_or(AcpiError::NoRsdp)?;
+
+    for entry in root.table[HEADER_LEN..].chunks_exact(root.entry_len) {
+        let mut addr = [0; 8];
+        addr[..root.entry_len].copy_from_slice(entry);
+        let addr = PhysAddr::try_new(u64::from_le_bytes(addr))
+            .map_err(|_| AcpiError::InvalidTable(signature))?;
+
+        // Safety: Tables listed in the root table are valid.
+        if unsafe { phys_slice(addr, 4) } != signature.as_bytes() {
+            continue;
+        }
+        // Safety: see above
+        return unsafe { table_at(addr, signature) };
+    }
+    Err(AcpiError::NotFound(signature))
+}
+
+/// Parse the RSDP at `addr`, and return the root table it points to.
+///
+/// # Safety
+/// `addr` must be the address of the RSDP.
+unsafe fn parse_rsdp(addr: PhysAddr) -> Result<RootTable, AcpiError> {
+    // Safety: The caller guarantees that the RSDP is there. Its first version
+    // is 20 bytes long.
+    let rsdp = unsafe { phys_slice(addr, 20) };
+    if &rsdp[..8] != b"RSD PTR " || !checksum_ok(rsdp) {
+        return Err(AcpiError::InvalidRsdp);
+    }
+
+    let revision = rsdp[15];
+    if revision >= 2 {
+        // Safety: ACPI 2.0 extended the RSDP to 36 bytes.
+        let rsdp = unsafe { phys_slice(addr, 36) };
+        if !checksum_ok(rsdp) {
+            return Err(AcpiError::InvalidRsdp);
+        }
+        let xsdt = u64::from_le_bytes(rsdp[24..32].try_into().unwrap());
+        if xsdt != 0 {
+            let xsdt = PhysAddr::try_new(xsdt).map_err(|_| AcpiError::InvalidRsdp)?;
+            return Ok(RootTable {
+                // Safety: The RSDP points to the XSDT.
+                table: unsafe { table_at(xsdt, "XSDT") }?,
+                entry_len: 8,
+            });
+        }
+    }
+
+    let rsdt = u32::from_le_bytes(rsdp[16..20].try_into().unwrap());
+    Ok(RootTable {
+        // Safety: The RSDP points to the RSDT.
+        table: unsafe { table_at(PhysAddr::new(rsdt.into()), "RSDT") }?,
+        entry_len: 4,
+    })
+}
+
+/// Returns the table at `addr` after checking its header.
+///
+/// # Safety
+/// `addr` must be the address of an ACPI table.
+unsafe fn table_at(addr: PhysAddr, signature: &'static str) -> Result<&'static [u8], AcpiError> {
+    // Safety: The caller guarantees that a table starts at `addr`.
+    let header = unsafe { phys_slice(addr, HEADER_LEN) };
+    let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    if &header[..4] != signature.as_bytes() || len < HEADER_LEN {
+        return Err(AcpiError::InvalidTable(signature));
+    }
+
+    // Safety: The header says how long the table is.
+    let table = unsafe { phys_slice(addr, len) };
+    if !checksum_ok(table) {
+        return Err(AcpiError::InvalidTable(signature));
+    }
+    Ok(table)
+}
+
+/// Returns `true` if the bytes of `data` add up to zero, which is how all ACPI
+/// structures are checksummed.
+fn checksum_ok(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Returns `len` bytes of physical memory starting at `addr`.
+///
+/// # Safety
+/// The memory must be part of a firmware table, which is never modified.
+unsafe fn phys_slice(addr: PhysAddr, len: usize) -> &'static [u8] {
+    // Safety: The caller guarantees that the memory is never modified, and all
+    // physical memory in the memory map is mapped.
+    unsafe { slice::from_raw_parts(mem::phys_to_virt(addr).as_ptr(), len) }
+}
@@ -0,0 +1,218 @@
+//! A write-back cache for block devices.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+
+use super::{BlockDevice, BlockError};
+use crate::prelude::*;
+
+/// The unit the cache works in. Block sizes must divide it.
+pub const PAGE_SIZE: usize = 4096;
+
+/// A cache of the pages of a [`BlockDevice`], which can be read and written at
+/// any byte offset.
+///
+/// Writes only change the cached page, and are written back when the page is
+/// evicted or the cache is [flushed][Self::flush]. Pages are evicted least
+/// recently used first.
+pub struct BufferCache {
+    device: Arc<dyn BlockDevice>,
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+struct CacheState {
+    pages: BTreeMap<u64, Page>,
+    /// Incremented on every access, to find the least recently used page.
+    clock: u64,
+}
+
+struct Page {
+    data: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+impl BufferCache {
+    /// The default number of cached pages.
+    pub const DEFAULT_CAPACITY: usize = 256;
+
+    /// Create a cache holding up to `capacity` pages of `device`.
+    ///
+    /// # Panics
+    /// Panics if the block size of `device` doesn't divide [`PAGE_SIZE`], or if
+    /// `capacity` is `0`.
+    pub fn new(device: Arc<dyn BlockDevice>, capacity: usize) -> Self {
+        let block_size = device.block_size();
+        assert!(
+            block_size.is_power_of_two() && block_size <= PAGE_SIZE,
+            "unsupported block size {block_size}"
+        );
+        assert!(capacity > 0, "the cache needs room for at least one page");
+
+        Self {
+            device,
+            capacity,
+            state: Mutex::new(CacheState {
+                pages: BTreeMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Returns the cached device.
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    /// Returns the size of the device in bytes.
+    pub fn len(&self) -> u64 {
+        self.device.len()
+    }
+
+    /// Returns `true` if the device is empty.
+    pub fn is_empty(&self) -> bool {
+        self.device.is_empty()
+    }
+
+    /// Read `buf.len()` bytes starting at the byte `offset`.
+    pub async fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(offset, buf.len())?;
+        let mut state = self.state.lock_async().await;
+
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = (pos % PAGE_SIZE as u64) as usize;
+            let chunk = (PAGE_SIZE - within).min(buf.len() - done);
+
+            let page = self.page(&mut state, pos / PAGE_SIZE as u64, false).await?;
+            buf[done..done + chunk].copy_from_slice(&page.data[within..within + chunk]);
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Write `buf` starting at the byte `offset`.
+    pub async fn write(&self, offset: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.device.read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        self.check_range(offset, buf.len())?;
+        let mut state = self.state.lock_async().await;
+
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = (pos % PAGE_SIZE as u64) as usize;
+            let chunk = (PAGE_SIZE - within).min(buf.len() - done);
+
+            // Pages that are overwritten completely don't need to be read.
+            let overwrite = within == 0 && chunk == PAGE_SIZE;
+            let page = self
+                .page(&mut state, pos / PAGE_SIZE as u64, overwrite)
+                .await?;
+            page.data[within..within + chunk].copy_from_slice(&buf[done..done + chunk]);
+            page.dirty = true;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Write all changed pages back, and flush the device.
+    pub async fn flush(&self) -> Result<(), BlockError> {
+        let mut state = self.state.lock_async().await;
+        let dirty: Vec<u64> = state
+            .pages
+            .iter()
+            .filter(|(_, page)| page.dirty)
+            .map(|(&idx, _)| idx)
+            .collect();
+        for idx in dirty {
+            self.write_back(&mut state, idx).await?;
+        }
+        self.device.flush().await
+    }
+
+    fn check_range(&self, offset: u64, len: usize) -> Result<(), BlockError> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.len() => Ok(()),
+            _ => Err(BlockError::OutOfRange(
+                offset / self.device.block_size() as u64,
+            )),
+        }
+    }
+
+    /// Returns the first block and size in bytes of the page `idx`, which is
+    /// smaller than [`PAGE_SIZE`] at the end of the device.
+    fn page_range(&self, idx: u64) -> (u64, usize) {
+        let start = idx * PAGE_SIZE as u64;
+        let len = (self.len() - start).min(PAGE_SIZE as u64) as usize;
+        (start / self.device.block_size() as u64, len)
+    }
+
+    /// Returns the page `idx`, reading it from the device unless `overwrite`
+    /// is set, in which case a new page is zeroed.
+    async fn page<'s>(
+        &self,
+        state: &'s mut CacheState,
+        idx: u64,
+        overwrite: bool,
+    ) -> Result<&'s mut Page, BlockError> {
+        state.clock += 1;
+        let clock = state.clock;
+
+        if !state.pages.contains_key(&idx) {
+            if state.pages.len() >= self.capacity {
+                self.evict(state).await?;
+            }
+
+            let (start, len) = self.page_range(idx);
+            let data = if overwrite {
+                vec![0; len]
+            } else {
+                self.device.read_blocks(start, len).await?
+            };
+            state.pages.insert(
+                idx,
+                Page {
+                    data,
+                    dirty: false,
+                    last_used: clock,
+                },
+            );
+        }
+
+        let page = state.pages.get_mut(&idx).expect("the page was just cached");
+        page.last_used = clock;
+        Ok(page)
+    }
+
+    /// Remove the least recently used page, writing it back if needed.
+    async fn evict(&self, state: &mut CacheState) -> Result<(), BlockError> {
+        let Some(idx) = state
+            .pages
+            .iter()
+            .min_by_key(|(_, page)| page.last_used)
+            .map(|(&idx, _)| idx)
+        else {
+            return Ok(());
+        };
+        self.write_back(state, idx).await?;
+        state.pages.remove(&idx);
+        Ok(())
+    }
+
+    /// Write the page `idx` to the device if it is dirty.
+    async fn write_back(&self, state: &mut CacheState, idx: u64) -> Result<(), BlockError> {
+        let Some(page) = state.pages.get_mut(&idx).filter(|page| page.dirty) else {
+            return Ok(());
+        };
+        let (start, _) = self.page_range(idx);
+
+        // The device owns the buffer while writing, so it gets a copy. The page
+        // stays dirty if writing fails.
+        self.device.write_blocks(start, page.data.clone()).await?;
+        page.dirty = false;
+        Ok(())
+    }
+}
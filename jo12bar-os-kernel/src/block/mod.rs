@@ -0,0 +1,222 @@
+//! Block devices.
+//!
+//! A [`BlockDevice`] is storage that is read and written in fixed-size blocks,
+//! e.g. a disk. Devices are asynchronous: they are handed [`Request`]s, and
+//! [complete][Request::complete] them later, usually from an interrupt handler.
+//! Completing a request wakes the task waiting for it, so that reads and
+//! writes can be awaited from tasks on the [`Executor`][crate::task::Executor].
+//! Devices that handle one request at a time can keep the others in a
+//! [`RequestQueue`][queue::RequestQueue].
+//!
+//! Filesystems like [`fat`][crate::fs::fat] access devices through a
+//! [`BufferCache`][cache::BufferCache], which works on whole pages and writes
+//! changes back lazily.
+
+use alloc::{vec, vec::Vec};
+use core::{fmt, mem};
+
+use crossbeam_queue::ArrayQueue;
+use thiserror::Error;
+
+use crate::{locals, prelude::*, task::channel::oneshot};
+
+pub mod cache;
+pub mod queue;
+pub mod ramdisk;
+
+/// The number of completions [`DEFERRED_DROPS`] holds.
+const DEFERRED_DROP_CAPACITY: usize = 64;
+
+/// The channel of a completed request, and the buffer if nobody waited for it.
+type DeferredDrop = (Completion, Option<(Vec<u8>, Result<(), BlockError>)>);
+type Completion = oneshot::Sender<(Vec<u8>, Result<(), BlockError>)>;
+
+/// Requests completed in interrupt handlers, which can't use the allocator to
+/// free them. They are dropped by the next request that is sent.
+///
+/// This is first used when a request is sent, so it is never initialized
+/// inside an interrupt.
+static DEFERRED_DROPS: Lazy<ArrayQueue<DeferredDrop>> =
+    Lazy::new(|| ArrayQueue::new(DEFERRED_DROP_CAPACITY));
+
+/// Errors returned by block devices.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum BlockError {
+    #[error("block {0} is past the end of the device")]
+    OutOfRange(u64),
+    #[error("buffer length {0} is not a multiple of the block size")]
+    UnalignedBuffer(usize),
+    #[error("the device is read-only")]
+    ReadOnly,
+    #[error("the device failed")]
+    DeviceFailure,
+}
+
+/// Storage that is accessed in blocks of [`block_size`][Self::block_size] bytes.
+pub trait BlockDevice: Send + Sync {
+    /// Returns the size of a block in bytes, which must be a power of two.
+    fn block_size(&self) -> usize;
+
+    /// Returns the number of blocks.
+    fn block_count(&self) -> u64;
+
+    /// Returns `true` if the device can't be written to.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Start processing `request`.
+    ///
+    /// The request was already checked against the size of the device. It
+    /// must eventually be [completed][Request::complete], which may happen
+    /// before this returns.
+    fn submit(&self, request: Request);
+}
+
+impl dyn BlockDevice {
+    /// Returns the size of the device in bytes.
+    pub fn len(&self) -> u64 {
+        self.block_count() * self.block_size() as u64
+    }
+
+    /// Returns `true` if the device has no blocks.
+    pub fn is_empty(&self) -> bool {
+        self.block_count() == 0
+    }
+
+    /// Read `len` bytes, which must be a multiple of the block size, starting
+    /// at the block `start`.
+    pub async fn read_blocks(&self, start: u64, len: usize) -> Result<Vec<u8>, BlockError> {
+        self.check_range(start, len)?;
+        self.send(RequestKind::Read, start, vec![0; len]).await
+    }
+
+    /// Write `buf`, whose length must be a multiple of the block size, to the
+    /// blocks starting at `start`.
+    ///
+    /// Returns the buffer, so that it can be reused.
+    pub async fn write_blocks(&self, start: u64, buf: Vec<u8>) -> Result<Vec<u8>, BlockError> {
+        if self.read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        self.check_range(start, buf.len())?;
+        self.send(RequestKind::Write, start, buf).await
+    }
+
+    /// Wait until all written blocks reached the storage.
+    pub async fn flush(&self) -> Result<(), BlockError> {
+        self.send(RequestKind::Flush, 0, Vec::new()).await?;
+        Ok(())
+    }
+
+    fn check_range(&self, start: u64, len: usize) -> Result<(), BlockError> {
+        let block_size = self.block_size();
+        if !len.is_multiple_of(block_size) {
+            return Err(BlockError::UnalignedBuffer(len));
+        }
+        match start.checked_add((len / block_size) as u64) {
+            Some(end) if end <= self.block_count() => Ok(()),
+            _ => Err(BlockError::OutOfRange(start)),
+        }
+    }
+
+    async fn send(
+        &self,
+        kind: RequestKind,
+        start: u64,
+        buf: Vec<u8>,
+    ) -> Result<Vec<u8>, BlockError> {
+        while let Some(completed) = DEFERRED_DROPS.pop() {
+            drop(completed);
+        }
+
+        let (completion, receiver) = oneshot::channel();
+        self.submit(Request {
+            kind,
+            start,
+            buf,
+            completion,
+        });
+
+        // The request is dropped without being completed if the driver gave up
+        // on it.
+        let (buf, result) = receiver.await.map_err(|_| BlockError::DeviceFailure)?;
+        result.map(|()| buf)
+    }
+}
+
+/// What a [`Request`] asks the device to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    /// Fill the buffer with the blocks starting at [`Request::start`].
+    Read,
+    /// Write the buffer to the blocks starting at [`Request::start`].
+    Write,
+    /// Make sure all completed writes reached the storage. The buffer is empty.
+    Flush,
+}
+
+/// A request to a [`BlockDevice`], which owns the buffer until it is
+/// completed.
+pub struct Request {
+    kind: RequestKind,
+    start: u64,
+    buf: Vec<u8>,
+    completion: Completion,
+}
+
+impl Request {
+    /// Returns what the request asks for.
+    pub fn kind(&self) -> RequestKind {
+        self.kind
+    }
+
+    /// Returns the first block of the request.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the buffer to write from, whose length is a multiple of the
+    /// block size.
+    pub fn buf(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Returns the buffer to read into.
+    pub fn buf_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// Finish the request, and wake the task waiting for it.
+    ///
+    /// This doesn't block or allocate. Inside an interrupt handler, the
+    /// request's channel, and its buffer if the waiting future was dropped,
+    /// are freed later by a task, since the allocator can't be used there.
+    pub fn complete(self, result: Result<(), BlockError>) {
+        let Request {
+            buf,
+            mut completion,
+            ..
+        } = self;
+        // Nobody is waiting anymore if the future was dropped.
+        let rejected = completion.send_by_ref((buf, result)).err();
+
+        if locals!().in_interrupt() {
+            if let Err(completed) = DEFERRED_DROPS.push((completion, rejected)) {
+                // Leaking them is better than freeing them here.
+                mem::forget(completed);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("kind", &self.kind)
+            .field("start", &self.start)
+            .field("len", &self.buf.len())
+            .finish()
+    }
+}
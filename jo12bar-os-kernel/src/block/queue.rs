@@ -0,0 +1,95 @@
+//! A queue for devices that process one request at a time.
+
+use alloc::collections::VecDeque;
+
+use super::{BlockError, Request};
+use crate::prelude::*;
+
+/// The requests of a device that processes one request at a time.
+///
+/// The driver [pushes][Self::push] requests from
+/// [`BlockDevice::submit`][super::BlockDevice::submit], and starts the
+/// [active][Self::with_active] request whenever one becomes active. Once the
+/// device is done, usually in an interrupt handler, the driver calls
+/// [`finish`][Self::finish], which completes the active request and makes the
+/// next one active.
+///
+/// Interrupts are disabled while the queue is locked, so that interrupt
+/// handlers can use it without deadlocking with the code they interrupted.
+pub struct RequestQueue {
+    state: TicketLock<QueueState>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    active: Option<Request>,
+    pending: VecDeque<Request>,
+}
+
+impl Default for RequestQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestQueue {
+    /// Create an empty queue.
+    pub fn new() -> Self {
+        Self {
+            state: TicketLock::new_non_preemtable(QueueState::default()),
+        }
+    }
+
+    /// Add `request` to the queue.
+    ///
+    /// Returns `true` if the device was idle and `request` became active, in
+    /// which case the driver has to start it.
+    pub fn push(&self, request: Request) -> bool {
+        let mut state = self.state.lock();
+        if state.active.is_none() {
+            state.active = Some(request);
+            true
+        } else {
+            state.pending.push_back(request);
+            false
+        }
+    }
+
+    /// Call `f` with the active request, if there is one.
+    ///
+    /// `f` runs with interrupts disabled.
+    pub fn with_active<R>(&self, f: impl FnOnce(&mut Request) -> R) -> Option<R> {
+        self.state.lock().active.as_mut().map(f)
+    }
+
+    /// Complete the active request with `result`.
+    ///
+    /// Returns `true` if another request became active, in which case the
+    /// driver has to start it. Interrupt handlers can call this, since
+    /// [completing][Request::complete] the request frees nothing inside them.
+    pub fn finish(&self, result: Result<(), BlockError>) -> bool {
+        let (finished, has_next) = {
+            let mut state = self.state.lock();
+            let finished = state.active.take();
+            state.active = state.pending.pop_front();
+            (finished, state.active.is_some())
+        };
+
+        // Complete outside of the lock, since waking a task can take a while.
+        if let Some(request) = finished {
+            request.complete(result);
+        }
+        has_next
+    }
+
+    /// Returns the number of requests in the queue, including the active one.
+    pub fn len(&self) -> usize {
+        let state = self.state.lock();
+        state.pending.len() + usize::from(state.active.is_some())
+    }
+
+    /// Returns `true` if there are no requests.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,154 @@
+//! A block device in memory.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use super::{queue::RequestQueue, BlockDevice, BlockError, Request, RequestKind};
+use crate::{
+    interrupts::{self, InterruptError},
+    prelude::*,
+};
+
+/// A block device backed by a buffer on the kernel heap.
+///
+/// Requests are completed right away, unless the disk is
+/// [interrupt driven][Self::into_interrupt_driven], but still go through a
+/// [`RequestQueue`] like those of a real device.
+pub struct RamDisk {
+    block_size: usize,
+    read_only: bool,
+    /// Also accessed from the interrupt handler, so interrupts are disabled
+    /// while it is locked.
+    data: TicketLock<Vec<u8>>,
+    queue: RequestQueue,
+    /// The vector raised for each request, if the disk is interrupt driven.
+    vector: SpinOnce<u8>,
+}
+
+impl RamDisk {
+    /// Create a zeroed disk of `block_count` blocks of `block_size` bytes.
+    ///
+    /// # Panics
+    /// Panics if `block_size` is not a power of two.
+    pub fn new(block_size: usize, block_count: u64) -> Self {
+        Self::from_vec(block_size, vec![0; block_size * block_count as usize])
+    }
+
+    /// Create a disk with the contents `data`, whose length is rounded down to
+    /// whole blocks.
+    ///
+    /// # Panics
+    /// Panics if `block_size` is not a power of two.
+    pub fn from_vec(block_size: usize, mut data: Vec<u8>) -> Self {
+        assert!(
+            block_size.is_power_of_two(),
+            "block size {block_size} is not a power of two"
+        );
+        data.truncate(data.len() / block_size * block_size);
+        Self {
+            block_size,
+            read_only: false,
+            data: TicketLock::new_non_preemtable(data),
+            queue: RequestQueue::new(),
+            vector: SpinOnce::new(),
+        }
+    }
+
+    /// Process requests in an interrupt handler, like a real device would.
+    ///
+    /// The disk [registers][interrupts::register_handler] a vector, and raises
+    /// it on the submitting core for every request that becomes active. The
+    /// handler processes the active request and [finishes][RequestQueue::finish]
+    /// it.
+    pub fn into_interrupt_driven(self) -> Result<Arc<Self>, InterruptError> {
+        let disk = Arc::new(self);
+        // Vectors are never freed, so neither is the disk. This also keeps the
+        // handler from dropping the last reference inside the interrupt.
+        let this = disk.clone();
+        let vector = interrupts::register_handler(move || this.handle_interrupt())?;
+        if disk.vector.set(vector).is_err() {
+            unreachable!("the disk is only made interrupt driven once");
+        }
+        Ok(disk)
+    }
+
+    /// Reject writes to the disk.
+    pub fn into_read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Returns a copy of the contents of the disk.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.lock().clone()
+    }
+
+    /// Process the active request, and start the next one.
+    fn handle_interrupt(&self) {
+        let Some(result) = self.queue.with_active(|request| self.process(request)) else {
+            return;
+        };
+        if self.queue.finish(result) {
+            self.raise();
+        }
+    }
+
+    /// Raise the vector of the disk, so that the handler processes the active
+    /// request.
+    fn raise(&self) {
+        let vector = *self.vector.get().expect("the disk is interrupt driven");
+        interrupts::send_self(vector).expect("the local APIC was mapped by register_handler");
+    }
+
+    fn process(&self, request: &mut Request) -> Result<(), BlockError> {
+        let offset = request.start() as usize * self.block_size;
+        let mut data = self.data.lock();
+        match request.kind() {
+            RequestKind::Read => {
+                let len = request.buf().len();
+                request
+                    .buf_mut()
+                    .copy_from_slice(&data[offset..offset + len]);
+            }
+            RequestKind::Write => {
+                let buf = request.buf();
+                data[offset..offset + buf.len()].copy_from_slice(buf);
+            }
+            RequestKind::Flush => {}
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / self.block_size) as u64
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn submit(&self, request: Request) {
+        if !self.queue.push(request) {
+            // Whoever made the active request is already processing the queue.
+            return;
+        }
+        if self.vector.get().is_some() {
+            self.raise();
+            return;
+        }
+        loop {
+            let result = self
+                .queue
+                .with_active(|request| self.process(request))
+                .expect("a request is active");
+            if !self.queue.finish(result) {
+                break;
+            }
+        }
+    }
+}
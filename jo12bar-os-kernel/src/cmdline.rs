@@ -0,0 +1,276 @@
+//! The kernel command line.
+//!
+//! The runner passes the command line to the kernel as the file `cmdline` in
+//! the [initial ramdisk][crate::initrd]. It is parsed into a [`KernelConfig`]
+//! while booting, which any subsystem can query using [`config`].
+//!
+//! The command line is a whitespace-separated list of options, each either
+//! `key=value` or a bare `key`. The kernel itself understands
+//! - `log=<directives>`: log filter directives, see [`filter`][crate::logger::filter].
+//!   Replaces the ones from `KERNEL_LOG`.
+//! - `fb_log=on|off`: whether log records are shown on the framebuffer.
+//! - `demos=<names>`: comma-separated list of demos to run, or `all` or `none`.
+//!   See [`Demo`].
+//!
+//! Other options are ignored, but can be looked up using [`KernelConfig::get`].
+
+use core::{fmt, str::FromStr};
+
+use log::{info, warn};
+use thiserror::Error;
+
+use crate::{initrd, prelude::*};
+
+/// The configuration parsed from the command line, once [`init`] was called.
+static CONFIG: SpinOnce<KernelConfig> = SpinOnce::new();
+
+/// The file in the initial ramdisk containing the command line.
+pub const CMDLINE_FILE: &str = "cmdline";
+
+/// The configuration used before [`init`], or if there is no command line.
+static DEFAULT_CONFIG: KernelConfig = KernelConfig::new();
+
+/// Errors returned when parsing options of the command line.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum CmdlineError {
+    #[error("the command line is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("option {0} needs a value")]
+    MissingValue(&'static str),
+    #[error("option {0} expects on or off, not {1}")]
+    InvalidSwitch(&'static str, &'static str),
+    #[error("unknown demo {0}")]
+    UnknownDemo(&'static str),
+}
+
+/// The demos run by the kernel's `main`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Demo {
+    /// Heap allocations.
+    Alloc,
+    /// A record at each log level.
+    Logs,
+    /// Writing, reading and removing files.
+    Fs,
+    /// Kernel threads computing and reporting a result.
+    Threads,
+    /// An async task.
+    Async,
+    /// An async task using a RAM disk through the buffer cache.
+    Block,
+    /// Mounting the FAT image from the initial ramdisk, and changing a file
+    /// on it.
+    Fat,
+}
+
+impl Demo {
+    /// All demos.
+    pub const ALL: [Demo; 7] = [
+        Demo::Alloc,
+        Demo::Logs,
+        Demo::Fs,
+        Demo::Threads,
+        Demo::Async,
+        Demo::Block,
+        Demo::Fat,
+    ];
+
+    /// Returns the name used on the command line.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Demo::Alloc => "alloc",
+            Demo::Logs => "logs",
+            Demo::Fs => "fs",
+            Demo::Threads => "threads",
+            Demo::Async => "async",
+            Demo::Block => "block",
+            Demo::Fat => "fat",
+        }
+    }
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl FromStr for Demo {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Demo::ALL
+            .into_iter()
+            .find(|demo| demo.name() == s)
+            .ok_or(())
+    }
+}
+
+/// The kernel's configuration, parsed from the command line.
+#[derive(Clone, Copy)]
+pub struct KernelConfig {
+    cmdline: &'static str,
+    log_directives: Option<&'static str>,
+    framebuffer_log: bool,
+    /// A bit per [`Demo`].
+    demos: u8,
+}
+
+impl KernelConfig {
+    /// The configuration with an empty command line.
+    pub const fn new() -> Self {
+        Self {
+            cmdline: "",
+            log_directives: None,
+            framebuffer_log: true,
+            demos: u8::MAX,
+        }
+    }
+
+    /// Parse `cmdline`, as described in the [module docs][self].
+    ///
+    /// Invalid options are logged and ignored.
+    pub fn parse(cmdline: &'static str) -> Self {
+        let mut config = Self {
+            cmdline,
+            ..Self::new()
+        };
+
+        for option in cmdline.split_whitespace() {
+            if let Err(e) = config.apply(option) {
+                warn!("Ignoring kernel command line option {option}: {e}");
+            }
+        }
+        config
+    }
+
+    fn apply(&mut self, option: &'static str) -> Result<(), CmdlineError> {
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (option, None),
+        };
+
+        match key {
+            "log" => self.log_directives = Some(value.ok_or(CmdlineError::MissingValue("log"))?),
+            "fb_log" => self.framebuffer_log = parse_switch("fb_log", value)?,
+            "demos" => {
+                let value = value.ok_or(CmdlineError::MissingValue("demos"))?;
+                self.demos = match value {
+                    "all" => u8::MAX,
+                    "none" => 0,
+                    names => names.split(',').filter(|name| !name.is_empty()).try_fold(
+                        0,
+                        |demos, name| {
+                            let demo: Demo =
+                                name.parse().map_err(|_| CmdlineError::UnknownDemo(name))?;
+                            Ok(demos | demo.bit())
+                        },
+                    )?,
+                };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Returns the whole command line.
+    pub fn cmdline(&self) -> &'static str {
+        self.cmdline
+    }
+
+    /// Returns the value of the last `key=value` option, or `""` for a bare
+    /// `key`.
+    pub fn get(&self, key: &str) -> Option<&'static str> {
+        self.cmdline
+            .split_whitespace()
+            .rev()
+            .find_map(|option| match option.split_once('=') {
+                Some((k, value)) => (k == key).then_some(value),
+                None => (option == key).then_some(""),
+            })
+    }
+
+    /// Returns the log filter directives, if they were given.
+    pub fn log_directives(&self) -> Option<&'static str> {
+        self.log_directives
+    }
+
+    /// Returns `true` if log records should be shown on the framebuffer.
+    pub fn framebuffer_log(&self) -> bool {
+        self.framebuffer_log
+    }
+
+    /// Returns `true` if `demo` should be run.
+    pub fn demo_enabled(&self, demo: Demo) -> bool {
+        self.demos & demo.bit() != 0
+    }
+}
+
+impl Default for KernelConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for KernelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let demos = DebugDemos(*self);
+        f.debug_struct("KernelConfig")
+            .field("cmdline", &self.cmdline)
+            .field("log_directives", &self.log_directives)
+            .field("framebuffer_log", &self.framebuffer_log)
+            .field("demos", &demos)
+            .finish()
+    }
+}
+
+struct DebugDemos(KernelConfig);
+
+impl fmt::Debug for DebugDemos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                Demo::ALL
+                    .into_iter()
+                    .filter(|&demo| self.0.demo_enabled(demo))
+                    .map(Demo::name),
+            )
+            .finish()
+    }
+}
+
+fn parse_switch(key: &'static str, value: Option<&'static str>) -> Result<bool, CmdlineError> {
+    match value {
+        None | Some("on") => Ok(true),
+        Some("off") => Ok(false),
+        Some(value) => Err(CmdlineError::InvalidSwitch(key, value)),
+    }
+}
+
+/// Parse the command line in the [initial ramdisk][crate::initrd].
+///
+/// Must be called after [`initrd::init`][crate::initrd::init], and only once.
+pub fn init() {
+    let Ok(cmdline) = initrd::get().and_then(|initrd| initrd.read(CMDLINE_FILE)) else {
+        return;
+    };
+    let cmdline = match core::str::from_utf8(cmdline) {
+        Ok(cmdline) => cmdline.trim_end_matches(['\0', '\n', ' ']),
+        Err(_) => {
+            warn!(
+                "Ignoring kernel command line: {}",
+                CmdlineError::InvalidUtf8
+            );
+            return;
+        }
+    };
+
+    info!("Kernel command line: {cmdline}");
+    if CONFIG.set(KernelConfig::parse(cmdline)).is_err() {
+        warn!("The kernel command line was already parsed");
+    }
+}
+
+/// Returns the kernel's configuration.
+pub fn config() -> &'static KernelConfig {
+    CONFIG.get().unwrap_or(&DEFAULT_CONFIG)
+}
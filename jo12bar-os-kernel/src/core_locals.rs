@@ -16,10 +16,13 @@ use core::{
 };
 
 use alloc::boxed::Box;
-use mem_util::{sync::InterruptState, types::CoreId};
+use mem_util::{
+    sync::{InterruptState, Parker},
+    types::CoreId,
+};
 use x86_64::VirtAddr;
 
-use crate::cpu;
+use crate::{cpu, thread};
 
 /// A counter used to sign an ID for each core.
 ///
@@ -143,6 +146,14 @@ pub struct CoreLocals {
     /// We only reenable interrupts once this hits 0. This is decremented in
     /// [`CoreLocals::enable_interrupts()`].
     interrupts_disable_count: AtomicU64,
+
+    /// Current depth of critical sections (e.g. held locks).
+    ///
+    /// This is incremented whenever a critical section is entered through
+    /// [`CoreInterruptState`], and decremented once it's exited. The scheduler
+    /// never preempts a thread while this is non-zero, since that could leave
+    /// a preemtable lock held by a thread that isn't running.
+    critical_section_depth: AtomicU64,
     // /// A lock holding the local apic. This can be [None] if the apic has not been
     // /// initialized.
     // ///
@@ -170,6 +181,7 @@ impl CoreLocals {
             // interrupts_disable_count is 1, because the boot section does not allow
             // for interrupts, after all we have not initialized them.
             interrupts_disable_count: AtomicU64::new(1),
+            critical_section_depth: AtomicU64::new(0),
             // apic: unsafe { UnwrapTicketLock::new_non_preemtable_uninit() },
 
             // #[cfg(feature = "test")]
@@ -197,6 +209,52 @@ impl CoreLocals {
         self.exception_depth.count() > 0
     }
 
+    /// Returns the current depth of interrupts.
+    pub fn interrupt_depth(&self) -> u64 {
+        self.interrupt_depth.count()
+    }
+
+    /// Returns `true` if this core is currently inside a critical section, e.g.
+    /// because it holds a lock.
+    pub fn in_critical_section(&self) -> bool {
+        self.critical_section_depth.load(atomic::Ordering::SeqCst) > 0
+    }
+
+    /// Take a snapshot of this core's interrupt bookkeeping.
+    ///
+    /// Used by the scheduler to save the state of a kernel thread that is being
+    /// switched away from.
+    pub fn save_bookkeeping(&self) -> InterruptBookkeeping {
+        InterruptBookkeeping {
+            interrupt_depth: self.interrupt_depth.count(),
+            exception_depth: self.exception_depth.count(),
+            interrupts_disable_count: self.interrupts_disable_count.load(atomic::Ordering::SeqCst),
+            critical_section_depth: self.critical_section_depth.load(atomic::Ordering::SeqCst),
+        }
+    }
+
+    /// Overwrite this core's interrupt bookkeeping with a snapshot taken by
+    /// [`CoreLocals::save_bookkeeping()`].
+    ///
+    /// # Safety
+    /// - Interrupts must be disabled.
+    /// - `bookkeeping` must describe the state of the code that is about to
+    ///   resume executing on this core.
+    pub unsafe fn restore_bookkeeping(&self, bookkeeping: InterruptBookkeeping) {
+        self.interrupt_depth
+            .0
+            .store(bookkeeping.interrupt_depth, atomic::Ordering::SeqCst);
+        self.exception_depth
+            .0
+            .store(bookkeeping.exception_depth, atomic::Ordering::SeqCst);
+        self.interrupts_disable_count.store(
+            bookkeeping.interrupts_disable_count,
+            atomic::Ordering::SeqCst,
+        );
+        self.critical_section_depth
+            .store(bookkeeping.critical_section_depth, atomic::Ordering::SeqCst);
+    }
+
     /// Try to enable interrupts if possible.
     ///
     /// This will decrement [Self::interrupt_depth] and will only enable interrupts
@@ -302,6 +360,34 @@ impl Default for CoreLocals {
     }
 }
 
+/// A snapshot of the interrupt bookkeeping stored in [`CoreLocals`].
+///
+/// Each kernel thread has its own interrupt, exception and critical section
+/// nesting, so the scheduler saves this when switching away from a thread and
+/// restores it when switching back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptBookkeeping {
+    interrupt_depth: u64,
+    exception_depth: u64,
+    interrupts_disable_count: u64,
+    critical_section_depth: u64,
+}
+
+impl InterruptBookkeeping {
+    /// The bookkeeping of a thread that runs for the very first time.
+    ///
+    /// Interrupts are disabled exactly once, since the scheduler disables them
+    /// before switching to a thread.
+    pub const fn new_thread() -> Self {
+        Self {
+            interrupt_depth: 0,
+            exception_depth: 0,
+            interrupts_disable_count: 1,
+            critical_section_depth: 0,
+        }
+    }
+}
+
 /// Start the core boot process, allowing the `locals!` macro to access the
 /// `BOOT_CORE_LOCALS` region.
 ///
@@ -386,6 +472,7 @@ pub unsafe fn init(core_id: CoreId) {
         // interrupts_disable_count is 1, because the boot section does not allow
         // for interrupts, after all we have not initialized them.
         interrupts_disable_count: AtomicU64::new(1),
+        critical_section_depth: AtomicU64::new(0),
         // apic: unsafe { UnwrapTicketLock::new_non_preemtable_uninit() },
 
         // #[cfg(feature = "test")]
@@ -452,6 +539,10 @@ impl InterruptState for CoreInterruptState {
         // #[cfg(feature = "test")]
         // test_locals!().lock_count.fetch_add(1, Ordering::AcqRel);
 
+        locals!()
+            .critical_section_depth
+            .fetch_add(1, atomic::Ordering::SeqCst);
+
         if disable_interrupts {
             // Safety: Disabling interrupts is ok for entering critical sections
             unsafe {
@@ -464,6 +555,10 @@ impl InterruptState for CoreInterruptState {
         // #[cfg(feature = "test")]
         // test_locals!().lock_count.fetch_sub(1, Ordering::AcqRel);
 
+        locals!()
+            .critical_section_depth
+            .fetch_sub(1, atomic::Ordering::SeqCst);
+
         if enable_interrupts {
             // Safety: only called once, when a critical section is exited.
             unsafe { locals!().enable_interrupts() }
@@ -475,6 +570,26 @@ impl InterruptState for CoreInterruptState {
     }
 }
 
+impl Parker for CoreInterruptState {
+    type Thread = thread::Thread;
+
+    fn current_thread() -> Option<Self::Thread> {
+        if thread::is_initialized() && !Self::in_interrupt() && !Self::in_exception() {
+            Some(thread::current())
+        } else {
+            None
+        }
+    }
+
+    fn park() {
+        thread::park();
+    }
+
+    fn unpark(thread: &Self::Thread) {
+        thread.unpark();
+    }
+}
+
 impl fmt::Debug for CoreInterruptState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("CoreInterruptState")
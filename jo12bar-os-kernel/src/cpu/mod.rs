@@ -100,4 +100,13 @@ mod instructions {
         // Safety: Writing to the FS segment is safe, as no side-effects are possible.
         unsafe { IA32_FS_BASE.write(base) };
     }
+
+    /// Returns the initial APIC ID of the current core.
+    ///
+    /// Unlike the core ID in the core locals, this works before they are set
+    /// up, and doesn't touch memory.
+    #[inline]
+    pub fn apic_id() -> u8 {
+        (core::arch::x86_64::__cpuid(1).ebx >> 24) as u8
+    }
 }
@@ -0,0 +1,201 @@
+//! The BIOS parameter block, which describes the layout of a FAT volume.
+
+use crate::fs::{FsError, FsResult};
+
+/// The variant of FAT, which determines the size of FAT entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    /// 12-bit FAT entries.
+    Fat12,
+    /// 16-bit FAT entries.
+    Fat16,
+    /// 28-bit FAT entries, stored in 32 bits.
+    Fat32,
+}
+
+impl FatType {
+    /// Returns the smallest value marking the end of a cluster chain.
+    pub fn end_of_chain(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xff8,
+            FatType::Fat16 => 0xfff8,
+            FatType::Fat32 => 0x0fff_fff8,
+        }
+    }
+
+    /// Returns the value written to mark the end of a cluster chain.
+    pub fn end_of_chain_marker(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+}
+
+/// The largest number of data clusters, so that cluster numbers stay below the
+/// FAT32 value that marks bad clusters.
+const MAX_CLUSTER_COUNT: u32 = 0x0fff_fff5;
+
+/// The layout of a FAT volume, parsed from its boot sector.
+#[derive(Debug, Clone, Copy)]
+pub struct Bpb {
+    pub fat_type: FatType,
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    pub reserved_sectors: u32,
+    pub fat_count: u32,
+    /// The size of a single FAT, in sectors.
+    pub fat_sectors: u32,
+    pub total_sectors: u32,
+    /// The number of entries of the fixed root directory of FAT12/16.
+    pub root_entry_count: u32,
+    /// The first cluster of the root directory of FAT32.
+    pub root_cluster: u32,
+    /// The sector of the FSInfo structure of FAT32, or `0`.
+    pub fs_info_sector: u32,
+    /// The number of data clusters. Clusters are numbered from `2`.
+    pub cluster_count: u32,
+}
+
+impl Bpb {
+    /// Parse and check the boot sector.
+    pub fn parse(sector: &[u8; 512]) -> FsResult<Self> {
+        let u16_at =
+            |offset: usize| u16::from_le_bytes([sector[offset], sector[offset + 1]]) as u32;
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+
+        if sector[510..512] != [0x55, 0xaa] || !matches!(sector[0], 0xeb | 0xe9) {
+            return Err(FsError::WrongFilesystem("FAT"));
+        }
+
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved_sectors = u16_at(14);
+        let fat_count = sector[16] as u32;
+        let root_entry_count = u16_at(17);
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32),
+            count => count,
+        };
+        let fat_sectors = match u16_at(22) {
+            0 => u32_at(36),
+            count => count,
+        };
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || fat_sectors == 0
+        {
+            return Err(FsError::WrongFilesystem("FAT"));
+        }
+
+        // The fields are untrusted, so this is computed in `u64`, which can't
+        // overflow, and everything has to fit into the volume.
+        let root_dir_sectors = (root_entry_count * 32).div_ceil(bytes_per_sector);
+        let data_start = reserved_sectors as u64
+            + fat_count as u64 * fat_sectors as u64
+            + root_dir_sectors as u64;
+        let data_sectors = (total_sectors as u64)
+            .checked_sub(data_start)
+            .ok_or(FsError::Corrupted)? as u32;
+        let cluster_count = data_sectors / sectors_per_cluster;
+        if cluster_count > MAX_CLUSTER_COUNT {
+            return Err(FsError::Corrupted);
+        }
+
+        // This is how the FAT type is determined, regardless of any labels.
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let bpb = Self {
+            fat_type,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_count,
+            fat_sectors,
+            total_sectors,
+            root_entry_count,
+            root_cluster: if fat_type == FatType::Fat32 {
+                u32_at(44)
+            } else {
+                0
+            },
+            fs_info_sector: if fat_type == FatType::Fat32 {
+                u16_at(48)
+            } else {
+                0
+            },
+            cluster_count,
+        };
+
+        let fat_entries = bpb.fat_sectors as u64 * bpb.bytes_per_sector as u64 * 8
+            / match fat_type {
+                FatType::Fat12 => 12,
+                FatType::Fat16 => 16,
+                FatType::Fat32 => 32,
+            };
+        if fat_entries < cluster_count as u64 + 2
+            || (fat_type == FatType::Fat32 && !bpb.is_valid_cluster(bpb.root_cluster))
+            || (fat_type != FatType::Fat32 && root_entry_count == 0)
+        {
+            return Err(FsError::Corrupted);
+        }
+        Ok(bpb)
+    }
+
+    /// Returns the size of a cluster in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster as u64 * self.bytes_per_sector as u64
+    }
+
+    /// Returns the byte offset of the FAT with the index `fat`.
+    pub fn fat_offset(&self, fat: u32) -> u64 {
+        (self.reserved_sectors as u64 + fat as u64 * self.fat_sectors as u64)
+            * self.bytes_per_sector as u64
+    }
+
+    /// Returns the byte offset and size of the fixed root directory of
+    /// FAT12/16.
+    pub fn root_dir_region(&self) -> (u64, u64) {
+        (
+            self.fat_offset(self.fat_count),
+            self.root_entry_count as u64 * 32,
+        )
+    }
+
+    /// Returns the byte offset of the first data cluster.
+    fn data_offset(&self) -> u64 {
+        let (root_start, root_len) = self.root_dir_region();
+        root_start + root_len.next_multiple_of(self.bytes_per_sector as u64)
+    }
+
+    /// Returns the byte offset of `cluster`, which must be
+    /// [valid][Self::is_valid_cluster].
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        debug_assert!(
+            self.is_valid_cluster(cluster),
+            "cluster {cluster} is not a data cluster"
+        );
+        self.data_offset() + (cluster - 2) as u64 * self.cluster_size()
+    }
+
+    /// Returns `true` if `cluster` is a data cluster.
+    pub fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    /// Returns the size of the volume in bytes.
+    pub fn volume_size(&self) -> u64 {
+        self.total_sectors as u64 * self.bytes_per_sector as u64
+    }
+}
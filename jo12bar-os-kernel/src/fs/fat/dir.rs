@@ -0,0 +1,384 @@
+//! Directory entries, and the long file names stored alongside them.
+//!
+//! Each file has a 32-byte entry with an 8.3 short name. Names that don't fit
+//! are additionally stored in long file name (LFN) entries right before it,
+//! 13 UTF-16 code units each, in reverse order.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::fs::{path, FsError, FsResult};
+
+/// The size of a directory entry.
+pub const ENTRY_LEN: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// The attributes of an LFN entry.
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// The first byte of a free entry.
+pub const FREE: u8 = 0xe5;
+/// The first byte of a free entry, after which all entries are free.
+pub const END: u8 = 0x00;
+
+/// Set in the ordinal of the last LFN entry, which comes first.
+const LAST_LFN_ENTRY: u8 = 0x40;
+/// The number of UTF-16 code units in an LFN entry.
+const LFN_CHARS: usize = 13;
+/// The offsets of the UTF-16 code units in an LFN entry.
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The maximum length of a long name, in UTF-16 code units.
+const MAX_LFN_LEN: usize = 255;
+
+/// `NTRes` flags marking an all-lowercase base name or extension.
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXT: u8 = 0x10;
+
+/// Date and time written to new entries, 1980-01-01 00:00, since the kernel
+/// doesn't know the wall-clock time.
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+/// A file or directory found in a directory.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// The long name, or the short name if there is none.
+    pub name: String,
+    pub short_name: [u8; 11],
+    pub attr: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+    /// The index of the first slot used by the entry, including LFN entries.
+    pub first_slot: usize,
+    /// The index of the slot with the short entry.
+    pub slot: usize,
+}
+
+impl Entry {
+    /// Returns `true` for directories.
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    /// Returns `true` for `.` and `..`.
+    fn is_dot(&self) -> bool {
+        self.short_name[0] == b'.'
+    }
+}
+
+/// Returns the first cluster stored in a short entry.
+pub fn first_cluster(raw: &[u8]) -> u32 {
+    let hi = u16::from_le_bytes([raw[20], raw[21]]) as u32;
+    let lo = u16::from_le_bytes([raw[26], raw[27]]) as u32;
+    (hi << 16) | lo
+}
+
+/// Store the first cluster and size in a short entry.
+pub fn set_cluster_and_size(raw: &mut [u8], cluster: u32, size: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// Parse the entries of a directory, skipping free slots, volume labels, `.`
+/// and `..`.
+pub fn parse(data: &[u8]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut lfn = LfnBuilder::default();
+
+    for (slot, raw) in data.as_chunks::<ENTRY_LEN>().0.iter().enumerate() {
+        match raw[0] {
+            END => break,
+            FREE => {
+                lfn.reset();
+                continue;
+            }
+            _ => {}
+        }
+
+        let attr = raw[11];
+        if attr & 0x3f == ATTR_LONG_NAME {
+            lfn.push(slot, raw);
+            continue;
+        }
+        if attr & ATTR_VOLUME_ID != 0 {
+            lfn.reset();
+            continue;
+        }
+
+        let short_name: [u8; 11] = raw[..11].try_into().unwrap();
+        let (name, first_slot) = match lfn.finish(checksum(&short_name)) {
+            Some((name, first_slot)) => (name, first_slot),
+            None => (short_name_to_string(&short_name, raw[12]), slot),
+        };
+        let entry = Entry {
+            name,
+            short_name,
+            attr,
+            first_cluster: first_cluster(raw),
+            size: u32::from_le_bytes(raw[28..32].try_into().unwrap()),
+            first_slot,
+            slot,
+        };
+        if !entry.is_dot() {
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+/// Collects the LFN entries before a short entry.
+#[derive(Default)]
+struct LfnBuilder {
+    chars: Vec<u16>,
+    /// The ordinal expected next. `0` if no LFN is being collected.
+    next_ord: u8,
+    checksum: u8,
+    first_slot: usize,
+}
+
+impl LfnBuilder {
+    fn reset(&mut self) {
+        self.chars.clear();
+        self.next_ord = 0;
+    }
+
+    fn push(&mut self, slot: usize, raw: &[u8; ENTRY_LEN]) {
+        let ord = raw[0] & !LAST_LFN_ENTRY;
+        if raw[0] & LAST_LFN_ENTRY != 0 {
+            if ord == 0 || ord as usize * LFN_CHARS > MAX_LFN_LEN + LFN_CHARS {
+                self.reset();
+                return;
+            }
+            self.chars.clear();
+            self.chars.resize(ord as usize * LFN_CHARS, 0xffff);
+            self.checksum = raw[13];
+            self.first_slot = slot;
+        } else if ord != self.next_ord || ord == 0 || raw[13] != self.checksum {
+            self.reset();
+            return;
+        }
+
+        let start = (ord as usize - 1) * LFN_CHARS;
+        for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            self.chars[start + i] = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        }
+        self.next_ord = ord - 1;
+    }
+
+    /// Returns the long name and its first slot, if all LFN entries before
+    /// the short entry with `checksum` were found.
+    fn finish(&mut self, checksum: u8) -> Option<(String, usize)> {
+        let complete = self.next_ord == 0 && !self.chars.is_empty() && self.checksum == checksum;
+        let chars = core::mem::take(&mut self.chars);
+        self.reset();
+        if !complete {
+            return None;
+        }
+
+        let len = chars.iter().position(|&c| c == 0).unwrap_or(chars.len());
+        let name: String = char::decode_utf16(chars[..len].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        (!name.is_empty()).then_some((name, self.first_slot))
+    }
+}
+
+/// The checksum of a short name, stored in its LFN entries.
+pub fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+fn short_name_to_string(short_name: &[u8; 11], nt_res: u8) -> String {
+    let mut short_name = *short_name;
+    if short_name[0] == 0x05 {
+        short_name[0] = FREE;
+    }
+
+    let part = |bytes: &[u8], lowercase: bool| -> String {
+        bytes
+            .iter()
+            .map(|&b| if lowercase { b.to_ascii_lowercase() } else { b })
+            .map(char::from)
+            .collect::<String>()
+            .trim_end_matches(' ')
+            .into()
+    };
+
+    let mut name = part(&short_name[..8], nt_res & LOWERCASE_BASE != 0);
+    let ext = part(&short_name[8..], nt_res & LOWERCASE_EXT != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+/// Returns `true` if `a` and `b` name the same file, since FAT names are case
+/// insensitive.
+pub fn names_equal(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_uppercase)
+        .eq(b.chars().flat_map(char::to_uppercase))
+}
+
+/// Check that `name` can be stored as a long name.
+pub fn validate_long_name(name: &str) -> FsResult<()> {
+    path::validate_name(name)?;
+    if name.encode_utf16().count() > MAX_LFN_LEN {
+        return Err(FsError::NameTooLong);
+    }
+    if name
+        .chars()
+        .any(|c| c < ' ' || matches!(c, '"' | '*' | ':' | '<' | '>' | '?' | '\\' | '|'))
+        || name.ends_with(['.', ' '])
+    {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+/// Returns `true` if `b` may be part of a short name.
+fn is_short_name_char(b: u8) -> bool {
+    b.is_ascii_uppercase() || b.is_ascii_digit() || b >= 0x80 || b"$%'-_@~`!(){}^#&".contains(&b)
+}
+
+/// Returns the short name `name` is stored as, if it is a valid uppercase 8.3
+/// name that needs no LFN entries.
+pub fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || ext.contains('.')
+        || !name.is_ascii()
+        || !base.bytes().chain(ext.bytes()).all(is_short_name_char)
+    {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short_name)
+}
+
+/// Generate a short name for `name` that isn't in `existing`, like
+/// `LONGNA~1.TXT`.
+pub fn generate_short_name(name: &str, existing: &[[u8; 11]]) -> FsResult<[u8; 11]> {
+    let to_short_chars = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| c.to_ascii_uppercase())
+            .map(|c| {
+                if c.is_ascii() && is_short_name_char(c as u8) {
+                    c as u8
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => (base, ext),
+        _ => (trimmed, ""),
+    };
+    let mut base = to_short_chars(base);
+    let mut ext = to_short_chars(ext);
+    if base.is_empty() {
+        base.push(b'_');
+    }
+    ext.truncate(3);
+
+    for n in 1..1_000_000u32 {
+        let tail = alloc::format!("~{n}");
+        let base_len = base.len().min(8 - tail.len());
+
+        let mut short_name = [b' '; 11];
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+        if short_name[0] == FREE {
+            short_name[0] = 0x05;
+        }
+
+        if !existing.contains(&short_name) {
+            return Ok(short_name);
+        }
+    }
+    Err(FsError::NoSpace)
+}
+
+/// Returns the number of LFN entries needed to store `name`, `0` if it can be
+/// stored as `short_name` alone.
+pub fn lfn_entry_count(name: &str, short_name: &[u8; 11]) -> usize {
+    if exact_short_name(name).as_ref() == Some(short_name) {
+        0
+    } else {
+        name.encode_utf16().count().div_ceil(LFN_CHARS)
+    }
+}
+
+/// Encode the entries for a new file: LFN entries for `name`, if needed,
+/// followed by the short entry.
+pub fn encode(
+    name: &str,
+    short_name: &[u8; 11],
+    attr: u8,
+    first_cluster: u32,
+) -> Vec<[u8; ENTRY_LEN]> {
+    let lfn_count = lfn_entry_count(name, short_name);
+    let mut entries = Vec::with_capacity(lfn_count + 1);
+
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    if !chars.len().is_multiple_of(LFN_CHARS) {
+        chars.push(0);
+        chars.resize(chars.len().next_multiple_of(LFN_CHARS), 0xffff);
+    }
+    let checksum = checksum(short_name);
+    for ord in (1..=lfn_count).rev() {
+        let mut raw = [0; ENTRY_LEN];
+        raw[0] = ord as u8;
+        if ord == lfn_count {
+            raw[0] |= LAST_LFN_ENTRY;
+        }
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = checksum;
+        let part = &chars[(ord - 1) * LFN_CHARS..ord * LFN_CHARS];
+        for (&c, &offset) in part.iter().zip(&LFN_CHAR_OFFSETS) {
+            raw[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entries.push(raw);
+    }
+
+    entries.push(short_entry(short_name, attr, first_cluster));
+    entries
+}
+
+/// Encode a short entry.
+pub fn short_entry(short_name: &[u8; 11], attr: u8, first_cluster: u32) -> [u8; ENTRY_LEN] {
+    let mut raw = [0; ENTRY_LEN];
+    raw[..11].copy_from_slice(short_name);
+    raw[11] = attr;
+    // creation, last access and write dates
+    raw[16..18].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    raw[18..20].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    raw[24..26].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    set_cluster_and_size(&mut raw, first_cluster, 0);
+    raw
+}
+
+/// Encode the `.` and `..` entries of a new directory.
+pub fn dot_entries(cluster: u32, parent_cluster: u32) -> [[u8; ENTRY_LEN]; 2] {
+    [
+        short_entry(b".          ", ATTR_DIRECTORY, cluster),
+        short_entry(b"..         ", ATTR_DIRECTORY, parent_cluster),
+    ]
+}
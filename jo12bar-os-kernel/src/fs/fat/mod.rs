@@ -0,0 +1,710 @@
+//! A driver for FAT12, FAT16 and FAT32 filesystems on a [`BlockDevice`].
+//!
+//! The driver is synchronous. It accesses the device through a
+//! [`BufferCache`], and [blocks][task::block_on] while waiting for it.
+//!
+//! The volume starts with the [BIOS parameter block][bpb::Bpb], followed by
+//! the file allocation tables (FATs), which link the clusters of each file
+//! into a chain. FAT12/16 volumes have a fixed-size root directory before the
+//! first cluster, while the root directory of FAT32 is a cluster chain like
+//! any other directory.
+//!
+//! All operations on a volume are serialized by a single [`Mutex`], which is
+//! also held while waiting for the device. Inodes are cached by the position
+//! of their directory entry, so that all handles to a file see the same size
+//! and clusters.
+//!
+//! Timestamps aren't maintained, since the kernel doesn't know the wall-clock
+//! time.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use self::{
+    bpb::Bpb,
+    dir::{Entry, ENTRY_LEN},
+};
+use super::{DirEntry, FileSystem, FsError, FsResult, Inode, Metadata, NodeKind};
+use crate::{
+    block::{
+        cache::{BufferCache, PAGE_SIZE},
+        BlockDevice,
+    },
+    prelude::*,
+    task,
+};
+
+mod bpb;
+mod dir;
+
+pub use bpb::FatType;
+
+/// The signatures of the FSInfo structure of FAT32.
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+
+/// The largest size of a directory, which holds at most 65536 entries.
+const MAX_DIR_LEN: usize = 0x10000 * ENTRY_LEN;
+
+/// A mounted FAT volume.
+pub struct FatFs {
+    this: Weak<FatFs>,
+    cache: BufferCache,
+    bpb: Bpb,
+    state: Mutex<FatState>,
+}
+
+/// The mutable state of a volume, also used as the lock serializing all
+/// operations.
+struct FatState {
+    /// Where to start looking for a free cluster.
+    next_free: u32,
+    /// `true` once the free cluster count in the FSInfo structure was marked
+    /// as unknown.
+    fs_info_invalidated: bool,
+    /// Open inodes, by the byte offset of their directory entry.
+    inodes: BTreeMap<u64, Weak<FatInode>>,
+}
+
+/// The contents of a directory, and where they came from.
+struct DirData {
+    data: Vec<u8>,
+    /// The clusters of the directory, or empty for the fixed root directory
+    /// of FAT12/16.
+    clusters: Vec<u32>,
+}
+
+impl FatFs {
+    /// Open the FAT volume on `device`.
+    pub fn new(device: Arc<dyn BlockDevice>) -> FsResult<Arc<Self>> {
+        Self::with_cache_capacity(device, BufferCache::DEFAULT_CAPACITY)
+    }
+
+    /// Open the FAT volume on `device`, caching up to `capacity` pages of it.
+    pub fn with_cache_capacity(
+        device: Arc<dyn BlockDevice>,
+        capacity: usize,
+    ) -> FsResult<Arc<Self>> {
+        if device.block_size() > PAGE_SIZE || capacity == 0 {
+            return Err(FsError::Unsupported);
+        }
+        let cache = BufferCache::new(device, capacity);
+
+        let mut boot_sector = [0; 512];
+        task::block_on(cache.read(0, &mut boot_sector))?;
+        let bpb = Bpb::parse(&boot_sector)?;
+        if cache.len() < bpb.volume_size() {
+            return Err(FsError::Corrupted);
+        }
+
+        Ok(Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            cache,
+            bpb,
+            state: Mutex::new(FatState {
+                next_free: 2,
+                fs_info_invalidated: false,
+                inodes: BTreeMap::new(),
+            }),
+        }))
+    }
+
+    /// Returns the variant of FAT used by the volume.
+    pub fn fat_type(&self) -> FatType {
+        self.bpb.fat_type
+    }
+
+    /// Returns the size of a cluster in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        self.bpb.cluster_size()
+    }
+
+    fn this(&self) -> Arc<FatFs> {
+        self.this.upgrade().expect("the filesystem is alive")
+    }
+
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        self.check_range(offset, buf.len())?;
+        Ok(task::block_on(self.cache.read(offset, buf))?)
+    }
+
+    fn write_bytes(&self, offset: u64, buf: &[u8]) -> FsResult<()> {
+        self.check_range(offset, buf.len())?;
+        Ok(task::block_on(self.cache.write(offset, buf))?)
+    }
+
+    /// Make sure a corrupted volume can't make us access data outside of it.
+    fn check_range(&self, offset: u64, len: usize) -> FsResult<()> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.bpb.volume_size() => Ok(()),
+            _ => Err(FsError::Corrupted),
+        }
+    }
+
+    fn write_zeros(&self, offset: u64, len: u64) -> FsResult<()> {
+        let zeros = vec![0; len.min(self.cluster_size()) as usize];
+        let mut done = 0;
+        while done < len {
+            let chunk = (len - done).min(zeros.len() as u64);
+            self.write_bytes(offset + done, &zeros[..chunk as usize])?;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Returns the byte offset and size of the entry of `cluster` in the FAT
+    /// with the index `fat`.
+    fn fat_entry_location(&self, fat: u32, cluster: u32) -> (u64, usize) {
+        let start = self.bpb.fat_offset(fat);
+        match self.bpb.fat_type {
+            FatType::Fat12 => (start + cluster as u64 * 3 / 2, 2),
+            FatType::Fat16 => (start + cluster as u64 * 2, 2),
+            FatType::Fat32 => (start + cluster as u64 * 4, 4),
+        }
+    }
+
+    /// Returns the FAT entry of `cluster`, read from the first FAT.
+    fn fat_entry(&self, cluster: u32) -> FsResult<u32> {
+        let (offset, len) = self.fat_entry_location(0, cluster);
+        let mut raw = [0; 4];
+        self.read_bytes(offset, &mut raw[..len])?;
+        let raw = u32::from_le_bytes(raw);
+
+        Ok(match self.bpb.fat_type {
+            FatType::Fat12 if cluster % 2 == 1 => raw >> 4,
+            FatType::Fat12 => raw & 0xfff,
+            FatType::Fat16 => raw,
+            FatType::Fat32 => raw & 0x0fff_ffff,
+        })
+    }
+
+    /// Set the FAT entry of `cluster` in all FATs.
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> FsResult<()> {
+        for fat in 0..self.bpb.fat_count {
+            let (offset, len) = self.fat_entry_location(fat, cluster);
+            let mut raw = [0; 4];
+            self.read_bytes(offset, &mut raw[..len])?;
+            let old = u32::from_le_bytes(raw);
+
+            let new = match self.bpb.fat_type {
+                FatType::Fat12 if cluster % 2 == 1 => (old & 0x000f) | (value << 4),
+                FatType::Fat12 => (old & 0xf000) | (value & 0xfff),
+                FatType::Fat16 => value,
+                // The upper 4 bits are reserved.
+                FatType::Fat32 => (old & 0xf000_0000) | (value & 0x0fff_ffff),
+            };
+            self.write_bytes(offset, &new.to_le_bytes()[..len])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the clusters of the chain starting at `first`, which is empty
+    /// if `first` is `0`.
+    fn chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        self.chain_max(first, usize::MAX)
+    }
+
+    /// Like [`chain`][Self::chain], but fails if the chain is longer than
+    /// `max_len` clusters.
+    fn chain_max(&self, first: u32, max_len: usize) -> FsResult<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            if !self.bpb.is_valid_cluster(cluster)
+                || clusters.len() > self.bpb.cluster_count as usize
+                || clusters.len() >= max_len
+            {
+                return Err(FsError::Corrupted);
+            }
+            clusters.push(cluster);
+
+            cluster = match self.fat_entry(cluster)? {
+                next if next >= self.bpb.fat_type.end_of_chain() => 0,
+                0 => return Err(FsError::Corrupted),
+                next => next,
+            };
+        }
+        Ok(clusters)
+    }
+
+    /// Allocate a zeroed cluster, and append it to the chain ending in `prev`.
+    fn alloc_cluster(&self, state: &mut FatState, prev: Option<u32>) -> FsResult<u32> {
+        let count = self.bpb.cluster_count;
+        for i in 0..count {
+            let cluster = 2 + (state.next_free - 2 + i) % count;
+            if self.fat_entry(cluster)? != 0 {
+                continue;
+            }
+
+            self.invalidate_fs_info(state)?;
+            self.write_zeros(self.bpb.cluster_offset(cluster), self.cluster_size())?;
+            self.set_fat_entry(cluster, self.bpb.fat_type.end_of_chain_marker())?;
+            if let Some(prev) = prev {
+                self.set_fat_entry(prev, cluster)?;
+            }
+            state.next_free = 2 + (cluster - 2 + 1) % count;
+            return Ok(cluster);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Keep the first `keep` clusters of the chain, and free the rest.
+    fn truncate_chain(&self, state: &mut FatState, clusters: &[u32], keep: usize) -> FsResult<()> {
+        if keep >= clusters.len() {
+            return Ok(());
+        }
+        self.invalidate_fs_info(state)?;
+        if let Some(&last) = keep.checked_sub(1).and_then(|idx| clusters.get(idx)) {
+            self.set_fat_entry(last, self.bpb.fat_type.end_of_chain_marker())?;
+        }
+        for &cluster in &clusters[keep..] {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Mark the free cluster count of FAT32 as unknown before it changes, since
+    /// it isn't kept up to date.
+    fn invalidate_fs_info(&self, state: &mut FatState) -> FsResult<()> {
+        if state.fs_info_invalidated
+            || self.bpb.fat_type != FatType::Fat32
+            || self.bpb.fs_info_sector == 0
+        {
+            return Ok(());
+        }
+        state.fs_info_invalidated = true;
+
+        let offset = self.bpb.fs_info_sector as u64 * self.bpb.bytes_per_sector as u64;
+        let mut fs_info = [0; 512];
+        self.read_bytes(offset, &mut fs_info)?;
+        let u32_at = |at: usize| u32::from_le_bytes(fs_info[at..at + 4].try_into().unwrap());
+        if u32_at(0) == FS_INFO_LEAD_SIGNATURE && u32_at(484) == FS_INFO_STRUCT_SIGNATURE {
+            self.write_bytes(offset + 488, &u32::MAX.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Read the contents of `dir`.
+    fn read_dir_data(&self, dir: &FatInode) -> FsResult<DirData> {
+        if dir.is_fixed_root() {
+            let (start, len) = self.bpb.root_dir_region();
+            let mut data = vec![0; len as usize];
+            self.read_bytes(start, &mut data)?;
+            return Ok(DirData {
+                data,
+                clusters: Vec::new(),
+            });
+        }
+
+        let cluster_size = self.cluster_size() as usize;
+        let clusters = self.chain_max(dir.first_cluster(), MAX_DIR_LEN / cluster_size)?;
+        let len = clusters.len() * cluster_size;
+        let mut data = Vec::new();
+        data.try_reserve_exact(len).map_err(|_| FsError::NoSpace)?;
+        data.resize(len, 0);
+        for (&cluster, chunk) in clusters.iter().zip(data.chunks_mut(cluster_size)) {
+            self.read_bytes(self.bpb.cluster_offset(cluster), chunk)?;
+        }
+        Ok(DirData { data, clusters })
+    }
+
+    /// Returns the byte offset of the entry in `slot` of `dir`.
+    fn slot_offset(&self, dir: &DirData, slot: usize) -> u64 {
+        let offset = (slot * ENTRY_LEN) as u64;
+        if dir.clusters.is_empty() {
+            return self.bpb.root_dir_region().0 + offset;
+        }
+        let cluster = dir.clusters[(offset / self.cluster_size()) as usize];
+        self.bpb.cluster_offset(cluster) + offset % self.cluster_size()
+    }
+
+    /// Find `count` consecutive free slots in `dir`, growing it if needed.
+    fn find_free_slots(
+        &self,
+        state: &mut FatState,
+        dir: &mut DirData,
+        count: usize,
+    ) -> FsResult<usize> {
+        loop {
+            let mut run = 0;
+            for (slot, raw) in dir.data.as_chunks::<ENTRY_LEN>().0.iter().enumerate() {
+                if matches!(raw[0], dir::FREE | dir::END) {
+                    run += 1;
+                    if run == count {
+                        return Ok(slot + 1 - count);
+                    }
+                } else {
+                    run = 0;
+                }
+            }
+
+            // The fixed root directory of FAT12/16 can't grow.
+            let Some(&last) = dir.clusters.last() else {
+                return Err(FsError::NoSpace);
+            };
+            if dir.data.len() + self.cluster_size() as usize > MAX_DIR_LEN {
+                return Err(FsError::NoSpace);
+            }
+            dir.data
+                .try_reserve_exact(self.cluster_size() as usize)
+                .map_err(|_| FsError::NoSpace)?;
+            let cluster = self.alloc_cluster(state, Some(last))?;
+            dir.clusters.push(cluster);
+            dir.data
+                .resize(dir.data.len() + self.cluster_size() as usize, 0);
+        }
+    }
+
+    /// Returns the inode for `entry` of `dir`, reusing it if it is open.
+    fn inode(&self, state: &mut FatState, dir: &DirData, entry: &Entry) -> Arc<FatInode> {
+        let entry_offset = self.slot_offset(dir, entry.slot);
+        if let Some(inode) = state.inodes.get(&entry_offset).and_then(Weak::upgrade) {
+            return inode;
+        }
+
+        let inode = Arc::new(FatInode {
+            fs: self.this(),
+            kind: if entry.is_dir() {
+                NodeKind::Directory
+            } else {
+                NodeKind::File
+            },
+            entry_offset: Some(entry_offset),
+            first_cluster: AtomicU32::new(entry.first_cluster),
+            size: AtomicU32::new(if entry.is_dir() { 0 } else { entry.size }),
+            removed: AtomicBool::new(false),
+        });
+        state.inodes.retain(|_, inode| inode.strong_count() > 0);
+        state.inodes.insert(entry_offset, Arc::downgrade(&inode));
+        inode
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &'static str {
+        match self.bpb.fat_type {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        let root_cluster = match self.bpb.fat_type {
+            FatType::Fat32 => self.bpb.root_cluster,
+            _ => 0,
+        };
+        Arc::new(FatInode {
+            fs: self.this(),
+            kind: NodeKind::Directory,
+            entry_offset: None,
+            first_cluster: AtomicU32::new(root_cluster),
+            size: AtomicU32::new(0),
+            removed: AtomicBool::new(false),
+        })
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        let _state = self.state.lock();
+        Ok(task::block_on(self.cache.flush())?)
+    }
+}
+
+/// A file or directory of a [`FatFs`].
+struct FatInode {
+    fs: Arc<FatFs>,
+    kind: NodeKind,
+    /// The byte offset of the inode's entry in its directory, or [`None`] for
+    /// the root directory.
+    entry_offset: Option<u64>,
+    /// The first cluster, or `0` for empty files.
+    first_cluster: AtomicU32,
+    /// The size of files in bytes. Directories don't store their size.
+    size: AtomicU32,
+    /// Set once the inode was removed from its directory, after which its
+    /// clusters may be reused.
+    removed: AtomicBool,
+}
+
+impl FatInode {
+    fn first_cluster(&self) -> u32 {
+        self.first_cluster.load(Ordering::Relaxed)
+    }
+
+    fn size(&self) -> u32 {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    fn is_fixed_root(&self) -> bool {
+        self.entry_offset.is_none() && self.fs.bpb.fat_type != FatType::Fat32
+    }
+
+    /// Check that the inode is still usable, and of the type `kind`.
+    fn check(&self, kind: NodeKind) -> FsResult<()> {
+        if self.removed.load(Ordering::Relaxed) {
+            return Err(FsError::NotFound);
+        }
+        match (self.kind, kind) {
+            (NodeKind::File, NodeKind::Directory) => Err(FsError::NotADirectory),
+            (NodeKind::Directory, NodeKind::File) => Err(FsError::IsADirectory),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the first cluster and size back to the directory entry.
+    fn write_entry(&self) -> FsResult<()> {
+        let Some(offset) = self.entry_offset else {
+            return Ok(());
+        };
+        let mut raw = [0; ENTRY_LEN];
+        self.fs.read_bytes(offset, &mut raw)?;
+        dir::set_cluster_and_size(&mut raw, self.first_cluster(), self.size());
+        self.fs.write_bytes(offset, &raw)
+    }
+
+    /// Read or write the file contents at `offset`, one cluster at a time.
+    fn for_each_chunk(
+        &self,
+        offset: u64,
+        len: usize,
+        mut f: impl FnMut(u64, usize, usize) -> FsResult<()>,
+    ) -> FsResult<()> {
+        let clusters = self.fs.chain(self.first_cluster())?;
+        let cluster_size = self.fs.cluster_size();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let cluster = *clusters
+                .get((pos / cluster_size) as usize)
+                .ok_or(FsError::Corrupted)?;
+            let within = pos % cluster_size;
+            let chunk = ((cluster_size - within) as usize).min(len - done);
+            f(self.fs.bpb.cluster_offset(cluster) + within, done, chunk)?;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Change the size of the file, allocating or freeing clusters.
+    fn resize(&self, state: &mut FatState, len: u64) -> FsResult<()> {
+        let len = u32::try_from(len).map_err(|_| FsError::FileTooLarge)?;
+        let old_len = self.size();
+        let fs = &self.fs;
+        let cluster_size = fs.cluster_size();
+        let mut clusters = fs.chain(self.first_cluster())?;
+        let old_count = clusters.len();
+        let needed = (len as u64).div_ceil(cluster_size) as usize;
+
+        // Clusters are zeroed when they are allocated, but the end of the last
+        // one might contain old data.
+        let allocated = old_count as u64 * cluster_size;
+        let zero_end = (len as u64).min(allocated);
+        if zero_end > old_len as u64 {
+            self.for_each_chunk(
+                old_len as u64,
+                (zero_end - old_len as u64) as usize,
+                |at, _, chunk| fs.write_zeros(at, chunk as u64),
+            )?;
+        }
+
+        while clusters.len() < needed {
+            match fs.alloc_cluster(state, clusters.last().copied()) {
+                Ok(cluster) => clusters.push(cluster),
+                Err(e) => {
+                    fs.truncate_chain(state, &clusters, old_count)?;
+                    return Err(e);
+                }
+            }
+        }
+        fs.truncate_chain(state, &clusters, needed)?;
+
+        self.first_cluster
+            .store(if needed == 0 { 0 } else { clusters[0] }, Ordering::Relaxed);
+        self.size.store(len, Ordering::Relaxed);
+        self.write_entry()
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let len = match self.kind {
+            NodeKind::File => self.size() as u64,
+            NodeKind::Directory => self.read_dir().map_or(0, |entries| entries.len() as u64),
+        };
+        Metadata {
+            ino: self.entry_offset.unwrap_or(1),
+            kind: self.kind,
+            len,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let _state = self.fs.state.lock();
+        self.check(NodeKind::File)?;
+
+        let size = self.size() as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        self.for_each_chunk(offset, len, |at, done, chunk| {
+            self.fs.read_bytes(at, &mut buf[done..done + chunk])
+        })?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut state = self.fs.state.lock();
+        self.check(NodeKind::File)?;
+
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or(FsError::FileTooLarge)?;
+        if end > self.size() as u64 {
+            self.resize(&mut state, end)?;
+        }
+        self.for_each_chunk(offset, buf.len(), |at, done, chunk| {
+            self.fs.write_bytes(at, &buf[done..done + chunk])
+        })?;
+        Ok(buf.len())
+    }
+
+    fn set_len(&self, len: u64) -> FsResult<()> {
+        let mut state = self.fs.state.lock();
+        self.check(NodeKind::File)?;
+        self.resize(&mut state, len)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let mut state = self.fs.state.lock();
+        self.check(NodeKind::Directory)?;
+
+        let dir = self.fs.read_dir_data(self)?;
+        let entry = find(&dir, name).ok_or(FsError::NotFound)?;
+        Ok(self.fs.inode(&mut state, &dir, &entry))
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> FsResult<Arc<dyn Inode>> {
+        let mut state = self.fs.state.lock();
+        self.check(NodeKind::Directory)?;
+        dir::validate_long_name(name)?;
+
+        let fs = &self.fs;
+        let mut dir = fs.read_dir_data(self)?;
+        let entries = dir::parse(&dir.data);
+        if entries
+            .iter()
+            .any(|entry| dir::names_equal(&entry.name, name))
+        {
+            return Err(FsError::AlreadyExists);
+        }
+        let existing: Vec<_> = entries.iter().map(|entry| entry.short_name).collect();
+        let short_name = match dir::exact_short_name(name) {
+            Some(short_name) if !existing.contains(&short_name) => short_name,
+            _ => dir::generate_short_name(name, &existing)?,
+        };
+
+        let (attr, first_cluster) = match kind {
+            NodeKind::File => (dir::ATTR_ARCHIVE, 0),
+            NodeKind::Directory => {
+                let cluster = fs.alloc_cluster(&mut state, None)?;
+                // `..` of entries in the root directory is always `0`.
+                let parent = if self.entry_offset.is_none() {
+                    0
+                } else {
+                    self.first_cluster()
+                };
+                let dots = dir::dot_entries(cluster, parent);
+                fs.write_bytes(fs.bpb.cluster_offset(cluster), dots.as_flattened())?;
+                (dir::ATTR_DIRECTORY, cluster)
+            }
+        };
+
+        let raw_entries = dir::encode(name, &short_name, attr, first_cluster);
+        let slot = match fs.find_free_slots(&mut state, &mut dir, raw_entries.len()) {
+            Ok(slot) => slot,
+            Err(e) => {
+                if first_cluster != 0 {
+                    fs.truncate_chain(&mut state, &[first_cluster], 0)?;
+                }
+                return Err(e);
+            }
+        };
+        for (i, raw) in raw_entries.iter().enumerate() {
+            fs.write_bytes(fs.slot_offset(&dir, slot + i), raw)?;
+        }
+
+        let entry = Entry {
+            name: name.into(),
+            short_name,
+            attr,
+            first_cluster,
+            size: 0,
+            first_slot: slot,
+            slot: slot + raw_entries.len() - 1,
+        };
+        Ok(fs.inode(&mut state, &dir, &entry))
+    }
+
+    fn remove(&self, name: &str) -> FsResult<()> {
+        let mut state = self.fs.state.lock();
+        self.check(NodeKind::Directory)?;
+
+        let fs = &self.fs;
+        let dir = fs.read_dir_data(self)?;
+        let entry = find(&dir, name).ok_or(FsError::NotFound)?;
+        let inode = fs.inode(&mut state, &dir, &entry);
+
+        if entry.is_dir() && !dir::parse(&fs.read_dir_data(&inode)?.data).is_empty() {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+
+        for slot in entry.first_slot..=entry.slot {
+            fs.write_bytes(fs.slot_offset(&dir, slot), &[dir::FREE])?;
+        }
+        let clusters = fs.chain(inode.first_cluster())?;
+        fs.truncate_chain(&mut state, &clusters, 0)?;
+
+        // Handles that are still open can't use the freed clusters anymore.
+        inode.removed.store(true, Ordering::Relaxed);
+        inode.first_cluster.store(0, Ordering::Relaxed);
+        state.inodes.remove(&fs.slot_offset(&dir, entry.slot));
+        Ok(())
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        let _state = self.fs.state.lock();
+        self.check(NodeKind::Directory)?;
+
+        let dir = self.fs.read_dir_data(self)?;
+        Ok(dir::parse(&dir.data)
+            .into_iter()
+            .map(|entry| DirEntry {
+                kind: if entry.is_dir() {
+                    NodeKind::Directory
+                } else {
+                    NodeKind::File
+                },
+                ino: self.fs.slot_offset(&dir, entry.slot),
+                name: entry.name,
+            })
+            .collect())
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        self.fs.sync()
+    }
+}
+
+/// Find the entry called `name`, which can also be its short name.
+fn find(dir: &DirData, name: &str) -> Option<Entry> {
+    let short_name = dir::exact_short_name(&name.to_uppercase());
+    dir::parse(&dir.data)
+        .into_iter()
+        .find(|entry| dir::names_equal(&entry.name, name) || Some(entry.short_name) == short_name)
+}
@@ -0,0 +1,664 @@
+//! The virtual filesystem.
+//!
+//! Filesystems implement [`FileSystem`] and [`Inode`], and are attached to the
+//! directory tree with [`mount`]. Paths are resolved by finding the mount point
+//! that is the longest prefix of the [normalized][path::normalize] path, and
+//! looking up the remaining components starting at the root of the filesystem
+//! mounted there.
+//!
+//! [`init`] mounts a [`tmpfs`] at `/`, and fills it with the contents of the
+//! [initial ramdisk][crate::initrd]. Files too large to copy into the heap
+//! are skipped, but can still be read in place through [`initrd::get`].
+//!
+//! Files are accessed through [`File`] handles, which keep the [`Inode`] alive
+//! even if the file is removed or its filesystem unmounted while it is open.
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt;
+
+use log::{info, warn};
+use thiserror::Error;
+
+use crate::{
+    block::BlockError,
+    initrd::{self, EntryKind},
+    mem::allocator::HEAP_SIZE,
+    prelude::*,
+};
+
+pub mod fat;
+pub mod path;
+pub mod tmpfs;
+
+/// The mounted filesystems.
+static MOUNTS: RwTicketLock<Vec<Mount>> = RwTicketLock::new(Vec::new());
+
+/// The result of filesystem operations.
+pub type FsResult<T> = Result<T, FsError>;
+
+/// Errors returned by filesystem operations.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum FsError {
+    #[error("no such file or directory")]
+    NotFound,
+    #[error("file exists")]
+    AlreadyExists,
+    #[error("not a directory")]
+    NotADirectory,
+    #[error("is a directory")]
+    IsADirectory,
+    #[error("directory not empty")]
+    DirectoryNotEmpty,
+    #[error("invalid path")]
+    InvalidPath,
+    #[error("file name too long")]
+    NameTooLong,
+    #[error("file is not open for {0}")]
+    NotOpenFor(&'static str),
+    #[error("invalid seek")]
+    InvalidSeek,
+    #[error("file too large")]
+    FileTooLarge,
+    #[error("no space left on device")]
+    NoSpace,
+    #[error("read-only filesystem")]
+    ReadOnly,
+    #[error("mount point is busy")]
+    Busy,
+    #[error("not a mount point")]
+    NotMounted,
+    #[error("operation not supported")]
+    Unsupported,
+    #[error("I/O error: {0}")]
+    Io(BlockError),
+    #[error("the filesystem is corrupted")]
+    Corrupted,
+    #[error("not a {0} filesystem")]
+    WrongFilesystem(&'static str),
+}
+
+impl From<BlockError> for FsError {
+    fn from(value: BlockError) -> Self {
+        match value {
+            BlockError::ReadOnly => FsError::ReadOnly,
+            other => FsError::Io(other),
+        }
+    }
+}
+
+/// The type of an [`Inode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A regular file.
+    File,
+    /// A directory.
+    Directory,
+}
+
+/// Information about an [`Inode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// The inode number, unique within its filesystem.
+    pub ino: u64,
+    /// The type of the inode.
+    pub kind: NodeKind,
+    /// The size in bytes for files, and the number of entries for directories.
+    pub len: u64,
+}
+
+impl Metadata {
+    /// Returns `true` for directories.
+    pub fn is_dir(&self) -> bool {
+        self.kind == NodeKind::Directory
+    }
+
+    /// Returns `true` for regular files.
+    pub fn is_file(&self) -> bool {
+        self.kind == NodeKind::File
+    }
+}
+
+/// An entry of a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The name of the entry, without its directory.
+    pub name: String,
+    /// The type of the entry.
+    pub kind: NodeKind,
+    /// The inode number of the entry.
+    pub ino: u64,
+}
+
+/// A file or directory of a [`FileSystem`].
+///
+/// Operations that don't apply to the inode's [`NodeKind`] return
+/// [`FsError::IsADirectory`] or [`FsError::NotADirectory`].
+pub trait Inode: Send + Sync {
+    /// Returns information about the inode.
+    fn metadata(&self) -> Metadata;
+
+    /// Read from the file starting at `offset`, returning the number of bytes
+    /// read. Returns `0` at the end of the file.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize>;
+
+    /// Write to the file starting at `offset`, extending it if necessary, and
+    /// return the number of bytes written.
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize>;
+
+    /// Truncate or zero-extend the file to `len` bytes.
+    fn set_len(&self, len: u64) -> FsResult<()>;
+
+    /// Returns the entry called `name` of this directory.
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>>;
+
+    /// Create an empty entry called `name` in this directory.
+    fn create(&self, name: &str, kind: NodeKind) -> FsResult<Arc<dyn Inode>>;
+
+    /// Remove the entry called `name` from this directory. Directories must be
+    /// empty.
+    fn remove(&self, name: &str) -> FsResult<()>;
+
+    /// Returns the entries of this directory, without `.` and `..`.
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>>;
+
+    /// Write any changes to the inode to the underlying storage.
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+/// A filesystem that can be [mounted][mount].
+pub trait FileSystem: Send + Sync {
+    /// Returns the name of the filesystem type, e.g. `tmpfs`.
+    fn name(&self) -> &'static str;
+
+    /// Returns the root directory.
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Write all changes to the underlying storage.
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+/// A filesystem attached to the directory tree.
+struct Mount {
+    /// The normalized path of the mount point.
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// Information about a mounted filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    /// The path of the mount point.
+    pub path: String,
+    /// The [name][FileSystem::name] of the filesystem type.
+    pub fs_type: &'static str,
+}
+
+/// Attach `fs` to the directory tree at `path`.
+///
+/// The first filesystem must be mounted at `/`. Other mount points must be
+/// existing directories, which are hidden until the filesystem is unmounted.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> FsResult<()> {
+    let path = path::normalize(path)?;
+
+    if path != "/" && !lookup(&path)?.metadata().is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(FsError::Busy);
+    }
+    if mounts.is_empty() && path != "/" {
+        return Err(FsError::NotFound);
+    }
+    info!("Mounted {} at {path}", fs.name());
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+/// Detach the filesystem mounted at `path`, after [syncing][FileSystem::sync]
+/// it. If syncing fails, the filesystem stays mounted.
+///
+/// Fails with [`FsError::Busy`] if other filesystems are mounted below it.
+pub fn unmount(path: &str) -> FsResult<()> {
+    let path = path::normalize(path)?;
+
+    // Syncing may block, so it can't happen while holding the lock. The
+    // mounts are checked again afterwards, in case they changed meanwhile.
+    let (_, fs) = find_unmountable(&MOUNTS.read(), &path)?;
+    fs.sync()?;
+
+    let mut mounts = MOUNTS.write();
+    let (idx, current) = find_unmountable(&mounts, &path)?;
+    if !Arc::ptr_eq(&fs, &current) {
+        return Err(FsError::NotMounted);
+    }
+    mounts.remove(idx);
+    Ok(())
+}
+
+/// Returns the index and filesystem of the mount at `path`, if it can be
+/// [unmounted][unmount].
+fn find_unmountable(mounts: &[Mount], path: &str) -> FsResult<(usize, Arc<dyn FileSystem>)> {
+    let idx = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(FsError::NotMounted)?;
+    let has_children = mounts
+        .iter()
+        .any(|mount| mount.path != path && path::strip_mount_point(&mount.path, path).is_some());
+    if has_children {
+        return Err(FsError::Busy);
+    }
+    Ok((idx, mounts[idx].fs.clone()))
+}
+
+/// Returns the mounted filesystems, in the order they were mounted.
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS
+        .read()
+        .iter()
+        .map(|mount| MountInfo {
+            path: mount.path.clone(),
+            fs_type: mount.fs.name(),
+        })
+        .collect()
+}
+
+/// Returns `true` if `path` is a mount point.
+fn is_mount_point(path: &str) -> bool {
+    MOUNTS.read().iter().any(|mount| mount.path == path)
+}
+
+/// Returns the inode at the normalized `path`.
+fn lookup(path: &str) -> FsResult<Arc<dyn Inode>> {
+    let (fs, rest) = {
+        let mounts = MOUNTS.read();
+        let (mount, rest) = mounts
+            .iter()
+            .filter_map(|mount| Some((mount, path::strip_mount_point(path, &mount.path)?)))
+            .max_by_key(|(mount, _)| mount.path.len())
+            .ok_or(FsError::NotFound)?;
+        (mount.fs.clone(), rest.to_string())
+    };
+
+    let inode = path::components(&rest).try_fold(fs.root(), |dir, name| dir.lookup(name));
+    inode
+}
+
+/// Returns the directory containing the normalized `path`, and the name of
+/// `path` in it.
+fn lookup_parent(path: &str) -> FsResult<(Arc<dyn Inode>, &str)> {
+    let (parent, name) = path::split_parent(path).ok_or(FsError::Busy)?;
+    Ok((lookup(parent)?, name))
+}
+
+/// Where [`File::seek`] seeks from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    /// An offset from the start of the file.
+    Start(u64),
+    /// An offset from the end of the file.
+    End(i64),
+    /// An offset from the current position.
+    Current(i64),
+}
+
+/// How a [`File`] is opened, like [`std::fs::OpenOptions`].
+///
+/// [`std::fs::OpenOptions`]: https://doc.rust-lang.org/std/fs/struct.OpenOptions.html
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+}
+
+impl OpenOptions {
+    /// Options with all flags unset.
+    pub const fn new() -> Self {
+        Self {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+        }
+    }
+
+    /// Allow reading.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Allow writing.
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Write to the end of the file, regardless of the current position.
+    /// Implies [`write`][Self::write].
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Truncate the file to 0 bytes when opening it. Requires
+    /// [`write`][Self::write].
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Create the file if it doesn't exist.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Create the file, failing if it already exists.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Open the file at `path` with these options.
+    pub fn open(&self, path: &str) -> FsResult<File> {
+        let path = path::normalize(path)?;
+        let write = self.write || self.append;
+        if (self.truncate || self.create || self.create_new) && !write {
+            return Err(FsError::NotOpenFor("writing"));
+        }
+
+        let inode = match lookup(&path) {
+            Ok(_) if self.create_new => return Err(FsError::AlreadyExists),
+            Ok(inode) => inode,
+            Err(FsError::NotFound) if self.create || self.create_new => {
+                let (parent, name) = lookup_parent(&path)?;
+                parent.create(name, NodeKind::File)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        if write && inode.metadata().is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        if self.truncate {
+            inode.set_len(0)?;
+        }
+
+        Ok(File {
+            inode,
+            path,
+            pos: 0,
+            read: self.read,
+            write,
+            append: self.append,
+        })
+    }
+}
+
+/// An open file.
+pub struct File {
+    inode: Arc<dyn Inode>,
+    path: String,
+    pos: u64,
+    read: bool,
+    write: bool,
+    append: bool,
+}
+
+impl File {
+    /// Open the file at `path` for reading.
+    pub fn open(path: &str) -> FsResult<Self> {
+        OpenOptions::new().read(true).open(path)
+    }
+
+    /// Open the file at `path` for writing, creating it if it doesn't exist
+    /// and truncating it if it does.
+    pub fn create(path: &str) -> FsResult<Self> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    }
+
+    /// Returns the normalized path the file was opened with.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns information about the file.
+    pub fn metadata(&self) -> Metadata {
+        self.inode.metadata()
+    }
+
+    /// Read from the current position, returning the number of bytes read.
+    /// Returns `0` at the end of the file.
+    pub fn read(&mut self, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.read {
+            return Err(FsError::NotOpenFor("reading"));
+        }
+        let len = self.inode.read_at(self.pos, buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    /// Read from the current position until the end of the file, appending
+    /// to `buf`. Returns the number of bytes read.
+    pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> FsResult<usize> {
+        let start = buf.len();
+        let remaining = self.metadata().len.saturating_sub(self.pos);
+        // The length comes from the filesystem and may be corrupted, so
+        // running out of memory is an error rather than a panic.
+        let remaining = usize::try_from(remaining).map_err(|_| FsError::NoSpace)?;
+        buf.try_reserve(remaining).map_err(|_| FsError::NoSpace)?;
+
+        let mut chunk = [0; 512];
+        loop {
+            let len = self.read(&mut chunk)?;
+            if len == 0 {
+                return Ok(buf.len() - start);
+            }
+            buf.try_reserve(len).map_err(|_| FsError::NoSpace)?;
+            buf.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    /// Write at the current position, or at the end of the file if it was
+    /// opened for appending. Returns the number of bytes written.
+    pub fn write(&mut self, buf: &[u8]) -> FsResult<usize> {
+        if !self.write {
+            return Err(FsError::NotOpenFor("writing"));
+        }
+        if self.append {
+            self.pos = self.metadata().len;
+        }
+        let len = self.inode.write_at(self.pos, buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    /// Write all of `buf`.
+    pub fn write_all(&mut self, mut buf: &[u8]) -> FsResult<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(FsError::NoSpace),
+                len => buf = &buf[len..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the current position, returning the new position.
+    ///
+    /// Seeking past the end of the file is allowed, and a write there fills
+    /// the gap with zeros.
+    pub fn seek(&mut self, pos: SeekFrom) -> FsResult<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.metadata().len, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        self.pos = base
+            .checked_add_signed(offset)
+            .ok_or(FsError::InvalidSeek)?;
+        Ok(self.pos)
+    }
+
+    /// Returns the current position.
+    pub fn stream_position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Truncate or zero-extend the file to `len` bytes.
+    pub fn set_len(&self, len: u64) -> FsResult<()> {
+        if !self.write {
+            return Err(FsError::NotOpenFor("writing"));
+        }
+        self.inode.set_len(len)
+    }
+
+    /// Write any changes to the underlying storage.
+    pub fn sync(&self) -> FsResult<()> {
+        self.inode.sync()
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("File")
+            .field("path", &self.path)
+            .field("pos", &self.pos)
+            .field("read", &self.read)
+            .field("write", &self.write)
+            .field("append", &self.append)
+            .finish()
+    }
+}
+
+/// Returns information about the file or directory at `path`.
+pub fn metadata(path: &str) -> FsResult<Metadata> {
+    Ok(lookup(&path::normalize(path)?)?.metadata())
+}
+
+/// Returns `true` if there is a file or directory at `path`.
+pub fn exists(path: &str) -> bool {
+    metadata(path).is_ok()
+}
+
+/// Returns the contents of the file at `path`.
+pub fn read(path: &str) -> FsResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Replace the contents of the file at `path`, creating it if necessary.
+pub fn write(path: &str, data: &[u8]) -> FsResult<()> {
+    File::create(path)?.write_all(data)
+}
+
+/// Returns the entries of the directory at `path`.
+pub fn read_dir(path: &str) -> FsResult<Vec<DirEntry>> {
+    lookup(&path::normalize(path)?)?.read_dir()
+}
+
+/// Create a directory at `path`. Its parent must exist.
+pub fn create_dir(path: &str) -> FsResult<()> {
+    let path = path::normalize(path)?;
+    let (parent, name) = lookup_parent(&path).map_err(|e| match e {
+        FsError::Busy => FsError::AlreadyExists,
+        e => e,
+    })?;
+    parent.create(name, NodeKind::Directory)?;
+    Ok(())
+}
+
+/// Create a directory at `path`, and any missing parents.
+pub fn create_dir_all(path: &str) -> FsResult<()> {
+    let path = path::normalize(path)?;
+    let mut dir = String::new();
+    for name in path::components(&path) {
+        dir.push('/');
+        dir.push_str(name);
+        match create_dir(&dir) {
+            Ok(()) => {}
+            Err(FsError::AlreadyExists) if metadata(&dir)?.is_dir() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Remove the file or empty directory at `path`.
+pub fn remove(path: &str) -> FsResult<()> {
+    let path = path::normalize(path)?;
+    if is_mount_point(&path) {
+        return Err(FsError::Busy);
+    }
+    let (parent, name) = lookup_parent(&path)?;
+    parent.remove(name)
+}
+
+/// The largest initial ramdisk file [`init`] copies into the heap.
+const MAX_INITRD_COPY: usize = HEAP_SIZE as usize / 4;
+
+/// Mount a [`tmpfs`] at `/`, and copy the initial ramdisk into it.
+///
+/// Files larger than [`MAX_INITRD_COPY`] are skipped with a warning, so they
+/// don't use up the heap.
+///
+/// Must be called once the heap is initialized.
+pub fn init() {
+    if let Err(e) = mount("/", tmpfs::TmpFs::new()) {
+        warn!("Couldn't mount the root filesystem: {e}");
+        return;
+    }
+
+    let Ok(initrd) = initrd::get() else {
+        return;
+    };
+    let mut copied = 0;
+    for entry in initrd.entries() {
+        let path = alloc::format!("/{}", entry.path);
+        if entry.kind() == EntryKind::File && entry.data.len() > MAX_INITRD_COPY {
+            warn!(
+                "Not copying {path} ({} bytes) from the initial ramdisk, it is too large for the heap",
+                entry.data.len()
+            );
+            continue;
+        }
+        let result = match entry.kind() {
+            EntryKind::Directory => create_dir_all(&path),
+            EntryKind::File => path::split_parent(&path)
+                .map_or(Ok(()), |(parent, _)| create_dir_all(parent))
+                .and_then(|()| write(&path, entry.data)),
+            EntryKind::Symlink | EntryKind::Other => Err(FsError::Unsupported),
+        };
+        match result {
+            Ok(()) => copied += 1,
+            Err(e) => warn!("Couldn't copy {path} from the initial ramdisk: {e}"),
+        }
+    }
+    info!("Copied {copied} entries of the initial ramdisk to /");
+}
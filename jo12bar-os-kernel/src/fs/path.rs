@@ -0,0 +1,81 @@
+//! Paths in the [VFS][super].
+//!
+//! Paths are absolute, with components separated by `/`. They are normalized
+//! lexically: empty components and `.` are dropped, and `..` removes the
+//! previous component, so `..` of a mount point is the directory containing
+//! the mount point.
+
+use alloc::string::String;
+
+use super::{FsError, FsResult};
+
+/// The maximum length of a single path component, in bytes.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Returns the normalized form of the absolute `path`.
+pub fn normalize(path: &str) -> FsResult<String> {
+    if !path.starts_with('/') {
+        return Err(FsError::InvalidPath);
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                let parent_len = normalized.rfind('/').unwrap_or(0);
+                normalized.truncate(parent_len);
+            }
+            name => {
+                validate_name(name)?;
+                normalized.push('/');
+                normalized.push_str(name);
+            }
+        }
+    }
+
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// Check that `name` can be used as a single path component.
+pub fn validate_name(name: &str) -> FsResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(FsError::InvalidPath);
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(FsError::NameTooLong);
+    }
+    Ok(())
+}
+
+/// Iterate over the components of the normalized `path`.
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|component| !component.is_empty())
+}
+
+/// Split the normalized `path` into its parent directory and last component.
+///
+/// Returns [`None`] for `/`.
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let (parent, name) = path.rsplit_once('/')?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((if parent.is_empty() { "/" } else { parent }, name))
+}
+
+/// Returns the part of the normalized `path` below `mount_point`, or [`None`]
+/// if `path` isn't below it.
+pub fn strip_mount_point<'a>(path: &'a str, mount_point: &str) -> Option<&'a str> {
+    if mount_point == "/" {
+        return Some(path);
+    }
+    match path.strip_prefix(mount_point)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
@@ -0,0 +1,193 @@
+//! A filesystem that keeps everything on the kernel heap.
+//!
+//! Files are stored as a single [`Vec`] each, and directories as a map from
+//! names to inodes. Running out of memory is reported as
+//! [`FsError::NoSpace`] instead of panicking.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{path, DirEntry, FileSystem, FsError, FsResult, Inode, Metadata, NodeKind};
+use crate::prelude::*;
+
+/// A heap-backed filesystem.
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    /// Create an empty filesystem.
+    pub fn new() -> Arc<Self> {
+        let next_ino = Arc::new(AtomicU64::new(1));
+        Arc::new(Self {
+            root: TmpInode::new(next_ino, NodeKind::Directory),
+        })
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// A file or directory of a [`TmpFs`].
+struct TmpInode {
+    ino: u64,
+    kind: NodeKind,
+    /// The source of inode numbers, shared by all inodes of the filesystem.
+    next_ino: Arc<AtomicU64>,
+    data: TicketLock<TmpData>,
+}
+
+enum TmpData {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<TmpInode>>),
+}
+
+impl TmpInode {
+    fn new(next_ino: Arc<AtomicU64>, kind: NodeKind) -> Arc<Self> {
+        let data = match kind {
+            NodeKind::File => TmpData::File(Vec::new()),
+            NodeKind::Directory => TmpData::Directory(BTreeMap::new()),
+        };
+        Arc::new(Self {
+            ino: next_ino.fetch_add(1, Ordering::Relaxed),
+            kind,
+            next_ino,
+            data: TicketLock::new(data),
+        })
+    }
+}
+
+/// Resize `file` to `len` bytes, failing instead of panicking if there isn't
+/// enough memory.
+fn resize(file: &mut Vec<u8>, len: u64) -> FsResult<()> {
+    let len = usize::try_from(len).map_err(|_| FsError::FileTooLarge)?;
+    if let Some(additional) = len.checked_sub(file.len()) {
+        file.try_reserve(additional).map_err(|_| FsError::NoSpace)?;
+    }
+    file.resize(len, 0);
+    Ok(())
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Metadata {
+        let len = match &*self.data.lock() {
+            TmpData::File(data) => data.len(),
+            TmpData::Directory(entries) => entries.len(),
+        };
+        Metadata {
+            ino: self.ino,
+            kind: self.kind,
+            len: len as u64,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let data = self.data.lock();
+        let TmpData::File(data) = &*data else {
+            return Err(FsError::IsADirectory);
+        };
+
+        let Some(src) = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| data.get(offset..))
+        else {
+            return Ok(0);
+        };
+        let len = src.len().min(buf.len());
+        buf[..len].copy_from_slice(&src[..len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut data = self.data.lock();
+        let TmpData::File(data) = &mut *data else {
+            return Err(FsError::IsADirectory);
+        };
+
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or(FsError::FileTooLarge)?;
+        if end > data.len() as u64 {
+            resize(data, end)?;
+        }
+        let offset = offset as usize;
+        data[offset..offset + buf.len()].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn set_len(&self, len: u64) -> FsResult<()> {
+        match &mut *self.data.lock() {
+            TmpData::File(data) => resize(data, len),
+            TmpData::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        match &*self.data.lock() {
+            TmpData::Directory(entries) => entries
+                .get(name)
+                .map(|inode| inode.clone() as Arc<dyn Inode>)
+                .ok_or(FsError::NotFound),
+            TmpData::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> FsResult<Arc<dyn Inode>> {
+        path::validate_name(name)?;
+        let mut data = self.data.lock();
+        let TmpData::Directory(entries) = &mut *data else {
+            return Err(FsError::NotADirectory);
+        };
+        if entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let inode = TmpInode::new(self.next_ino.clone(), kind);
+        entries.insert(name.to_string(), inode.clone());
+        Ok(inode)
+    }
+
+    fn remove(&self, name: &str) -> FsResult<()> {
+        let mut data = self.data.lock();
+        let TmpData::Directory(entries) = &mut *data else {
+            return Err(FsError::NotADirectory);
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?;
+
+        // Directories are only ever locked after their parent, so this can't
+        // deadlock.
+        if let TmpData::Directory(children) = &*inode.data.lock() {
+            if !children.is_empty() {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+        }
+        entries.remove(name);
+        Ok(())
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        match &*self.data.lock() {
+            TmpData::Directory(entries) => Ok(entries
+                .iter()
+                .map(|(name, inode)| DirEntry {
+                    name: name.clone(),
+                    kind: inode.kind,
+                    ino: inode.ino,
+                })
+                .collect()),
+            TmpData::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+}
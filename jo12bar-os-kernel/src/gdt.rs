@@ -1,6 +1,5 @@
 //! Global Descriptor Table setup and configuration.
 
-use lazy_static::lazy_static;
 use mem_util::KiB;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
@@ -13,48 +12,57 @@ use x86_64::{
     registers::segmentation::SS,
 };
 
+use crate::prelude::*;
+
 /// Index of the double_fault interrupt handler's stack in the Interrupt Stack Table.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
 /// Index of the page_fault interrupt handler's stack in the Interrup Stack Table.
 pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 
-lazy_static! {
-    /// The task state segment, which holds the privlege stack table, interrupt
-    /// stack table, and I/O map base address.
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = KiB!(20);
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+/// The task state segment, which holds the privlege stack table, interrupt
+/// stack table, and I/O map base address.
+static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
+    let mut tss = TaskStateSegment::new();
 
-            #[allow(unused_unsafe)] // TODO(jo12bar): rust started complaining about the unsafe block, even though it's required
-            let stack_start = VirtAddr::from_ptr(unsafe { core::ptr::addr_of!(STACK) });
-            stack_start + STACK_SIZE as _
-        };
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = KiB!(20);
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
-        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = KiB!(20);
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        #[allow(unused_unsafe)]
+        // TODO(jo12bar): rust started complaining about the unsafe block, even though it's required
+        let stack_start = VirtAddr::from_ptr(unsafe { core::ptr::addr_of!(STACK) });
+        stack_start + STACK_SIZE as _
+    };
 
-            #[allow(unused_unsafe)] // TODO(jo12bar): rust started complaining about the unsafe block, even though it's required
-            let stack_start = VirtAddr::from_ptr(unsafe { core::ptr::addr_of!(STACK) });
-            stack_start + STACK_SIZE as _
-        };
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = KiB!(20);
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
-        tss
+        #[allow(unused_unsafe)]
+        // TODO(jo12bar): rust started complaining about the unsafe block, even though it's required
+        let stack_start = VirtAddr::from_ptr(unsafe { core::ptr::addr_of!(STACK) });
+        stack_start + STACK_SIZE as _
     };
 
-    /// The global descriptor table and its segment selectors. Primarily used for setting up the [`TSS`].
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        (gdt, Selectors { code_selector, data_selector, tss_selector })
-    };
-}
+    tss
+});
+
+/// The global descriptor table and its segment selectors. Primarily used for setting up the [`TSS`].
+static GDT: Lazy<(GlobalDescriptorTable, Selectors)> = Lazy::new(|| {
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let data_selector = gdt.append(Descriptor::kernel_data_segment());
+    let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            data_selector,
+            tss_selector,
+        },
+    )
+});
 
 struct Selectors {
     code_selector: SegmentSelector,
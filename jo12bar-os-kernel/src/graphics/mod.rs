@@ -6,8 +6,13 @@
 use core::slice;
 
 use embedded_graphics::{mono_font::ascii::FONT_8X13, prelude::*};
+use log::LevelFilter;
 
-use crate::{graphics::tty::color, logger::LOGGER, prelude::*};
+use crate::{
+    graphics::tty::color,
+    logger::{dmesg::DMESG, format::RecordFormat, targets::CanvasLogger, TargetLogger, LOGGER},
+    prelude::*,
+};
 
 use self::{
     canvas::CanvasWriter,
@@ -88,8 +93,6 @@ fn init_framebuffer_logger() {
 
     fb.clear(bg_color).unwrap();
 
-    // TODO: Finish this
-
     let canvas_writer: CanvasWriter<_> = CanvasWriter::builder()
         .font(FONT_8X13)
         .canvas(fb)
@@ -98,27 +101,21 @@ fn init_framebuffer_logger() {
         .margin_top(10)
         .margin_bottom(10)
         .background_color(bg_color)
-        .log_errors(true)
+        // Logging from inside of a log target would deadlock.
+        .log_errors(false)
         .build()
         .expect("Canvas writer should be fully initialized");
 
-    // let canvas_lock = TicketLock::new_non_preemtable(canvas_writer);
-
-    // let mut fb_logger: OwnLogger<CanvasWriter<Framebuffer>, _> = OwnLogger::new(canvas_lock);
-    // setup_logger_module_rename(&mut fb_logger);
-
-    // if let Some(dispatch_logger) = unsafe { LOGGER.as_ref() } {
-    //     let logger = TargetLogger::new_secondary_boxed("framebuffer", Box::from(fb_logger));
-
-    //     dispatch_logger.with_logger(logger)
-    // } else {
-    //     panic!("No global logger found to register the framebuffer logger");
-    // }
-
-    if let Some(hacky_logger) = unsafe { LOGGER.as_ref() } {
-        hacky_logger.set_canvas_writer(Some(canvas_writer));
-    } else {
-        panic!("No global logger found to register the framebuffer logger.");
+    let target = TargetLogger::new_boxed(
+        "framebuffer",
+        LevelFilter::Info,
+        CanvasLogger::new(canvas_writer),
+    )
+    // The screen is too narrow for file paths.
+    .with_format(RecordFormat::FULL.location(false));
+    if let Err(e) = LOGGER.add_target_replaying(target, &DMESG) {
+        log::warn!("Failed to register the framebuffer logger: {e}");
+        return;
     }
 
     log::info!("Framebuffer logger initialized.");
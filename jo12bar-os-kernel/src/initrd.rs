@@ -0,0 +1,342 @@
+//! The initial ramdisk.
+//!
+//! The runner packs a directory into a `cpio` archive in the "new ASCII"
+//! (`newc`) format, which the bootloader loads and maps as its ramdisk. [`init`]
+//! checks the archive, after which its files can be read using [`get`].
+//!
+//! The archive is read in place and its files are read-only. Parsing it doesn't
+//! allocate, but [`fs::init`][crate::fs::init] copies its files into the heap.
+
+use core::{fmt, slice};
+
+use log::{debug, info, warn};
+use thiserror::Error;
+
+use crate::prelude::*;
+
+/// The initial ramdisk, once [`init`] was called.
+static INITRD: SpinOnce<Initrd> = SpinOnce::new();
+
+/// The magic number of `newc` headers.
+const MAGIC: &[u8] = b"070701";
+/// The size of a `newc` header.
+const HEADER_LEN: usize = 110;
+/// The name of the entry ending the archive.
+const TRAILER: &str = "TRAILER!!!";
+
+/// The file type bits of [`Entry::mode`].
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Errors returned when reading the initial ramdisk.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum InitrdError {
+    #[error("there is no initial ramdisk")]
+    NoInitrd,
+    #[error("entry at offset {0:#x} is not a newc cpio header")]
+    BadMagic(usize),
+    #[error("entry at offset {0:#x} has an invalid header field")]
+    InvalidHeader(usize),
+    #[error("entry at offset {0:#x} has a name that is not valid UTF-8")]
+    InvalidName(usize),
+    #[error("the archive is truncated")]
+    Truncated,
+    #[error("file not found")]
+    NotFound,
+    #[error("not a regular file")]
+    NotAFile,
+    #[error("not a directory")]
+    NotADirectory,
+}
+
+/// The type of an [`Entry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A regular file.
+    File,
+    /// A directory.
+    Directory,
+    /// A symbolic link, whose data is the link target.
+    Symlink,
+    /// Anything else, e.g. a device node.
+    Other,
+}
+
+/// A file, directory or other entry of the archive.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// The path, relative to the root of the archive and without a leading
+    /// `./` or `/`. The root itself is `""`.
+    pub path: &'static str,
+    /// The file type and permissions, as in `st_mode`.
+    pub mode: u32,
+    /// The modification time, in seconds since the Unix epoch.
+    pub mtime: u32,
+    /// The contents.
+    pub data: &'static [u8],
+}
+
+impl Entry {
+    /// Returns the type of the entry.
+    pub fn kind(&self) -> EntryKind {
+        match self.mode & S_IFMT {
+            S_IFREG => EntryKind::File,
+            S_IFDIR => EntryKind::Directory,
+            S_IFLNK => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        }
+    }
+
+    /// Returns the last component of the path.
+    pub fn name(&self) -> &'static str {
+        self.path.rsplit('/').next().unwrap_or(self.path)
+    }
+
+    /// Returns `true` if the entry is directly inside the directory `dir`.
+    fn is_child_of(&self, dir: &str) -> bool {
+        if self.path.is_empty() {
+            return false;
+        }
+        let parent = self.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        parent == dir
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("path", &self.path)
+            .field("kind", &self.kind())
+            .field("mode", &format_args!("{:#o}", self.mode))
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+/// A `newc` cpio archive.
+#[derive(Clone, Copy)]
+pub struct Initrd {
+    data: &'static [u8],
+}
+
+impl Initrd {
+    /// Check that `data` is a well-formed archive.
+    pub fn parse(data: &'static [u8]) -> Result<Self, InitrdError> {
+        let initrd = Self { data };
+        let mut offset = 0;
+        while let Some((_, next)) = initrd.entry_at(offset)? {
+            offset = next;
+        }
+        Ok(initrd)
+    }
+
+    /// Returns the size of the archive in bytes.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns `true` if the archive has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries().next().is_none()
+    }
+
+    /// Iterate over all entries, in the order they were archived.
+    pub fn entries(&self) -> Entries {
+        Entries {
+            initrd: *self,
+            offset: Some(0),
+        }
+    }
+
+    /// Returns the entry at `path`.
+    ///
+    /// Leading `/` and `./` are ignored, as are trailing `/`.
+    pub fn get(&self, path: &str) -> Option<Entry> {
+        let path = normalize(path);
+        if path.is_empty() {
+            return Some(Entry {
+                path: "",
+                mode: S_IFDIR | 0o555,
+                mtime: 0,
+                data: &[],
+            });
+        }
+        self.entries().find(|entry| entry.path == path)
+    }
+
+    /// Returns the contents of the regular file at `path`.
+    pub fn read(&self, path: &str) -> Result<&'static [u8], InitrdError> {
+        let entry = self.get(path).ok_or(InitrdError::NotFound)?;
+        match entry.kind() {
+            EntryKind::File => Ok(entry.data),
+            _ => Err(InitrdError::NotAFile),
+        }
+    }
+
+    /// Iterate over the entries directly inside the directory at `path`.
+    ///
+    /// Archivers don't always add entries for directories, so a directory
+    /// exists as long as some entry is inside it.
+    pub fn read_dir<'a>(
+        &self,
+        path: &'a str,
+    ) -> Result<impl Iterator<Item = Entry> + 'a, InitrdError> {
+        let path = normalize(path);
+        match self.get(path) {
+            Some(entry) if entry.kind() != EntryKind::Directory => {
+                return Err(InitrdError::NotADirectory)
+            }
+            Some(_) => {}
+            None => {
+                let prefix_len = path.len();
+                let implied = self.entries().any(|entry| {
+                    entry.path.starts_with(path)
+                        && entry.path.as_bytes().get(prefix_len) == Some(&b'/')
+                });
+                if !implied {
+                    return Err(InitrdError::NotFound);
+                }
+            }
+        }
+        Ok(self.entries().filter(move |entry| entry.is_child_of(path)))
+    }
+
+    /// Read the entry starting at `offset`, returning it and the offset of
+    /// the next one, or [`None`] at the end of the archive.
+    fn entry_at(&self, offset: usize) -> Result<Option<(Entry, usize)>, InitrdError> {
+        let header = self
+            .data
+            .get(offset..offset + HEADER_LEN)
+            .ok_or(InitrdError::Truncated)?;
+        if &header[..6] != MAGIC {
+            return Err(InitrdError::BadMagic(offset));
+        }
+        let field = |idx: usize| {
+            let start = 6 + idx * 8;
+            core::str::from_utf8(&header[start..start + 8])
+                .ok()
+                .and_then(|field| u32::from_str_radix(field, 16).ok())
+                .ok_or(InitrdError::InvalidHeader(offset))
+        };
+        let mode = field(1)?;
+        let mtime = field(5)?;
+        let file_len = field(6)? as usize;
+        let name_len = field(11)? as usize;
+
+        // The name includes a NUL terminator, and both the name and the data
+        // are padded to 4 bytes.
+        let name_start = offset + HEADER_LEN;
+        let name = self
+            .data
+            .get(name_start..name_start + name_len)
+            .ok_or(InitrdError::Truncated)?;
+        let name = name.strip_suffix(b"\0").unwrap_or(name);
+        let name = core::str::from_utf8(name).map_err(|_| InitrdError::InvalidName(offset))?;
+        if name == TRAILER {
+            return Ok(None);
+        }
+
+        let data_start = align_up(name_start + name_len);
+        let data = self
+            .data
+            .get(data_start..data_start + file_len)
+            .ok_or(InitrdError::Truncated)?;
+
+        let entry = Entry {
+            path: normalize(name),
+            mode,
+            mtime,
+            data,
+        };
+        Ok(Some((entry, align_up(data_start + file_len))))
+    }
+}
+
+impl fmt::Debug for Initrd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.entries()).finish()
+    }
+}
+
+/// An iterator over the entries of an [`Initrd`].
+pub struct Entries {
+    initrd: Initrd,
+    /// The offset of the next entry, or [`None`] at the end.
+    offset: Option<usize>,
+}
+
+impl Iterator for Entries {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The archive was checked by `Initrd::parse`, so errors can't happen.
+        let (entry, next) = self.initrd.entry_at(self.offset?).ok().flatten()?;
+        self.offset = Some(next);
+        Some(entry)
+    }
+}
+
+fn normalize(path: &str) -> &str {
+    let mut path = path.trim_end_matches('/');
+    loop {
+        if let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix('/') {
+            path = rest;
+        } else {
+            break;
+        }
+    }
+    if path == "." {
+        ""
+    } else {
+        path
+    }
+}
+
+fn align_up(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Check the initial ramdisk loaded by the bootloader.
+///
+/// Failures are logged, after which [`get`] returns an error.
+///
+/// # Safety
+/// - Must only be called once, on the bootstrap processor.
+/// - The ramdisk must never be unmapped or reused.
+pub unsafe fn init(boot_info: &bootloader_api::BootInfo) {
+    let Some(addr) = boot_info.ramdisk_addr.as_ref() else {
+        info!("No initial ramdisk");
+        return;
+    };
+
+    // Safety: The bootloader mapped the ramdisk at `addr`, see above.
+    let data: &'static [u8] =
+        unsafe { slice::from_raw_parts(*addr as *const u8, boot_info.ramdisk_len as usize) };
+
+    match Initrd::parse(data) {
+        Ok(initrd) => {
+            info!(
+                "Initial ramdisk at {addr:#x} with {} entries ({} bytes)",
+                initrd.entries().count(),
+                initrd.len()
+            );
+            for entry in initrd.entries() {
+                debug!("initrd: {entry:?}");
+            }
+            if INITRD.set(initrd).is_err() {
+                warn!("The initial ramdisk was already loaded");
+            }
+        }
+        Err(e) => warn!("Ignoring initial ramdisk: {e}"),
+    }
+}
+
+/// Returns the initial ramdisk.
+pub fn get() -> Result<&'static Initrd, InitrdError> {
+    INITRD.get().ok_or(InitrdError::NoInitrd)
+}
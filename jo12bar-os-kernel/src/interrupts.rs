@@ -1,11 +1,24 @@
 //! Interrupt setup and handlers.
 
-use lazy_static::lazy_static;
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use log::debug;
 use pic8259::ChainedPics;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use thiserror::Error;
+use x86_64::{
+    registers::model_specific::Msr,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    PhysAddr,
+};
 
-use crate::{cpu::halt, gdt, locals, prelude::*, serial_print};
+use crate::{
+    gdt, locals,
+    mem::mmio::{Mmio, MmioError},
+    prelude::*,
+    serial_print,
+    symbols::SymbolizedAddress,
+};
 
 /// Interrupt vector number offset for the primary Programmable Interrupt Controller.
 pub const PIC_1_OFFSET: u8 = 32;
@@ -35,30 +48,79 @@ impl InterruptIndex {
     // }
 }
 
-lazy_static! {
-    /// The interrupt descriptor table, which lives for the entire time the kernel is running.
-    static ref IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
+/// The first of the vectors handed out by [`register_handler`].
+pub const DYNAMIC_VECTORS_START: u8 = 0x40;
+/// The number of vectors handed out by [`register_handler`].
+pub const DYNAMIC_VECTOR_COUNT: usize = 32;
+/// The vector the local APIC raises for spurious interrupts.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
 
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-            idt.page_fault
-                .set_handler_fn(page_fault_handler)
-                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
-        }
+/// An interrupt handler registered with [`register_handler`].
+type DynamicHandler = Box<dyn Fn() + Send + Sync>;
+
+/// The handlers of the dynamic vectors, indexed from [`DYNAMIC_VECTORS_START`].
+static DYNAMIC_HANDLERS: [SpinOnce<DynamicHandler>; DYNAMIC_VECTOR_COUNT] =
+    [const { SpinOnce::new() }; DYNAMIC_VECTOR_COUNT];
+/// The number of dynamic vectors handed out so far.
+static NEXT_DYNAMIC_VECTOR: AtomicUsize = AtomicUsize::new(0);
+
+/// The registers of the local APIC, mapped by the first [`register_handler`].
+///
+/// The PICs deliver the legacy interrupts through the local APIC, which the
+/// firmware set up for that. Only interrupts that are sent to the local APIC
+/// directly, like MSIs, need it to be acknowledged.
+static LOCAL_APIC: SpinOnce<Mmio> = SpinOnce::new();
 
-        idt[InterruptIndex::Timer.as_u8()]
-            .set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_u8()]
-            .set_handler_fn(keyboard_interrupt_handler);
+/// Errors returned when registering interrupt handlers.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum InterruptError {
+    #[error("all dynamic interrupt vectors are in use")]
+    OutOfVectors,
+    #[error("failed to map the local APIC: {0}")]
+    LocalApic(MmioError),
+}
 
-        idt
+impl From<MmioError> for InterruptError {
+    fn from(value: MmioError) -> Self {
+        Self::LocalApic(value)
+    }
+}
+
+/// Set the IDT entries of the dynamic vectors `DYNAMIC_VECTORS_START + n`.
+macro_rules! set_dynamic_handlers {
+    ($idt:ident, $($n:literal)*) => {
+        $(
+            $idt[DYNAMIC_VECTORS_START + $n].set_handler_fn(dynamic_interrupt_handler::<$n>);
+        )*
+        static_assertions::const_assert_eq!([$($n),*].len(), DYNAMIC_VECTOR_COUNT);
     };
 }
 
+/// The interrupt descriptor table, which lives for the entire time the kernel is running.
+static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.page_fault
+            .set_handler_fn(page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+    }
+
+    idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
+    idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+    set_dynamic_handlers!(
+        idt, 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+    );
+    idt[SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
+
+    idt
+});
+
 /// Initialize the [`InterruptDescriptorTable`] and enable interrupts.
 pub fn init() {
     debug!("Loading IDT");
@@ -80,10 +142,89 @@ pub fn init() {
     );
 }
 
+/// Allocate an interrupt vector and run `handler` whenever it is raised.
+///
+/// This is meant for interrupts that are sent to the local APIC directly, like
+/// MSIs, which the handler of the vector acknowledges. `handler` runs inside
+/// of the interrupt, so it must not block. Vectors are never
+/// freed.
+pub fn register_handler(handler: impl Fn() + Send + Sync + 'static) -> Result<u8, InterruptError> {
+    local_apic()?;
+
+    let index = NEXT_DYNAMIC_VECTOR.fetch_add(1, Ordering::AcqRel);
+    if index >= DYNAMIC_VECTOR_COUNT {
+        return Err(InterruptError::OutOfVectors);
+    }
+    if DYNAMIC_HANDLERS[index].set(Box::new(handler)).is_err() {
+        unreachable!("dynamic interrupt vectors are handed out once");
+    }
+    let vector = DYNAMIC_VECTORS_START + index as u8;
+    debug!("Registered a handler for interrupt vector {vector:#x}");
+    Ok(vector)
+}
+
+/// Raise `vector` on this core, as if a device had sent it to the local APIC.
+///
+/// The interrupt is delivered once interrupts are enabled.
+pub fn send_self(vector: u8) -> Result<(), InterruptError> {
+    /// The low half of the interrupt command register. Writing it sends the
+    /// interrupt.
+    const ICR_LOW: u64 = 0x300;
+    /// Send the interrupt to this core only, with fixed delivery.
+    const DESTINATION_SELF: u32 = 0b01 << 18;
+
+    local_apic()?.write::<u32>(ICR_LOW, DESTINATION_SELF | u32::from(vector));
+    Ok(())
+}
+
+/// Returns the ID of the local APIC of this core, which is where interrupts
+/// for this core are sent.
+pub fn local_apic_id() -> Result<u8, InterruptError> {
+    /// The ID register, with the ID in bits 24 to 31.
+    const ID: u64 = 0x20;
+
+    Ok((local_apic()?.read::<u32>(ID) >> 24) as u8)
+}
+
+/// Returns the registers of the local APIC, mapping them if they aren't yet.
+fn local_apic() -> Result<&'static Mmio, InterruptError> {
+    /// The MSR with the physical address of the local APIC in bits 12 to 51.
+    const IA32_APIC_BASE: u32 = 0x1b;
+    /// The spurious interrupt vector register, with the enable bit 8.
+    const SPURIOUS_INTERRUPT_VECTOR: u64 = 0xf0;
+    const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+    if let Some(local_apic) = LOCAL_APIC.get() {
+        return Ok(local_apic);
+    }
+
+    // Safety: The MSR exists on every x86_64 CPU, and reading it has no side
+    // effects.
+    let base = unsafe { Msr::new(IA32_APIC_BASE).read() } & 0x000f_ffff_ffff_f000;
+    // Safety: The local APIC registers are device memory.
+    let mmio = unsafe { Mmio::map(PhysAddr::new(base), 0x1000) }?;
+
+    // If another core mapped it first, our mapping stays unused.
+    if LOCAL_APIC.set(mmio).is_ok() {
+        let svr: u32 = mmio.read(SPURIOUS_INTERRUPT_VECTOR);
+        if svr & APIC_SOFTWARE_ENABLE == 0 {
+            debug!("Enabling the local APIC");
+            mmio.write(
+                SPURIOUS_INTERRUPT_VECTOR,
+                svr & !0xff | APIC_SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR),
+            );
+        }
+    }
+    Ok(LOCAL_APIC.get().expect("the local APIC was just mapped"))
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     let _guard = crate::locals!().inc_exception();
 
-    log::info!("EXCEPTION: BREAKPOINT\n{stack_frame:#?}");
+    log::info!(
+        "EXCEPTION: BREAKPOINT at {}\n{stack_frame:#?}",
+        SymbolizedAddress::new(stack_frame.instruction_pointer.as_u64()),
+    );
 }
 
 extern "x86-interrupt" fn double_fault_handler(
@@ -92,23 +233,17 @@ extern "x86-interrupt" fn double_fault_handler(
 ) -> ! {
     let _guard = crate::locals!().inc_exception();
 
-    panic!("EXCEPTION: DOUBLE FAULT (error_code=0x{error_code:x})\n{stack_frame:#?}");
+    panic!(
+        "EXCEPTION: DOUBLE FAULT (error_code=0x{error_code:x}) at {}\n{stack_frame:#?}",
+        SymbolizedAddress::new(stack_frame.instruction_pointer.as_u64()),
+    );
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use core::fmt::Write;
-
     let _guard = crate::locals!().inc_interrupt();
 
-    unsafe {
-        if let Some(Some(l)) = core::ptr::addr_of!(crate::logger::LOGGER).as_ref() {
-            if let Some(mut canvas_writer_lock) = l.try_lock() {
-                if let Some(canvas_writer) = canvas_writer_lock.as_mut() {
-                    write!(canvas_writer, ".").unwrap();
-                }
-            }
-        }
-    }
+    crate::panic::halt_if_panicking();
+    crate::time::tick();
 
     serial_print!(".");
 
@@ -116,6 +251,10 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+
+    // This might switch to another thread, so the end of interrupt must already
+    // have been sent. We return here once this thread is scheduled again.
+    crate::thread::timer_tick();
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
@@ -133,6 +272,29 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
+extern "x86-interrupt" fn dynamic_interrupt_handler<const N: usize>(
+    _stack_frame: InterruptStackFrame,
+) {
+    /// The end of interrupt register.
+    const EOI: u64 = 0xb0;
+
+    let _guard = crate::locals!().inc_interrupt();
+
+    crate::panic::halt_if_panicking();
+    if let Some(handler) = DYNAMIC_HANDLERS[N].get() {
+        handler();
+    }
+
+    if let Some(local_apic) = LOCAL_APIC.get() {
+        local_apic.write::<u32>(EOI, 0);
+    }
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // Spurious interrupts must not be acknowledged.
+    let _guard = crate::locals!().inc_interrupt();
+}
+
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
@@ -141,13 +303,19 @@ extern "x86-interrupt" fn page_fault_handler(
 
     let _guard = crate::locals!().inc_exception();
 
-    log::warn!(
-        "EXCEPTION: Page fault\n    \
+    if Cr2::read().is_ok_and(crate::mem::stack::is_guard_page) {
+        log::error!("Kernel stack overflow detected (guard page hit)");
+    }
+
+    // Records logged from exceptions are only written once the interrupted code
+    // continues, which it never does. Panic instead, so that the report is
+    // written right away.
+    panic!(
+        "EXCEPTION: Page fault at {}\n    \
         Accessed address: {:?}\n    \
         Error code: {error_code:?}\n\
         {stack_frame:#?}",
+        SymbolizedAddress::new(stack_frame.instruction_pointer.as_u64()),
         Cr2::read(),
     );
-
-    halt();
 }
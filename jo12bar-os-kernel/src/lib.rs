@@ -13,21 +13,32 @@ use bootloader_api::BootInfo;
 use core_locals::core_boot;
 use mem::BootInfoFrameAllocator;
 use mem_util::KiB;
+use prelude::LockCell;
 use x86_64::{
     structures::paging::{PageSize, Size4KiB},
     VirtAddr,
 };
 
+pub mod acpi;
+pub mod block;
+pub mod cmdline;
 pub mod core_locals;
 pub mod cpu;
+pub mod fs;
 pub mod gdt;
 pub mod graphics;
+pub mod initrd;
 pub mod interrupts;
 pub mod logger;
 pub mod mem;
+pub mod panic;
+pub mod pci;
 pub mod prelude;
 pub mod serial;
+pub mod symbols;
 pub mod task;
+pub mod thread;
+pub mod time;
 
 /// Contains the [BootInfo] provided by the Bootloader
 ///
@@ -66,6 +77,21 @@ pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
         // ports are initialized.
         unsafe { logger::init() };
 
+        // Safety: This is the bootstrap processor, and the bootloader's ramdisk
+        // is never unmapped.
+        unsafe { initrd::init(boot_info) };
+        cmdline::init();
+        if let Some(directives) = cmdline::config().log_directives() {
+            if let Err(e) = logger::LOGGER.set_directives(directives) {
+                log::warn!("Invalid log directives {directives:?} on the kernel command line: {e}");
+            }
+        }
+
+        #[cfg(feature = "lock-debug")]
+        mem_util::sync::lock_debug::set_report_sink(serial::report_suspected_deadlock);
+        #[cfg(feature = "lockdep")]
+        mem_util::sync::lockdep::set_report_sink(serial::report_lockdep_violation);
+
         // // Safety: inherently unsafe and can crash, but if cpuid isn't supported
         // // we will crash at some point in the future anyways, so we might as well
         // // crash early
@@ -76,15 +102,30 @@ pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
         unsafe {
             let phys_mem_offset =
                 VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
-            let mut mapper = mem::init(phys_mem_offset);
-            let mut frame_allocator = BootInfoFrameAllocator::init(&boot_info.memory_regions);
+            let mapper = mem::init(phys_mem_offset);
+            let frame_allocator = BootInfoFrameAllocator::init(&boot_info.memory_regions);
+
+            mem::PAGE_TABLE.lock_uninit().write(mapper);
+            mem::FRAME_ALLOCATOR.lock_uninit().write(frame_allocator);
 
-            mem::allocator::init_heap(&mut mapper, &mut frame_allocator)
-                .expect("heap initialization failed");
+            mem::allocator::init_heap(
+                &mut *mem::PAGE_TABLE.lock(),
+                &mut *mem::FRAME_ALLOCATOR.lock(),
+            )
+            .expect("heap initialization failed");
+
+            symbols::init(boot_info, phys_mem_offset);
         }
 
+        // Safety: This is the bootstrap processor, memory is initialized, and
+        // the bootloader found the RSDP.
+        unsafe { acpi::init(boot_info) };
+
         // Safety: This is the bootstrap processor, and logging and alloc are working
-        unsafe { graphics::init(true) };
+        unsafe { graphics::init(cmdline::config().framebuffer_log()) };
+
+        fs::init();
+        pci::init();
     } /* else {
           unsafe {
               // Safety: inherently unsafe and can crash, but if cpuid isn't supported
@@ -99,8 +140,15 @@ pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
         core_locals::init(core_id);
     }
 
-    // Enable interrupts for this processor
     gdt::init();
+
+    if core_id.is_bsp() {
+        // Safety: This is the bootstrap processor, and memory and core locals are
+        // initialized.
+        unsafe { thread::init() };
+    }
+
+    // Enable interrupts for this processor
     interrupts::init();
 }
 
@@ -0,0 +1,232 @@
+//! The kernel log buffer.
+//!
+//! [`DMESG`] keeps the most recent log records in a fixed-size ring buffer, so
+//! that they can be shown again later, e.g. when the framebuffer logger is
+//! attached, or by a shell command. It doesn't need the heap, so it records
+//! everything from the very first log message onward.
+//!
+//! Records are stored together with their [`RecordContext`], so that they can be
+//! replayed in the format of any target. Once the buffer is full, the oldest
+//! records are dropped.
+
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+use mem_util::{sync::lock_cell::LockCellInternal, KiB};
+
+use crate::prelude::*;
+
+use super::{
+    encoding::{self, MAX_RECORD_LEN},
+    format::{FormattedRecord, RecordContext, RecordFormat},
+    LogTarget,
+};
+
+/// The size of the kernel log buffer in bytes.
+pub const DMESG_SIZE: usize = KiB!(64);
+
+/// The global kernel log buffer.
+pub static DMESG: Dmesg = Dmesg::new();
+
+/// A ring buffer of log records.
+pub struct Dmesg {
+    /// Non-preemtable, so that records can be logged from interrupts.
+    buffer: TicketLock<RingBuffer>,
+}
+
+impl Dmesg {
+    /// Create a new, empty buffer.
+    pub const fn new() -> Self {
+        Self {
+            buffer: TicketLock::new_non_preemtable(RingBuffer {
+                data: [0; DMESG_SIZE],
+                head: 0,
+                used: 0,
+                records: 0,
+                dropped: 0,
+            }),
+        }
+    }
+
+    /// Add `record` to the buffer, dropping the oldest records if there isn't
+    /// enough space.
+    pub fn push(&self, record: &log::Record, context: &RecordContext) {
+        let mut encoded = [0; MAX_RECORD_LEN];
+        let len = encoding::encode(record, context, &mut encoded);
+        self.buffer.lock().push(&encoded[..len]);
+    }
+
+    /// Call `f` for each buffered record, oldest first.
+    ///
+    /// The buffer is locked while `f` runs, so `f` must not log.
+    pub fn for_each(&self, mut f: impl FnMut(&log::Record, &RecordContext)) {
+        let buffer = self.buffer.lock();
+        let mut scratch = [0; MAX_RECORD_LEN];
+
+        let mut offset = 0;
+        for _ in 0..buffer.records {
+            let len = buffer.read_u32(offset) as usize;
+            let bytes = &mut scratch[..len];
+            buffer.read_at(offset, bytes);
+            offset += len;
+
+            encoding::decode(bytes, &mut f);
+        }
+    }
+
+    /// Write all buffered records to `writer` using `format`, one per line.
+    pub fn write_to(&self, writer: &mut impl Write, format: RecordFormat) -> fmt::Result {
+        let mut result = Ok(());
+        self.for_each(|record, context| {
+            if result.is_ok() {
+                result = writeln!(
+                    writer,
+                    "{}",
+                    FormattedRecord {
+                        record,
+                        context,
+                        format,
+                    }
+                );
+            }
+        });
+        result
+    }
+
+    /// Returns all buffered records formatted using `format`.
+    pub fn contents(&self, format: RecordFormat) -> String {
+        let mut contents = String::new();
+        self.write_to(&mut contents, format)
+            .expect("writing to a String can't fail");
+        contents
+    }
+
+    /// Returns the number of buffered records.
+    pub fn len(&self) -> usize {
+        self.buffer.lock().records
+    }
+
+    /// Returns `true` if no records are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of records that were dropped to make space for newer
+    /// ones.
+    pub fn dropped(&self) -> u64 {
+        self.buffer.lock().dropped
+    }
+
+    /// Remove all buffered records.
+    pub fn clear(&self) {
+        let mut buffer = self.buffer.lock();
+        buffer.head = 0;
+        buffer.used = 0;
+        buffer.records = 0;
+    }
+
+    /// Force-unlock the buffer.
+    ///
+    /// # Safety
+    /// Inherently unsafe. Only use in the global panic handler.
+    pub unsafe fn force_unlock(&self) {
+        if !self.buffer.is_unlocked() {
+            // Safety: see above
+            unsafe { self.buffer.force_unlock() };
+        }
+    }
+}
+
+impl Default for Dmesg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Dmesg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let buffer = self.buffer.lock();
+        f.debug_struct("Dmesg")
+            .field("records", &buffer.records)
+            .field("bytes", &buffer.used)
+            .field("dropped", &buffer.dropped)
+            .finish()
+    }
+}
+
+/// A [`LogTarget`] writing to [`DMESG`].
+///
+/// This is a zero-sized type, so that it can be added to the logger before the
+/// heap is initialized.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DmesgLogger;
+
+impl LogTarget for DmesgLogger {
+    fn log(&self, record: &FormattedRecord) {
+        DMESG.push(record.record, record.context);
+    }
+
+    unsafe fn force_unlock(&self) {
+        // Safety: see [`LogTarget::force_unlock`]
+        unsafe { DMESG.force_unlock() }
+    }
+}
+
+/// A byte ring buffer holding whole records.
+struct RingBuffer {
+    data: [u8; DMESG_SIZE],
+    /// The index of the first byte of the oldest record.
+    head: usize,
+    /// The number of bytes used by records.
+    used: usize,
+    /// The number of records.
+    records: usize,
+    /// The number of records dropped so far.
+    dropped: u64,
+}
+
+impl RingBuffer {
+    /// Copy `bytes` into the buffer, starting `offset` bytes after `head`.
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) {
+        let start = (self.head + offset) % DMESG_SIZE;
+        let first = bytes.len().min(DMESG_SIZE - start);
+        self.data[start..start + first].copy_from_slice(&bytes[..first]);
+        self.data[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+    }
+
+    /// Fill `bytes` from the buffer, starting `offset` bytes after `head`.
+    fn read_at(&self, offset: usize, bytes: &mut [u8]) {
+        let start = (self.head + offset) % DMESG_SIZE;
+        let first = bytes.len().min(DMESG_SIZE - start);
+        let len = bytes.len();
+        bytes[..first].copy_from_slice(&self.data[start..start + first]);
+        bytes[first..].copy_from_slice(&self.data[..len - first]);
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        self.read_at(offset, &mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Drop the oldest record.
+    fn pop(&mut self) {
+        let len = self.read_u32(0) as usize;
+        self.head = (self.head + len) % DMESG_SIZE;
+        self.used -= len;
+        self.records -= 1;
+        self.dropped += 1;
+    }
+
+    /// Append an encoded record, dropping the oldest records if there isn't
+    /// enough space.
+    fn push(&mut self, record: &[u8]) {
+        while DMESG_SIZE - self.used < record.len() {
+            self.pop();
+        }
+
+        self.write_at(self.used, record);
+        self.used += record.len();
+        self.records += 1;
+    }
+}
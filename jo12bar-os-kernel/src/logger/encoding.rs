@@ -0,0 +1,172 @@
+//! A compact binary encoding of log records, used to store them without
+//! allocating.
+//!
+//! An encoded record starts with a header, followed by its target, file and
+//! message:
+//!
+//! | bytes  | content                                       |
+//! |--------|-----------------------------------------------|
+//! | 0..4   | length of the record including the header     |
+//! | 4..12  | [`RecordContext::ticks`]                      |
+//! | 12..16 | line                                          |
+//! | 16     | [`RecordContext::core`]                       |
+//! | 17     | [`Level`]                                     |
+//! | 18     | flags, see `FLAG_*`                           |
+//! | 19     | reserved                                      |
+//! | 20..22 | length of the target                          |
+//! | 22..24 | length of the file                            |
+
+use core::fmt::{self, Write};
+
+use log::Level;
+use mem_util::{types::CoreId, KiB};
+
+use super::format::RecordContext;
+
+/// The maximum size of an encoded record, including its header.
+///
+/// Longer messages are truncated.
+pub const MAX_RECORD_LEN: usize = KiB!(1);
+
+/// The maximum length of a record's target or file name. Longer ones are
+/// truncated.
+const MAX_NAME_LEN: usize = 255;
+
+/// The length of the header in front of each record.
+const HEADER_LEN: usize = 24;
+
+/// The record was logged from inside of an interrupt.
+const FLAG_INTERRUPT: u8 = 1 << 0;
+/// The record has a line.
+const FLAG_LINE: u8 = 1 << 1;
+/// The record has a file.
+const FLAG_FILE: u8 = 1 << 2;
+
+/// Encode `record` and `context` into `buf`, returning the encoded length.
+pub(super) fn encode(
+    record: &log::Record,
+    context: &RecordContext,
+    buf: &mut [u8; MAX_RECORD_LEN],
+) -> usize {
+    let target = truncate(record.target(), MAX_NAME_LEN);
+    let file = record.file().map(|file| truncate(file, MAX_NAME_LEN));
+
+    let mut flags = 0;
+    if context.in_interrupt {
+        flags |= FLAG_INTERRUPT;
+    }
+    if record.line().is_some() {
+        flags |= FLAG_LINE;
+    }
+    if file.is_some() {
+        flags |= FLAG_FILE;
+    }
+
+    let mut writer = SliceWriter {
+        buf,
+        written: HEADER_LEN,
+    };
+    writer.append(target);
+    writer.append(file.unwrap_or_default());
+    // The message is truncated if it doesn't fit.
+    let _ = write!(writer, "{}", record.args());
+    let len = writer.written;
+
+    let header = &mut buf[..HEADER_LEN];
+    header[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+    header[4..12].copy_from_slice(&context.ticks.to_le_bytes());
+    header[12..16].copy_from_slice(&record.line().unwrap_or(0).to_le_bytes());
+    header[16] = context.core.0;
+    header[17] = record.level() as u8;
+    header[18] = flags;
+    header[19] = 0;
+    header[20..22].copy_from_slice(&(target.len() as u16).to_le_bytes());
+    header[22..24].copy_from_slice(&(file.map_or(0, str::len) as u16).to_le_bytes());
+
+    len
+}
+
+/// Returns the length of the encoded record starting at `bytes`.
+///
+/// `bytes` must contain at least the first four bytes of the record.
+pub(super) fn encoded_len(bytes: &[u8]) -> usize {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize
+}
+
+/// Decode the record in `bytes` and pass it to `f`.
+pub(super) fn decode<R>(bytes: &[u8], f: impl FnOnce(&log::Record, &RecordContext) -> R) -> R {
+    let header = &bytes[..HEADER_LEN];
+    let context = RecordContext {
+        ticks: u64::from_le_bytes(header[4..12].try_into().unwrap()),
+        core: CoreId(header[16]),
+        in_interrupt: header[18] & FLAG_INTERRUPT != 0,
+    };
+    let line = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    let level = Level::iter()
+        .nth((header[17] as usize).saturating_sub(1))
+        .unwrap_or(Level::Error);
+    let flags = header[18];
+    let target_len = u16::from_le_bytes([header[20], header[21]]) as usize;
+    let file_len = u16::from_le_bytes([header[22], header[23]]) as usize;
+
+    let (target, rest) = bytes[HEADER_LEN..encoded_len(bytes)].split_at(target_len);
+    let (file, message) = rest.split_at(file_len);
+
+    f(
+        &log::Record::builder()
+            .args(format_args!("{}", as_str(message)))
+            .level(level)
+            .target(as_str(target))
+            .file((flags & FLAG_FILE != 0).then(|| as_str(file)))
+            .line((flags & FLAG_LINE != 0).then_some(line))
+            .build(),
+        &context,
+    )
+}
+
+/// Appends strings to a buffer, truncating them at char boundaries once it is
+/// full.
+struct SliceWriter<'a> {
+    buf: &'a mut [u8; MAX_RECORD_LEN],
+    written: usize,
+}
+
+impl SliceWriter<'_> {
+    /// Append as much of `s` as fits, returning `true` if all of it did.
+    fn append(&mut self, s: &str) -> bool {
+        let truncated = truncate(s, MAX_RECORD_LEN - self.written);
+        self.buf[self.written..self.written + truncated.len()]
+            .copy_from_slice(truncated.as_bytes());
+        self.written += truncated.len();
+        truncated.len() == s.len()
+    }
+}
+
+impl Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.append(s) {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+/// Converts bytes copied from a `str` back.
+fn as_str(bytes: &[u8]) -> &str {
+    // All parts of a record are truncated at char boundaries.
+    core::str::from_utf8(bytes).unwrap_or("<invalid utf-8>")
+}
+
+/// Returns the longest prefix of `s` that is at most `max_len` bytes long and
+/// ends at a char boundary.
+fn truncate(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let end = (0..=max_len)
+        .rev()
+        .find(|&idx| s.is_char_boundary(idx))
+        .unwrap_or(0);
+    &s[..end]
+}
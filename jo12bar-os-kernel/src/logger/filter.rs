@@ -0,0 +1,256 @@
+//! Per-module log level filtering.
+//!
+//! Filters are configured using [`env_logger`](https://docs.rs/env_logger)-style
+//! directive strings, like `info,jo12bar_os_kernel::mem=debug,task=trace`. Each
+//! comma-separated directive is one of
+//! - `level`: sets the level of all modules without a more specific directive,
+//! - `path`: enables all records of the module `path` and its submodules, or
+//! - `path=level`: sets the level of the module `path` and its submodules.
+//!
+//! Paths that don't match a record's target as-is are also tried relative to the
+//! kernel crate, so `task` is short for `jo12bar_os_kernel::task`. If multiple
+//! directives match a record, the longest path wins.
+//!
+//! The directives used at boot are taken from the `KERNEL_LOG` environment
+//! variable at build time, unless the [kernel command line][crate::cmdline]
+//! has a `log=` option. They can be changed at runtime using
+//! [`DispatchLogger::set_directives`][super::DispatchLogger::set_directives].
+
+use core::{fmt, str::FromStr};
+
+use log::LevelFilter;
+use thiserror::Error;
+
+/// The maximum number of directives in a [`LogFilter`].
+pub const MAX_DIRECTIVES: usize = 16;
+
+/// The maximum combined length of all module paths in a [`LogFilter`].
+pub const MAX_PATHS_LEN: usize = 256;
+
+/// The directives used at boot, taken from the `KERNEL_LOG` environment variable
+/// at build time.
+pub const BOOT_DIRECTIVES: &str = match option_env!("KERNEL_LOG") {
+    Some(directives) => directives,
+    None => "info",
+};
+
+/// The crate name that relative module paths are resolved against.
+const KERNEL_CRATE: &str = "jo12bar_os_kernel";
+
+/// Errors returned when parsing a [`LogFilter`].
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum FilterError {
+    #[error("directive {0} has an invalid log level")]
+    InvalidLevel(usize),
+    #[error("directive {0} has an empty module path")]
+    EmptyPath(usize),
+    #[error("a log filter can't hold more than {MAX_DIRECTIVES} directives")]
+    TooManyDirectives,
+    #[error("the module paths of a log filter can't be longer than {MAX_PATHS_LEN} bytes")]
+    PathsTooLong,
+}
+
+/// A module path and the most verbose level enabled for it.
+#[derive(Debug, Clone, Copy)]
+struct Directive {
+    /// The start of the path in [`LogFilter::paths`].
+    start: u16,
+    /// The end of the path in [`LogFilter::paths`].
+    end: u16,
+    level: LevelFilter,
+}
+
+impl Directive {
+    const EMPTY: Self = Self {
+        start: 0,
+        end: 0,
+        level: LevelFilter::Off,
+    };
+}
+
+/// A set of per-module level filters.
+///
+/// This doesn't allocate, so that it can be used before the heap is initialized.
+#[derive(Clone)]
+pub struct LogFilter {
+    /// The level used for modules without a matching directive.
+    default: LevelFilter,
+    /// The module paths of all directives, back to back.
+    paths: [u8; MAX_PATHS_LEN],
+    paths_len: usize,
+    directives: [Directive; MAX_DIRECTIVES],
+    directives_len: usize,
+}
+
+impl LogFilter {
+    /// Create a filter that enables all records up to `default`.
+    pub const fn new(default: LevelFilter) -> Self {
+        Self {
+            default,
+            paths: [0; MAX_PATHS_LEN],
+            paths_len: 0,
+            directives: [Directive::EMPTY; MAX_DIRECTIVES],
+            directives_len: 0,
+        }
+    }
+
+    /// Parse a directive string, as described in the [module docs][self].
+    ///
+    /// Modules without a matching directive default to [`LevelFilter::Error`],
+    /// unless the string contains a bare level.
+    pub fn parse(directives: &str) -> Result<Self, FilterError> {
+        let mut filter = Self::new(LevelFilter::Error);
+
+        for (idx, directive) in directives
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+            .enumerate()
+        {
+            match directive.split_once('=') {
+                Some((path, level)) => {
+                    let level = level
+                        .trim()
+                        .parse()
+                        .map_err(|_| FilterError::InvalidLevel(idx))?;
+                    let path = path.trim();
+                    if path.is_empty() {
+                        return Err(FilterError::EmptyPath(idx));
+                    }
+                    filter.push(path, level)?;
+                }
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    Err(_) => filter.push(directive, LevelFilter::max())?,
+                },
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Add a directive for `path`, replacing any existing one.
+    pub fn push(&mut self, path: &str, level: LevelFilter) -> Result<(), FilterError> {
+        if let Some(idx) = (0..self.directives_len).find(|&idx| self.path(idx) == path) {
+            self.directives[idx].level = level;
+            return Ok(());
+        }
+
+        if self.directives_len == MAX_DIRECTIVES {
+            return Err(FilterError::TooManyDirectives);
+        }
+        let start = self.paths_len;
+        let end = start + path.len();
+        if end > MAX_PATHS_LEN {
+            return Err(FilterError::PathsTooLong);
+        }
+
+        self.paths[start..end].copy_from_slice(path.as_bytes());
+        self.paths_len = end;
+        self.directives[self.directives_len] = Directive {
+            start: start as u16,
+            end: end as u16,
+            level,
+        };
+        self.directives_len += 1;
+        Ok(())
+    }
+
+    /// Returns the level used for modules without a matching directive.
+    pub fn default_level(&self) -> LevelFilter {
+        self.default
+    }
+
+    /// Set the level used for modules without a matching directive.
+    pub fn set_default_level(&mut self, level: LevelFilter) {
+        self.default = level;
+    }
+
+    /// Returns the most verbose level enabled for `target`, which is usually a
+    /// module path.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let relative = target
+            .strip_prefix(KERNEL_CRATE)
+            .and_then(|t| t.strip_prefix("::"));
+
+        self.directives()
+            .filter(|(path, _)| {
+                path_matches(path, target) || relative.is_some_and(|t| path_matches(path, t))
+            })
+            .max_by_key(|(path, _)| path.len())
+            .map_or(self.default, |(_, level)| level)
+    }
+
+    /// Returns `true` if a record with `level` and `target` passes this filter.
+    pub fn enabled(&self, level: log::Level, target: &str) -> bool {
+        level <= self.level_for(target)
+    }
+
+    /// Returns the most verbose level enabled for any module.
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives()
+            .map(|(_, level)| level)
+            .fold(self.default, Ord::max)
+    }
+
+    /// Iterate over the module paths and levels of all directives.
+    pub fn directives(&self) -> impl Iterator<Item = (&str, LevelFilter)> {
+        (0..self.directives_len).map(|idx| (self.path(idx), self.directives[idx].level))
+    }
+
+    fn path(&self, idx: usize) -> &str {
+        let directive = &self.directives[idx];
+        core::str::from_utf8(&self.paths[directive.start as usize..directive.end as usize])
+            .expect("directive paths are copied from a str")
+    }
+}
+
+/// Returns `true` if `path` is `module` or one of its parents.
+fn path_matches(path: &str, module: &str) -> bool {
+    module
+        .strip_prefix(path)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new(LevelFilter::Info)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Formats the filter as a directive string, which can be parsed again.
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default)?;
+        for (path, level) in self.directives() {
+            write!(f, ",{path}={level}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogFilter")
+            .field("default", &self.default)
+            .field("directives", &DebugDirectives(self))
+            .finish()
+    }
+}
+
+struct DebugDirectives<'a>(&'a LogFilter);
+
+impl fmt::Debug for DebugDirectives<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.0.directives()).finish()
+    }
+}
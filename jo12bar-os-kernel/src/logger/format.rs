@@ -0,0 +1,158 @@
+//! Formatting of log records.
+//!
+//! When a record is logged, the [`DispatchLogger`][super::DispatchLogger]
+//! captures a [`RecordContext`] for it, and each target formats the record
+//! according to its own [`RecordFormat`].
+
+use core::fmt;
+
+use mem_util::types::CoreId;
+
+use crate::{locals, time};
+
+/// Information about where and when a record was logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordContext {
+    /// The number of timer ticks since interrupts were first enabled.
+    pub ticks: u64,
+    /// The core the record was logged on.
+    pub core: CoreId,
+    /// `true` if the record was logged from inside of an interrupt or exception.
+    pub in_interrupt: bool,
+}
+
+impl RecordContext {
+    /// Capture the context of the current core.
+    pub fn capture() -> Self {
+        let locals = locals!();
+        Self {
+            ticks: time::ticks(),
+            core: locals.core_id,
+            in_interrupt: locals.in_interrupt() || locals.in_exception(),
+        }
+    }
+}
+
+/// Which parts of a record a target writes.
+///
+/// The level and message are always written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordFormat {
+    /// Write the time since boot.
+    pub timestamp: bool,
+    /// Write the core the record was logged on, and whether it was logged from
+    /// an interrupt.
+    pub core: bool,
+    /// Write the record's target, which is usually its module path.
+    pub module: bool,
+    /// Write the file and line the record was logged from.
+    pub location: bool,
+    /// Use ANSI escape sequences to color the output.
+    pub colored: bool,
+}
+
+impl RecordFormat {
+    /// Write everything, with colors unless the `no-colored-log` feature is set.
+    pub const FULL: Self = Self {
+        timestamp: true,
+        core: true,
+        module: true,
+        location: true,
+        colored: !cfg!(feature = "no-colored-log"),
+    };
+
+    /// Only write the level and message.
+    pub const MINIMAL: Self = Self {
+        timestamp: false,
+        core: false,
+        module: false,
+        location: false,
+        colored: false,
+    };
+
+    /// Returns this format with colors enabled or disabled.
+    pub const fn colored(mut self, colored: bool) -> Self {
+        self.colored = colored;
+        self
+    }
+
+    /// Returns this format with the file and line enabled or disabled.
+    pub const fn location(mut self, location: bool) -> Self {
+        self.location = location;
+        self
+    }
+}
+
+impl Default for RecordFormat {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+/// A record together with its context and the format it should be written in.
+///
+/// Formatting this using [`Display`][fmt::Display] writes a single line without
+/// a trailing newline.
+#[derive(Clone, Copy)]
+pub struct FormattedRecord<'a> {
+    /// The record passed to [`log::Log::log`].
+    pub record: &'a log::Record<'a>,
+    /// Where and when the record was logged.
+    pub context: &'a RecordContext,
+    /// The format of the target the record is written to.
+    pub format: RecordFormat,
+}
+
+impl fmt::Display for FormattedRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const SGR_RESET: &str = "\x1b[0m";
+        const SGR_BRBLACK: &str = "\x1b[90m";
+
+        let Self {
+            record,
+            context,
+            format,
+        } = *self;
+
+        let (reset, dim, level_color) = if format.colored {
+            let level_color = match record.level() {
+                log::Level::Error => "\x1b[31m", // red
+                log::Level::Warn => "\x1b[33m",  // yellow
+                log::Level::Info => "\x1b[32m",  // green
+                log::Level::Debug => "\x1b[34m", // blue
+                log::Level::Trace => "\x1b[35m", // magenta
+            };
+            (SGR_RESET, SGR_BRBLACK, level_color)
+        } else {
+            ("", "", "")
+        };
+
+        write!(f, "{reset}{dim}")?;
+        if format.timestamp {
+            let uptime = time::ticks_to_duration(context.ticks);
+            write!(
+                f,
+                "[{:>5}.{:03}] ",
+                uptime.as_secs(),
+                uptime.subsec_millis()
+            )?;
+        }
+        if format.core {
+            let irq = if context.in_interrupt { "!" } else { " " };
+            write!(f, "[C{}{irq}] ", context.core)?;
+        }
+        write!(f, "[{level_color}{:<5}{dim}]", record.level())?;
+        if format.module {
+            write!(f, " {}", record.target())?;
+        }
+        if format.location {
+            match (record.file(), record.line()) {
+                (Some(file), Some(line)) => write!(f, " @ {file}:{line}")?,
+                (Some(file), None) => write!(f, " @ {file}")?,
+                _ => {}
+            }
+        }
+
+        write!(f, "{reset} {}", record.args())
+    }
+}
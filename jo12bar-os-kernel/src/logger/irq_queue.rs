@@ -0,0 +1,199 @@
+//! A lock-free queue for records logged from inside of interrupts.
+//!
+//! Log targets take locks, so an interrupt that logs while the code it
+//! interrupted holds one of them would deadlock. Instead, records logged from
+//! interrupts are pushed into an [`IrqQueue`] and written later, either by the
+//! next record logged outside of an interrupt, or by [`drain_task`].
+//!
+//! The queue is a bounded multi-producer multi-consumer queue, based on
+//! [Dmitry Vyukov's design](https://www.1024cores.net/home/lock-free-algorithms/queues/bounded-mpmc-queue).
+//! Records are encoded into the queue's slots, so pushing never allocates.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    future::poll_fn,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use futures_util::task::AtomicWaker;
+
+use super::{
+    encoding::{self, MAX_RECORD_LEN},
+    format::RecordContext,
+    LOGGER,
+};
+
+/// The number of records that can be queued before new ones are dropped.
+pub const IRQ_QUEUE_SIZE: usize = 32;
+
+/// A slot in an [`IrqQueue`].
+struct Slot {
+    /// The position this slot is ready for.
+    ///
+    /// Equal to the position if the slot is free to be written to, and one more
+    /// than the position if it holds a record.
+    seq: AtomicUsize,
+    /// The encoded record.
+    data: UnsafeCell<[u8; MAX_RECORD_LEN]>,
+}
+
+/// A bounded lock-free FIFO queue of log records.
+pub struct IrqQueue {
+    slots: [Slot; IRQ_QUEUE_SIZE],
+    /// The position the next record is pushed to.
+    enqueue_pos: AtomicUsize,
+    /// The position the next record is popped from.
+    dequeue_pos: AtomicUsize,
+    /// The number of records dropped because the queue was full.
+    dropped: AtomicU64,
+    /// Woken whenever a record is pushed.
+    waker: AtomicWaker,
+}
+
+// Safety: A slot's data is only accessed by the single core that claimed its
+// position, which is synchronized using `seq`.
+unsafe impl Sync for IrqQueue {}
+
+impl IrqQueue {
+    /// Create a new, empty queue.
+    pub const fn new() -> Self {
+        let mut slots = [const {
+            Slot {
+                seq: AtomicUsize::new(0),
+                data: UnsafeCell::new([0; MAX_RECORD_LEN]),
+            }
+        }; IRQ_QUEUE_SIZE];
+        let mut idx = 0;
+        while idx < IRQ_QUEUE_SIZE {
+            slots[idx].seq = AtomicUsize::new(idx);
+            idx += 1;
+        }
+
+        Self {
+            slots,
+            enqueue_pos: AtomicUsize::new(0),
+            dequeue_pos: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Push a record onto the queue.
+    ///
+    /// Returns `false` and drops the record if the queue is full.
+    pub fn push(&self, record: &log::Record, context: &RecordContext) -> bool {
+        let mut pos = self.enqueue_pos.load(Ordering::Relaxed);
+        let slot = loop {
+            let slot = &self.slots[pos % IRQ_QUEUE_SIZE];
+            let seq = slot.seq.load(Ordering::Acquire);
+
+            match (seq as isize).wrapping_sub(pos as isize) {
+                0 => match self.enqueue_pos.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break slot,
+                    Err(current) => pos = current,
+                },
+                diff if diff < 0 => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                _ => pos = self.enqueue_pos.load(Ordering::Relaxed),
+            }
+        };
+
+        // Safety: We claimed `pos`, and the slot is free, so no one else accesses
+        // its data until we update `seq`.
+        encoding::encode(record, context, unsafe { &mut *slot.data.get() });
+        slot.seq.store(pos + 1, Ordering::Release);
+
+        self.waker.wake();
+        true
+    }
+
+    /// Pop the oldest record from the queue and pass it to `f`.
+    ///
+    /// Returns `false` if the queue is empty.
+    pub fn pop(&self, f: impl FnOnce(&log::Record, &RecordContext)) -> bool {
+        let mut pos = self.dequeue_pos.load(Ordering::Relaxed);
+        let slot = loop {
+            let slot = &self.slots[pos % IRQ_QUEUE_SIZE];
+            let seq = slot.seq.load(Ordering::Acquire);
+
+            match (seq as isize).wrapping_sub(pos.wrapping_add(1) as isize) {
+                0 => match self.dequeue_pos.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break slot,
+                    Err(current) => pos = current,
+                },
+                diff if diff < 0 => return false,
+                _ => pos = self.dequeue_pos.load(Ordering::Relaxed),
+            }
+        };
+
+        // Safety: We claimed `pos`, and the slot holds a record, so no one else
+        // accesses its data until we update `seq`.
+        let data = unsafe { &*slot.data.get() };
+        encoding::decode(data, f);
+        slot.seq.store(pos + IRQ_QUEUE_SIZE, Ordering::Release);
+
+        true
+    }
+
+    /// Returns `true` if no records are queued.
+    pub fn is_empty(&self) -> bool {
+        let enqueue = self.enqueue_pos.load(Ordering::Acquire);
+        let dequeue = self.dequeue_pos.load(Ordering::Acquire);
+        enqueue == dequeue
+    }
+
+    /// Returns the number of records that were dropped because the queue was
+    /// full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for IrqQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for IrqQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IrqQueue")
+            .field("enqueue_pos", &self.enqueue_pos.load(Ordering::Relaxed))
+            .field("dequeue_pos", &self.dequeue_pos.load(Ordering::Relaxed))
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Write records logged from interrupts as soon as they are queued.
+///
+/// This future will never terminate, so you should run it as a background task via `spawn()`.
+pub async fn drain_task() {
+    loop {
+        poll_fn(|cx| {
+            LOGGER.irq_queue.waker.register(cx.waker());
+            if LOGGER.irq_queue.is_empty() {
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await;
+
+        LOGGER.drain_irq_queue();
+    }
+}
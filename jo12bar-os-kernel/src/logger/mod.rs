@@ -0,0 +1,589 @@
+//! A module containing logging and debug utilities.
+//!
+//! All records are passed to the global [`DispatchLogger`] in [`LOGGER`], which
+//! forwards them to a set of [`TargetLogger`]s. Each target has a name and its
+//! own level filter, and targets can be added or removed at runtime. The
+//! available targets are in [`targets`].
+//!
+//! Before a record reaches any target, it has to pass the logger's
+//! per-module [`LogFilter`], which is configured using directive strings like
+//! `jo12bar_os_kernel::mem=debug,task=trace`. See [`filter`] for details.
+//!
+//! Each record is written together with the time, core and source location it
+//! was logged from. Which of those are included is configured per target using
+//! a [`RecordFormat`].
+//!
+//! Records logged from inside of interrupts don't take any locks. They are put
+//! into a lock-free [`IrqQueue`], and written once the interrupt is over, either
+//! by the next record logged outside of an interrupt or by
+//! [`irq_queue::drain_task`].
+//!
+//! All records are also kept in the kernel log buffer [`DMESG`][dmesg::DMESG], so that targets
+//! added later can replay them using [`DispatchLogger::add_target_replaying`].
+//!
+//! Based on the modular logger from WasabiOS. See:
+//! <https://github.com/Wasabi375/WasabiOS/blob/2246c42cc2e296f9831b5daf5cb933fcead9ff3b/wasabi-kernel/src/logger.rs>
+
+use alloc::boxed::Box;
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use log::{info, warn, LevelFilter};
+use mem_util::sync::lock_cell::{LockCellInternal, RwCellInternal};
+use thiserror::Error;
+
+use crate::prelude::*;
+
+use self::{
+    dmesg::{Dmesg, DmesgLogger},
+    filter::{FilterError, LogFilter, BOOT_DIRECTIVES},
+    format::{FormattedRecord, RecordContext, RecordFormat},
+    irq_queue::IrqQueue,
+    targets::SerialLogger,
+};
+
+pub mod dmesg;
+mod encoding;
+pub mod filter;
+pub mod format;
+pub mod irq_queue;
+pub mod targets;
+
+/// The maximum number of targets the [`DispatchLogger`] can hold.
+pub const MAX_TARGETS: usize = 8;
+
+/// The static logger used by the [`log::log`] macro.
+pub static LOGGER: DispatchLogger = DispatchLogger::new();
+
+/// Something that log records can be written to.
+///
+/// Targets are called from within interrupts, so they must neither allocate nor
+/// block on preemtable locks.
+pub trait LogTarget: Send + Sync {
+    /// Write `record` to the target.
+    fn log(&self, record: &FormattedRecord);
+
+    /// Flush any buffered records.
+    fn flush(&self) {}
+
+    /// Force-unlock any locks held by the target.
+    ///
+    /// # Safety
+    /// Inherently unsafe. Only use in the global panic handler.
+    unsafe fn force_unlock(&self) {}
+}
+
+impl<T: LogTarget + ?Sized> LogTarget for &T {
+    fn log(&self, record: &FormattedRecord) {
+        (**self).log(record)
+    }
+
+    fn flush(&self) {
+        (**self).flush()
+    }
+
+    unsafe fn force_unlock(&self) {
+        // Safety: see above
+        unsafe { (**self).force_unlock() }
+    }
+}
+
+/// A named [`LogTarget`] with its own level filter and [`RecordFormat`].
+pub struct TargetLogger {
+    name: &'static str,
+    /// The [`LevelFilter`] as a `usize`.
+    level: AtomicUsize,
+    format: SeqLock<RecordFormat>,
+    target: Box<dyn LogTarget>,
+}
+
+impl TargetLogger {
+    /// Create a new target called `name`, that receives records up to `level`.
+    ///
+    /// Records are written using [`RecordFormat::FULL`] unless changed using
+    /// [`TargetLogger::with_format`].
+    pub fn new(name: &'static str, level: LevelFilter, target: Box<dyn LogTarget>) -> Self {
+        Self {
+            name,
+            level: AtomicUsize::new(level as usize),
+            format: SeqLock::new_non_preemtable(RecordFormat::FULL),
+            target,
+        }
+    }
+
+    /// Returns this target with records written using `format`.
+    pub fn with_format(mut self, format: RecordFormat) -> Self {
+        *self.format.get_mut() = format;
+        self
+    }
+
+    /// Create a new target called `name`, that receives records up to `level`.
+    ///
+    /// This only allocates if `target` is not a zero-sized type.
+    pub fn new_boxed(
+        name: &'static str,
+        level: LevelFilter,
+        target: impl LogTarget + 'static,
+    ) -> Self {
+        Self::new(name, level, Box::new(target))
+    }
+
+    /// Returns the name of this target.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the most verbose level this target receives.
+    pub fn level(&self) -> LevelFilter {
+        level_filter_from_usize(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Set the most verbose level this target receives.
+    ///
+    /// Prefer [`DispatchLogger::set_target_level`] for targets that were added to
+    /// a logger, since that also updates [`log::max_level()`].
+    pub fn set_level(&self, level: LevelFilter) {
+        self.level.store(level as usize, Ordering::Relaxed);
+    }
+
+    /// Returns the format records are written in.
+    pub fn format(&self) -> RecordFormat {
+        self.format.read()
+    }
+
+    /// Set the format records are written in.
+    pub fn set_format(&self, format: RecordFormat) {
+        self.format.set(format);
+    }
+
+    /// Write `record` to the target, if it passes the target's level filter.
+    fn log(&self, record: &log::Record, context: &RecordContext) {
+        if record.level() <= self.level() {
+            self.target.log(&FormattedRecord {
+                record,
+                context,
+                format: self.format(),
+            });
+        }
+    }
+}
+
+impl fmt::Debug for TargetLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TargetLogger")
+            .field("name", &self.name)
+            .field("level", &self.level())
+            .field("format", &self.format())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Converts a `usize` created from a [`LevelFilter`] back.
+fn level_filter_from_usize(level: usize) -> LevelFilter {
+    LevelFilter::iter().nth(level).unwrap_or(LevelFilter::max())
+}
+
+/// Errors returned by [`DispatchLogger`].
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum LoggerError {
+    #[error("a log target named {0:?} already exists")]
+    DuplicateTarget(&'static str),
+    #[error("the logger can't hold more than {MAX_TARGETS} targets")]
+    TooManyTargets,
+}
+
+/// A logger that forwards each record that passes its [`LogFilter`] to all of
+/// its [`TargetLogger`]s whose level filter allows it.
+pub struct DispatchLogger {
+    /// Non-preemtable, so that records can be logged from interrupts.
+    ///
+    /// When both locks are needed, `targets` must be locked first.
+    filter: RwTicketLock<LogFilter>,
+    /// Non-preemtable, so that records can be logged from interrupts.
+    ///
+    /// This is a fixed-size array, so that targets can be added before the heap
+    /// is initialized.
+    targets: RwTicketLock<[Option<TargetLogger>; MAX_TARGETS]>,
+    /// Records logged from inside of interrupts, that were not written yet.
+    irq_queue: IrqQueue,
+    /// Set while a core is writing the records in `irq_queue`, so that they are
+    /// written in order.
+    draining: AtomicBool,
+    /// Set once records from interrupts should be written immediately, e.g.
+    /// during a panic.
+    direct: AtomicBool,
+}
+
+impl DispatchLogger {
+    /// Create a new logger without any targets, that doesn't filter any modules.
+    pub const fn new() -> Self {
+        Self {
+            filter: RwTicketLock::new_non_preemtable(LogFilter::new(LevelFilter::Trace)),
+            targets: RwTicketLock::new_non_preemtable([const { None }; MAX_TARGETS]),
+            irq_queue: IrqQueue::new(),
+            draining: AtomicBool::new(false),
+            direct: AtomicBool::new(false),
+        }
+    }
+
+    /// Add `target` to this logger.
+    pub fn add_target(&self, target: TargetLogger) -> Result<(), LoggerError> {
+        let mut targets = self.targets.write();
+
+        if targets.iter().flatten().any(|t| t.name == target.name) {
+            return Err(LoggerError::DuplicateTarget(target.name));
+        }
+        let slot = targets
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(LoggerError::TooManyTargets)?;
+        *slot = Some(target);
+
+        self.update_max_level(&targets);
+        Ok(())
+    }
+
+    /// Add `target` to this logger, after writing all records in `history` to it.
+    ///
+    /// No records are lost or duplicated between the replay and `target` being
+    /// added, since the logger is locked for the whole time.
+    pub fn add_target_replaying(
+        &self,
+        target: TargetLogger,
+        history: &Dmesg,
+    ) -> Result<(), LoggerError> {
+        let mut targets = self.targets.write();
+
+        if targets.iter().flatten().any(|t| t.name == target.name) {
+            return Err(LoggerError::DuplicateTarget(target.name));
+        }
+        let slot = targets
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(LoggerError::TooManyTargets)?;
+
+        history.for_each(|record, context| target.log(record, context));
+        *slot = Some(target);
+
+        self.update_max_level(&targets);
+        Ok(())
+    }
+
+    /// Remove the target called `name`, returning it.
+    pub fn remove_target(&self, name: &str) -> Option<TargetLogger> {
+        let target = {
+            let mut targets = self.targets.write();
+            let target = targets
+                .iter_mut()
+                .find(|slot| slot.as_ref().is_some_and(|t| t.name == name))?
+                .take();
+            self.update_max_level(&targets);
+            target
+        };
+
+        // Flush outside of the lock, since the target might log while doing so.
+        if let Some(target) = &target {
+            target.target.flush();
+        }
+        target
+    }
+
+    /// Returns the level filter of the target called `name`.
+    pub fn target_level(&self, name: &str) -> Option<LevelFilter> {
+        self.targets
+            .read()
+            .iter()
+            .flatten()
+            .find(|t| t.name == name)
+            .map(TargetLogger::level)
+    }
+
+    /// Set the level filter of the target called `name`, returning the old one.
+    ///
+    /// Returns [`None`] if there is no such target.
+    pub fn set_target_level(&self, name: &str, level: LevelFilter) -> Option<LevelFilter> {
+        let targets = self.targets.read();
+        let target = targets.iter().flatten().find(|t| t.name == name)?;
+
+        let old = target.level();
+        target.set_level(level);
+        self.update_max_level(&targets);
+        Some(old)
+    }
+
+    /// Set the format of the target called `name`, returning the old one.
+    ///
+    /// Returns [`None`] if there is no such target.
+    pub fn set_target_format(&self, name: &str, format: RecordFormat) -> Option<RecordFormat> {
+        let targets = self.targets.read();
+        let target = targets.iter().flatten().find(|t| t.name == name)?;
+
+        let old = target.format();
+        target.set_format(format);
+        Some(old)
+    }
+
+    /// Call `f` for each target.
+    pub fn for_each_target(&self, mut f: impl FnMut(&TargetLogger)) {
+        self.targets.read().iter().flatten().for_each(&mut f);
+    }
+
+    /// Returns a copy of the current [`LogFilter`].
+    pub fn filter(&self) -> LogFilter {
+        self.filter.read().clone()
+    }
+
+    /// Replace the current [`LogFilter`].
+    pub fn set_filter(&self, filter: LogFilter) {
+        *self.filter.write() = filter;
+        self.update_max_level(&self.targets.read());
+    }
+
+    /// Replace the current [`LogFilter`] with one parsed from `directives`.
+    ///
+    /// See [`filter`] for the syntax. The current filter is kept if `directives`
+    /// is invalid.
+    pub fn set_directives(&self, directives: &str) -> Result<(), FilterError> {
+        self.set_filter(LogFilter::parse(directives)?);
+        Ok(())
+    }
+
+    /// Set the level of the module `path` and its submodules, keeping all other
+    /// directives.
+    pub fn set_module_level(&self, path: &str, level: LevelFilter) -> Result<(), FilterError> {
+        self.filter.write().push(path, level)?;
+        self.update_max_level(&self.targets.read());
+        Ok(())
+    }
+
+    /// Set [`log::max_level()`] to the most verbose level that passes both the
+    /// filter and any target, so that records no one wants are discarded early.
+    fn update_max_level(&self, targets: &[Option<TargetLogger>; MAX_TARGETS]) {
+        let max_level = targets
+            .iter()
+            .flatten()
+            .map(TargetLogger::level)
+            .max()
+            .unwrap_or(LevelFilter::Off);
+        log::set_max_level(max_level.min(self.filter.read().max_level()));
+    }
+
+    /// Returns the number of records logged from interrupts that were dropped,
+    /// because too many were logged before they could be written.
+    pub fn dropped_irq_records(&self) -> u64 {
+        self.irq_queue.dropped()
+    }
+
+    /// Write all records that were logged from inside of interrupts.
+    ///
+    /// Does nothing if another core is already doing so.
+    pub fn drain_irq_queue(&self) {
+        if self
+            .draining
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        while self.irq_queue.pop(|record, context| {
+            if log::Log::enabled(self, record.metadata()) {
+                self.dispatch(record, context);
+            }
+        }) {}
+
+        self.draining.store(false, Ordering::Release);
+    }
+
+    /// Write `record` to all targets.
+    fn dispatch(&self, record: &log::Record, context: &RecordContext) {
+        for target in self.targets.read().iter().flatten() {
+            target.log(record, context);
+        }
+    }
+
+    /// Force-unlock the logger and all of its targets.
+    ///
+    /// Afterwards, records logged from interrupts are written immediately.
+    ///
+    /// # Safety
+    /// Inherently unsafe. Only use in the global panic handler.
+    pub unsafe fn force_unlock(&self) {
+        self.direct.store(true, Ordering::SeqCst);
+        // The core that was draining might never finish.
+        self.draining.store(false, Ordering::SeqCst);
+
+        // Safety: see above
+        unsafe {
+            // Readers don't block logging, only a writer does.
+            if !self.targets.open_to_read() {
+                self.targets.force_unlock();
+            }
+            if !self.filter.open_to_read() {
+                self.filter.force_unlock();
+            }
+            for target in self.targets.get().iter().flatten() {
+                target.target.force_unlock();
+            }
+        }
+    }
+}
+
+impl Default for DispatchLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl log::Log for DispatchLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter
+            .read()
+            .enabled(metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        let context = RecordContext::capture();
+
+        // The filter is only applied when the record is taken out of the queue,
+        // since that needs a lock.
+        if context.in_interrupt && !self.direct.load(Ordering::Relaxed) {
+            self.irq_queue.push(record, &context);
+            return;
+        }
+
+        // Records from interrupts were logged before this one.
+        self.drain_irq_queue();
+
+        if self.enabled(record.metadata()) {
+            self.dispatch(record, &context);
+        }
+    }
+
+    fn flush(&self) {
+        self.drain_irq_queue();
+        for target in self.targets.read().iter().flatten() {
+            target.target.flush();
+        }
+    }
+}
+
+/// Initializes the logger, piping all [log::log] calls into the first serial
+/// port.
+///
+/// More targets can be added later using [`DispatchLogger::add_target`].
+///
+/// # Safety
+/// Must only ever be called once at the start of the kernel boot process and after
+/// serial is initialized.
+pub unsafe fn init() {
+    LOGGER
+        .add_target(TargetLogger::new_boxed(
+            "dmesg",
+            LevelFilter::Trace,
+            DmesgLogger,
+        ))
+        .expect("the dmesg logger was already added");
+    LOGGER
+        .add_target(TargetLogger::new_boxed(
+            "serial",
+            LevelFilter::Trace,
+            SerialLogger,
+        ))
+        .expect("the serial logger was already added");
+
+    let filter = LogFilter::parse(BOOT_DIRECTIVES);
+    LOGGER.set_filter(filter.clone().unwrap_or_default());
+
+    log::set_logger(&LOGGER).expect("logger has already been set");
+
+    match filter {
+        Ok(filter) => info!("Logger initialized with filter {filter}."),
+        Err(e) => warn!("Invalid KERNEL_LOG directives {BOOT_DIRECTIVES:?}: {e}"),
+    }
+}
+
+/// A macro logging and returning the result of any expression.
+/// The result of the expression is logged using the [log::debug] macro.
+///
+/// ```
+/// assert_eq!(5, dbg!(5)); // also calls log::debug!(5)
+/// ```
+#[allow(unused_macros)]
+#[macro_export]
+macro_rules! dbg {
+    () => {
+        log::debug!(
+            "[{}:{}:{}]",
+            ::core::file!(),
+            ::core::line!(),
+            ::core::column!()
+        )
+    };
+    ($val:expr) => {
+        // Use of `match` here is intentional because it affects the lifetimes
+        // of temporaries - https://stackoverflow.com/a/48732525/1063961
+        match $val {
+            tmp => {
+                log::debug!(
+                    "[{}:{}:{}] {} = {:#?}",
+                    ::core::file!(),
+                    ::core::line!(),
+                    ::core::column!(),
+                    ::core::stringify!($val),
+                    &tmp
+                );
+                tmp
+            }
+        }
+    };
+}
+
+/// Same as [todo!] but only calls a [log::warn] instead of [panic].
+#[allow(unused_macros)]
+#[macro_export]
+macro_rules! todo_warn {
+    () => {
+        log::warn!(
+            "[{}:{}:{}] not yet implemented",
+            ::core::file!(),
+            ::core::line!(),
+            ::core::column!(),
+        )
+    };
+    ($($arg:tt)+) => {
+        log::warn!(
+            "[{}:{}:{}] not yet implemented: {}",
+            ::core::file!(),
+            ::core::line!(),
+            ::core::column!(),
+            ::core::format_args!($($arg)+),
+        )
+    };
+}
+
+/// Same as [todo!] but only calls a [log::warn] instead of [panic].
+#[allow(unused_macros)]
+#[macro_export]
+macro_rules! todo_error {
+    () => {
+        log::error!(
+            "[{}:{}:{}] not yet implemented",
+            ::core::file!(),
+            ::core::line!(),
+            ::core::column!(),
+        )
+    };
+    ($($arg:tt)+) => {
+        log::error!(
+            "[{}:{}:{}] not yet implemented: {}",
+            ::core::file!(),
+            ::core::line!(),
+            ::core::column!(),
+            ::core::format_args!($($arg)+),
+        )
+    };
+}
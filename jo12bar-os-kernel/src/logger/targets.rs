@@ -0,0 +1,63 @@
+//! The [`LogTarget`]s available to the [`DispatchLogger`][super::DispatchLogger].
+
+use core::fmt::Write;
+
+use mem_util::sync::lock_cell::LockCellInternal;
+
+use crate::{
+    graphics::{canvas::CanvasWriter, framebuffer::Framebuffer},
+    prelude::*,
+    serial::SERIAL1,
+};
+
+use super::{format::FormattedRecord, LogTarget};
+
+/// Logs to the first serial port.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SerialLogger;
+
+impl LogTarget for SerialLogger {
+    fn log(&self, record: &FormattedRecord) {
+        // Nowhere to report serial errors to.
+        let _ = writeln!(SERIAL1.lock(), "{record}");
+    }
+
+    unsafe fn force_unlock(&self) {
+        if let Some(serial) = Lazy::get(&SERIAL1) {
+            if !serial.is_unlocked() {
+                // Safety: see [`LogTarget::force_unlock`]
+                unsafe { serial.force_unlock() };
+            }
+        }
+    }
+}
+
+/// Logs to a [`CanvasWriter`], usually the one covering the hardware framebuffer.
+pub struct CanvasLogger {
+    /// Non-preemtable, so that records can be logged from interrupts.
+    writer: TicketLock<CanvasWriter<'static, Framebuffer>>,
+}
+
+impl CanvasLogger {
+    /// Create a new [`CanvasLogger`] writing to `writer`.
+    ///
+    /// `writer` should not log its own errors, since that would deadlock.
+    pub fn new(writer: CanvasWriter<'static, Framebuffer>) -> Self {
+        Self {
+            writer: TicketLock::new_non_preemtable(writer),
+        }
+    }
+}
+
+impl LogTarget for CanvasLogger {
+    fn log(&self, record: &FormattedRecord) {
+        let _ = writeln!(self.writer.lock(), "{record}");
+    }
+
+    unsafe fn force_unlock(&self) {
+        if !self.writer.is_unlocked() {
+            // Safety: see [`LogTarget::force_unlock`]
+            unsafe { self.writer.force_unlock() };
+        }
+    }
+}
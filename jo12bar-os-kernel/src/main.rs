@@ -8,20 +8,30 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
-use core::panic::PanicInfo;
+use alloc::{boxed::Box, rc::Rc, string::String, sync::Arc, vec, vec::Vec};
+use core::{panic::PanicInfo, time::Duration};
 use log::{debug, error, info, trace, warn};
 
 use jo12bar_os_kernel::{
+    block::{cache::BufferCache, ramdisk::RamDisk, BlockDevice},
     bootloader_config_common,
+    cmdline::{self, Demo},
     core_locals::CoreInterruptState,
     cpu::halt,
-    dbg, graphics, init,
-    logger::LOGGER,
+    dbg,
+    fs::{self, fat::FatFs},
+    graphics, init, initrd, logger,
     prelude::*,
     task::{keyboard, Executor, Task},
+    thread,
 };
 
+/// The FAT image the runner puts into the initial ramdisk.
+const FAT_IMAGE: &str = "fat.img";
+/// The number of pages the FAT demo caches, which is kept small to spare the
+/// heap.
+const FAT_CACHE_PAGES: usize = 4;
+
 /// Configuration for the bootloader.
 const BOOTLOADER_CONFIG: bootloader_api::BootloaderConfig = {
     let config = bootloader_api::BootloaderConfig::new_default();
@@ -40,67 +50,167 @@ fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
 
     info!("Kernel initialized");
 
-    debug!("Here's the allocator before any allocations:");
-    dbg!(&jo12bar_os_kernel::mem::allocator::ALLOCATOR);
-    if let Some(a) = jo12bar_os_kernel::mem::allocator::ALLOCATOR.try_lock() {
-        dbg!(&*a);
+    if let Ok(motd) = fs::read("/etc/motd") {
+        info!("{}", String::from_utf8_lossy(&motd).trim_end());
     }
 
-    // Allocate a number on the heap
-    let heap_value = Box::new(41);
-    info!("heap_value at {heap_value:p}");
+    let config = cmdline::config();
 
-    // Create a dynamically-sized vector
-    let mut vec = Vec::new();
-    for i in 0..500 {
-        vec.push(i);
-    }
-    info!("vec at {:p}", vec.as_slice());
-
-    // Create a reference-counted vector -> will be freed when count reaches 0
-    let rc_vec = Rc::new(vec![1, 2, 3]);
-    info!("current reference count is {}", Rc::strong_count(&rc_vec));
-    let cloned = Rc::clone(&rc_vec);
-    info!("current reference count is {}", Rc::strong_count(&rc_vec));
-    core::mem::drop(cloned);
-    info!("current reference count is {}", Rc::strong_count(&rc_vec));
-
-    // Try allocating / deallocating something multiple times, printing its address each time
-    for _ in 0..10 {
-        {
-            let heap_value = Box::new(42u8);
-            debug!(
-                "allocated u8 heap_value={0} at {0:p}, deallocating after this log",
-                heap_value
-            );
-            drop(heap_value);
+    if config.demo_enabled(Demo::Alloc) {
+        debug!("Here's the allocator before any allocations:");
+        dbg!(&jo12bar_os_kernel::mem::allocator::ALLOCATOR);
+        if let Some(a) = jo12bar_os_kernel::mem::allocator::ALLOCATOR.try_lock() {
+            dbg!(&*a);
+        }
+
+        // Allocate a number on the heap
+        let heap_value = Box::new(41);
+        info!("heap_value at {heap_value:p}");
+
+        // Create a dynamically-sized vector
+        let mut vec = Vec::new();
+        for i in 0..500 {
+            vec.push(i);
         }
+        info!("vec at {:p}", vec.as_slice());
+
+        // Create a reference-counted vector -> will be freed when count reaches 0
+        let rc_vec = Rc::new(vec![1, 2, 3]);
+        info!("current reference count is {}", Rc::strong_count(&rc_vec));
+        let cloned = Rc::clone(&rc_vec);
+        info!("current reference count is {}", Rc::strong_count(&rc_vec));
+        core::mem::drop(cloned);
+        info!("current reference count is {}", Rc::strong_count(&rc_vec));
+
+        // Try allocating / deallocating something multiple times, printing its address each time
+        for _ in 0..10 {
+            {
+                let heap_value = Box::new(42u8);
+                debug!(
+                    "allocated u8 heap_value={0} at {0:p}, deallocating after this log",
+                    heap_value
+                );
+                drop(heap_value);
+            }
+        }
+
+        debug!("Here's the allocator after several allocations:");
+        dbg!(&jo12bar_os_kernel::mem::allocator::ALLOCATOR);
+        if let Some(a) = jo12bar_os_kernel::mem::allocator::ALLOCATOR.try_lock() {
+            dbg!(&*a);
+        }
+
+        dbg!();
+        dbg!(&graphics::framebuffer::HARDWARE_FRAMEBUFFER);
+        dbg!(&CoreInterruptState);
     }
 
-    debug!("Here's the allocator after several allocations:");
-    dbg!(&jo12bar_os_kernel::mem::allocator::ALLOCATOR);
-    if let Some(a) = jo12bar_os_kernel::mem::allocator::ALLOCATOR.try_lock() {
-        dbg!(&*a);
+    if config.demo_enabled(Demo::Logs) {
+        trace!("Test trace log");
+        debug!("Test debug log");
+        info!("Test info log");
+        warn!("Test warn log");
+        error!("Test error log");
     }
 
-    dbg!();
-    dbg!(&graphics::framebuffer::HARDWARE_FRAMEBUFFER);
-    dbg!(&CoreInterruptState);
+    if config.demo_enabled(Demo::Fs) {
+        if let Err(e) = fs_demo() {
+            error!("Filesystem demo failed: {e}");
+        }
+    }
+
+    if config.demo_enabled(Demo::Fat) {
+        if let Err(e) = fat_demo() {
+            error!("FAT demo failed: {e}");
+        }
+    }
 
-    trace!("Test trace log");
-    debug!("Test debug log");
-    info!("Test info log");
-    warn!("Test warn log");
-    error!("Test error log");
+    if config.demo_enabled(Demo::Threads) {
+        let worker = thread::spawn(|| (1..=20u64).product::<u64>());
+        let _reporter = thread::spawn(move || {
+            let result = worker.join();
+            info!("20! = {result} (computed on a kernel thread)");
+        });
+        thread::sleep(Duration::from_millis(100));
+    }
 
     let mut executor = Executor::new();
-    executor.spawn(example_task());
+    if config.demo_enabled(Demo::Async) {
+        executor.spawn(example_task());
+    }
+    if config.demo_enabled(Demo::Block) {
+        executor.spawn(block_demo());
+    }
     executor.spawn(keyboard::print_keypresses());
+    executor.spawn(logger::irq_queue::drain_task());
     executor.run();
 
     halt();
 }
 
+fn fs_demo() -> fs::FsResult<()> {
+    fs::create_dir_all("/tmp/demo")?;
+    fs::write("/tmp/demo/hello.txt", b"Hello from tmpfs!")?;
+
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .open("/tmp/demo/hello.txt")?;
+    file.write_all(b" Appended.")?;
+    file.seek(fs::SeekFrom::Start(0))?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    info!("{}: {}", file.path(), String::from_utf8_lossy(&contents));
+
+    for entry in fs::read_dir("/")? {
+        debug!("/{} ({:?}, inode {})", entry.name, entry.kind, entry.ino);
+    }
+    fs::remove("/tmp/demo/hello.txt")?;
+    fs::remove("/tmp/demo")
+}
+
+fn fat_demo() -> fs::FsResult<()> {
+    // The image is read from the initial ramdisk directly, since it is too
+    // large for `fs::init` to copy.
+    let image = initrd::get()
+        .and_then(|initrd| initrd.read(FAT_IMAGE))
+        .map_err(|e| {
+            warn!("Couldn't read {FAT_IMAGE} from the initial ramdisk: {e}");
+            fs::FsError::NotFound
+        })?;
+    let disk = RamDisk::from_vec(512, image.to_vec());
+    fs::create_dir_all("/mnt/fat")?;
+    let fat = FatFs::with_cache_capacity(Arc::new(disk), FAT_CACHE_PAGES)?;
+    fs::mount("/mnt/fat", fat)?;
+    walk("/mnt/fat")?;
+
+    let path = "/mnt/fat/docs/Written by the kernel.txt";
+    let mut file = fs::File::create(path)?;
+    file.write_all(b"Hello from the kernel!")?;
+    // Extending the file allocates zeroed clusters, truncating frees them.
+    file.set_len(3000)?;
+    info!("{path}: {} bytes after extending", file.metadata().len);
+    file.set_len(5)?;
+    info!("{path}: {}", String::from_utf8_lossy(&fs::read(path)?));
+
+    fs::remove(path)?;
+    fs::unmount("/mnt/fat")
+}
+
+/// Log the files below `dir`.
+fn walk(dir: &str) -> fs::FsResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = alloc::format!("{dir}/{}", entry.name);
+        if entry.kind == fs::NodeKind::Directory {
+            debug!("{path}/");
+            walk(&path)?;
+        } else {
+            debug!("{path} ({} bytes)", fs::metadata(&path)?.len);
+        }
+    }
+    Ok(())
+}
+
 async fn async_number() -> u32 {
     42
 }
@@ -110,15 +220,38 @@ async fn example_task() {
     log::info!("async number: {number}");
 }
 
+async fn block_demo() {
+    // Requests are completed from an interrupt handler, like on a real disk.
+    let disk: Arc<dyn BlockDevice> = match RamDisk::new(512, 64).into_interrupt_driven() {
+        Ok(disk) => disk,
+        Err(e) => {
+            error!("Block device demo failed: {e}");
+            return;
+        }
+    };
+    let cache = BufferCache::new(disk.clone(), 2);
+
+    let message = b"Hello from the buffer cache!";
+    let result = async {
+        // The write straddles two pages, and only reaches the disk when the
+        // cache is flushed.
+        cache.write(4090, message).await?;
+        cache.flush().await?;
+        disk.read_blocks(7, 1024).await
+    }
+    .await;
+
+    match result {
+        Ok(blocks) => info!(
+            "read back from the RAM disk: {}",
+            String::from_utf8_lossy(&blocks[506..506 + message.len()])
+        ),
+        Err(e) => error!("Block device demo failed: {e}"),
+    }
+}
+
 /// Called on panic.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    unsafe {
-        if let Some(l) = LOGGER.as_ref() {
-            l.force_unlock();
-        }
-    }
-    // unsafe { jo12bar_os_kernel::exit_qemu(jo12bar_os_kernel::QemuExitCode::Failure) };
-    error!("{}", info);
-    halt();
+    jo12bar_os_kernel::panic::handle_panic(info)
 }
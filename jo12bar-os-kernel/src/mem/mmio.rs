@@ -0,0 +1,208 @@
+//! Mapping device memory.
+//!
+//! Memory-mapped I/O regions, like PCI BARs or the local APIC, are mapped
+//! uncached into their own virtual region. Mappings are never removed, since
+//! devices are never unplugged.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use mem_util::GiB;
+use thiserror::Error;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use crate::prelude::*;
+
+/// Start (virtual) address of the region device memory is mapped in.
+pub const MMIO_START: VirtAddr = VirtAddr::new(0x6666_0000_0000);
+/// Size of the region device memory is mapped in.
+pub const MMIO_SIZE: u64 = GiB!(64);
+
+/// The number of pages of the MMIO region that have been handed out so far.
+static NEXT_PAGE: AtomicU64 = AtomicU64::new(0);
+
+/// Errors that can occur when mapping device memory.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum MmioError {
+    #[error("the MMIO region is out of virtual address space")]
+    OutOfAddressSpace,
+    #[error("no frame left for page tables")]
+    FrameAllocationFailed,
+    #[error("the page is already mapped")]
+    AlreadyMapped,
+}
+
+impl From<MapToError<Size4KiB>> for MmioError {
+    fn from(value: MapToError<Size4KiB>) -> Self {
+        match value {
+            MapToError::FrameAllocationFailed => Self::FrameAllocationFailed,
+            MapToError::ParentEntryHugePage | MapToError::PageAlreadyMapped(_) => {
+                Self::AlreadyMapped
+            }
+        }
+    }
+}
+
+/// A register that can be accessed through [`Mmio`].
+pub trait Register: Copy + private::Sealed {}
+
+impl Register for u8 {}
+impl Register for u16 {}
+impl Register for u32 {}
+impl Register for u64 {}
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+}
+
+/// A mapped region of device memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mmio {
+    base: VirtAddr,
+    len: u64,
+}
+
+impl Mmio {
+    /// Map `len` bytes of device memory starting at `phys`.
+    ///
+    /// # Safety
+    /// `phys..phys + len` must be device memory, and not RAM used by anything
+    /// else, since it can be written through the mapping.
+    pub unsafe fn map(phys: PhysAddr, len: u64) -> Result<Self, MmioError> {
+        let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+        let last_frame = PhysFrame::containing_address(phys + len.max(1) - 1u64);
+        let page_count = last_frame - first_frame + 1;
+
+        // Only hand out the pages if they fit, so that a failed mapping doesn't
+        // use up the region.
+        let mut start = NEXT_PAGE.load(Ordering::SeqCst);
+        loop {
+            let end = start
+                .checked_add(page_count)
+                .filter(|&end| end <= MMIO_SIZE / Size4KiB::SIZE)
+                .ok_or(MmioError::OutOfAddressSpace)?;
+            match NEXT_PAGE.compare_exchange_weak(start, end, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(next) => start = next,
+            }
+        }
+        let first_page = Page::containing_address(MMIO_START + start * Size4KiB::SIZE);
+
+        let mut page_table = crate::mem::PAGE_TABLE.lock();
+        let mut frame_allocator = crate::mem::FRAME_ALLOCATOR.lock();
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_CACHE
+            | PageTableFlags::WRITE_THROUGH
+            | PageTableFlags::NO_EXECUTE;
+
+        for (page, frame) in Page::range(first_page, first_page + page_count)
+            .zip(PhysFrame::range_inclusive(first_frame, last_frame))
+        {
+            // Safety: The page is part of the MMIO region, which is only ever
+            // used by this module, and each page is handed out once, or again
+            // after a failed mapping unmapped it. The caller guarantees that the frame is device memory.
+            let result = unsafe { page_table.map_to(page, frame, flags, &mut *frame_allocator) };
+            match result {
+                Ok(flush) => flush.flush(),
+                Err(e) => {
+                    for mapped in Page::range(first_page, page) {
+                        if let Ok((_, flush)) = page_table.unmap(mapped) {
+                            flush.flush();
+                        }
+                    }
+                    // Give the pages back, unless they were followed by
+                    // another mapping in the meantime.
+                    let _ = NEXT_PAGE.compare_exchange(
+                        start + page_count,
+                        start,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(Self {
+            base: first_page.start_address() + (phys - first_frame.start_address()),
+            len,
+        })
+    }
+
+    /// Returns the virtual address of the start of the region.
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a subregion of `len` bytes starting at `offset`.
+    ///
+    /// # Panics
+    /// Panics if the subregion doesn't fit into this region.
+    pub fn slice(&self, offset: u64, len: u64) -> Self {
+        assert!(
+            offset.checked_add(len).is_some_and(|end| end <= self.len),
+            "MMIO slice {offset:#x}+{len:#x} is out of bounds"
+        );
+        Self {
+            base: self.base + offset,
+            len,
+        }
+    }
+
+    /// Read the register at `offset`.
+    ///
+    /// # Panics
+    /// Panics if the register is out of bounds or not naturally aligned.
+    pub fn read<R: Register>(&self, offset: u64) -> R {
+        let ptr = self.register_ptr::<R>(offset);
+        // Safety: The register is in bounds and aligned, and the region stays
+        // mapped forever.
+        unsafe { ptr.read_volatile() }
+    }
+
+    /// Write `value` to the register at `offset`.
+    ///
+    /// # Panics
+    /// Panics if the register is out of bounds or not naturally aligned.
+    pub fn write<R: Register>(&self, offset: u64, value: R) {
+        let ptr = self.register_ptr::<R>(offset);
+        // Safety: The register is in bounds and aligned, and the region stays
+        // mapped forever.
+        unsafe { ptr.write_volatile(value) }
+    }
+
+    fn register_ptr<R: Register>(&self, offset: u64) -> *mut R {
+        let size = size_of::<R>() as u64;
+        assert!(
+            offset.checked_add(size).is_some_and(|end| end <= self.len),
+            "MMIO register at {offset:#x} is out of bounds"
+        );
+        let addr = self.base + offset;
+        assert!(
+            addr.is_aligned(size),
+            "MMIO register at {offset:#x} is not aligned"
+        );
+        addr.as_mut_ptr()
+    }
+}
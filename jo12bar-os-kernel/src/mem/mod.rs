@@ -1,6 +1,10 @@
 //! Memory setup, mapping, and allocation.
 
 pub mod allocator;
+pub mod mmio;
+pub mod stack;
+
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
 use mem_util::KiB;
@@ -9,6 +13,39 @@ use x86_64::{
     PhysAddr, VirtAddr,
 };
 
+use crate::prelude::*;
+
+/// The kernel's active page table.
+///
+/// # Safety
+/// This is initialized during [`crate::init()`], and must not be accessed before that.
+pub static PAGE_TABLE: UnwrapTicketLock<OffsetPageTable<'static>> =
+    unsafe { UnwrapTicketLock::new_uninit() };
+
+/// The kernel's physical frame allocator.
+///
+/// # Safety
+/// This is initialized during [`crate::init()`], and must not be accessed before that.
+pub static FRAME_ALLOCATOR: UnwrapTicketLock<BootInfoFrameAllocator> =
+    unsafe { UnwrapTicketLock::new_uninit() };
+
+/// Where the complete physical memory is mapped, set by [`init`].
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the virtual address at which the physical address `phys` is mapped.
+///
+/// Only physical memory that the bootloader reported in the memory map is
+/// mapped, which includes RAM and firmware tables but usually not device
+/// memory. Device memory is mapped using [`mmio`].
+///
+/// # Panics
+/// Panics if called before [`crate::init()`] initialized memory.
+pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+    assert_ne!(offset, 0, "physical memory is not mapped yet");
+    VirtAddr::new(offset + phys.as_u64())
+}
+
 /// Initialize a new [`OffsetPageTable`].
 ///
 /// # Safety
@@ -17,6 +54,8 @@ use x86_64::{
 /// - This function must only be called once to avoid aliasing `&mut` references
 ///   (which is undefined behaviour).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+
     let level_4_table = unsafe {
         // Safety:
         // - The caller needs to verify that physical_memory_offset is valid
@@ -68,6 +107,11 @@ pub struct BootInfoFrameAllocator {
     next: usize,
 }
 
+// Safety: The memory map is never modified after the bootloader hands it to us,
+// so sharing the reference between cores is fine, even though Rust can't tell
+// because `MemoryRegions` contains a raw pointer.
+unsafe impl Send for BootInfoFrameAllocator {}
+
 impl BootInfoFrameAllocator {
     /// Create a [`FrameAllocator`] from the passed memory map.
     ///
@@ -0,0 +1,157 @@
+//! Kernel stacks protected by guard pages.
+//!
+//! Each [`Stack`] lives in its own slice of the kernel stack region, and the page
+//! directly below it is left unmapped. Overflowing a stack therefore causes a
+//! page fault instead of silently corrupting whatever lives below it.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use mem_util::GiB;
+use thiserror::Error;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
+    },
+    VirtAddr,
+};
+
+use crate::prelude::*;
+
+/// Start (virtual) address of the region kernel stacks are allocated in.
+pub const STACKS_START: VirtAddr = VirtAddr::new(0x5555_0000_0000);
+/// Size of the region kernel stacks are allocated in.
+pub const STACKS_SIZE: u64 = GiB!(1);
+
+/// The number of unmapped guard pages below each stack.
+pub const GUARD_PAGE_COUNT: u64 = 1;
+
+/// The number of pages of the stack region that have been handed out so far.
+static NEXT_PAGE: AtomicU64 = AtomicU64::new(0);
+
+/// Stacks that have been freed. Their pages are still mapped, so they are
+/// handed out again before any new pages are mapped.
+static FREE_STACKS: TicketLock<Vec<StackRange>> = TicketLock::new(Vec::new());
+
+/// Errors that can occur when allocating a [`Stack`].
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum StackAllocError {
+    #[error("the kernel stack region is out of virtual address space")]
+    OutOfAddressSpace,
+    #[error("failed to map stack pages: {0:?}")]
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for StackAllocError {
+    fn from(value: MapToError<Size4KiB>) -> Self {
+        Self::Map(value)
+    }
+}
+
+/// The pages used by a [`Stack`], not including its guard pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StackRange {
+    /// The lowest mapped page of the stack.
+    bottom: Page,
+    /// The number of mapped pages.
+    page_count: u64,
+}
+
+/// A kernel stack with guard pages, allocated from the kernel stack region.
+///
+/// When dropped, the stack is kept mapped and reused by the next call to
+/// [`Stack::allocate()`] with the same size.
+#[derive(Debug)]
+pub struct Stack {
+    range: StackRange,
+}
+
+impl Stack {
+    /// Allocate and map a new stack that is `page_count` pages large.
+    pub fn allocate(page_count: u64) -> Result<Self, StackAllocError> {
+        {
+            let mut free_stacks = FREE_STACKS.lock();
+            if let Some(idx) = free_stacks.iter().position(|r| r.page_count == page_count) {
+                let range = free_stacks.swap_remove(idx);
+                return Ok(Self { range });
+            }
+        }
+
+        let slot_page_count = page_count + GUARD_PAGE_COUNT;
+        let slot_start = NEXT_PAGE.fetch_add(slot_page_count, Ordering::SeqCst);
+        if (slot_start + slot_page_count) * Size4KiB::SIZE > STACKS_SIZE {
+            return Err(StackAllocError::OutOfAddressSpace);
+        }
+
+        let bottom = Page::containing_address(
+            STACKS_START + (slot_start + GUARD_PAGE_COUNT) * Size4KiB::SIZE,
+        );
+        let range = StackRange { bottom, page_count };
+
+        let mut page_table = crate::mem::PAGE_TABLE.lock();
+        let mut frame_allocator = crate::mem::FRAME_ALLOCATOR.lock();
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        for page in Page::range(bottom, bottom + page_count) {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            // Safety: The page is part of the kernel stack region, which is only
+            // ever used by this module, and each page is handed out exactly once.
+            unsafe {
+                page_table
+                    .map_to(page, frame, flags, &mut *frame_allocator)?
+                    .flush();
+            }
+        }
+
+        Ok(Self { range })
+    }
+
+    /// The lowest address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        self.range.bottom.start_address()
+    }
+
+    /// The address one past the highest byte of the stack.
+    ///
+    /// This is where the stack pointer starts, since the stack grows downwards.
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + self.range.page_count * Size4KiB::SIZE
+    }
+
+    /// The number of usable pages in this stack.
+    pub fn page_count(&self) -> u64 {
+        self.range.page_count
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        FREE_STACKS.lock().push(self.range);
+    }
+}
+
+/// Returns `true` if `addr` points into the guard pages of a kernel stack.
+///
+/// This is used by the page fault handler to report stack overflows.
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    if addr < STACKS_START || addr >= STACKS_START + STACKS_SIZE {
+        return false;
+    }
+
+    let page_offset = (addr - STACKS_START) / Size4KiB::SIZE;
+    let allocated_pages = NEXT_PAGE.load(Ordering::Relaxed);
+    if page_offset >= allocated_pages {
+        return false;
+    }
+
+    // Every page that has been handed out is mapped, except for guard pages.
+    // Don't block here, since this is called from the page fault handler.
+    let Some(page_table) = crate::mem::PAGE_TABLE.try_lock() else {
+        return false;
+    };
+    let page = Page::<Size4KiB>::containing_address(addr);
+    page_table.translate_page(page).is_err()
+}
@@ -0,0 +1,299 @@
+//! The kernel's panic handling.
+//!
+//! [`handle_panic`] stops all other cores, writes a report with the state of the
+//! panicking core to the log, and shows it on a "kernel panic" screen.
+//!
+//! There is no way to interrupt other cores yet, so they are stopped by their
+//! next timer interrupt, which calls [`halt_if_panicking`].
+
+use core::{
+    arch::asm,
+    fmt::{self, Write},
+    panic::PanicInfo,
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+use embedded_graphics::{mono_font::ascii::FONT_8X13, prelude::*};
+use log::LevelFilter;
+use uart_16550::SerialPort;
+use x86_64::registers::{
+    control::{Cr0, Cr0Flags, Cr2, Cr3, Cr4, Cr4Flags},
+    rflags::{self, RFlags},
+};
+
+use crate::{
+    cpu::{self, halt},
+    graphics::{
+        canvas::{CanvasWriter, CanvasWriterScrollBehaviour},
+        framebuffer::{startup::HARDWARE_FRAMEBUFFER_START_INFO, Framebuffer},
+        tty::color,
+    },
+    locals,
+    logger::LOGGER,
+    symbols::{self, SymbolizedAddress},
+    DEFAULT_STACK_SIZE,
+};
+
+/// The maximum number of frames in a [`Backtrace`].
+pub const MAX_BACKTRACE_FRAMES: usize = 32;
+
+/// The APIC ID of the core that is handling a panic, or `!0` if there is no
+/// panic.
+static PANICKING_CORE: AtomicU16 = AtomicU16::new(!0);
+
+/// Returns `true` once any core has panicked.
+pub fn is_panicking() -> bool {
+    PANICKING_CORE.load(Ordering::Relaxed) != !0
+}
+
+/// Stop the current core if another core panicked.
+///
+/// Called by the timer interrupt handler.
+pub fn halt_if_panicking() {
+    if is_panicking() {
+        // Safety: The kernel is going down anyways.
+        unsafe { cpu::disable_interrupts() };
+        halt();
+    }
+}
+
+/// Handle a panic. Called by the `#[panic_handler]`.
+///
+/// If the `panic-exit-qemu` feature is set, this exits QEMU with
+/// [`QemuExitCode::Failure`][crate::QemuExitCode::Failure] instead of halting.
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    // Safety: The kernel is going down anyways.
+    unsafe { cpu::disable_interrupts() };
+
+    // The core locals might be what panicked, so they are only used once this
+    // core is known to be the only one handling a panic.
+    let core = u16::from(cpu::apic_id());
+    match PANICKING_CORE.compare_exchange(!0, core, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => {}
+        Err(other) if other == core => {
+            // Panicked while handling a panic. Don't touch anything that might
+            // have caused that.
+            // Safety: 0x3F8 is the first serial port. This might garble output
+            // that is being written, but the kernel is going down anyways.
+            let mut serial = unsafe { SerialPort::new(0x3F8) };
+            let _ = writeln!(serial, "\nnested kernel panic: {info}");
+            exit();
+        }
+        // Another core is already handling its panic.
+        Err(_) => halt(),
+    }
+
+    // Safety: We're in the panic handler, and all other cores will stop.
+    unsafe { LOGGER.force_unlock() };
+
+    // The panic screen takes over the framebuffer.
+    LOGGER.set_target_level("framebuffer", LevelFilter::Off);
+
+    let report = PanicReport::capture(info);
+    log::error!("{report}");
+
+    // Safety: We're the only core still running, and the framebuffer logger is
+    // disabled.
+    if let Some(mut writer) = unsafe { panic_screen_writer() } {
+        let _ = report.write_screen(&mut writer);
+    }
+
+    exit();
+}
+
+/// Exit QEMU if the `panic-exit-qemu` feature is set, or halt otherwise.
+fn exit() -> ! {
+    #[cfg(feature = "panic-exit-qemu")]
+    // Safety: The feature should only be set when running in QEMU.
+    unsafe {
+        crate::exit_qemu(crate::QemuExitCode::Failure);
+    }
+
+    halt();
+}
+
+/// Create a new [`CanvasWriter`] for the hardware framebuffer.
+///
+/// # Safety
+/// Nothing else may use the hardware framebuffer from now on.
+unsafe fn panic_screen_writer() -> Option<CanvasWriter<'static, Framebuffer>> {
+    // Safety: only written while booting.
+    let (start, info) = unsafe { HARDWARE_FRAMEBUFFER_START_INFO }?;
+    // Safety: see above
+    let mut fb = unsafe { Framebuffer::new_at_virt_addr(start, info) };
+    fb.clear(color::DEFAULT_BACKGROUND).ok()?;
+
+    CanvasWriter::builder()
+        .font(FONT_8X13)
+        .canvas(fb)
+        .margin_left(10)
+        .margin_right(10)
+        .margin_top(10)
+        .margin_bottom(10)
+        .background_color(color::DEFAULT_BACKGROUND)
+        .scroll_behaviour(CanvasWriterScrollBehaviour::Clear)
+        .log_errors(false)
+        .build()
+        .ok()
+}
+
+/// The state of the panicking core.
+pub struct PanicReport<'a> {
+    info: &'a PanicInfo<'a>,
+    core: u8,
+    interrupt_depth: u64,
+    in_exception: bool,
+    cr0: Cr0Flags,
+    cr2: Option<u64>,
+    cr3: u64,
+    cr4: Cr4Flags,
+    rflags: RFlags,
+    backtrace: Backtrace,
+}
+
+impl<'a> PanicReport<'a> {
+    /// Capture the state of the current core.
+    #[inline(always)]
+    pub fn capture(info: &'a PanicInfo<'a>) -> Self {
+        let locals = locals!();
+        Self {
+            info,
+            core: locals.core_id.0,
+            interrupt_depth: locals.interrupt_depth(),
+            in_exception: locals.in_exception(),
+            cr0: Cr0::read(),
+            cr2: Cr2::read().ok().map(|addr| addr.as_u64()),
+            cr3: Cr3::read().0.start_address().as_u64(),
+            cr4: Cr4::read(),
+            rflags: rflags::read(),
+            backtrace: Backtrace::capture(),
+        }
+    }
+
+    /// Write the report framed by a box, as shown on the panic screen.
+    fn write_screen(&self, writer: &mut impl Write) -> fmt::Result {
+        const SGR_RED: &str = "\x1b[31m";
+        const SGR_RESET: &str = "\x1b[0m";
+        const RULE: &str =
+            "+------------------------------------------------------------------------------+";
+
+        writeln!(writer, "{SGR_RED}{RULE}")?;
+        writeln!(writer, "| {:^76} |", "KERNEL PANIC")?;
+        writeln!(writer, "{RULE}{SGR_RESET}")?;
+        writeln!(writer)?;
+        writeln!(writer, "{self}")?;
+        writeln!(writer)?;
+        writeln!(writer, "{SGR_RED}{RULE}{SGR_RESET}")?;
+        write!(writer, "The system has been halted.")
+    }
+}
+
+impl fmt::Display for PanicReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "kernel panic on core {}: {}", self.core, self.info)?;
+        writeln!(
+            f,
+            "interrupt depth: {}, in exception: {}",
+            self.interrupt_depth, self.in_exception
+        )?;
+        writeln!(f, "CR0: {:#018x} {:?}", self.cr0.bits(), self.cr0)?;
+        match self.cr2 {
+            Some(cr2) => writeln!(f, "CR2: {cr2:#018x}")?,
+            None => writeln!(f, "CR2: <non-canonical>")?,
+        }
+        writeln!(f, "CR3: {:#018x}", self.cr3)?;
+        writeln!(f, "CR4: {:#018x} {:?}", self.cr4.bits(), self.cr4)?;
+        writeln!(f, "RFLAGS: {:#018x} {:?}", self.rflags.bits(), self.rflags)?;
+        writeln!(
+            f,
+            "kernel image offset: {:#x}",
+            symbols::kernel_image_offset()
+        )?;
+        write!(f, "{}", self.backtrace)
+    }
+}
+
+/// Return addresses of the current call stack.
+///
+/// Formatting this using [`Display`][fmt::Display] prints the function each
+/// frame returns to, see [`symbols`].
+///
+/// Walks the chain of saved frame pointers, so it needs the kernel to be built
+/// with `-C force-frame-pointers=yes`.
+#[derive(Clone)]
+pub struct Backtrace {
+    frames: [u64; MAX_BACKTRACE_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// Capture the backtrace of the caller.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let rbp: u64;
+        // Safety: Only reads a register.
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+        // Safety: rbp is the current frame pointer.
+        unsafe { Self::from_frame_pointer(rbp) }
+    }
+
+    /// Walk the frame pointer chain starting at `rbp`.
+    ///
+    /// # Safety
+    /// `rbp` must be a frame pointer of the current stack.
+    pub unsafe fn from_frame_pointer(mut rbp: u64) -> Self {
+        let mut backtrace = Self {
+            frames: [0; MAX_BACKTRACE_FRAMES],
+            len: 0,
+        };
+
+        while backtrace.len < MAX_BACKTRACE_FRAMES && rbp != 0 && rbp % 8 == 0 {
+            // Safety: Each frame starts with the caller's frame pointer, followed
+            // by the return address.
+            let (next, return_address) = unsafe {
+                let frame = rbp as *const u64;
+                (frame.read(), frame.add(1).read())
+            };
+            if return_address == 0 {
+                break;
+            }
+            backtrace.frames[backtrace.len] = return_address;
+            backtrace.len += 1;
+
+            // The stack grows down, so callers have higher frame pointers. Anything
+            // else means the chain is corrupted, or we reached the end.
+            if next <= rbp || next - rbp > DEFAULT_STACK_SIZE {
+                break;
+            }
+            rbp = next;
+        }
+
+        backtrace
+    }
+
+    /// Returns the return addresses, innermost first.
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "backtrace:")?;
+        if self.len == 0 {
+            return write!(f, " <empty>");
+        }
+        for (idx, &address) in self.frames().iter().enumerate() {
+            let address = SymbolizedAddress::return_address(address);
+            write!(f, "\n  {idx:>2}: {address}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.frames()).finish()
+    }
+}
@@ -0,0 +1,263 @@
+//! Access to the configuration space of PCI functions.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use log::warn;
+use mem_util::MiB;
+use x86_64::{instructions::port::Port, PhysAddr};
+
+use super::PciAddress;
+use crate::{acpi, mem::mmio::Mmio, prelude::*};
+
+/// Vendor ID, 16 bits. `0xffff` if the function doesn't exist.
+pub const VENDOR_ID: u16 = 0x00;
+/// Device ID, 16 bits.
+pub const DEVICE_ID: u16 = 0x02;
+/// Command register, 16 bits.
+pub const COMMAND: u16 = 0x04;
+/// Status register, 16 bits.
+pub const STATUS: u16 = 0x06;
+/// Revision ID, 8 bits, followed by the programming interface, subclass and
+/// class code.
+pub const REVISION_ID: u16 = 0x08;
+/// Header type, 8 bits. Bit 7 is set for multi-function devices.
+pub const HEADER_TYPE: u16 = 0x0e;
+/// The first base address register.
+pub const BAR0: u16 = 0x10;
+/// Secondary bus number of PCI-to-PCI bridges, 8 bits.
+pub const SECONDARY_BUS: u16 = 0x19;
+/// Offset of the first capability, 8 bits.
+pub const CAPABILITIES_POINTER: u16 = 0x34;
+
+/// Respond to I/O space accesses.
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+/// Respond to memory space accesses.
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+/// Allow the function to access memory itself, which includes sending MSIs.
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+/// Don't assert the legacy INTx interrupt line.
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+/// The function has a list of capabilities.
+pub const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// A way to access the configuration space of PCI functions.
+///
+/// Offsets must be 4-byte aligned. Reading from functions that don't exist
+/// returns all ones.
+pub trait ConfigAccess: Send + Sync {
+    /// Read the 32-bit register at `offset`.
+    fn read(&self, address: PciAddress, offset: u16) -> u32;
+
+    /// Write the 32-bit register at `offset`.
+    fn write(&self, address: PciAddress, offset: u16, value: u32);
+
+    /// A short name for log messages.
+    fn name(&self) -> &'static str;
+}
+
+/// Configuration access through the I/O ports `0xcf8` and `0xcfc`.
+///
+/// This only reaches the first 256 bytes of the configuration space, and only
+/// segment 0, but works on every PC.
+pub struct LegacyAccess {
+    /// The address and data ports, which are used together. Interrupts are
+    /// disabled while they are locked, so that drivers can access the
+    /// configuration space from interrupt handlers.
+    ports: TicketLock<(Port<u32>, Port<u32>)>,
+}
+
+impl LegacyAccess {
+    /// The size of the configuration space reachable through the ports.
+    const CONFIG_SIZE: u16 = 256;
+
+    /// Create the access.
+    ///
+    /// # Safety
+    /// Nothing else may use the ports `0xcf8` and `0xcfc`.
+    pub unsafe fn new() -> Self {
+        Self {
+            ports: TicketLock::new_non_preemtable((Port::new(0xcf8), Port::new(0xcfc))),
+        }
+    }
+
+    /// Returns the value to write to the address port, or [`None`] if the
+    /// register can't be reached.
+    fn port_address(address: PciAddress, offset: u16) -> Option<u32> {
+        assert!(
+            offset.is_multiple_of(4),
+            "unaligned PCI config access at {offset:#x}"
+        );
+        if address.segment != 0 || offset >= Self::CONFIG_SIZE {
+            return None;
+        }
+        Some(
+            1 << 31
+                | u32::from(address.bus) << 16
+                | u32::from(address.device) << 11
+                | u32::from(address.function) << 8
+                | u32::from(offset),
+        )
+    }
+}
+
+impl ConfigAccess for LegacyAccess {
+    fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        let Some(port_address) = Self::port_address(address, offset) else {
+            return u32::MAX;
+        };
+        let mut ports = self.ports.lock();
+        // Safety: We own the ports, and the address port was written with a
+        // valid configuration address first.
+        unsafe {
+            ports.0.write(port_address);
+            ports.1.read()
+        }
+    }
+
+    fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        let Some(port_address) = Self::port_address(address, offset) else {
+            return;
+        };
+        let mut ports = self.ports.lock();
+        // Safety: see above
+        unsafe {
+            ports.0.write(port_address);
+            ports.1.write(value);
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "legacy"
+    }
+}
+
+/// An entry of the ACPI MCFG table: the physical memory that the
+/// configuration spaces of a range of buses are mapped to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EcamRegion {
+    /// The address of the configuration space of bus 0, even if that bus is
+    /// not part of the region.
+    pub base: PhysAddr,
+    /// The PCI segment group.
+    pub segment: u16,
+    /// The first bus of the region.
+    pub start_bus: u8,
+    /// The last bus of the region.
+    pub end_bus: u8,
+}
+
+/// Memory-mapped configuration access ("enhanced configuration access
+/// mechanism"), described by the ACPI MCFG table.
+///
+/// Each function has 4 KiB of configuration space. The space of each bus is
+/// mapped when it is first accessed.
+pub struct Ecam {
+    regions: Vec<EcamRegion>,
+    /// The mapped buses. Like the ports of [`LegacyAccess`], this is locked
+    /// with interrupts disabled.
+    buses: TicketLock<BTreeMap<(u16, u8), Mmio>>,
+}
+
+impl Ecam {
+    /// The size of the configuration space of one bus.
+    const BUS_SIZE: u64 = MiB!(1);
+
+    /// Create the access from the MCFG table, or return [`None`] if there is
+    /// no usable MCFG table.
+    pub fn from_acpi() -> Option<Self> {
+        /// The MCFG table has 8 reserved bytes after the header.
+        const ENTRIES_OFFSET: usize = acpi::HEADER_LEN + 8;
+        const ENTRY_LEN: usize = 16;
+
+        let mcfg = match acpi::find_table("MCFG") {
+            Ok(mcfg) => mcfg,
+            Err(e) => {
+                warn!("No memory-mapped PCI configuration space: {e}");
+                return None;
+            }
+        };
+        let regions: Vec<EcamRegion> = mcfg
+            .get(ENTRIES_OFFSET..)?
+            .as_chunks::<ENTRY_LEN>()
+            .0
+            .iter()
+            .map(|entry| EcamRegion {
+                base: PhysAddr::new(u64::from_le_bytes(entry[0..8].try_into().unwrap())),
+                segment: u16::from_le_bytes(entry[8..10].try_into().unwrap()),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+            .filter(|region| region.start_bus <= region.end_bus)
+            .collect();
+
+        (!regions.is_empty()).then(|| Self {
+            regions,
+            buses: TicketLock::new_non_preemtable(BTreeMap::new()),
+        })
+    }
+
+    /// Returns the regions this access covers.
+    pub fn regions(&self) -> &[EcamRegion] {
+        &self.regions
+    }
+
+    /// Returns the mapped configuration space of the bus of `address`, or
+    /// [`None`] if no region covers the bus.
+    fn bus(&self, address: PciAddress) -> Option<Mmio> {
+        let key = (address.segment, address.bus);
+        if let Some(&bus) = self.buses.lock().get(&key) {
+            return Some(bus);
+        }
+
+        let region = self.regions.iter().find(|region| {
+            region.segment == address.segment
+                && (region.start_bus..=region.end_bus).contains(&address.bus)
+        })?;
+
+        // Mapping takes the page table lock, so it happens outside of our own
+        // lock. If two cores map the same bus, one of the mappings is unused.
+        // Safety: The firmware guarantees that the region is device memory.
+        let mapped = unsafe {
+            Mmio::map(
+                region.base + u64::from(address.bus) * Self::BUS_SIZE,
+                Self::BUS_SIZE,
+            )
+        };
+        match mapped {
+            Ok(bus) => Some(*self.buses.lock().entry(key).or_insert(bus)),
+            Err(e) => {
+                warn!("Failed to map the configuration space of PCI bus {key:x?}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Returns the offset of the register within the space of its bus.
+    fn bus_offset(address: PciAddress, offset: u16) -> u64 {
+        assert!(
+            offset.is_multiple_of(4),
+            "unaligned PCI config access at {offset:#x}"
+        );
+        (u64::from(address.device) << 15 | u64::from(address.function) << 12) + u64::from(offset)
+    }
+}
+
+impl ConfigAccess for Ecam {
+    fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        match self.bus(address) {
+            Some(bus) => bus.read(Self::bus_offset(address, offset)),
+            None => u32::MAX,
+        }
+    }
+
+    fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        if let Some(bus) = self.bus(address) {
+            bus.write(Self::bus_offset(address, offset), value);
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "ECAM"
+    }
+}
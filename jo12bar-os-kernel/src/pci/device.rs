@@ -0,0 +1,379 @@
+//! PCI functions and their base address registers and capabilities.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use x86_64::PhysAddr;
+
+use super::{config, PciAddress, PciError};
+use crate::mem::mmio::Mmio;
+
+/// The class of a function, which says what kind of device it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClassCode {
+    /// The base class, e.g. `0x01` for mass storage controllers.
+    pub class: u8,
+    /// The subclass, e.g. `0x06` for SATA controllers.
+    pub subclass: u8,
+    /// The programming interface, e.g. `0x01` for AHCI.
+    pub prog_if: u8,
+}
+
+impl ClassCode {
+    /// Returns a name for the class and subclass, if they are well known.
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "mass storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "network controller",
+            (0x03, 0x00) => "VGA controller",
+            (0x03, _) => "display controller",
+            (0x04, _) => "multimedia controller",
+            (0x05, _) => "memory controller",
+            (0x06, 0x00) => "host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "bridge",
+            (0x07, _) => "communication controller",
+            (0x08, _) => "system peripheral",
+            (0x0c, 0x03) => "USB controller",
+            (0x0c, 0x05) => "SMBus controller",
+            (0x0c, _) => "serial bus controller",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for ClassCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}",
+            self.class, self.subclass, self.prog_if
+        )?;
+        if let Some(name) = self.name() {
+            write!(f, " ({name})")?;
+        }
+        Ok(())
+    }
+}
+
+/// A decoded base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    /// A region of memory.
+    Memory {
+        /// The physical address of the region.
+        address: PhysAddr,
+        /// The size of the region in bytes.
+        size: u64,
+        /// Reads have no side effects, so the region may be cached.
+        prefetchable: bool,
+        /// The BAR uses the next register for the upper half of the address.
+        is_64bit: bool,
+    },
+    /// A range of I/O ports.
+    Io {
+        /// The first port.
+        port: u16,
+        /// The number of ports.
+        size: u16,
+    },
+}
+
+/// A function of a PCI device, found by [enumeration][super::init].
+///
+/// Most devices have a single function, so the PCI specification's "function"
+/// is what drivers usually think of as a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    /// Where the function is.
+    pub address: PciAddress,
+    /// The vendor ID.
+    pub vendor_id: u16,
+    /// The device ID, assigned by the vendor.
+    pub device_id: u16,
+    /// The class code.
+    pub class: ClassCode,
+    /// The revision, assigned by the vendor.
+    pub revision: u8,
+    /// The header type, without the multi-function bit. `0` for normal
+    /// functions, `1` for PCI-to-PCI bridges.
+    pub header_type: u8,
+}
+
+impl PciDevice {
+    /// The capability ID of MSI.
+    pub const CAPABILITY_MSI: u8 = 0x05;
+    /// The capability ID of vendor-specific capabilities.
+    pub const CAPABILITY_VENDOR: u8 = 0x09;
+    /// The capability ID of PCI Express.
+    pub const CAPABILITY_PCI_EXPRESS: u8 = 0x10;
+    /// The capability ID of MSI-X.
+    pub const CAPABILITY_MSIX: u8 = 0x11;
+
+    /// Read the function at `address`, or return [`None`] if there is none.
+    pub(super) fn probe(address: PciAddress) -> Option<Self> {
+        let id = super::read_config(address, config::VENDOR_ID);
+        let vendor_id = id as u16;
+        if vendor_id == 0xffff {
+            return None;
+        }
+
+        let class = super::read_config(address, config::REVISION_ID);
+        let header_type = super::read_config(address, config::HEADER_TYPE & !3) >> 16;
+        Some(Self {
+            address,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class: ClassCode {
+                class: (class >> 24) as u8,
+                subclass: (class >> 16) as u8,
+                prog_if: (class >> 8) as u8,
+            },
+            revision: class as u8,
+            header_type: header_type as u8 & 0x7f,
+        })
+    }
+
+    /// Returns `true` if the device has more than one function. Only
+    /// meaningful for function 0.
+    pub fn is_multi_function(&self) -> bool {
+        self.read_u8(config::HEADER_TYPE) & 0x80 != 0
+    }
+
+    /// Returns `true` if this is a PCI-to-PCI bridge.
+    pub fn is_bridge(&self) -> bool {
+        self.header_type == 1
+    }
+
+    /// Returns the bus behind this bridge.
+    pub fn secondary_bus(&self) -> Option<u8> {
+        self.is_bridge()
+            .then(|| self.read_u8(config::SECONDARY_BUS))
+    }
+
+    /// Read the 32-bit register at `offset` in the configuration space.
+    ///
+    /// # Panics
+    /// Panics if `offset` is not 4-byte aligned.
+    pub fn read_u32(&self, offset: u16) -> u32 {
+        super::read_config(self.address, offset)
+    }
+
+    /// Read the 16-bit register at `offset` in the configuration space.
+    ///
+    /// # Panics
+    /// Panics if `offset` is not 2-byte aligned.
+    pub fn read_u16(&self, offset: u16) -> u16 {
+        assert!(
+            offset.is_multiple_of(2),
+            "unaligned PCI config access at {offset:#x}"
+        );
+        (self.read_u32(offset & !3) >> ((offset & 3) * 8)) as u16
+    }
+
+    /// Read the 8-bit register at `offset` in the configuration space.
+    pub fn read_u8(&self, offset: u16) -> u8 {
+        (self.read_u32(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Write the 32-bit register at `offset` in the configuration space.
+    ///
+    /// # Panics
+    /// Panics if `offset` is not 4-byte aligned.
+    pub fn write_u32(&self, offset: u16, value: u32) {
+        super::write_config(self.address, offset, value);
+    }
+
+    /// Write the 16-bit register at `offset` in the configuration space.
+    ///
+    /// Configuration space can only be written 32 bits at a time, so this
+    /// writes back the other half of the register as it was read. Use
+    /// [`set_command`][Self::set_command] for the command register, since its
+    /// other half is the status register, whose bits are cleared by writing
+    /// them.
+    ///
+    /// # Panics
+    /// Panics if `offset` is not 2-byte aligned.
+    pub fn write_u16(&self, offset: u16, value: u16) {
+        assert!(
+            offset.is_multiple_of(2),
+            "unaligned PCI config access at {offset:#x}"
+        );
+        let shift = (offset & 3) * 8;
+        let old = self.read_u32(offset & !3) & !(0xffff << shift);
+        self.write_u32(offset & !3, old | u32::from(value) << shift);
+    }
+
+    /// Returns the command register.
+    pub fn command(&self) -> u16 {
+        self.read_u16(config::COMMAND)
+    }
+
+    /// Write the command register.
+    pub fn set_command(&self, command: u16) {
+        // Writing zeroes to the status register leaves it alone.
+        self.write_u32(config::COMMAND, command.into());
+    }
+
+    /// Set and clear bits of the command register.
+    pub fn update_command(&self, set: u16, clear: u16) {
+        self.set_command(self.command() & !clear | set);
+    }
+
+    /// Let the function access memory, which DMA and MSIs need.
+    pub fn enable_bus_master(&self) {
+        self.update_command(config::COMMAND_BUS_MASTER, 0);
+    }
+
+    /// Returns the number of base address registers in the header.
+    pub fn bar_count(&self) -> u8 {
+        match self.header_type {
+            0 => 6,
+            1 => 2,
+            _ => 0,
+        }
+    }
+
+    /// Decode the base address register `index`, or return [`None`] if the
+    /// function doesn't use it.
+    ///
+    /// The size is found by writing all ones to the register, during which
+    /// decoding is disabled. `index` must not be the upper half of a 64-bit
+    /// BAR, use [`bars`][Self::bars] to skip those.
+    pub fn bar(&self, index: u8) -> Result<Option<Bar>, PciError> {
+        if index >= self.bar_count() {
+            return Err(PciError::InvalidBar(index));
+        }
+        let offset = config::BAR0 + u16::from(index) * 4;
+        let low = self.read_u32(offset);
+
+        if low & 1 != 0 {
+            let size_mask = self.probe_bar(offset, low) & !3;
+            let size = (!size_mask).wrapping_add(1) as u16;
+            return Ok((size_mask != 0 && size != 0).then_some(Bar::Io {
+                port: (low & !3) as u16,
+                size,
+            }));
+        }
+
+        let is_64bit = match (low >> 1) & 3 {
+            0 => false,
+            2 => true,
+            _ => return Err(PciError::InvalidBar(index)),
+        };
+        let mut address = u64::from(low & !0xf);
+        let mut size_mask = u64::from(self.probe_bar(offset, low) & !0xf);
+        if is_64bit {
+            if index + 1 >= self.bar_count() {
+                return Err(PciError::InvalidBar(index));
+            }
+            let high = self.read_u32(offset + 4);
+            address |= u64::from(high) << 32;
+            size_mask |= u64::from(self.probe_bar(offset + 4, high)) << 32;
+        }
+        if size_mask == 0 {
+            return Ok(None);
+        }
+        if !is_64bit {
+            size_mask |= 0xffff_ffff << 32;
+        }
+
+        Ok(Some(Bar::Memory {
+            address: PhysAddr::try_new(address).map_err(|_| PciError::InvalidBar(index))?,
+            size: (!size_mask).wrapping_add(1),
+            prefetchable: low & (1 << 3) != 0,
+            is_64bit,
+        }))
+    }
+
+    /// Returns the index and decoded value of all used base address
+    /// registers.
+    pub fn bars(&self) -> Result<Vec<(u8, Bar)>, PciError> {
+        let mut bars = Vec::new();
+        let mut index = 0;
+        while index < self.bar_count() {
+            let bar = self.bar(index)?;
+            let next = match bar {
+                Some(Bar::Memory { is_64bit: true, .. }) => index + 2,
+                _ => index + 1,
+            };
+            if let Some(bar) = bar {
+                bars.push((index, bar));
+            }
+            index = next;
+        }
+        Ok(bars)
+    }
+
+    /// Write all ones to the BAR register at `offset`, and return what was
+    /// read back before restoring `original`.
+    fn probe_bar(&self, offset: u16, original: u32) -> u32 {
+        let command = self.command();
+        self.set_command(command & !(config::COMMAND_IO_SPACE | config::COMMAND_MEMORY_SPACE));
+
+        self.write_u32(offset, u32::MAX);
+        let mask = self.read_u32(offset);
+        self.write_u32(offset, original);
+
+        self.set_command(command);
+        mask
+    }
+
+    /// Map the memory BAR `index`, and enable memory decoding.
+    ///
+    /// Every call creates a new mapping, so drivers should map each BAR once.
+    pub fn map_bar(&self, index: u8) -> Result<Mmio, PciError> {
+        let Bar::Memory { address, size, .. } =
+            self.bar(index)?.ok_or(PciError::InvalidBar(index))?
+        else {
+            return Err(PciError::NotMemoryBar(index));
+        };
+
+        // Safety: The firmware placed the BAR in device memory.
+        let mmio = unsafe { Mmio::map(address, size) }?;
+        self.update_command(config::COMMAND_MEMORY_SPACE, 0);
+        Ok(mmio)
+    }
+
+    /// Returns the ID and offset of every capability.
+    pub fn capabilities(&self) -> Vec<(u8, u16)> {
+        /// Bounds the walk in case the list loops.
+        const MAX_CAPABILITIES: usize = 48;
+
+        let mut capabilities = Vec::new();
+        if self.read_u16(config::STATUS) & config::STATUS_CAPABILITIES == 0 {
+            return capabilities;
+        }
+
+        let mut offset = u16::from(self.read_u8(config::CAPABILITIES_POINTER) & !3);
+        while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+            let header = self.read_u16(offset);
+            capabilities.push((header as u8, offset));
+            offset = (header >> 8) & !3;
+        }
+        capabilities
+    }
+
+    /// Returns the offset of the first capability with the ID `id`.
+    pub fn find_capability(&self, id: u8) -> Option<u16> {
+        self.capabilities()
+            .into_iter()
+            .find(|&(cap_id, _)| cap_id == id)
+            .map(|(_, offset)| offset)
+    }
+}
+
+impl fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{:04x}:{:04x}] rev {:02x}, class {}",
+            self.address, self.vendor_id, self.device_id, self.revision, self.class
+        )
+    }
+}
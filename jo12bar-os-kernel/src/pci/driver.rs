@@ -0,0 +1,152 @@
+//! Matching drivers to PCI functions.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use log::{info, warn};
+
+use super::{PciAddress, PciDevice, PciError};
+use crate::prelude::*;
+
+/// The registered drivers.
+static DRIVERS: TicketLock<Vec<&'static Driver>> = TicketLock::new(Vec::new());
+/// The name of the driver bound to each function.
+static BINDINGS: TicketLock<BTreeMap<PciAddress, &'static str>> = TicketLock::new(BTreeMap::new());
+
+/// Which functions a [`Driver`] supports. Fields that are [`None`] match
+/// anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeviceId {
+    /// The vendor ID.
+    pub vendor_id: Option<u16>,
+    /// The device ID.
+    pub device_id: Option<u16>,
+    /// The base class.
+    pub class: Option<u8>,
+    /// The subclass.
+    pub subclass: Option<u8>,
+    /// The programming interface.
+    pub prog_if: Option<u8>,
+}
+
+impl DeviceId {
+    /// Match a specific device of a vendor.
+    pub const fn device(vendor_id: u16, device_id: u16) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            device_id: Some(device_id),
+            class: None,
+            subclass: None,
+            prog_if: None,
+        }
+    }
+
+    /// Match all functions of a class and subclass.
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Self {
+            vendor_id: None,
+            device_id: None,
+            class: Some(class),
+            subclass: Some(subclass),
+            prog_if: None,
+        }
+    }
+
+    /// Only match functions with the programming interface `prog_if`.
+    pub const fn with_prog_if(mut self, prog_if: u8) -> Self {
+        self.prog_if = Some(prog_if);
+        self
+    }
+
+    /// Returns `true` if `device` matches.
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        fn field<T: PartialEq>(expected: Option<T>, actual: T) -> bool {
+            expected.is_none_or(|expected| expected == actual)
+        }
+
+        field(self.vendor_id, device.vendor_id)
+            && field(self.device_id, device.device_id)
+            && field(self.class, device.class.class)
+            && field(self.subclass, device.class.subclass)
+            && field(self.prog_if, device.class.prog_if)
+    }
+}
+
+/// A driver for PCI functions.
+#[derive(Debug)]
+pub struct Driver {
+    /// The name of the driver, for log messages.
+    pub name: &'static str,
+    /// The functions the driver supports.
+    pub ids: &'static [DeviceId],
+    /// Set up a matching function, which the driver then owns. If this fails,
+    /// other drivers may try the function.
+    pub probe: fn(&PciDevice) -> Result<(), PciError>,
+}
+
+impl Driver {
+    /// Returns `true` if the driver supports `device`.
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        self.ids.iter().any(|id| id.matches(device))
+    }
+}
+
+/// Register `driver`, and probe it with all matching functions that don't
+/// have a driver yet.
+///
+/// Drivers registered before [`pci::init`][super::init] are probed once
+/// enumeration is done.
+pub fn register_driver(driver: &'static Driver) {
+    DRIVERS.lock().push(driver);
+    for device in super::devices() {
+        try_bind(driver, device);
+    }
+}
+
+/// Returns the name of the driver bound to the function at `address`.
+pub fn driver_of(address: PciAddress) -> Option<&'static str> {
+    BINDINGS.lock().get(&address).copied()
+}
+
+/// Probe all registered drivers with all functions.
+pub(super) fn bind_all() {
+    let drivers = DRIVERS.lock().clone();
+    for device in super::devices() {
+        for &driver in &drivers {
+            if try_bind(driver, device) {
+                break;
+            }
+        }
+    }
+}
+
+/// Probe `driver` with `device` if it matches and is not bound. Returns `true`
+/// if the driver is now bound to the device.
+fn try_bind(driver: &'static Driver, device: &PciDevice) -> bool {
+    if !driver.matches(device) {
+        return false;
+    }
+
+    // Claim the device first, so that it is only probed by one driver at a time.
+    {
+        let mut bindings = BINDINGS.lock();
+        if bindings.contains_key(&device.address) {
+            return false;
+        }
+        bindings.insert(device.address, driver.name);
+    }
+
+    match (driver.probe)(device) {
+        Ok(()) => {
+            info!("PCI {}: bound to {}", device.address, driver.name);
+            true
+        }
+        Err(e) => {
+            warn!(
+                "PCI {}: {} failed to probe: {e}",
+                device.address, driver.name
+            );
+            BINDINGS.lock().remove(&device.address);
+            false
+        }
+    }
+}
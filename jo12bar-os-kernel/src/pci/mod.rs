@@ -0,0 +1,223 @@
+//! The PCI bus.
+//!
+//! [`init`] enumerates all PCI functions, accessing their configuration space
+//! through [ECAM][config::Ecam] if the ACPI MCFG table describes it, and
+//! through the [legacy I/O ports][config::LegacyAccess] otherwise. Drivers
+//! are matched to functions by vendor, device and class in the
+//! [driver registry][driver], and can map the functions'
+//! [BARs][PciDevice::map_bar] and set up [MSIs][msi].
+
+use alloc::{boxed::Box, collections::BTreeSet, vec::Vec};
+use core::fmt;
+
+use log::{debug, info};
+use thiserror::Error;
+
+use crate::{interrupts::InterruptError, mem::mmio::MmioError, prelude::*};
+
+pub mod config;
+pub mod device;
+pub mod driver;
+pub mod msi;
+
+pub use device::{Bar, ClassCode, PciDevice};
+pub use driver::{register_driver, DeviceId, Driver};
+
+use config::{ConfigAccess, Ecam, LegacyAccess};
+
+/// How the configuration space is accessed, chosen by [`init`].
+static CONFIG_ACCESS: SpinOnce<Box<dyn ConfigAccess>> = SpinOnce::new();
+/// All functions, found by [`init`].
+static DEVICES: SpinOnce<Vec<PciDevice>> = SpinOnce::new();
+
+/// Errors returned by PCI functions and drivers.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum PciError {
+    #[error("BAR {0} does not exist or is invalid")]
+    InvalidBar(u8),
+    #[error("BAR {0} is not a memory BAR")]
+    NotMemoryBar(u8),
+    #[error("the function has no {0} capability")]
+    MissingCapability(&'static str),
+    #[error("MSI-X entry {0} does not exist")]
+    InvalidMsixEntry(u16),
+    #[error("mapping failed: {0}")]
+    Map(MmioError),
+    #[error("{0}")]
+    Interrupt(InterruptError),
+}
+
+impl From<MmioError> for PciError {
+    fn from(value: MmioError) -> Self {
+        Self::Map(value)
+    }
+}
+
+impl From<InterruptError> for PciError {
+    fn from(value: InterruptError) -> Self {
+        Self::Interrupt(value)
+    }
+}
+
+/// The location of a PCI function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciAddress {
+    /// The segment group, which is always 0 unless the MCFG table lists more.
+    pub segment: u16,
+    /// The bus.
+    pub bus: u8,
+    /// The device on the bus, below 32.
+    pub device: u8,
+    /// The function of the device, below 8.
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// Create an address.
+    ///
+    /// # Panics
+    /// Panics if `device` or `function` are out of range.
+    pub const fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        assert!(device < 32 && function < 8, "invalid PCI address");
+        Self {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
+/// Choose how to access the configuration space, enumerate all functions,
+/// and bind the registered drivers to them.
+///
+/// Must be called after [`acpi::init`][crate::acpi::init].
+pub fn init() {
+    // Enumeration starts at the first bus of every ECAM region.
+    let (access, roots): (Box<dyn ConfigAccess>, Vec<(u16, u8)>) = match Ecam::from_acpi() {
+        Some(ecam) => {
+            let roots = ecam
+                .regions()
+                .iter()
+                .map(|region| (region.segment, region.start_bus))
+                .collect();
+            (Box::new(ecam), roots)
+        }
+        // Safety: Only the PCI module uses the configuration ports.
+        None => (
+            Box::new(unsafe { LegacyAccess::new() }),
+            Vec::from([(0, 0)]),
+        ),
+    };
+    info!(
+        "Accessing PCI configuration space through {}",
+        access.name()
+    );
+    if CONFIG_ACCESS.set(access).is_err() {
+        panic!("pci::init() should be called only once");
+    }
+
+    let mut devices = Vec::new();
+    let mut visited = BTreeSet::new();
+    for (segment, bus) in roots {
+        scan_root(segment, bus, &mut devices, &mut visited);
+    }
+    for device in &devices {
+        info!("PCI {device}");
+    }
+    debug!("Found {} PCI functions", devices.len());
+    if DEVICES.set(devices).is_err() {
+        unreachable!("DEVICES is only set by init()");
+    }
+
+    driver::bind_all();
+}
+
+/// Returns all functions, or nothing before [`init`].
+pub fn devices() -> &'static [PciDevice] {
+    DEVICES.get().map_or(&[], Vec::as_slice)
+}
+
+/// Returns the function at `address`, if it exists.
+pub fn device(address: PciAddress) -> Option<&'static PciDevice> {
+    devices().iter().find(|device| device.address == address)
+}
+
+/// Read the 32-bit configuration register at `offset`. Returns all ones
+/// before [`init`].
+fn read_config(address: PciAddress, offset: u16) -> u32 {
+    CONFIG_ACCESS
+        .get()
+        .map_or(u32::MAX, |access| access.read(address, offset))
+}
+
+/// Write the 32-bit configuration register at `offset`.
+fn write_config(address: PciAddress, offset: u16, value: u32) {
+    if let Some(access) = CONFIG_ACCESS.get() {
+        access.write(address, offset, value);
+    }
+}
+
+/// Scan the buses behind the host bridge(s) of the root bus `bus`.
+///
+/// If the first host bridge is a multi-function device, each of its functions
+/// is the host bridge of the bus with the same number.
+fn scan_root(
+    segment: u16,
+    bus: u8,
+    devices: &mut Vec<PciDevice>,
+    visited: &mut BTreeSet<(u16, u8)>,
+) {
+    match PciDevice::probe(PciAddress::new(segment, bus, 0, 0)) {
+        Some(host) if bus == 0 && host.is_multi_function() => {
+            for function in 0..8 {
+                if PciDevice::probe(PciAddress::new(segment, 0, 0, function)).is_some() {
+                    scan_bus(segment, function, devices, visited);
+                }
+            }
+        }
+        _ => scan_bus(segment, bus, devices, visited),
+    }
+}
+
+/// Add all functions on `bus` to `devices`, following PCI-to-PCI bridges.
+fn scan_bus(
+    segment: u16,
+    bus: u8,
+    devices: &mut Vec<PciDevice>,
+    visited: &mut BTreeSet<(u16, u8)>,
+) {
+    // Misconfigured bridges could lead back to buses that were seen already.
+    if !visited.insert((segment, bus)) {
+        return;
+    }
+
+    for device in 0..32 {
+        let Some(first) = PciDevice::probe(PciAddress::new(segment, bus, device, 0)) else {
+            continue;
+        };
+        let functions = if first.is_multi_function() { 8 } else { 1 };
+
+        for function in 0..functions {
+            let Some(function) = PciDevice::probe(PciAddress::new(segment, bus, device, function))
+            else {
+                continue;
+            };
+            devices.push(function);
+            if let Some(secondary) = function.secondary_bus().filter(|&b| b != 0) {
+                scan_bus(segment, secondary, devices, visited);
+            }
+        }
+    }
+}
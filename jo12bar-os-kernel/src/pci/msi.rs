@@ -0,0 +1,183 @@
+//! Message signaled interrupts.
+//!
+//! Instead of asserting an interrupt line, functions raise MSIs by writing to
+//! the address of a local APIC, which raises the vector in the written data.
+//! Vectors are allocated with [`register_handler`][crate::interrupts::register_handler].
+//! Enabling MSIs disables the legacy INTx line of the function.
+
+use super::{config, PciDevice, PciError};
+use crate::{interrupts, mem::mmio::Mmio};
+
+/// The message control register of both capabilities, 16 bits.
+const MESSAGE_CONTROL: u16 = 0x02;
+
+/// MSI: enable bit of the message control register.
+const MSI_ENABLE: u16 = 1 << 0;
+/// MSI: the number of enabled vectors, as a power of two.
+const MSI_MULTIPLE_MESSAGE_ENABLE: u16 = 0b111 << 4;
+/// MSI: the message address has an upper half.
+const MSI_64BIT: u16 = 1 << 7;
+/// MSI: the function has a mask bit per vector.
+const MSI_PER_VECTOR_MASKING: u16 = 1 << 8;
+
+/// MSI-X: the table size minus one.
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+/// MSI-X: mask all vectors, regardless of their own mask bits.
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+/// MSI-X: enable bit of the message control register.
+const MSIX_ENABLE: u16 = 1 << 15;
+
+/// MSI-X: the size of a table entry.
+const MSIX_ENTRY_LEN: u64 = 16;
+/// MSI-X: the vector control register of an entry, with the mask bit 0.
+const MSIX_ENTRY_CONTROL: u64 = 12;
+
+/// Returns the address and data of a message that raises `vector` on this
+/// core.
+fn message(vector: u8) -> Result<(u32, u32), PciError> {
+    let apic_id = interrupts::local_apic_id()?;
+    // Fixed delivery, edge triggered, physical destination.
+    Ok((0xfee0_0000 | u32::from(apic_id) << 12, vector.into()))
+}
+
+/// Make `device` raise `vector` using MSI.
+///
+/// Only a single vector is enabled, even if the function supports more.
+pub fn enable_msi(device: &PciDevice, vector: u8) -> Result<(), PciError> {
+    let cap = device
+        .find_capability(PciDevice::CAPABILITY_MSI)
+        .ok_or(PciError::MissingCapability("MSI"))?;
+    let control = device.read_u16(cap + MESSAGE_CONTROL);
+    let (address, data) = message(vector)?;
+
+    device.write_u32(cap + 0x04, address);
+    let data_offset = if control & MSI_64BIT != 0 {
+        device.write_u32(cap + 0x08, 0);
+        cap + 0x0c
+    } else {
+        cap + 0x08
+    };
+    device.write_u16(data_offset, data as u16);
+    if control & MSI_PER_VECTOR_MASKING != 0 {
+        device.write_u32(data_offset + 0x04, 0);
+    }
+
+    device.update_command(config::COMMAND_BUS_MASTER | config::COMMAND_INTX_DISABLE, 0);
+    device.write_u16(
+        cap + MESSAGE_CONTROL,
+        control & !MSI_MULTIPLE_MESSAGE_ENABLE | MSI_ENABLE,
+    );
+    Ok(())
+}
+
+/// The MSI-X table of a function, whose entries each raise their own vector.
+///
+/// The table lives in one of the memory BARs of the function, see
+/// [`table_bar`][Self::table_bar], which the driver maps once and passes to
+/// [`new`][Self::new]. Creating it enables MSI-X with all entries masked.
+/// Entries are set up with [`set_entry`][Self::set_entry], and only raised
+/// after [`enable`][Self::enable].
+#[derive(Debug)]
+pub struct MsiX {
+    device: PciDevice,
+    /// The offset of the capability.
+    cap: u16,
+    table: Mmio,
+}
+
+impl MsiX {
+    /// Returns the index of the BAR containing the MSI-X table of `device`.
+    pub fn table_bar(device: &PciDevice) -> Result<u8, PciError> {
+        let cap = Self::capability(device)?;
+        Ok((device.read_u32(cap + 0x04) & 0b111) as u8)
+    }
+
+    /// Use the MSI-X table of `device`, and mask all of its entries.
+    ///
+    /// `bar` must be the mapping of the BAR returned by
+    /// [`table_bar`][Self::table_bar].
+    pub fn new(device: &PciDevice, bar: &Mmio) -> Result<Self, PciError> {
+        let cap = Self::capability(device)?;
+        let control = device.read_u16(cap + MESSAGE_CONTROL);
+        let table_len = u64::from(control & MSIX_TABLE_SIZE) + 1;
+
+        let table = device.read_u32(cap + 0x04);
+        let offset = u64::from(table & !0b111);
+        if offset + table_len * MSIX_ENTRY_LEN > bar.len() {
+            return Err(PciError::InvalidBar((table & 0b111) as u8));
+        }
+
+        let msix = Self {
+            device: *device,
+            cap,
+            table: bar.slice(offset, table_len * MSIX_ENTRY_LEN),
+        };
+
+        // The entries can only be accessed while MSI-X is enabled, and masking
+        // the function keeps them from being raised until they are set up.
+        device.write_u16(
+            cap + MESSAGE_CONTROL,
+            control | MSIX_ENABLE | MSIX_FUNCTION_MASK,
+        );
+        for index in 0..msix.len() {
+            msix.set_masked(index, true)?;
+        }
+        Ok(msix)
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> u16 {
+        (self.table.len() / MSIX_ENTRY_LEN) as u16
+    }
+
+    /// Returns `true` if the table has no entries, which never happens.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Make the entry `index` raise `vector`, and unmask it.
+    pub fn set_entry(&self, index: u16, vector: u8) -> Result<(), PciError> {
+        let entry = self.entry(index)?;
+        let (address, data) = message(vector)?;
+
+        self.set_masked(index, true)?;
+        self.table.write::<u32>(entry, address);
+        self.table.write::<u32>(entry + 0x04, 0);
+        self.table.write::<u32>(entry + 0x08, data);
+        self.set_masked(index, false)
+    }
+
+    /// Mask or unmask the entry `index`.
+    pub fn set_masked(&self, index: u16, masked: bool) -> Result<(), PciError> {
+        let control = self.entry(index)? + MSIX_ENTRY_CONTROL;
+        let value = self.table.read::<u32>(control) & !1;
+        self.table.write::<u32>(control, value | u32::from(masked));
+        Ok(())
+    }
+
+    /// Let the function raise its unmasked entries, and disable INTx.
+    pub fn enable(&self) {
+        self.device
+            .update_command(config::COMMAND_BUS_MASTER | config::COMMAND_INTX_DISABLE, 0);
+        let control = self.device.read_u16(self.cap + MESSAGE_CONTROL);
+        self.device.write_u16(
+            self.cap + MESSAGE_CONTROL,
+            control & !MSIX_FUNCTION_MASK | MSIX_ENABLE,
+        );
+    }
+
+    /// Returns the offset of the MSI-X capability of `device`.
+    fn capability(device: &PciDevice) -> Result<u16, PciError> {
+        device
+            .find_capability(PciDevice::CAPABILITY_MSIX)
+            .ok_or(PciError::MissingCapability("MSI-X"))
+    }
+
+    /// Returns the offset of the entry `index` in the table.
+    fn entry(&self, index: u16) -> Result<u64, PciError> {
+        if index >= self.len() {
+            return Err(PciError::InvalidMsixEntry(index));
+        }
+        Ok(u64::from(index) * MSIX_ENTRY_LEN)
+    }
+}
@@ -2,7 +2,7 @@
 
 use crate::core_locals::CoreInterruptState;
 
-pub use mem_util::sync::lock_cell::{LockCell, LockCellGuard};
+pub use mem_util::sync::lock_cell::{LockCell, LockCellGuard, RwLockCell};
 
 /// A [`TicketLock`][mem_util::sync::ticket_lock::TicketLock] setup with the [`CoreInterruptState`].
 pub type TicketLock<T> = mem_util::sync::ticket_lock::TicketLock<T, CoreInterruptState>;
@@ -12,3 +12,24 @@ pub type RwTicketLock<T> = mem_util::sync::ticket_lock::RwTicketLock<T, CoreInte
 
 /// A [`UnwrapTicketLock`][mem_util::sync::ticket_lock::UnwrapTicketLock] setup with the [`CoreInterruptState`].
 pub type UnwrapTicketLock<T> = mem_util::sync::ticket_lock::UnwrapTicketLock<T, CoreInterruptState>;
+
+/// A [`Mutex`][mem_util::sync::mutex::Mutex] setup with the [`CoreInterruptState`].
+pub type Mutex<T> = mem_util::sync::mutex::Mutex<T, CoreInterruptState>;
+
+/// A [`Condvar`][mem_util::sync::condvar::Condvar] setup with the [`CoreInterruptState`].
+pub type Condvar = mem_util::sync::condvar::Condvar<CoreInterruptState>;
+
+/// A [`Semaphore`][mem_util::sync::semaphore::Semaphore] setup with the [`CoreInterruptState`].
+pub type Semaphore = mem_util::sync::semaphore::Semaphore<CoreInterruptState>;
+
+/// A [`SpinOnce`][mem_util::sync::once::SpinOnce] setup with the [`CoreInterruptState`].
+pub type SpinOnce<T> = mem_util::sync::once::SpinOnce<T, CoreInterruptState>;
+
+/// A [`Lazy`][mem_util::sync::once::Lazy] setup with the [`CoreInterruptState`].
+pub type Lazy<T, F = fn() -> T> = mem_util::sync::once::Lazy<T, CoreInterruptState, F>;
+
+/// A [`SeqLock`][mem_util::sync::seq_lock::SeqLock] setup with the [`CoreInterruptState`].
+pub type SeqLock<T> = mem_util::sync::seq_lock::SeqLock<T, CoreInterruptState>;
+
+/// A [`PerCore`][mem_util::sync::per_core::PerCore] setup with the [`CoreInterruptState`].
+pub type PerCore<T, const N: usize> = mem_util::sync::per_core::PerCore<T, CoreInterruptState, N>;
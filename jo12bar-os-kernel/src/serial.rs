@@ -1,20 +1,19 @@
 //! Utilities for communication over serial ports (primarily logging).
 
-use lazy_static::lazy_static;
 use uart_16550::SerialPort;
 use x86_64::instructions::interrupts;
 
 use crate::prelude::*;
 
-lazy_static! {
-    /// The global UART serial port protected by a spinlock.
-    pub static ref SERIAL1: TicketLock<SerialPort> = {
-        // Safety: 0x3F8 is the standard port number for the first serial interface on x86.
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        TicketLock::new_non_preemtable(serial_port)
-    };
-}
+/// The global UART serial port protected by a spinlock.
+///
+/// Non-preemtable, since the first log message might come from an interrupt.
+pub static SERIAL1: Lazy<TicketLock<SerialPort>> = Lazy::new_non_preemtable(|| {
+    // Safety: 0x3F8 is the standard port number for the first serial interface on x86.
+    let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+    serial_port.init();
+    TicketLock::new_non_preemtable(serial_port)
+});
 
 #[doc(hidden)]
 pub fn _serial_print(args: ::core::fmt::Arguments) {
@@ -53,3 +52,36 @@ macro_rules! serial_println {
         $crate::serial_print!("{}\n", format_args!($fmt, $($arg)*))
     };
 }
+
+/// Writes reports of suspected deadlocks to the serial port.
+#[cfg(feature = "lock-debug")]
+pub(crate) fn report_suspected_deadlock(report: &mem_util::sync::lock_debug::DeadlockReport<'_>) {
+    write_lock_report(report);
+}
+
+/// Writes reports of the lock ordering validator to the serial port.
+#[cfg(feature = "lockdep")]
+pub(crate) fn report_lockdep_violation(report: &mem_util::sync::lockdep::LockdepReport<'_>) {
+    write_lock_report(report);
+}
+
+/// Writes a report about a locking problem to the serial port.
+///
+/// The problem might involve [`SERIAL1`] itself, so if it is locked the report
+/// is written to the port directly.
+#[cfg(any(feature = "lock-debug", feature = "lockdep"))]
+fn write_lock_report(report: &dyn core::fmt::Display) {
+    use core::fmt::Write;
+
+    match SERIAL1.try_lock() {
+        Some(mut serial) => {
+            let _ = writeln!(serial, "{report}");
+        }
+        None => {
+            // Safety: 0x3F8 is the standard port number for the first serial interface
+            // on x86, and it was initialized when SERIAL1 was created.
+            let mut serial = unsafe { SerialPort::new(0x3F8) };
+            let _ = writeln!(serial, "{report}");
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Symbolization of kernel addresses.
+//!
+//! The bootloader leaves the kernel's ELF file in memory, so [`init`] reads the
+//! symbol table from it. [`resolve`] can then map addresses, e.g. from a
+//! [`Backtrace`][crate::panic::Backtrace], back to the function containing them.
+//!
+//! Nothing is copied, so this doesn't need the heap, but it only works if the
+//! kernel was built with a symbol table, i.e. wasn't stripped.
+
+use core::{
+    fmt, slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use log::{debug, info, warn};
+use thiserror::Error;
+use x86_64::VirtAddr;
+
+use crate::prelude::*;
+
+/// The symbol table of the running kernel, once [`init`] was called.
+static SYMBOLS: SpinOnce<SymbolTable> = SpinOnce::new();
+
+/// The offset the kernel was loaded at, set by [`init`].
+static KERNEL_IMAGE_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// `sh_type` of a symbol table section.
+const SHT_SYMTAB: u32 = 2;
+/// The size of a section header.
+const SECTION_HEADER_LEN: usize = 64;
+/// The size of a symbol table entry.
+const SYMBOL_LEN: usize = 24;
+/// `st_info` type of a function symbol.
+const STT_FUNC: u8 = 2;
+
+/// Errors returned when reading the kernel's symbol table.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum SymbolError {
+    #[error("the kernel image is not a 64-bit little-endian ELF file")]
+    NotElf,
+    #[error("the kernel image is truncated")]
+    Truncated,
+    #[error("the kernel image has no symbol table")]
+    NoSymbolTable,
+}
+
+/// A function symbol of the kernel, and an offset into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    /// The mangled name of the function.
+    pub name: &'a str,
+    /// The offset of the address into the function.
+    pub offset: u64,
+}
+
+/// Prints the demangled name without its hash, followed by the offset.
+impl fmt::Display for Symbol<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#}+{:#x}",
+            rustc_demangle::demangle(self.name),
+            self.offset
+        )
+    }
+}
+
+/// An address, followed by the function containing it if that is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolizedAddress {
+    /// The address.
+    pub addr: u64,
+    /// The function containing the address.
+    pub symbol: Option<Symbol<'static>>,
+}
+
+impl SymbolizedAddress {
+    /// Symbolize an instruction address, e.g. an instruction pointer.
+    pub fn new(addr: u64) -> Self {
+        Self {
+            addr,
+            symbol: resolve(addr),
+        }
+    }
+
+    /// Symbolize a return address, see [`resolve_return_address`].
+    pub fn return_address(addr: u64) -> Self {
+        Self {
+            addr,
+            symbol: resolve_return_address(addr),
+        }
+    }
+}
+
+/// Prints `0x<addr> <function+offset>`.
+///
+/// The runner resolves addresses that aren't followed by a symbol using the
+/// kernel's ELF file on the host.
+impl fmt::Display for SymbolizedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}", self.addr)?;
+        if let Some(symbol) = self.symbol {
+            write!(f, " <{symbol}>")?;
+        }
+        Ok(())
+    }
+}
+
+/// The symbol and string tables of an ELF file.
+struct SymbolTable {
+    symbols: &'static [u8],
+    strings: &'static [u8],
+    /// Added to symbol values to get the address they were loaded at.
+    load_offset: u64,
+}
+
+impl SymbolTable {
+    /// Find the symbol and string tables in `elf`.
+    fn parse(elf: &'static [u8], load_offset: u64) -> Result<Self, SymbolError> {
+        // magic, 64-bit, little-endian
+        if elf.get(..6) != Some(b"\x7fELF\x02\x01") {
+            return Err(SymbolError::NotElf);
+        }
+
+        let section_headers = read_u64(elf, 0x28)? as usize;
+        let section_count = read_u16(elf, 0x3c)? as usize;
+        // The offsets and sizes come from the image, so they may overflow.
+        let section = |idx: usize| {
+            idx.checked_mul(SECTION_HEADER_LEN)
+                .and_then(|offset| section_headers.checked_add(offset))
+                .and_then(|start| elf.get(start..start.checked_add(SECTION_HEADER_LEN)?))
+                .ok_or(SymbolError::Truncated)
+        };
+        let section_data = |header: &[u8]| {
+            let offset = read_u64(header, 0x18)? as usize;
+            let size = read_u64(header, 0x20)? as usize;
+            offset
+                .checked_add(size)
+                .and_then(|end| elf.get(offset..end))
+                .ok_or(SymbolError::Truncated)
+        };
+
+        for idx in 0..section_count {
+            let header = section(idx)?;
+            if read_u32(header, 0x04)? != SHT_SYMTAB {
+                continue;
+            }
+
+            let strings_idx = read_u32(header, 0x28)? as usize;
+            return Ok(Self {
+                symbols: section_data(header)?,
+                strings: section_data(section(strings_idx)?)?,
+                load_offset,
+            });
+        }
+
+        Err(SymbolError::NoSymbolTable)
+    }
+
+    /// Returns the number of function symbols.
+    fn function_count(&self) -> usize {
+        self.functions().count()
+    }
+
+    /// Iterate over the name, address and size of all function symbols.
+    fn functions(&self) -> impl Iterator<Item = (&'static [u8], u64, u64)> + '_ {
+        let strings = self.strings;
+        self.symbols
+            .as_chunks::<SYMBOL_LEN>()
+            .0
+            .iter()
+            .filter(|symbol| symbol[4] & 0xf == STT_FUNC)
+            .filter_map(move |symbol| {
+                let name_start = read_u32(symbol, 0).ok()? as usize;
+                let value = read_u64(symbol, 8).ok()?;
+                let size = read_u64(symbol, 16).ok()?;
+
+                let name = strings.get(name_start..)?;
+                let name_len = name.iter().position(|&b| b == 0)?;
+                Some((&name[..name_len], value, size))
+            })
+    }
+
+    /// Find the function containing `addr`.
+    fn resolve(&self, addr: u64) -> Option<Symbol<'static>> {
+        let addr = addr.checked_sub(self.load_offset)?;
+        let (name, start, _) = self
+            .functions()
+            .find(|&(_, start, size)| (start..start + size.max(1)).contains(&addr))?;
+
+        Some(Symbol {
+            name: core::str::from_utf8(name).ok()?,
+            offset: addr - start,
+        })
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, SymbolError> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(SymbolError::Truncated)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, SymbolError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(SymbolError::Truncated)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, SymbolError> {
+    bytes
+        .get(offset..offset + 8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(SymbolError::Truncated)
+}
+
+/// Read the kernel's symbol table from its ELF file.
+///
+/// Failures are logged, after which [`resolve`] always returns [`None`].
+///
+/// # Safety
+/// - `phys_mem_offset` must be the start of the mapping of all physical memory.
+/// - The memory the bootloader loaded the kernel's ELF file into must never be
+///   reused.
+pub unsafe fn init(boot_info: &bootloader_api::BootInfo, phys_mem_offset: VirtAddr) {
+    let start = phys_mem_offset + boot_info.kernel_addr;
+    // Safety: see above
+    let elf: &'static [u8] =
+        unsafe { slice::from_raw_parts(start.as_ptr(), boot_info.kernel_len as usize) };
+
+    KERNEL_IMAGE_OFFSET.store(boot_info.kernel_image_offset, Ordering::Relaxed);
+    info!("Kernel image offset: {:#x}", boot_info.kernel_image_offset);
+
+    match SymbolTable::parse(elf, boot_info.kernel_image_offset) {
+        Ok(table) => {
+            debug!("Loaded {} kernel symbols", table.function_count());
+            if SYMBOLS.set(table).is_err() {
+                warn!("Kernel symbols were already loaded");
+            }
+        }
+        Err(e) => warn!("Backtraces won't be symbolized: {e}"),
+    }
+}
+
+/// Returns the offset the kernel was loaded at, which has to be subtracted
+/// from addresses before looking them up in the kernel's ELF file.
+pub fn kernel_image_offset() -> u64 {
+    KERNEL_IMAGE_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Returns the kernel function containing `addr`.
+pub fn resolve(addr: u64) -> Option<Symbol<'static>> {
+    SYMBOLS.get()?.resolve(addr)
+}
+
+/// Returns the kernel function that called the function returning to
+/// `return_address`.
+///
+/// Return addresses point to the instruction after the call, which might
+/// already be part of another function if the call was the caller's last
+/// instruction.
+pub fn resolve_return_address(return_address: u64) -> Option<Symbol<'static>> {
+    let mut symbol = resolve(return_address.checked_sub(1)?)?;
+    symbol.offset += 1;
+    Some(symbol)
+}
@@ -0,0 +1,60 @@
+use alloc::{sync::Arc, task::Wake};
+use core::{
+    future::{Future, IntoFuture},
+    pin::pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use mem_util::sync::Parker;
+
+use crate::{core_locals::CoreInterruptState, thread};
+
+/// Run `fut` to completion on the current thread, which is parked while the
+/// future is pending.
+///
+/// This lets synchronous code, like filesystems, wait for async operations.
+/// Before threads are available, this spins instead of parking. It must not be
+/// called from interrupt handlers.
+pub fn block_on<Fut: IntoFuture>(fut: Fut) -> Fut::Output {
+    let mut fut = pin!(fut.into_future());
+
+    let thread_waker = Arc::new(ThreadWaker {
+        thread: CoreInterruptState::current_thread(),
+        woken: AtomicBool::new(false),
+    });
+    let waker = Waker::from(thread_waker.clone());
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut context) {
+            return output;
+        }
+        while !thread_waker.woken.swap(false, Ordering::AcqRel) {
+            match thread_waker.thread {
+                Some(_) => CoreInterruptState::park(),
+                None => core::hint::spin_loop(),
+            }
+        }
+    }
+}
+
+/// Wakes a thread blocked in [`block_on`].
+struct ThreadWaker {
+    /// The thread to unpark, or [`None`] if it is spinning.
+    thread: Option<thread::Thread>,
+    woken: AtomicBool,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(thread) = &self.thread {
+            thread.unpark();
+        }
+    }
+}
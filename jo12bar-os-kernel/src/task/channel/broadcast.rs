@@ -0,0 +1,268 @@
+//! A bounded multi-producer, multi-consumer channel where every receiver sees
+//! every value.
+//!
+//! Values are kept in a ring buffer. Receivers that fall behind by more than the
+//! channel's capacity miss the oldest values, and are told how many they missed
+//! with [`RecvError::Lagged`].
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use thiserror::Error;
+
+use super::{WaitQueue, Waiter};
+use crate::prelude::*;
+
+/// Create a broadcast channel that buffers up to `capacity` values.
+///
+/// # Panics
+/// Panics if `capacity` is 0.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(
+        capacity > 0,
+        "broadcast channel capacity must be greater than 0"
+    );
+
+    let mut slots = Vec::with_capacity(capacity);
+    slots.resize_with(capacity, || None);
+
+    let shared = Arc::new(Shared {
+        ring: TicketLock::new_non_preemtable(Ring { slots, tail: 0 }),
+        rx_waiters: WaitQueue::new(),
+        sender_count: AtomicUsize::new(1),
+        receiver_count: AtomicUsize::new(1),
+    });
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver {
+            shared,
+            next: 0,
+            waiter: None,
+        },
+    )
+}
+
+/// Error returned by [`Sender::send`] if there are no receivers.
+///
+/// Contains the value that couldn't be sent.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[error("there are no receivers")]
+pub struct SendError<T>(pub T);
+
+/// Error returned by [`Receiver::recv`].
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum RecvError {
+    #[error("all senders were dropped")]
+    Closed,
+    #[error("the receiver lagged behind and missed {0} values")]
+    Lagged(u64),
+}
+
+/// Error returned by [`Receiver::try_recv`].
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum TryRecvError {
+    #[error("there are no new values")]
+    Empty,
+    #[error("all senders were dropped")]
+    Closed,
+    #[error("the receiver lagged behind and missed {0} values")]
+    Lagged(u64),
+}
+
+struct Ring<T> {
+    slots: Vec<Option<T>>,
+    /// The sequence number of the next value that is sent.
+    tail: u64,
+}
+
+impl<T> Ring<T> {
+    fn capacity(&self) -> u64 {
+        self.slots.len() as u64
+    }
+
+    fn slot(&mut self, seq: u64) -> &mut Option<T> {
+        let idx = (seq % self.capacity()) as usize;
+        &mut self.slots[idx]
+    }
+}
+
+struct Shared<T> {
+    /// Non-preemtable, so that values can be sent from interrupt handlers.
+    ring: TicketLock<Ring<T>>,
+    /// Receivers waiting for the next value.
+    rx_waiters: WaitQueue,
+    sender_count: AtomicUsize,
+    receiver_count: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    fn is_closed(&self) -> bool {
+        self.sender_count.load(Ordering::Acquire) == 0
+    }
+}
+
+/// The sending half of a broadcast channel.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Send> Sender<T> {
+    /// Send `value` to all current receivers, returning how many there are.
+    ///
+    /// Doesn't wait, and the ring was allocated up front. If the ring is full,
+    /// the oldest value is dropped here, though, so this can only be called
+    /// from an interrupt handler if dropping a `T` doesn't free heap memory.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let receiver_count = self.shared.receiver_count.load(Ordering::Acquire);
+        if receiver_count == 0 {
+            return Err(SendError(value));
+        }
+
+        {
+            let mut ring = self.shared.ring.lock();
+            let tail = ring.tail;
+            *ring.slot(tail) = Some(value);
+            ring.tail += 1;
+        }
+
+        self.shared.rx_waiters.notify_all();
+        Ok(receiver_count)
+    }
+
+    /// Create a new receiver that receives all values sent after this call.
+    pub fn subscribe(&self) -> Receiver<T> {
+        self.shared.receiver_count.fetch_add(1, Ordering::Relaxed);
+        let next = self.shared.ring.lock().tail;
+
+        Receiver {
+            shared: self.shared.clone(),
+            next,
+            waiter: None,
+        }
+    }
+
+    /// Returns the number of receivers.
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receiver_count.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.sender_count.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.sender_count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.rx_waiters.notify_all();
+        }
+    }
+}
+
+/// The receiving half of a broadcast channel.
+///
+/// More receivers can be created using [`Sender::subscribe`].
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// The sequence number of the next value this receiver will see.
+    next: u64,
+    /// Set while waiting for the next value.
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl<T: Clone + Send> Receiver<T> {
+    /// Receive the next value, waiting until one is sent.
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Receive the next value if one is available right now.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut ring = self.shared.ring.lock();
+
+        if self.next == ring.tail {
+            return if self.shared.is_closed() {
+                Err(TryRecvError::Closed)
+            } else {
+                Err(TryRecvError::Empty)
+            };
+        }
+
+        let oldest = ring.tail.saturating_sub(ring.capacity());
+        if self.next < oldest {
+            let missed = oldest - self.next;
+            self.next = oldest;
+            return Err(TryRecvError::Lagged(missed));
+        }
+
+        let value = ring
+            .slot(self.next)
+            .clone()
+            .expect("broadcast slot is empty");
+        self.next += 1;
+        Ok(value)
+    }
+
+    /// Poll for the next value, registering the current task to be woken up
+    /// once one is available.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        loop {
+            let result = match self.try_recv() {
+                Ok(value) => Ok(value),
+                Err(TryRecvError::Closed) => Err(RecvError::Closed),
+                Err(TryRecvError::Lagged(missed)) => Err(RecvError::Lagged(missed)),
+                Err(TryRecvError::Empty) => {
+                    if let Some(waiter) = &self.waiter {
+                        if waiter.poll_notified(cx).is_pending() {
+                            return Poll::Pending;
+                        }
+                        self.waiter = None;
+                        continue;
+                    }
+
+                    let waiter = Waiter::for_task();
+                    let shared = &self.shared;
+                    let next = self.next;
+                    let has_value = || shared.ring.lock().tail != next || shared.is_closed();
+                    if shared.rx_waiters.push_unless(waiter.clone(), has_value) {
+                        self.waiter = Some(waiter);
+                    }
+                    continue;
+                }
+            };
+
+            self.cancel_wait();
+            return Poll::Ready(result);
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Remove this receiver from the wait queue, if it's in there.
+    fn cancel_wait(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            self.shared.rx_waiters.remove(&waiter);
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.cancel_wait();
+        self.shared.receiver_count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,28 @@
+//! Async channels for communication between tasks.
+//!
+//! - [`mpsc`]: bounded and unbounded multi-producer, single-consumer channels.
+//! - [`oneshot`]: a channel that sends a single value.
+//! - [`broadcast`]: a bounded channel where every receiver sees every value.
+//!
+//! [`mpsc::Sender::try_send`], [`oneshot::Sender::send`] and
+//! [`broadcast::Sender::send`] never wait or allocate. Whether they can be
+//! used from interrupt handlers depends on what they might drop, which each
+//! of them documents.
+//!
+//! The channel state is freed once the last handle to it is dropped, which
+//! requires the allocator. Interrupt handlers should therefore keep their
+//! channel handles alive, e.g. in a static.
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod oneshot;
+
+use mem_util::sync::wait_queue;
+
+use crate::core_locals::CoreInterruptState;
+
+/// A [`WaitQueue`][wait_queue::WaitQueue] for tasks waiting on a channel.
+type WaitQueue = wait_queue::WaitQueue<CoreInterruptState>;
+
+/// A [`Waiter`][wait_queue::Waiter] in a [`WaitQueue`].
+type Waiter = wait_queue::Waiter<CoreInterruptState>;
@@ -0,0 +1,371 @@
+//! Multi-producer, single-consumer channels.
+//!
+//! [`channel()`] creates a bounded channel backed by a fixed-size queue. Its
+//! [`Sender::try_send`] never allocates, so it can be used from interrupt
+//! handlers. [`unbounded_channel()`] creates a channel that grows as needed,
+//! which means that sending to it may allocate.
+
+use alloc::sync::Arc;
+use core::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use crossbeam_queue::{ArrayQueue, SegQueue};
+use futures_util::{task::AtomicWaker, Stream};
+use thiserror::Error;
+
+use super::{WaitQueue, Waiter};
+
+/// Create a bounded channel that can hold up to `capacity` values.
+///
+/// # Panics
+/// Panics if `capacity` is 0.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel capacity must be greater than 0");
+
+    let chan = Arc::new(Chan::new(Queue::Bounded(ArrayQueue::new(capacity))));
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Create an unbounded channel.
+pub fn unbounded_channel<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let chan = Arc::new(Chan::new(Queue::Unbounded(SegQueue::new())));
+    (UnboundedSender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Error returned by [`Sender::try_send`].
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum TrySendError<T> {
+    #[error("the channel is full")]
+    Full(T),
+    #[error("the receiver was dropped")]
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    /// Returns the value that couldn't be sent.
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Full(value) | Self::Closed(value) => value,
+        }
+    }
+}
+
+/// Error returned when sending to a channel whose receiver was dropped.
+///
+/// Contains the value that couldn't be sent.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[error("the receiver was dropped")]
+pub struct SendError<T>(pub T);
+
+/// Error returned by [`Receiver::try_recv`].
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum TryRecvError {
+    #[error("the channel is empty")]
+    Empty,
+    #[error("all senders were dropped")]
+    Disconnected,
+}
+
+enum Queue<T> {
+    Bounded(ArrayQueue<T>),
+    Unbounded(SegQueue<T>),
+}
+
+impl<T> Queue<T> {
+    fn push(&self, value: T) -> Result<(), T> {
+        match self {
+            Queue::Bounded(queue) => queue.push(value),
+            Queue::Unbounded(queue) => {
+                queue.push(value);
+                Ok(())
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        match self {
+            Queue::Bounded(queue) => queue.pop(),
+            Queue::Unbounded(queue) => queue.pop(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self {
+            Queue::Bounded(queue) => queue.is_full(),
+            Queue::Unbounded(_) => false,
+        }
+    }
+}
+
+/// The state shared between the senders and the receiver of a channel.
+struct Chan<T> {
+    queue: Queue<T>,
+    /// Woken when a value is sent, or the last sender is dropped.
+    rx_waker: AtomicWaker,
+    /// Tasks waiting for space in a bounded channel.
+    tx_waiters: WaitQueue,
+    sender_count: AtomicUsize,
+    rx_closed: AtomicBool,
+}
+
+impl<T> Chan<T> {
+    fn new(queue: Queue<T>) -> Self {
+        Self {
+            queue,
+            rx_waker: AtomicWaker::new(),
+            tx_waiters: WaitQueue::new(),
+            sender_count: AtomicUsize::new(1),
+            rx_closed: AtomicBool::new(false),
+        }
+    }
+
+    fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.rx_closed.load(Ordering::Acquire) {
+            return Err(TrySendError::Closed(value));
+        }
+
+        self.queue.push(value).map_err(TrySendError::Full)?;
+        self.rx_waker.wake();
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<T> {
+        let value = self.queue.pop()?;
+        self.tx_waiters.notify_one();
+        Some(value)
+    }
+
+    fn is_disconnected(&self) -> bool {
+        self.sender_count.load(Ordering::Acquire) == 0
+    }
+
+    fn add_sender(&self) {
+        self.sender_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn drop_sender(&self) {
+        if self.sender_count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.rx_waker.wake();
+        }
+    }
+}
+
+/// The sending half of a bounded channel created by [`channel()`].
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Send `value` if there is space in the channel.
+    ///
+    /// Doesn't wait, and doesn't allocate, since the buffer was allocated by
+    /// [`channel()`]. Interrupt handlers may call it as long as they don't drop
+    /// a rejected value that owns heap memory, and the receiving task outlives
+    /// the waker it registered, which is dropped here.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(value)
+    }
+
+    /// Send `value`, waiting for space in the channel if it's full.
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            chan: &self.chan,
+            value: Some(value),
+            waiter: None,
+        }
+    }
+
+    /// Returns `true` if the receiver was dropped or closed.
+    pub fn is_closed(&self) -> bool {
+        self.chan.rx_closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.add_sender();
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+/// The sending half of an unbounded channel created by [`unbounded_channel()`].
+pub struct UnboundedSender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> UnboundedSender<T> {
+    /// Send `value`.
+    ///
+    /// This might allocate, so it must not be used from interrupt handlers.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.chan
+            .try_send(value)
+            .map_err(|err| SendError(err.into_inner()))
+    }
+
+    /// Returns `true` if the receiver was dropped or closed.
+    pub fn is_closed(&self) -> bool {
+        self.chan.rx_closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        self.chan.add_sender();
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+/// Future returned by [`Sender::send`].
+#[must_use = "futures do nothing unless polled"]
+pub struct SendFuture<'s, T> {
+    chan: &'s Chan<T>,
+    /// [`None`] once the value was sent.
+    value: Option<T>,
+    /// Set while waiting for space in the channel.
+    waiter: Option<Arc<Waiter>>,
+}
+
+// The value is never pinned.
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let chan = self.chan;
+
+        loop {
+            if let Some(waiter) = &self.waiter {
+                if waiter.poll_notified(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.waiter = None;
+            }
+
+            let value = self
+                .value
+                .take()
+                .expect("SendFuture polled after completion");
+            match chan.try_send(value) {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(value)) => return Poll::Ready(Err(SendError(value))),
+                Err(TrySendError::Full(value)) => {
+                    self.value = Some(value);
+
+                    let waiter = Waiter::for_task();
+                    let still_full =
+                        || chan.queue.is_full() && !chan.rx_closed.load(Ordering::Acquire);
+                    if chan
+                        .tx_waiters
+                        .push_unless(waiter.clone(), || !still_full())
+                    {
+                        self.waiter = Some(waiter);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T> Drop for SendFuture<'_, T> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            // Don't swallow a notification meant for another sender.
+            if !self.chan.tx_waiters.remove(&waiter) {
+                self.chan.tx_waiters.notify_one();
+            }
+        }
+    }
+}
+
+/// The receiving half of a channel created by [`channel()`] or [`unbounded_channel()`].
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receive the next value.
+    ///
+    /// Returns [`None`] once all senders were dropped and the channel is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Receive the next value if one is available right now.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.chan.pop() {
+            return Ok(value);
+        }
+
+        if self.chan.is_disconnected() {
+            // A value might have been sent right before the last sender was dropped.
+            self.chan.pop().ok_or(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Poll for the next value, registering the current task to be woken up
+    /// once one is available.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(value) => return Poll::Ready(Some(value)),
+            Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        self.chan.rx_waker.register(cx.waker());
+
+        // A value might have been sent before the waker was registered.
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    /// Close the channel, making all further sends fail.
+    ///
+    /// Values that were already sent can still be received.
+    pub fn close(&mut self) {
+        self.chan.rx_closed.store(true, Ordering::Release);
+        self.chan.tx_waiters.notify_all();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx)
+    }
+}
@@ -0,0 +1,212 @@
+//! A channel for sending a single value.
+
+use alloc::sync::Arc;
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+use futures_util::task::AtomicWaker;
+use thiserror::Error;
+
+/// Create a new oneshot channel.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: AtomicU8::new(EMPTY),
+        value: UnsafeCell::new(None),
+        rx_waker: AtomicWaker::new(),
+    });
+
+    (
+        Sender {
+            inner: inner.clone(),
+            sent: false,
+        },
+        Receiver { inner },
+    )
+}
+
+/// Error returned by [`Receiver`] when the [`Sender`] was dropped without sending
+/// a value.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[error("the sender was dropped without sending a value")]
+pub struct RecvError;
+
+/// Error returned by [`Receiver::try_recv`].
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum TryRecvError {
+    #[error("no value was sent yet")]
+    Empty,
+    #[error("the sender was dropped without sending a value")]
+    Closed,
+}
+
+/// No value was sent yet, and both halves are alive.
+const EMPTY: u8 = 0;
+/// A value was sent and is waiting to be received.
+const SENT: u8 = 1;
+/// The sender was dropped without sending a value.
+const TX_DROPPED: u8 = 2;
+/// The receiver was dropped.
+const RX_DROPPED: u8 = 3;
+/// The value was received.
+const RECEIVED: u8 = 4;
+
+struct Inner<T> {
+    state: AtomicU8,
+    /// Written by the sender while `state` is [`EMPTY`], and only read by the
+    /// receiver once `state` is [`SENT`].
+    value: UnsafeCell<Option<T>>,
+    rx_waker: AtomicWaker,
+}
+
+// Safety: access to `value` is synchronized through `state`.
+unsafe impl<T: Send> Send for Inner<T> {}
+// Safety: access to `value` is synchronized through `state`.
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+impl<T> Inner<T> {
+    /// Take the sent value.
+    ///
+    /// # Safety
+    /// `state` must have been [`SENT`], and must have been changed by the caller,
+    /// so that the sender and other callers can't access `value` anymore.
+    unsafe fn take_value(&self) -> Option<T> {
+        // Safety: see above
+        unsafe { (*self.value.get()).take() }
+    }
+}
+
+/// The sending half of a oneshot channel.
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+    /// `true` once a value was sent.
+    sent: bool,
+}
+
+impl<T> Sender<T> {
+    /// Send `value` to the receiver.
+    ///
+    /// Returns the value back if the receiver was dropped.
+    ///
+    /// Doesn't wait or allocate, but frees the channel if the receiver is
+    /// already gone, which makes it unfit for interrupt handlers unless the
+    /// receiver outlives the call.
+    pub fn send(mut self, value: T) -> Result<(), T> {
+        self.send_by_ref(value)
+    }
+
+    /// Send `value` to the receiver, but keep the channel alive until the
+    /// sender is dropped.
+    ///
+    /// Returns the value back if the receiver was dropped. Unlike
+    /// [`send`][Self::send], this never frees the channel, so interrupt
+    /// handlers can use it if they drop the sender and a returned value later.
+    ///
+    /// # Panics
+    /// Panics if a value was already sent.
+    pub fn send_by_ref(&mut self, value: T) -> Result<(), T> {
+        assert!(!self.sent, "oneshot value was already sent");
+        self.sent = true;
+        let inner = &self.inner;
+
+        // Safety: As long as `state` is `EMPTY`, only the sender accesses the value,
+        // and `sent` keeps it from being written twice.
+        unsafe {
+            *inner.value.get() = Some(value);
+        }
+
+        match inner
+            .state
+            .compare_exchange(EMPTY, SENT, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {
+                inner.rx_waker.wake();
+                Ok(())
+            }
+            Err(_) => {
+                // Safety: The receiver was dropped, so it never accesses the value.
+                let value = unsafe { inner.take_value() };
+                Err(value.expect("value was just written"))
+            }
+        }
+    }
+
+    /// Returns `true` if the receiver was dropped.
+    pub fn is_closed(&self) -> bool {
+        !self.sent && self.inner.state.load(Ordering::Acquire) == RX_DROPPED
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if !self.sent
+            && self
+                .inner
+                .state
+                .compare_exchange(EMPTY, TX_DROPPED, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            self.inner.rx_waker.wake();
+        }
+    }
+}
+
+/// The receiving half of a oneshot channel.
+///
+/// This is a future that resolves to the sent value.
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receive the value if it was already sent.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        match self
+            .inner
+            .state
+            .compare_exchange(SENT, RECEIVED, Ordering::AcqRel, Ordering::Acquire)
+        {
+            // Safety: We just moved `state` from `SENT` to `RECEIVED`.
+            Ok(_) => Ok(unsafe { self.inner.take_value() }.expect("value was sent")),
+            Err(EMPTY) => Err(TryRecvError::Empty),
+            Err(TX_DROPPED) => Err(TryRecvError::Closed),
+            Err(RECEIVED) => panic!("oneshot value was already received"),
+            Err(state) => unreachable!("invalid oneshot state {state}"),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.try_recv() {
+            Ok(value) => return Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => return Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        self.inner.rx_waker.register(cx.waker());
+
+        // The value might have been sent before the waker was registered.
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.inner.state.swap(RX_DROPPED, Ordering::AcqRel) == SENT {
+            // Safety: We just moved `state` from `SENT` to `RX_DROPPED`.
+            drop(unsafe { self.inner.take_value() });
+        }
+    }
+}
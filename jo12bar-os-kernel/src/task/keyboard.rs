@@ -5,15 +5,18 @@ use core::{
     task::{Context, Poll},
 };
 
-use conquer_once::spin::OnceCell;
-use crossbeam_queue::ArrayQueue;
-use futures_util::{task::AtomicWaker, Stream, StreamExt};
+use futures_util::{Stream, StreamExt};
 use log::{trace, warn};
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 
-static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+use super::channel::mpsc::{self, TrySendError};
+use crate::prelude::*;
 
-static WAKER: AtomicWaker = AtomicWaker::new();
+/// The number of scancodes that can be buffered before user input is dropped.
+const SCANCODE_QUEUE_SIZE: usize = 100;
+
+/// Used by the keyboard interrupt handler to send scancodes to the [`ScancodeStream`].
+static SCANCODE_SENDER: SpinOnce<mpsc::Sender<u8>> = SpinOnce::new();
 
 /// Print keypresses to the log.
 ///
@@ -52,20 +55,21 @@ pub async fn print_keypresses() {
 ///
 /// Must not block or allocate.
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if queue.push(scancode).is_err() {
-            warn!("scancode queue full; dropping user input");
-        } else {
-            WAKER.wake();
-        }
-    } else {
+    let Some(sender) = SCANCODE_SENDER.get() else {
         warn!("scancode queue uninitialized");
+        return;
+    };
+
+    match sender.try_send(scancode) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => warn!("scancode queue full; dropping user input"),
+        Err(TrySendError::Closed(_)) => warn!("scancode stream was dropped; dropping user input"),
     }
 }
 
 /// An async stream of keyboard scancodes.
 pub struct ScancodeStream {
-    _private: (),
+    scancodes: mpsc::Receiver<u8>,
 }
 
 impl ScancodeStream {
@@ -73,36 +77,19 @@ impl ScancodeStream {
     ///
     /// Panics if called more than once.
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new() should be called only once");
-        Self { _private: () }
+        let (sender, scancodes) = mpsc::channel(SCANCODE_QUEUE_SIZE);
+        if SCANCODE_SENDER.set(sender).is_err() {
+            panic!("ScancodeStream::new() should be called only once");
+        }
+        Self { scancodes }
     }
 }
 
 impl Stream for ScancodeStream {
     type Item = u8;
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let queue = SCANCODE_QUEUE.try_get().expect("not initialized");
-
-        // fast path - there's a scancode immediately available!
-        if let Some(scancode) = queue.pop() {
-            return Poll::Ready(Some(scancode));
-        }
-
-        // nothing available, get woken up later.
-        WAKER.register(cx.waker());
-
-        // the interrupt handler might've added something since the last check,
-        // so check again just to be sure
-        match queue.pop() {
-            Some(scancode) => {
-                WAKER.take();
-                Poll::Ready(Some(scancode))
-            }
-            None => Poll::Pending,
-        }
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.scancodes.poll_recv(cx)
     }
 }
 
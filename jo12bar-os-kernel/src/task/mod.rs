@@ -1,9 +1,12 @@
 //! Async tasks and executors.
 
+mod block_on;
+pub mod channel;
 mod executor;
 pub mod keyboard;
 pub mod simple_executor;
 mod task_impl;
 
+pub use block_on::block_on;
 pub use executor::Executor;
 pub use task_impl::Task;
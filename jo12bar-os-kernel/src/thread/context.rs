@@ -0,0 +1,136 @@
+//! Saved register context of kernel threads, and the [`switch_to`] routine
+//! used to switch between them.
+
+use core::{arch::global_asm, mem::size_of};
+
+use x86_64::VirtAddr;
+
+/// The saved execution context of a kernel thread that is not running.
+///
+/// Only the stack pointer is stored here. Everything else that needs to survive
+/// a context switch is pushed onto the thread's own stack as [`SavedRegisters`].
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Context {
+    /// The thread's stack pointer, pointing at its [`SavedRegisters`].
+    rsp: u64,
+}
+
+/// The registers pushed onto a thread's stack by [`switch_to`].
+///
+/// These are the callee-saved registers of the System V ABI, followed by the
+/// return address that [`switch_to`] returns to once the thread is resumed.
+/// The order of the fields must match the order in which they are popped off
+/// the stack.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct SavedRegisters {
+    /// `r15`
+    pub r15: u64,
+    /// `r14`
+    pub r14: u64,
+    /// `r13`
+    pub r13: u64,
+    /// `r12`. Holds the argument passed to the entry point of new threads.
+    pub r12: u64,
+    /// `rbx`
+    pub rbx: u64,
+    /// `rbp`
+    pub rbp: u64,
+    /// The address execution continues at.
+    pub rip: u64,
+}
+
+impl Context {
+    /// Prepare the context of a thread that hasn't run yet.
+    ///
+    /// When first switched to, the thread will call `entry(arg)` on the stack
+    /// ending at `stack_top`. `entry` must never return.
+    ///
+    /// # Safety
+    /// `stack_top` must be the 16-byte aligned top of a mapped stack that
+    /// isn't used by anything else.
+    pub unsafe fn new_thread(
+        stack_top: VirtAddr,
+        entry: extern "C" fn(u64) -> !,
+        arg: u64,
+    ) -> Self {
+        assert!(
+            stack_top.is_aligned(16u64),
+            "thread stacks must be 16-byte aligned"
+        );
+
+        // The trampoline must be entered with a 16-byte aligned stack pointer,
+        // so that `entry` sees the alignment the ABI mandates after the `call`.
+        let registers_addr = stack_top - size_of::<SavedRegisters>() as u64 - 16u64;
+        let registers = SavedRegisters {
+            r12: arg,
+            r13: entry as usize as u64,
+            rip: jo12bar_os_thread_trampoline as unsafe extern "C" fn() -> ! as usize as u64,
+            ..Default::default()
+        };
+
+        // Safety: the caller guarantees that the stack is mapped and unused.
+        unsafe {
+            registers_addr
+                .as_mut_ptr::<SavedRegisters>()
+                .write(registers);
+        }
+
+        Self {
+            rsp: registers_addr.as_u64(),
+        }
+    }
+}
+
+extern "C" {
+    /// Save the callee-saved registers to the current stack, store the stack
+    /// pointer in `old`, and resume the thread whose stack pointer is in `new`.
+    ///
+    /// Returns once some other thread switches back to `old`.
+    fn jo12bar_os_switch_to(old: *mut Context, new: *const Context);
+
+    /// Entry point of new threads. Calls the function in `r13` with the
+    /// argument in `r12`.
+    fn jo12bar_os_thread_trampoline() -> !;
+}
+
+global_asm!(
+    ".global jo12bar_os_switch_to",
+    "jo12bar_os_switch_to:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, [rsi]",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    "",
+    ".global jo12bar_os_thread_trampoline",
+    "jo12bar_os_thread_trampoline:",
+    "mov rdi, r12",
+    "call r13",
+    "ud2",
+);
+
+/// Switch from the thread whose context will be saved in `old` to the thread
+/// whose context is stored in `new`.
+///
+/// # Safety
+/// - Interrupts must be disabled.
+/// - `new` must contain the context of a thread that isn't running, either
+///   because it was saved by a previous call to [`switch_to`] or because it
+///   was created by [`Context::new_thread`].
+/// - Both contexts must stay valid until the switch completes.
+pub unsafe fn switch_to(old: *mut Context, new: *const Context) {
+    // Safety: see above
+    unsafe { jo12bar_os_switch_to(old, new) }
+}
@@ -0,0 +1,286 @@
+//! Preemptive kernel threads.
+//!
+//! Each kernel thread runs on its own [`Stack`] with a guard page below it.
+//! Threads give up the CPU when they block ([`sleep`], [`park`],
+//! [`JoinHandle::join`]) or [yield][yield_now], and are preempted by the timer
+//! interrupt unless they are inside of a critical section.
+//!
+//! The code that called [`init()`] is adopted as the first kernel thread and keeps
+//! running on the stack the bootloader set up for it.
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+
+use crate::{mem::stack::Stack, prelude::*, time};
+
+use self::context::Context;
+
+pub mod context;
+mod scheduler;
+
+pub(crate) use scheduler::timer_tick;
+
+/// Initialize the scheduler and adopt the calling code as the first kernel thread.
+///
+/// # Safety
+/// - Must only be called once, on the bootstrap processor.
+/// - Memory and core locals must be initialized.
+pub unsafe fn init() {
+    // Safety: see above
+    unsafe { scheduler::init() };
+}
+
+/// Returns `true` once [`init()`] was called, and threads can be spawned and parked.
+pub fn is_initialized() -> bool {
+    scheduler::is_ready()
+}
+
+/// A unique identifier of a kernel thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// The scheduling state of a kernel thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ThreadState {
+    /// The thread is waiting in the run queue.
+    Ready,
+    /// The thread is currently running.
+    Running,
+    /// The thread is blocked until it is [unparked][Thread::unpark].
+    Parked,
+    /// The thread is blocked until a timer deadline passes.
+    Sleeping,
+    /// The thread has returned from its main function.
+    Finished,
+}
+
+impl ThreadState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Ready,
+            1 => Self::Running,
+            2 => Self::Parked,
+            3 => Self::Sleeping,
+            4 => Self::Finished,
+            _ => unreachable!("invalid thread state {value}"),
+        }
+    }
+}
+
+/// The main function of a thread, as it is passed to the thread's entry point.
+type ThreadMain = Box<dyn FnOnce() + Send + 'static>;
+
+/// Leak `main`, turning it into an argument for [`scheduler::thread_entry`].
+fn thread_main_into_raw(main: ThreadMain) -> u64 {
+    Box::into_raw(Box::new(main)) as u64
+}
+
+/// Take back ownership of a [`ThreadMain`] leaked by [`thread_main_into_raw`].
+///
+/// # Safety
+/// `raw` must have been created by [`thread_main_into_raw`] and must only be
+/// converted back once.
+unsafe fn thread_main_from_raw(raw: u64) -> ThreadMain {
+    // Safety: see above
+    unsafe { *Box::from_raw(raw as *mut ThreadMain) }
+}
+
+struct ThreadInner {
+    id: ThreadId,
+    state: AtomicU8,
+    /// Set by [`Thread::unpark`] if the thread wasn't parked, so that the next
+    /// call to [`park`] returns immediately.
+    unpark_token: AtomicBool,
+    /// The saved context while the thread isn't running.
+    context: UnsafeCell<Context>,
+    /// The stack of the thread. [`None`] for the adopted boot thread, and once
+    /// the thread has been reaped.
+    stack: TicketLock<Option<Stack>>,
+    /// Threads waiting for this thread to finish. [`None`] once it finished.
+    joiners: TicketLock<Option<Vec<Thread>>>,
+}
+
+// Safety: `context` is only accessed by the scheduler while switching to or away
+// from the thread, with interrupts disabled.
+unsafe impl Sync for ThreadInner {}
+
+/// A handle to a kernel thread.
+#[derive(Clone)]
+pub struct Thread {
+    inner: Arc<ThreadInner>,
+}
+
+impl Thread {
+    fn new(stack: Option<Stack>, context: Context) -> Self {
+        Self {
+            inner: Arc::new(ThreadInner {
+                id: ThreadId::new(),
+                state: AtomicU8::new(ThreadState::Ready as u8),
+                unpark_token: AtomicBool::new(false),
+                context: UnsafeCell::new(context),
+                stack: TicketLock::new(stack),
+                joiners: TicketLock::new(Some(Vec::new())),
+            }),
+        }
+    }
+
+    /// Create a thread for the code that is currently running. Its context is
+    /// filled in once it is first switched away from.
+    fn new_adopted() -> Self {
+        Self::new(None, Context::default())
+    }
+
+    /// The id of this thread.
+    pub fn id(&self) -> ThreadId {
+        self.inner.id
+    }
+
+    /// The current scheduling state of this thread.
+    pub fn state(&self) -> ThreadState {
+        ThreadState::from_u8(self.inner.state.load(Ordering::SeqCst))
+    }
+
+    fn set_state(&self, state: ThreadState) {
+        self.inner.state.store(state as u8, Ordering::SeqCst);
+    }
+
+    fn context_ptr(&self) -> *mut Context {
+        self.inner.context.get()
+    }
+
+    fn ptr_eq(&self, other: &Thread) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Wake up this thread if it is [parked][park].
+    ///
+    /// If it isn't parked, the next call to [`park`] by this thread returns
+    /// immediately. This can be called from interrupt handlers.
+    pub fn unpark(&self) {
+        scheduler::unpark(self);
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thread")
+            .field("id", &self.id())
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+/// Create a thread that runs `main` on a new stack, without scheduling it.
+fn create_thread(stack_page_count: u64, main: impl FnOnce() + Send + 'static) -> Thread {
+    let stack = Stack::allocate(stack_page_count).expect("failed to allocate kernel thread stack");
+    let main: ThreadMain = Box::new(main);
+
+    // Safety: The stack was just allocated and isn't used by anything else.
+    let context = unsafe {
+        Context::new_thread(
+            stack.top(),
+            scheduler::thread_entry,
+            thread_main_into_raw(main),
+        )
+    };
+
+    Thread::new(Some(stack), context)
+}
+
+/// An owned permission to join a kernel thread and get its result.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    thread: Thread,
+    result: Arc<TicketLock<Option<T>>>,
+}
+
+impl<T: Send> JoinHandle<T> {
+    /// The thread this handle refers to.
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    /// Returns `true` if the thread has finished running.
+    pub fn is_finished(&self) -> bool {
+        self.thread.inner.joiners.lock().is_none()
+    }
+
+    /// Block until the thread finishes, and return its result.
+    pub fn join(self) -> T {
+        // Register only once. park() may return spuriously, so check again
+        // after every wakeup.
+        if let Some(joiners) = self.thread.inner.joiners.lock().as_mut() {
+            joiners.push(current());
+        }
+        while !self.is_finished() {
+            park();
+        }
+
+        self.result
+            .lock()
+            .take()
+            .expect("finished thread did not store a result")
+    }
+}
+
+/// Spawn a new kernel thread running `f`.
+///
+/// # Panics
+/// Panics if called before [`init()`], or if no stack could be allocated.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(TicketLock::new(None));
+    let their_result = result.clone();
+
+    let thread = create_thread(crate::DEFAULT_STACK_PAGE_COUNT, move || {
+        let value = f();
+        *their_result.lock() = Some(value);
+    });
+    scheduler::spawn(thread.clone());
+
+    JoinHandle { thread, result }
+}
+
+/// Returns a handle to the thread that is currently running.
+pub fn current() -> Thread {
+    scheduler::current()
+}
+
+/// Give up the rest of the current time slice to other ready threads.
+pub fn yield_now() {
+    scheduler::yield_now();
+}
+
+/// Block the current thread for at least `duration`.
+///
+/// The resolution is one timer tick. This must not be called from interrupt
+/// handlers or while holding a lock.
+pub fn sleep(duration: Duration) {
+    let deadline = time::ticks() + time::duration_to_ticks(duration).max(1);
+    scheduler::sleep_until(deadline);
+}
+
+/// Block the current thread until it is [unparked][Thread::unpark].
+///
+/// Like [`std::thread::park`](https://doc.rust-lang.org/std/thread/fn.park.html),
+/// this may return spuriously. This must not be called from interrupt handlers or
+/// while holding a lock.
+pub fn park() {
+    scheduler::park();
+}
@@ -0,0 +1,375 @@
+//! The kernel thread scheduler.
+//!
+//! Runnable threads are kept in a FIFO run queue and switched between in a
+//! round-robin fashion. A thread is switched away from when it blocks, yields, or
+//! when the timer interrupt fires and the thread is preemptable.
+//!
+//! Threads might get moved onto the run queue from within interrupt handlers
+//! (e.g. when they are woken up), where the heap can't be used. Therefore, the
+//! run queue and the list of sleeping threads always have enough capacity for
+//! every thread that exists.
+//!
+//! TODO: This only supports a single core. Once more cores are booted, there
+//! needs to be a run queue per core, and the scheduler lock needs to be held
+//! until a switch is complete.
+
+use alloc::{collections::BTreeMap, collections::VecDeque, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{context, Thread, ThreadId, ThreadState};
+use crate::{core_locals::InterruptBookkeeping, locals, prelude::*, time};
+
+/// The global scheduler state.
+static SCHEDULER: TicketLock<Scheduler> = TicketLock::new_non_preemtable(Scheduler::new());
+
+/// Set once [`init()`] is done.
+static SCHEDULER_READY: AtomicBool = AtomicBool::new(false);
+
+type SchedulerGuard<'a> = LockCellGuard<'a, Scheduler, TicketLock<Scheduler>>;
+
+struct Scheduler {
+    /// The thread that is currently running.
+    current: Option<Thread>,
+    /// The thread that is run when no other thread is ready. It is never put
+    /// onto the run queue.
+    idle: Option<Thread>,
+    /// Threads that are ready to run.
+    ready: VecDeque<Thread>,
+    /// Threads that are sleeping, along with the tick they should be woken up at.
+    sleeping: Vec<(u64, Thread)>,
+    /// Threads that finished, but whose resources haven't been freed yet.
+    dead: Vec<Thread>,
+    /// All threads that haven't been reaped yet.
+    threads: BTreeMap<ThreadId, Thread>,
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        Self {
+            current: None,
+            idle: None,
+            ready: VecDeque::new(),
+            sleeping: Vec::new(),
+            dead: Vec::new(),
+            threads: BTreeMap::new(),
+        }
+    }
+
+    fn current(&self) -> &Thread {
+        self.current
+            .as_ref()
+            .expect("scheduler has no current thread")
+    }
+
+    /// Register a new thread, making sure the run queue and sleep list can hold
+    /// every thread without allocating.
+    fn register(&mut self, thread: Thread) {
+        self.threads.insert(thread.id(), thread);
+        let thread_count = self.threads.len();
+        self.ready.reserve(thread_count);
+        self.sleeping.reserve(thread_count);
+    }
+}
+
+/// Initialize the scheduler, adopting the current execution context as the
+/// first kernel thread.
+///
+/// # Safety
+/// - Must only be called once, on the bootstrap processor.
+/// - Memory and core locals must be initialized.
+pub(super) unsafe fn init() {
+    let boot_thread = Thread::new_adopted();
+    let idle_thread = super::create_thread(crate::DEFAULT_STACK_PAGE_COUNT, idle_main);
+
+    {
+        let mut sched = SCHEDULER.lock();
+        assert!(sched.current.is_none(), "scheduler already initialized");
+
+        boot_thread.set_state(ThreadState::Running);
+        sched.register(boot_thread.clone());
+        sched.register(idle_thread.clone());
+        sched.current = Some(boot_thread);
+        sched.idle = Some(idle_thread);
+    }
+
+    SCHEDULER_READY.store(true, Ordering::Release);
+}
+
+/// Returns `true` once the scheduler is initialized.
+pub(super) fn is_ready() -> bool {
+    SCHEDULER_READY.load(Ordering::Acquire)
+}
+
+/// Add a newly created thread to the run queue.
+pub(super) fn spawn(thread: Thread) {
+    assert!(
+        is_ready(),
+        "cannot spawn threads before the scheduler is initialized"
+    );
+
+    reap_dead_threads();
+
+    let mut sched = SCHEDULER.lock();
+    thread.set_state(ThreadState::Ready);
+    sched.register(thread.clone());
+    sched.ready.push_back(thread);
+}
+
+/// Returns a handle to the currently running thread.
+pub(super) fn current() -> Thread {
+    SCHEDULER.lock().current().clone()
+}
+
+/// Switch to the next ready thread, if there is one.
+pub(super) fn yield_now() {
+    if !is_ready() {
+        return;
+    }
+    assert_can_block();
+
+    let sched = SCHEDULER.lock();
+    if !sched.ready.is_empty() {
+        switch_away(sched);
+    }
+}
+
+/// Block the current thread until its unpark token is available.
+pub(super) fn park() {
+    assert!(
+        is_ready(),
+        "cannot park before the scheduler is initialized"
+    );
+    assert_can_block();
+
+    let sched = SCHEDULER.lock();
+    let current = sched.current();
+    if current.inner.unpark_token.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    current.set_state(ThreadState::Parked);
+    switch_away(sched);
+}
+
+/// Make `thread`'s unpark token available, waking it up if it's parked.
+///
+/// This can be called from interrupt handlers.
+pub(super) fn unpark(thread: &Thread) {
+    let mut sched = SCHEDULER.lock();
+    if thread.state() == ThreadState::Parked {
+        thread.set_state(ThreadState::Ready);
+        sched.ready.push_back(thread.clone());
+    } else {
+        thread.inner.unpark_token.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Block the current thread until the timer has ticked `deadline` times.
+pub(super) fn sleep_until(deadline: u64) {
+    if !is_ready() {
+        while time::ticks() < deadline {
+            crate::cpu::halt_single();
+        }
+        return;
+    }
+    assert_can_block();
+
+    let mut sched = SCHEDULER.lock();
+    let current = sched.current().clone();
+    current.set_state(ThreadState::Sleeping);
+    sched.sleeping.push((deadline, current));
+    switch_away(sched);
+}
+
+/// Finish the current thread, waking up any threads waiting to join it.
+pub(super) fn exit() -> ! {
+    let current = current();
+    let joiners = current.inner.joiners.lock().take().unwrap_or_default();
+    for joiner in joiners {
+        joiner.unpark();
+    }
+
+    let sched = SCHEDULER.lock();
+    current.set_state(ThreadState::Finished);
+    drop(current);
+    switch_away(sched);
+
+    unreachable!("finished thread was resumed");
+}
+
+/// Called by the timer interrupt handler on every tick.
+///
+/// Wakes up sleeping threads whose deadline passed, and preempts the current
+/// thread if it isn't inside of a critical section or a nested interrupt.
+pub(crate) fn timer_tick() {
+    if !is_ready() {
+        return;
+    }
+
+    let locals = locals!();
+    let can_preempt =
+        locals.interrupt_depth() == 1 && !locals.in_exception() && !locals.in_critical_section();
+
+    let mut sched = SCHEDULER.lock();
+
+    let now = time::ticks();
+    let mut i = 0;
+    while i < sched.sleeping.len() {
+        if sched.sleeping[i].0 <= now {
+            let (_, thread) = sched.sleeping.swap_remove(i);
+            thread.set_state(ThreadState::Ready);
+            sched.ready.push_back(thread);
+        } else {
+            i += 1;
+        }
+    }
+
+    if can_preempt && !sched.ready.is_empty() {
+        switch_away(sched);
+    }
+}
+
+/// Free the resources of threads that finished.
+fn reap_dead_threads() {
+    let dead = {
+        let mut sched = SCHEDULER.lock();
+        let dead = core::mem::take(&mut sched.dead);
+        for thread in dead.iter() {
+            sched.threads.remove(&thread.id());
+        }
+        dead
+    };
+
+    for thread in dead {
+        drop(thread.inner.stack.lock().take());
+    }
+}
+
+/// Panics if the current thread can't block right now.
+fn assert_can_block() {
+    let locals = locals!();
+    assert!(
+        !locals.in_interrupt() && !locals.in_exception(),
+        "kernel threads cannot block inside of interrupts or exceptions"
+    );
+    assert!(
+        !locals.in_critical_section(),
+        "kernel threads cannot block while inside of a critical section"
+    );
+}
+
+/// Switch from the current thread to the next ready thread, or the idle thread
+/// if no thread is ready.
+///
+/// The caller must have set the state of the current thread. If it is still
+/// [`ThreadState::Running`], it is put back onto the run queue.
+///
+/// Returns once the current thread is switched back to.
+fn switch_away(mut sched: SchedulerGuard<'_>) {
+    let current = sched
+        .current
+        .take()
+        .expect("scheduler has no current thread");
+    let current_is_idle = sched
+        .idle
+        .as_ref()
+        .is_some_and(|idle| idle.ptr_eq(&current));
+
+    match current.state() {
+        ThreadState::Running => {
+            current.set_state(ThreadState::Ready);
+            if !current_is_idle {
+                sched.ready.push_back(current.clone());
+            }
+        }
+        ThreadState::Finished => sched.dead.push(current.clone()),
+        ThreadState::Ready | ThreadState::Parked | ThreadState::Sleeping => {}
+    }
+
+    let next = sched
+        .ready
+        .pop_front()
+        .or_else(|| sched.idle.clone())
+        .expect("scheduler has no idle thread");
+    next.set_state(ThreadState::Running);
+
+    if next.ptr_eq(&current) {
+        sched.current = Some(next);
+        return;
+    }
+
+    let old_context = current.context_ptr();
+    let new_context = next.context_ptr();
+    sched.current = Some(next);
+
+    // Both threads are kept alive by `sched.threads` until they are reaped, and
+    // a thread is only reaped once it finished and was switched away from.
+    drop(current);
+
+    // Keep interrupts disabled until the switch is complete. They are enabled
+    // again by whichever thread we switch to, once its bookkeeping is restored.
+    // Safety: The matching `enable_interrupts` happens after the switch.
+    unsafe {
+        locals!().disable_interrupts();
+    }
+    drop(sched);
+
+    let bookkeeping = locals!().save_bookkeeping();
+
+    // Safety: Interrupts are disabled and both contexts belong to live threads.
+    // `new_context` was either saved by an earlier switch or created for a new
+    // thread.
+    unsafe {
+        context::switch_to(old_context, new_context);
+    }
+
+    // Safety: We just got switched back to, so this restores our own bookkeeping,
+    // which includes the `disable_interrupts` from above.
+    unsafe {
+        locals!().restore_bookkeeping(bookkeeping);
+        locals!().enable_interrupts();
+    }
+}
+
+/// Entry point of every new thread, called by the context switching trampoline.
+///
+/// `arg` is a pointer created by [`super::thread_main_into_raw`].
+pub(super) extern "C" fn thread_entry(arg: u64) -> ! {
+    // Safety: This thread just got switched to for the first time, so interrupts
+    // are disabled and the bookkeeping still belongs to the previous thread.
+    unsafe {
+        locals!().restore_bookkeeping(InterruptBookkeeping::new_thread());
+        locals!().enable_interrupts();
+    }
+
+    // Safety: `arg` was created by `create_thread` from a leaked box, and
+    // every thread is only started once.
+    let main = unsafe { super::thread_main_from_raw(arg) };
+    main();
+
+    exit();
+}
+
+/// The main function of the idle thread.
+fn idle_main() {
+    loop {
+        reap_dead_threads();
+
+        // Safety: Interrupts are enabled again right after checking for ready threads.
+        unsafe {
+            locals!().disable_interrupts();
+        }
+        if SCHEDULER.lock().ready.is_empty() {
+            // Safety: matches the `disable_interrupts` from above.
+            unsafe {
+                locals!().enable_interrupts_and_hlt();
+            }
+        } else {
+            // Safety: matches the `disable_interrupts` from above.
+            unsafe {
+                locals!().enable_interrupts();
+            }
+            yield_now();
+        }
+    }
+}
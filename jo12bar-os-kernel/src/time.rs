@@ -0,0 +1,50 @@
+//! Timekeeping based on the Programmable Interval Timer (PIT).
+//!
+//! The PIT is left at the divisor the firmware programmed it with, so it fires
+//! the timer interrupt roughly 18.2 times per second. Every interrupt is counted
+//! as one tick.
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Frequency of the PIT's input clock in Hz.
+pub const PIT_BASE_FREQUENCY_HZ: u64 = 1_193_182;
+
+/// The divisor used by the PIT.
+///
+/// A divisor of `0` is interpreted by the PIT as `65536`, which is the default.
+pub const PIT_DIVISOR: u64 = 65536;
+
+/// Number of timer ticks since interrupts were enabled on the bootstrap processor.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of timer ticks since interrupts were first enabled.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since interrupts were first enabled, with a resolution of
+/// one timer tick.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Converts a number of timer ticks into a [`Duration`].
+pub const fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * PIT_DIVISOR as u128 * 1_000_000_000 / PIT_BASE_FREQUENCY_HZ as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Converts a [`Duration`] into a number of timer ticks, rounding up.
+pub const fn duration_to_ticks(duration: Duration) -> u64 {
+    let divisor = PIT_DIVISOR as u128 * 1_000_000_000;
+    let ticks = (duration.as_nanos() * PIT_BASE_FREQUENCY_HZ as u128).div_ceil(divisor);
+    ticks as u64
+}
+
+/// Called by the timer interrupt handler once per interrupt.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,158 @@
+//! A condition variable, used to block until some condition guarded by a lock
+//! becomes true.
+
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::{
+    lock_cell::{LockCell, LockCellGuard},
+    mutex::Mutex,
+    wait_queue::{WaitQueue, Waiter},
+    Parker,
+};
+
+/// A condition variable.
+///
+/// Waiters are notified in FIFO order. Like
+/// [`std::sync::Condvar`](https://doc.rust-lang.org/std/sync/struct.Condvar.html),
+/// waiting can wake up spuriously, so the condition should always be checked in
+/// a loop, e.g. by using [`Condvar::wait_while`].
+pub struct Condvar<I: Parker> {
+    waiters: WaitQueue<I>,
+}
+
+impl<I: Parker> Condvar<I> {
+    /// Creates a new [`Condvar`].
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Release the lock held by `guard` and block the current thread until
+    /// this condition variable is notified. The lock is taken again before
+    /// returning.
+    ///
+    /// Works with any [`LockCell`], but blocking while holding a spinning lock
+    /// is not allowed, so this should usually be used with a [`Mutex`].
+    pub fn wait<'l, T, M: LockCell<T>>(
+        &self,
+        guard: LockCellGuard<'l, T, M>,
+    ) -> LockCellGuard<'l, T, M> {
+        let lockcell = guard.lockcell;
+
+        // Enqueue before releasing the lock, so that no notification is lost.
+        let waiter = Waiter::for_current_thread();
+        self.waiters.push(waiter.clone());
+        drop(guard);
+
+        waiter.wait();
+        lockcell.lock()
+    }
+
+    /// Block the current thread until `condition` returns `false`.
+    ///
+    /// `condition` is called with the lock held, before waiting for the first time
+    /// and after every notification.
+    pub fn wait_while<'l, T, M: LockCell<T>>(
+        &self,
+        mut guard: LockCellGuard<'l, T, M>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> LockCellGuard<'l, T, M> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Release the lock held by `guard` and suspend the current async task until
+    /// this condition variable is notified. The lock is taken again before
+    /// returning.
+    pub async fn wait_async<'l, T: Send>(
+        &self,
+        guard: LockCellGuard<'l, T, Mutex<T, I>>,
+    ) -> LockCellGuard<'l, T, Mutex<T, I>> {
+        let mutex = guard.lockcell;
+
+        // Enqueue before releasing the lock, so that no notification is lost.
+        let waiter = Waiter::for_task();
+        self.waiters.push(waiter.clone());
+        drop(guard);
+
+        Notified {
+            condvar: self,
+            waiter: Some(waiter),
+        }
+        .await;
+
+        mutex.lock_async().await
+    }
+
+    /// Wake up the first waiter.
+    ///
+    /// This can be used from within interrupts. Returns `false` if there
+    /// were no waiters.
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    /// Wake up all waiters.
+    ///
+    /// This can be used from within interrupts. Returns the number of waiters
+    /// that were woken up.
+    pub fn notify_all(&self) -> usize {
+        self.waiters.notify_all()
+    }
+}
+
+impl<I: Parker> Default for Condvar<I> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Parker> fmt::Debug for Condvar<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Condvar").finish_non_exhaustive()
+    }
+}
+
+/// Resolves once a waiter that is already in the [`Condvar`]'s queue is notified.
+struct Notified<'c, I: Parker> {
+    condvar: &'c Condvar<I>,
+    waiter: Option<Arc<Waiter<I>>>,
+}
+
+impl<I: Parker> Future for Notified<'_, I> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let waiter = self
+            .waiter
+            .as_ref()
+            .expect("Notified polled after completion");
+        let poll = waiter.poll_notified(cx);
+        if poll.is_ready() {
+            self.waiter = None;
+        }
+        poll
+    }
+}
+
+impl<I: Parker> Drop for Notified<'_, I> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            // Don't swallow a notification meant for someone who is still waiting.
+            if !self.condvar.waiters.remove(&waiter) {
+                self.condvar.notify_one();
+            }
+        }
+    }
+}
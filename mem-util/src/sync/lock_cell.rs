@@ -3,7 +3,9 @@
 //!
 //! [`LockCell`] gaurds simultaneous access (read _or_ write) to a value, while
 //! [`RwLockCell`] allows for either simultaneous read access to a value or a
-//! single write access.
+//! single write access. Read access can also be taken as upgradable, so that
+//! it can later be turned into write access, and write access can be
+//! downgraded to read access.
 
 use core::{
     fmt::Display,
@@ -35,6 +37,13 @@ where
     /// Get read-only access to the value of this lock. Blocks until access is granted.
     fn read(&self) -> ReadCellGuard<'_, T, Self>;
 
+    /// Get read-only access to the value of this lock, that can later be upgraded
+    /// to write access. Blocks until access is granted.
+    ///
+    /// There can only be one upgradable reader at a time, but it shares the lock
+    /// with normal readers.
+    fn upgradable_read(&self) -> UpgradableReadCellGuard<'_, T, Self>;
+
     /// Get mutable access to the value of this lock. Blocks until access is granted.
     ///
     /// The default implementation just calls [`<Self as LockCell<T>>::lock()`][LockCell::lock()].
@@ -138,6 +147,24 @@ where
     }
 }
 
+impl<'l, T, M> LockCellGuard<'l, T, M>
+where
+    M: ?Sized + RwCellInternal<T>,
+{
+    /// Turn this write guard into a read guard, without letting any writer
+    /// take the lock in between.
+    pub fn downgrade(self) -> ReadCellGuard<'l, T, M> {
+        let rw_cell = self.lockcell;
+        core::mem::forget(self);
+
+        // Safety: We held the write lock and gave up its guard.
+        unsafe {
+            rw_cell.downgrade_write();
+            ReadCellGuard::new(rw_cell)
+        }
+    }
+}
+
 impl<T, M: ?Sized + LockCellInternal<T>> !Sync for LockCellGuard<'_, T, M> {}
 
 impl<'l, T, M> Deref for LockCellGuard<'l, T, M>
@@ -233,6 +260,36 @@ pub trait RwCellInternal<T>: LockCellInternal<T> {
     /// core/interrupt etc could take the lock during or right after this call
     /// finishes.
     fn open_to_read(&self) -> bool;
+
+    /// Turn the write lock held by the caller into a read lock, without letting
+    /// any writer take the lock in between.
+    ///
+    /// # Safety
+    /// The caller must hold the write lock, and must give up its
+    /// [`LockCellGuard`] for a [`ReadCellGuard`].
+    unsafe fn downgrade_write(&self);
+
+    /// Turn the upgradable read lock held by the caller into a write lock,
+    /// waiting until all other readers are gone.
+    ///
+    /// # Safety
+    /// The caller must hold the upgradable read lock, and must give up its
+    /// [`UpgradableReadCellGuard`] for a [`LockCellGuard`].
+    unsafe fn upgrade_read(&self);
+
+    /// Turn the upgradable read lock held by the caller into a normal read lock.
+    ///
+    /// # Safety
+    /// The caller must hold the upgradable read lock, and must give up its
+    /// [`UpgradableReadCellGuard`] for a [`ReadCellGuard`].
+    unsafe fn downgrade_upgradable(&self);
+
+    /// Release the upgradable read lock.
+    ///
+    /// # Safety
+    /// This should only be called when the [`UpgradableReadCellGuard`]
+    /// corresponding to this [`RWLockCell`] is dropped.
+    unsafe fn release_upgradable(&self);
 }
 
 /// A guard structure that is used to guard read access to a lock.
@@ -289,6 +346,82 @@ impl<'l, T, M: ?Sized + RwCellInternal<T>> Drop for ReadCellGuard<'l, T, M> {
     }
 }
 
+/// A guard structure that is used to guard read access to a lock, that can be
+/// upgraded to write access.
+///
+/// This can be obtained from [`RWLockCell::upgradable_read`]
+#[derive(Debug)]
+pub struct UpgradableReadCellGuard<'l, T, M: ?Sized + RwCellInternal<T>> {
+    pub(super) rw_cell: &'l M,
+    pub(super) _phantom: PhantomData<T>,
+}
+
+impl<'l, T, M: ?Sized + RwCellInternal<T>> UpgradableReadCellGuard<'l, T, M> {
+    /// creates a new guard. This should only be called if you implement a [RWLockCell].
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that only 1 [UpgradableReadCellGuard] exists for
+    /// any given `rw_cell` at a time, and no [LockCellGuard].
+    pub unsafe fn new(rw_cell: &'l M) -> Self {
+        UpgradableReadCellGuard {
+            rw_cell,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Turn this guard into a write guard, waiting until all other readers are gone.
+    pub fn upgrade(self) -> LockCellGuard<'l, T, M> {
+        let rw_cell = self.rw_cell;
+        core::mem::forget(self);
+
+        // Safety: We held the upgradable read lock and gave up its guard.
+        unsafe {
+            rw_cell.upgrade_read();
+            LockCellGuard::new(rw_cell)
+        }
+    }
+
+    /// Turn this guard into a normal read guard, allowing someone else to take
+    /// an upgradable read guard.
+    pub fn downgrade(self) -> ReadCellGuard<'l, T, M> {
+        let rw_cell = self.rw_cell;
+        core::mem::forget(self);
+
+        // Safety: We held the upgradable read lock and gave up its guard.
+        unsafe {
+            rw_cell.downgrade_upgradable();
+            ReadCellGuard::new(rw_cell)
+        }
+    }
+}
+
+impl<'l, T, M: ?Sized + RwCellInternal<T>> !Sync for UpgradableReadCellGuard<'l, T, M> {}
+
+impl<'l, T, M: ?Sized + RwCellInternal<T>> Deref for UpgradableReadCellGuard<'l, T, M> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: While the guard exists there can't be any mut access to the lock
+        // and we only give out immutable access
+        unsafe { self.rw_cell.get() }
+    }
+}
+
+impl<'l, T: Display, M: ?Sized + RwCellInternal<T>> Display for UpgradableReadCellGuard<'l, T, M> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'l, T, M: ?Sized + RwCellInternal<T>> Drop for UpgradableReadCellGuard<'l, T, M> {
+    fn drop(&mut self) {
+        unsafe {
+            self.rw_cell.release_upgradable();
+        }
+    }
+}
+
 /// A wrapper for a [`LockCell`] of an `MaybeUninit<T>`.
 ///
 /// Unlike a normal [`LockCell`], [`UnwrapLock::lock`] will return `T` or panic
@@ -314,6 +447,7 @@ macro_rules! unwrap_lock_wrapper {
                 ///
                 /// # Safety
                 /// Caller must ensure that the [`UnwrapLockCell`] is initialized before it is accessed.
+                #[track_caller]
                 pub const unsafe fn new_uninit() -> Self {
                     unsafe {
                         crate::sync::lock_cell::UnwrapLockCell::new(
@@ -326,6 +460,7 @@ macro_rules! unwrap_lock_wrapper {
                 ///
                 /// # Safety
                 /// Caller must ensure that the [`UnwrapLockCell`] is initialized before it is accessed.
+                #[track_caller]
                 pub const unsafe fn new_non_preemtable_uninit() -> Self {
                     unsafe {
                         crate::sync::lock_cell::UnwrapLockCell::new(
@@ -349,6 +484,7 @@ impl<T: Send, L: LockCell<MaybeUninit<T>>> Drop for UnwrapLockCell<T, L> {
 }
 
 impl<T: Send, L: LockCell<MaybeUninit<T>> + Default> Default for UnwrapLockCell<T, L> {
+    #[track_caller]
     fn default() -> Self {
         Self {
             lockcell: Default::default(),
@@ -376,6 +512,7 @@ impl<T: Send, L: LockCell<MaybeUninit<T>>> UnwrapLockCell<T, L> {
     /// Gives access to the locked [`MaybeUninit`]. Blocks until the lock is accessible.
     ///
     /// This is intended for initialization of the [`UnwrapLockCell`].
+    #[track_caller]
     pub fn lock_uninit(&self) -> LockCellGuard<'_, MaybeUninit<T>, Self> {
         let inner_guard = self.lockcell.lock();
         core::mem::forget(inner_guard);
@@ -384,12 +521,14 @@ impl<T: Send, L: LockCell<MaybeUninit<T>>> UnwrapLockCell<T, L> {
 }
 
 impl<T: Send, L: LockCell<MaybeUninit<T>>> LockCell<T> for UnwrapLockCell<T, L> {
+    #[track_caller]
     fn lock(&self) -> LockCellGuard<'_, T, Self> {
         let inner_guard = self.lockcell.lock();
         core::mem::forget(inner_guard);
         unsafe { LockCellGuard::new(self) }
     }
 
+    #[track_caller]
     fn try_lock(&self) -> Option<LockCellGuard<'_, T, Self>> {
         if let Some(inner_guard) = self.lockcell.try_lock() {
             core::mem::forget(inner_guard);
@@ -460,11 +599,19 @@ impl<T: Send, L: LockCell<MaybeUninit<T>>> LockCellInternal<MaybeUninit<T>>
 }
 
 impl<T: Send, L: RwLockCell<MaybeUninit<T>>> RwLockCell<T> for UnwrapLockCell<T, L> {
+    #[track_caller]
     fn read(&self) -> ReadCellGuard<'_, T, Self> {
         let inner_guard = self.lockcell.read();
         core::mem::forget(inner_guard);
         unsafe { ReadCellGuard::new(self) }
     }
+
+    #[track_caller]
+    fn upgradable_read(&self) -> UpgradableReadCellGuard<'_, T, Self> {
+        let inner_guard = self.lockcell.upgradable_read();
+        core::mem::forget(inner_guard);
+        unsafe { UpgradableReadCellGuard::new(self) }
+    }
 }
 
 impl<T: Send, L: RwLockCell<MaybeUninit<T>>> RwCellInternal<T> for UnwrapLockCell<T, L> {
@@ -478,7 +625,27 @@ impl<T: Send, L: RwLockCell<MaybeUninit<T>>> RwCellInternal<T> for UnwrapLockCel
         }
     }
 
+    unsafe fn force_release_read(&self) {
+        unsafe { self.lockcell.force_release_read() }
+    }
+
     fn open_to_read(&self) -> bool {
         self.lockcell.open_to_read()
     }
+
+    unsafe fn downgrade_write(&self) {
+        unsafe { self.lockcell.downgrade_write() }
+    }
+
+    unsafe fn upgrade_read(&self) {
+        unsafe { self.lockcell.upgrade_read() }
+    }
+
+    unsafe fn downgrade_upgradable(&self) {
+        unsafe { self.lockcell.downgrade_upgradable() }
+    }
+
+    unsafe fn release_upgradable(&self) {
+        unsafe { self.lockcell.release_upgradable() }
+    }
 }
@@ -0,0 +1,269 @@
+//! Lock contention statistics and deadlock diagnostics.
+//!
+//! Only available with the `lock-debug` feature. Every [`TicketLock`] then
+//! records where it was last taken, how long callers spun before getting it, and
+//! how long it was held. Hold times are measured in CPU timestamp counter cycles.
+//!
+//! If a core spins on a lock for longer than [`deadlock_spin_threshold()`]
+//! iterations, a [`DeadlockReport`] is passed to the sink set with
+//! [`set_report_sink()`]. The report is repeated every time the threshold is
+//! passed again.
+//!
+//! [`TicketLock`]: super::ticket_lock::TicketLock
+
+use core::{
+    fmt,
+    panic::Location,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
+};
+
+use crate::types::CoreId;
+
+/// The default value of [`deadlock_spin_threshold()`].
+pub const DEFAULT_DEADLOCK_SPIN_THRESHOLD: u64 = 100_000_000;
+
+static DEADLOCK_SPIN_THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_DEADLOCK_SPIN_THRESHOLD);
+
+/// A function that is called with every [`DeadlockReport`].
+pub type ReportSink = fn(&DeadlockReport<'_>);
+
+/// The current [`ReportSink`], stored as a pointer so it can be swapped atomically.
+static REPORT_SINK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Set while a report is being written, so that locks taken by the sink
+/// itself don't produce nested reports.
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+/// Returns the number of spin iterations after which a waiting core reports
+/// a suspected deadlock.
+pub fn deadlock_spin_threshold() -> u64 {
+    DEADLOCK_SPIN_THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Set the number of spin iterations after which a waiting core reports a
+/// suspected deadlock. A threshold of 0 disables reporting.
+pub fn set_deadlock_spin_threshold(spins: u64) {
+    DEADLOCK_SPIN_THRESHOLD.store(spins, Ordering::Relaxed);
+}
+
+/// Set the function that receives [`DeadlockReport`]s.
+///
+/// The sink is called while the reporting core is still waiting for the lock,
+/// so it should not block on any lock itself.
+pub fn set_report_sink(sink: ReportSink) {
+    REPORT_SINK.store(sink as *mut (), Ordering::Release);
+}
+
+/// Pass `report` to the [`ReportSink`], unless a report is already being written.
+fn report(report: &DeadlockReport<'_>) {
+    let sink = REPORT_SINK.load(Ordering::Acquire);
+    if sink.is_null() {
+        return;
+    }
+
+    if REPORTING.swap(true, Ordering::Acquire) {
+        return;
+    }
+
+    // Safety: `REPORT_SINK` is only ever set from a valid `ReportSink`.
+    let sink: ReportSink = unsafe { core::mem::transmute::<*mut (), ReportSink>(sink) };
+    sink(report);
+
+    REPORTING.store(false, Ordering::Release);
+}
+
+/// Read the CPU's timestamp counter.
+#[inline]
+pub fn timestamp() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // Safety: `rdtsc` has no side effects.
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        0
+    }
+}
+
+/// A suspected deadlock, reported when a core spins on a lock for too long.
+#[derive(Debug, Clone, Copy)]
+pub struct DeadlockReport<'a> {
+    /// The type of the lock, e.g. `"TicketLock"`.
+    pub lock_type: &'static str,
+    /// The address of the lock.
+    pub lock_addr: usize,
+    /// The core that is waiting for the lock.
+    pub waiter: CoreId,
+    /// Where the waiting core tries to take the lock.
+    pub waiter_location: &'static Location<'static>,
+    /// The core that currently holds the lock, if known.
+    pub owner: Option<CoreId>,
+    /// Where the current owner took the lock, if known.
+    pub owner_location: Option<&'static Location<'static>>,
+    /// How many iterations the waiting core spun so far.
+    pub spins: u64,
+    /// The statistics of the lock at the time of the report.
+    pub stats: &'a LockStats,
+}
+
+impl fmt::Display for DeadlockReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "suspected deadlock: core {} spun {} times on {} at {:#x} ({})",
+            self.waiter, self.spins, self.lock_type, self.lock_addr, self.waiter_location
+        )?;
+
+        match self.owner {
+            Some(owner) => write!(f, ", held by core {owner}")?,
+            None => write!(f, ", owner unknown")?,
+        }
+        if let Some(location) = self.owner_location {
+            write!(f, " since {location}")?;
+        }
+
+        write!(f, "; {}", self.stats)
+    }
+}
+
+/// A snapshot of the contention statistics of a lock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// The number of times the lock was taken.
+    pub acquisitions: u64,
+    /// The number of times the lock was taken after spinning.
+    pub contended_acquisitions: u64,
+    /// The number of spin iterations over all acquisitions.
+    pub total_spins: u64,
+    /// The most spin iterations of a single acquisition.
+    pub max_spins: u64,
+    /// The timestamp counter cycles the lock was held for, over all acquisitions.
+    pub total_hold_cycles: u64,
+    /// The most timestamp counter cycles the lock was held for at once.
+    pub max_hold_cycles: u64,
+}
+
+impl fmt::Display for LockStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} acquisitions ({} contended), spins total/max: {}/{}, hold cycles total/max: {}/{}",
+            self.acquisitions,
+            self.contended_acquisitions,
+            self.total_spins,
+            self.max_spins,
+            self.total_hold_cycles,
+            self.max_hold_cycles
+        )
+    }
+}
+
+/// Debug information stored in each lock.
+#[derive(Debug)]
+pub struct LockDebugInfo {
+    /// Where the lock was last taken. Null if it was never taken.
+    location: AtomicPtr<Location<'static>>,
+    /// The timestamp at which the lock was last taken.
+    acquired_at: AtomicU64,
+    acquisitions: AtomicU64,
+    contended_acquisitions: AtomicU64,
+    total_spins: AtomicU64,
+    max_spins: AtomicU64,
+    total_hold_cycles: AtomicU64,
+    max_hold_cycles: AtomicU64,
+}
+
+impl LockDebugInfo {
+    /// Create empty debug information.
+    pub const fn new() -> Self {
+        Self {
+            location: AtomicPtr::new(ptr::null_mut()),
+            acquired_at: AtomicU64::new(0),
+            acquisitions: AtomicU64::new(0),
+            contended_acquisitions: AtomicU64::new(0),
+            total_spins: AtomicU64::new(0),
+            max_spins: AtomicU64::new(0),
+            total_hold_cycles: AtomicU64::new(0),
+            max_hold_cycles: AtomicU64::new(0),
+        }
+    }
+
+    /// Where the lock was last taken, or [`None`] if it was never taken.
+    pub fn location(&self) -> Option<&'static Location<'static>> {
+        let location = self.location.load(Ordering::Acquire);
+        // Safety: `location` is either null or was created from a `&'static Location`.
+        unsafe { location.as_ref() }
+    }
+
+    /// Returns a snapshot of the statistics.
+    pub fn stats(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended_acquisitions: self.contended_acquisitions.load(Ordering::Relaxed),
+            total_spins: self.total_spins.load(Ordering::Relaxed),
+            max_spins: self.max_spins.load(Ordering::Relaxed),
+            total_hold_cycles: self.total_hold_cycles.load(Ordering::Relaxed),
+            max_hold_cycles: self.max_hold_cycles.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Called after each spin iteration while waiting for the lock.
+    ///
+    /// Reports a suspected deadlock every time `spins` passes a multiple of the
+    /// [`deadlock_spin_threshold()`].
+    pub fn on_spin(
+        &self,
+        lock_type: &'static str,
+        lock_addr: usize,
+        waiter: CoreId,
+        waiter_location: &'static Location<'static>,
+        owner: Option<CoreId>,
+        spins: u64,
+    ) {
+        let threshold = deadlock_spin_threshold();
+        if threshold == 0 || !spins.is_multiple_of(threshold) {
+            return;
+        }
+
+        report(&DeadlockReport {
+            lock_type,
+            lock_addr,
+            waiter,
+            waiter_location,
+            owner,
+            owner_location: self.location(),
+            spins,
+            stats: &self.stats(),
+        });
+    }
+
+    /// Called once the lock was taken, after spinning `spins` times.
+    pub fn on_acquire(&self, location: &'static Location<'static>, spins: u64) {
+        self.location
+            .store(location as *const _ as *mut _, Ordering::Release);
+        self.acquired_at.store(timestamp(), Ordering::Relaxed);
+
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if spins > 0 {
+            self.contended_acquisitions.fetch_add(1, Ordering::Relaxed);
+            self.total_spins.fetch_add(spins, Ordering::Relaxed);
+            self.max_spins.fetch_max(spins, Ordering::Relaxed);
+        }
+    }
+
+    /// Called right before the lock is released.
+    pub fn on_release(&self) {
+        let held = timestamp().saturating_sub(self.acquired_at.load(Ordering::Relaxed));
+        self.total_hold_cycles.fetch_add(held, Ordering::Relaxed);
+        self.max_hold_cycles.fetch_max(held, Ordering::Relaxed);
+    }
+}
+
+impl Default for LockDebugInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
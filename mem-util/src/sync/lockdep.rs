@@ -0,0 +1,549 @@
+//! A lock ordering validator, modeled after Linux's
+//! [lockdep](https://docs.kernel.org/locking/lockdep-design.html).
+//!
+//! Only available with the `lockdep` feature. Every lock belongs to a
+//! [`LockClass`], which is identified by the location the lock was created at.
+//! All locks created by the same line of code share a class, so e.g. the locks
+//! of all channels of the same kind are treated as a single lock.
+//!
+//! Each core keeps a stack of the locks it currently holds. When a lock of class
+//! `B` is taken while holding a lock of class `A`, the dependency `A -> B` is
+//! recorded. If that dependency closes a cycle in the dependency graph, there
+//! is an order in which the involved locks can deadlock, even if it never
+//! happened so far, and a [`LockdepReport`] is passed to the sink set with
+//! [`set_report_sink()`]. Taking a preemtable lock inside of an interrupt is
+//! reported as well, since the interrupted code might be holding it.
+//!
+//! After the first report, validation is turned off, since the graph might be
+//! in an inconsistent state and the same problem would be reported again and
+//! again.
+//!
+//! Nesting two locks of the same class is not validated.
+
+use core::{
+    fmt,
+    panic::Location,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU16, AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::types::CoreId;
+
+/// The maximum number of lock classes that can be tracked.
+///
+/// Once this is exceeded, locks of new classes are no longer validated.
+pub const MAX_LOCK_CLASSES: usize = 128;
+
+/// The maximum number of locks a single core can hold at once.
+///
+/// Once this is exceeded, further locks taken by the core are no longer validated.
+pub const MAX_HELD_LOCKS: usize = 32;
+
+/// The maximum number of cores that can be validated.
+pub const MAX_CORES: usize = 64;
+
+/// The number of [`AtomicU64`]s needed for a bitset of all lock classes.
+const CLASS_SET_WORDS: usize = MAX_LOCK_CLASSES.div_ceil(64);
+
+/// Value of [`LockdepMap::class`] before the lock's class was looked up.
+const UNREGISTERED: u16 = u16::MAX;
+/// Value of [`LockdepMap::class`] if there was no space left for the lock's class.
+const UNTRACKED: u16 = u16::MAX - 1;
+
+/// Cleared once the first problem was reported.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// The creation location of each registered lock class, indexed by class ID.
+static CLASSES: [AtomicPtr<Location<'static>>; MAX_LOCK_CLASSES] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_LOCK_CLASSES];
+
+/// `DEPENDENCIES[a]` contains `b` if a lock of class `b` was taken while
+/// holding a lock of class `a`.
+static DEPENDENCIES: [[AtomicU64; CLASS_SET_WORDS]; MAX_LOCK_CLASSES] =
+    [const { [const { AtomicU64::new(0) }; CLASS_SET_WORDS] }; MAX_LOCK_CLASSES];
+
+/// The locks held by each core.
+static HELD: [HeldStack; MAX_CORES] = [const { HeldStack::new() }; MAX_CORES];
+
+/// A function that is called with every [`LockdepReport`].
+pub type ReportSink = fn(&LockdepReport<'_>);
+
+/// The current [`ReportSink`], stored as a pointer so it can be swapped atomically.
+static REPORT_SINK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Set the function that receives [`LockdepReport`]s.
+///
+/// If no sink is set, reports cause a panic instead.
+pub fn set_report_sink(sink: ReportSink) {
+    REPORT_SINK.store(sink as *mut (), Ordering::Release);
+}
+
+/// Returns `false` once validation was turned off because a problem was reported.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Turn validation off and pass `report` to the [`ReportSink`].
+fn report(report: &LockdepReport<'_>) {
+    if !ENABLED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+
+    let sink = REPORT_SINK.load(Ordering::Acquire);
+    if sink.is_null() {
+        panic!("{report}");
+    }
+
+    // Safety: `REPORT_SINK` is only ever set from a valid `ReportSink`.
+    let sink: ReportSink = unsafe { core::mem::transmute::<*mut (), ReportSink>(sink) };
+    sink(report);
+}
+
+/// A class of locks, that are validated as if they were a single lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockClass(u16);
+
+impl LockClass {
+    /// Where the locks of this class are created.
+    pub fn location(&self) -> &'static Location<'static> {
+        let location = CLASSES[self.0 as usize].load(Ordering::Acquire);
+        // Safety: Classes are only handed out after their location was set
+        // from a `&'static Location`.
+        unsafe { &*location }
+    }
+
+    /// Returns the classes that were taken while holding a lock of this class.
+    fn dependencies(&self) -> [u64; CLASS_SET_WORDS] {
+        let mut set = [0; CLASS_SET_WORDS];
+        for (word, dependencies) in set.iter_mut().zip(&DEPENDENCIES[self.0 as usize]) {
+            *word = dependencies.load(Ordering::Relaxed);
+        }
+        set
+    }
+
+    fn depends_on(&self, other: LockClass) -> bool {
+        let word = DEPENDENCIES[self.0 as usize][other.0 as usize / 64].load(Ordering::Relaxed);
+        word & (1 << (other.0 % 64)) != 0
+    }
+
+    fn add_dependency(&self, other: LockClass) {
+        DEPENDENCIES[self.0 as usize][other.0 as usize / 64]
+            .fetch_or(1 << (other.0 % 64), Ordering::Relaxed);
+    }
+
+    /// Look for a chain of dependencies from `self` to `to`.
+    ///
+    /// If one is found, it is written to `chain`, starting with `self` and ending
+    /// with `to`, and its length is returned.
+    fn find_chain(
+        &self,
+        to: LockClass,
+        chain: &mut [LockClass; MAX_LOCK_CLASSES],
+    ) -> Option<usize> {
+        // Breadth-first search, remembering how each class was reached.
+        let mut reached_from = [UNREGISTERED; MAX_LOCK_CLASSES];
+        let mut queue = [LockClass(0); MAX_LOCK_CLASSES];
+        let (mut head, mut tail) = (0, 1);
+        queue[0] = *self;
+        reached_from[self.0 as usize] = self.0;
+
+        while head < tail {
+            let class = queue[head];
+            head += 1;
+
+            if class == to {
+                // Walk back to `self`, then reverse to get the chain in order.
+                let mut len = 0;
+                let mut current = class;
+                loop {
+                    chain[len] = current;
+                    len += 1;
+                    if current == *self {
+                        break;
+                    }
+                    current = LockClass(reached_from[current.0 as usize]);
+                }
+                chain[..len].reverse();
+                return Some(len);
+            }
+
+            for (word_idx, mut word) in class.dependencies().into_iter().enumerate() {
+                while word != 0 {
+                    let next = (word_idx * 64 + word.trailing_zeros() as usize) as u16;
+                    word &= word - 1;
+
+                    if reached_from[next as usize] == UNREGISTERED {
+                        reached_from[next as usize] = class.0;
+                        queue[tail] = LockClass(next);
+                        tail += 1;
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for LockClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "class {} (created at {})", self.0, self.location())
+    }
+}
+
+/// A lock held by a core.
+#[derive(Debug, Clone, Copy)]
+pub struct HeldLock {
+    /// The address of the lock.
+    pub lock_addr: usize,
+    /// The class of the lock.
+    pub class: LockClass,
+    /// Where the lock was taken.
+    pub location: &'static Location<'static>,
+}
+
+impl fmt::Display for HeldLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "lock at {:#x} of {}, taken at {}",
+            self.lock_addr, self.class, self.location
+        )
+    }
+}
+
+/// A single entry of a [`HeldStack`].
+struct HeldEntry {
+    lock_addr: AtomicUsize,
+    class: AtomicU16,
+    location: AtomicPtr<Location<'static>>,
+}
+
+/// The locks held by a single core, in the order they were taken.
+///
+/// Only the owning core ever accesses its stack. Interrupts can push entries
+/// while the interrupted code modifies the stack, but they also remove them
+/// again before returning, so the stack is never observed in a broken state.
+struct HeldStack {
+    len: AtomicUsize,
+    /// The number of locks that didn't fit onto the stack and aren't tracked.
+    overflow: AtomicUsize,
+    entries: [HeldEntry; MAX_HELD_LOCKS],
+}
+
+impl HeldStack {
+    const fn new() -> Self {
+        Self {
+            len: AtomicUsize::new(0),
+            overflow: AtomicUsize::new(0),
+            entries: [const {
+                HeldEntry {
+                    lock_addr: AtomicUsize::new(0),
+                    class: AtomicU16::new(0),
+                    location: AtomicPtr::new(ptr::null_mut()),
+                }
+            }; MAX_HELD_LOCKS],
+        }
+    }
+
+    fn get(&self, idx: usize) -> HeldLock {
+        let entry = &self.entries[idx];
+        HeldLock {
+            lock_addr: entry.lock_addr.load(Ordering::Relaxed),
+            class: LockClass(entry.class.load(Ordering::Relaxed)),
+            // Safety: entries below `len` always hold a location taken from a
+            // `&'static Location`.
+            location: unsafe { &*entry.location.load(Ordering::Relaxed) },
+        }
+    }
+
+    fn set(&self, idx: usize, held: HeldLock) {
+        let entry = &self.entries[idx];
+        entry.lock_addr.store(held.lock_addr, Ordering::Relaxed);
+        entry.class.store(held.class.0, Ordering::Relaxed);
+        entry
+            .location
+            .store(held.location as *const _ as *mut _, Ordering::Relaxed);
+    }
+
+    /// Copy the held locks into `locks`, returning how many there are.
+    fn snapshot(&self, locks: &mut [Option<HeldLock>; MAX_HELD_LOCKS]) -> usize {
+        let len = self.len.load(Ordering::Relaxed);
+        for (idx, lock) in locks.iter_mut().enumerate().take(len) {
+            *lock = Some(self.get(idx));
+        }
+        len
+    }
+
+    fn push(&self, held: HeldLock) {
+        let len = self.len.load(Ordering::Relaxed);
+        if len == MAX_HELD_LOCKS {
+            self.overflow.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.set(len, held);
+        self.len.store(len + 1, Ordering::Relaxed);
+    }
+
+    /// Remove the most recently taken entry for the lock at `lock_addr`.
+    fn remove(&self, lock_addr: usize) {
+        let len = self.len.load(Ordering::Relaxed);
+        let Some(idx) = (0..len)
+            .rev()
+            .find(|&idx| self.entries[idx].lock_addr.load(Ordering::Relaxed) == lock_addr)
+        else {
+            // The lock was taken while the stack was full.
+            let overflow = self.overflow.load(Ordering::Relaxed);
+            self.overflow
+                .store(overflow.saturating_sub(1), Ordering::Relaxed);
+            return;
+        };
+
+        for idx in idx..len - 1 {
+            self.set(idx, self.get(idx + 1));
+        }
+        self.len.store(len - 1, Ordering::Relaxed);
+    }
+}
+
+/// A problem found by the validator.
+#[derive(Debug, Clone, Copy)]
+pub enum LockdepReport<'a> {
+    /// Taking `acquiring` while holding `held` inverts the order in which the
+    /// two classes were taken before.
+    OrderInversion {
+        /// The core that tried to take the lock.
+        core: CoreId,
+        /// The lock that was about to be taken.
+        acquiring: HeldLock,
+        /// The held lock that was taken after `acquiring` before.
+        held: HeldLock,
+        /// A chain of classes, each of which was taken while holding the
+        /// previous one, from `acquiring` to `held`.
+        chain: &'a [LockClass],
+        /// All locks held by `core`.
+        held_locks: &'a [Option<HeldLock>],
+    },
+    /// A preemtable lock was taken inside of an interrupt.
+    PreemtableInInterrupt {
+        /// The core that tried to take the lock.
+        core: CoreId,
+        /// The lock that was about to be taken.
+        acquiring: HeldLock,
+        /// All locks held by `core`.
+        held_locks: &'a [Option<HeldLock>],
+    },
+}
+
+impl fmt::Display for LockdepReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let held_locks = match self {
+            LockdepReport::OrderInversion {
+                core,
+                acquiring,
+                held,
+                chain,
+                held_locks,
+            } => {
+                writeln!(f, "lockdep: possible deadlock detected on core {core}")?;
+                writeln!(f, "  taking {acquiring}")?;
+                writeln!(f, "  while holding {held}")?;
+                writeln!(f, "  but the opposite order was seen before:")?;
+                for class in chain.iter() {
+                    writeln!(f, "    {class}")?;
+                }
+                held_locks
+            }
+            LockdepReport::PreemtableInInterrupt {
+                core,
+                acquiring,
+                held_locks,
+            } => {
+                writeln!(
+                    f,
+                    "lockdep: preemtable lock taken inside of an interrupt on core {core}"
+                )?;
+                writeln!(f, "  taking {acquiring}")?;
+                held_locks
+            }
+        };
+
+        write!(f, "  locks held by this core:")?;
+        if held_locks.is_empty() {
+            write!(f, " none")?;
+        }
+        for held in held_locks.iter().flatten() {
+            write!(f, "\n    {held}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The validator state stored in each lock.
+#[derive(Debug)]
+pub struct LockdepMap {
+    /// Where the lock was created. This identifies its class.
+    key: &'static Location<'static>,
+    /// The ID of the lock's class, or [`UNREGISTERED`] or [`UNTRACKED`].
+    class: AtomicU16,
+}
+
+impl LockdepMap {
+    /// Create the validator state for a lock created at the caller's location.
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            key: Location::caller(),
+            class: AtomicU16::new(UNREGISTERED),
+        }
+    }
+
+    /// Returns the class of the lock, or [`None`] if it isn't validated.
+    pub fn class(&self) -> Option<LockClass> {
+        match self.class.load(Ordering::Relaxed) {
+            UNTRACKED => None,
+            UNREGISTERED => {
+                let class = self.register();
+                self.class.store(class, Ordering::Relaxed);
+                (class != UNTRACKED).then_some(LockClass(class))
+            }
+            class => Some(LockClass(class)),
+        }
+    }
+
+    /// Find or allocate the class for [`LockdepMap::key`].
+    fn register(&self) -> u16 {
+        let key = self.key as *const _ as *mut Location<'static>;
+        for (idx, class) in CLASSES.iter().enumerate() {
+            let current = class.load(Ordering::Acquire);
+            // Different constants for the same location might not be deduplicated,
+            // so compare the locations, not just the pointers.
+            // Safety: registered locations were taken from a `&'static Location`.
+            if !current.is_null() && unsafe { *current == *self.key } {
+                return idx as u16;
+            }
+            if current.is_null() {
+                match class.compare_exchange(
+                    ptr::null_mut(),
+                    key,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => return idx as u16,
+                    // Safety: see above
+                    Err(other) if unsafe { *other == *self.key } => return idx as u16,
+                    Err(_) => {}
+                }
+            }
+        }
+        UNTRACKED
+    }
+
+    /// Validate taking the lock at `lock_addr` and record it as held by `core`.
+    ///
+    /// Must be called before waiting for the lock, so that problems are reported
+    /// instead of deadlocking.
+    pub fn acquire(
+        &self,
+        lock_addr: usize,
+        location: &'static Location<'static>,
+        core: CoreId,
+        preemtable: bool,
+        in_interrupt: bool,
+    ) {
+        if !is_enabled() {
+            return;
+        }
+        let Some(stack) = HELD.get(core.0 as usize) else {
+            return;
+        };
+        let Some(class) = self.class() else {
+            return;
+        };
+        let acquiring = HeldLock {
+            lock_addr,
+            class,
+            location,
+        };
+
+        let mut held_locks = [None; MAX_HELD_LOCKS];
+        let held_count = stack.snapshot(&mut held_locks);
+        let held_locks = &held_locks[..held_count];
+
+        if preemtable && in_interrupt {
+            report(&LockdepReport::PreemtableInInterrupt {
+                core,
+                acquiring,
+                held_locks,
+            });
+            return;
+        }
+
+        for held in held_locks.iter().flatten() {
+            if held.class == class || held.class.depends_on(class) {
+                continue;
+            }
+
+            let mut chain = [LockClass(0); MAX_LOCK_CLASSES];
+            if let Some(len) = class.find_chain(held.class, &mut chain) {
+                report(&LockdepReport::OrderInversion {
+                    core,
+                    acquiring,
+                    held: *held,
+                    chain: &chain[..len],
+                    held_locks,
+                });
+                return;
+            }
+
+            held.class.add_dependency(class);
+        }
+
+        stack.push(acquiring);
+    }
+
+    /// Record the lock at `lock_addr`, taken by a successful `try_lock`, as held
+    /// by `core`.
+    ///
+    /// Trying a lock never waits, so it can't deadlock: nothing is validated and
+    /// no dependencies on the held locks are added. Locks taken while it is held
+    /// still depend on it.
+    pub fn acquire_try(
+        &self,
+        lock_addr: usize,
+        location: &'static Location<'static>,
+        core: CoreId,
+    ) {
+        if !is_enabled() {
+            return;
+        }
+        let Some(stack) = HELD.get(core.0 as usize) else {
+            return;
+        };
+        let Some(class) = self.class() else {
+            return;
+        };
+        stack.push(HeldLock {
+            lock_addr,
+            class,
+            location,
+        });
+    }
+
+    /// Record that the lock at `lock_addr` was released by `core`.
+    pub fn release(&self, lock_addr: usize, core: CoreId) {
+        if !is_enabled() {
+            return;
+        }
+        if let Some(stack) = HELD.get(core.0 as usize) {
+            stack.remove(lock_addr);
+        }
+    }
+}
+
+impl Default for LockdepMap {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
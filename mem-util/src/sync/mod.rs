@@ -5,8 +5,19 @@
 
 use crate::types::CoreId;
 
+pub mod condvar;
 pub mod lock_cell;
+#[cfg(feature = "lock-debug")]
+pub mod lock_debug;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+pub mod mutex;
+pub mod once;
+pub mod per_core;
+pub mod semaphore;
+pub mod seq_lock;
 pub mod ticket_lock;
+pub mod wait_queue;
 
 /// Trait that allows access to OS-level constructs defining interrupt state,
 /// exception state, unique core IDs, and enter/exit lock (for interrupt
@@ -53,3 +64,31 @@ pub trait InterruptState: 'static {
     /// Returns the instance of this interrupt state. Must always be a zero-sized type (ZST).
     fn instance() -> Self;
 }
+
+/// Trait that allows blocking primitives like [`Mutex`][mutex::Mutex] to suspend
+/// the running thread of execution instead of spinning.
+///
+/// The wait queues used by these primitives are protected by non-preemtable
+/// locks using the same [`InterruptState`].
+pub trait Parker: InterruptState {
+    /// A handle to a thread of execution that can be used to resume it.
+    type Thread: Clone + Send + 'static;
+
+    /// Returns a handle to the current thread.
+    ///
+    /// Returns [`None`] if the current context can't be suspended, e.g. because
+    /// threads aren't available yet. Waiters spin instead in that case.
+    fn current_thread() -> Option<Self::Thread>;
+
+    /// Suspend the current thread until [`Parker::unpark()`] is called for it.
+    ///
+    /// If the thread was unparked since it was last parked, this returns
+    /// immediately. This is allowed to return spuriously.
+    fn park();
+
+    /// Resume `thread` if it is parked, or make the next call to [`Parker::park()`]
+    /// by `thread` return immediately.
+    ///
+    /// This must be usable from within interrupts.
+    fn unpark(thread: &Self::Thread);
+}
@@ -0,0 +1,241 @@
+//! A mutual exclusion lock that suspends waiting threads instead of spinning.
+//!
+//! [`Mutex`] is a [`LockCell`] implementation. Unlike a
+//! [`TicketLock`][super::ticket_lock::TicketLock], holding a [`Mutex`] is not a
+//! critical section, so the holder can be preempted or block while holding it.
+//! For the same reason it can't be used inside of interrupts.
+//!
+//! Waiters are granted the lock in FIFO order: when the lock is released while
+//! someone is waiting, ownership is handed directly to the first waiter.
+
+use alloc::sync::Arc;
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+
+use super::{
+    lock_cell::{LockCell, LockCellGuard, LockCellInternal},
+    wait_queue::{WaitQueue, Waiter},
+    Parker,
+};
+
+/// A mutual exclusion lock that suspends waiting threads and tasks.
+///
+/// - `T` is the type of data stored in the lock.
+/// - `I` gives access to the core's interrupt state and is used to suspend threads.
+pub struct Mutex<T, I: Parker> {
+    /// `true` while someone holds the lock.
+    locked: AtomicBool,
+    /// Threads and tasks waiting for the lock.
+    waiters: WaitQueue<I>,
+    /// The data held by the lock.
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send, I: Parker> Send for Mutex<T, I> {}
+unsafe impl<T: Send, I: Parker> Sync for Mutex<T, I> {}
+
+impl<T, I: Parker> Mutex<T, I> {
+    /// Creates a new unlocked [`Mutex`].
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes the mutex, returning the data it holds.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Returns a mutable reference to the data.
+    ///
+    /// No locking is needed, since this takes `self` mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Take the lock if it's free, without looking at the wait queue.
+    ///
+    /// This can't jump the queue: the lock is only ever released while no one
+    /// is waiting, otherwise it's handed to the first waiter.
+    fn try_acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Release the lock, handing it to the first waiter if there is one.
+    fn release(&self) {
+        self.waiters
+            .notify_one_or_else(|| self.locked.store(false, Ordering::Release));
+    }
+
+    fn guard(&self) -> LockCellGuard<'_, T, Self> {
+        LockCellGuard {
+            lockcell: self,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: Send, I: Parker> Mutex<T, I> {
+    /// Asynchronously acquire the lock.
+    ///
+    /// The returned future resolves once the lock is held. Dropping it before
+    /// that gives up its place in the queue.
+    pub fn lock_async(&self) -> MutexLockFuture<'_, T, I> {
+        MutexLockFuture {
+            mutex: self,
+            waiter: None,
+        }
+    }
+}
+
+impl<T: Send, I: Parker> LockCell<T> for Mutex<T, I> {
+    #[track_caller]
+    fn lock(&self) -> LockCellGuard<'_, T, Self> {
+        assert!(!I::in_interrupt(), "cannot use Mutex in interrupt");
+
+        if self.try_acquire() {
+            return self.guard();
+        }
+
+        let waiter = Waiter::for_current_thread();
+        if self
+            .waiters
+            .push_unless(waiter.clone(), || self.try_acquire())
+        {
+            // Once notified, the lock was handed to us.
+            waiter.wait();
+        }
+
+        self.guard()
+    }
+
+    #[track_caller]
+    fn try_lock(&self) -> Option<LockCellGuard<'_, T, Self>> {
+        if self.try_acquire() {
+            Some(self.guard())
+        } else {
+            None
+        }
+    }
+}
+
+impl<T, I: Parker> LockCellInternal<T> for Mutex<T, I> {
+    unsafe fn get(&self) -> &T {
+        unsafe { &*self.data.get() }
+    }
+
+    unsafe fn get_mut(&self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    unsafe fn unlock<'s, 'l: 's>(&'s self, guard: &mut LockCellGuard<'l, T, Self>) {
+        assert!(
+            core::ptr::eq(self, guard.lockcell),
+            "attempted to use a LockCellGuard to unlock a Mutex that doesn't actually own the Mutex"
+        );
+
+        // Safety: we checked that the LockCellGuard actually owns this Mutex.
+        unsafe {
+            self.force_unlock();
+        }
+    }
+
+    unsafe fn force_unlock(&self) {
+        self.release();
+    }
+
+    fn is_unlocked(&self) -> bool {
+        !self.locked.load(Ordering::Acquire)
+    }
+
+    fn is_preemtable(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Default, I: Parker> Default for Mutex<T, I> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T, I: Parker> fmt::Debug for Mutex<T, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mutex")
+            .field("locked", &self.locked.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Future returned by [`Mutex::lock_async`].
+#[must_use = "futures do nothing unless polled"]
+pub struct MutexLockFuture<'m, T, I: Parker> {
+    mutex: &'m Mutex<T, I>,
+    /// Set once this future is in the mutex's wait queue.
+    waiter: Option<Arc<Waiter<I>>>,
+}
+
+impl<'m, T: Send, I: Parker> Future for MutexLockFuture<'m, T, I> {
+    type Output = LockCellGuard<'m, T, Mutex<T, I>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+
+        match &self.waiter {
+            Some(waiter) => {
+                if waiter.poll_notified(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+            None => {
+                if mutex.try_acquire() {
+                    return Poll::Ready(mutex.guard());
+                }
+
+                let waiter = Waiter::for_task();
+                if !mutex
+                    .waiters
+                    .push_unless(waiter.clone(), || mutex.try_acquire())
+                {
+                    return Poll::Ready(mutex.guard());
+                }
+
+                let pending = waiter.poll_notified(cx).is_pending();
+                self.waiter = Some(waiter);
+                if pending {
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        // The lock was handed to us.
+        self.waiter = None;
+        Poll::Ready(mutex.guard())
+    }
+}
+
+impl<T, I: Parker> Drop for MutexLockFuture<'_, T, I> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            // If we were already handed the lock, pass it on.
+            let mutex = self.mutex;
+            if !mutex.waiters.remove(&waiter) {
+                mutex.release();
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! One-time initialization of values.
+//!
+//! [`SpinOnce`] is a cell that is written exactly once, and [`Lazy`] is a value
+//! that is initialized on first access. Both spin while another core is
+//! initializing them.
+//!
+//! Like [`TicketLock`][super::ticket_lock::TicketLock], they are preemtable or
+//! not depending on how they are created. The initialization of a preemtable
+//! cell can't happen inside of an interrupt, while a non-preemtable cell
+//! disables interrupts while it is being initialized. Accessing an initialized
+//! value is always possible, even inside of interrupts.
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+    hint::spin_loop,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::atomic::{AtomicU16, AtomicU8, Ordering},
+};
+
+use super::InterruptState;
+
+/// The value was not initialized yet.
+const INCOMPLETE: u8 = 0;
+/// The value is being initialized.
+const RUNNING: u8 = 1;
+/// The value is initialized.
+const COMPLETE: u8 = 2;
+
+/// A cell that can be written to only once.
+///
+/// - `T` is the type of data stored in the cell.
+/// - `I` gives access to the core's interrupt state.
+pub struct SpinOnce<T, I> {
+    /// One of [`INCOMPLETE`], [`RUNNING`] or [`COMPLETE`].
+    state: AtomicU8,
+    /// The core that is initializing the value, used to detect recursive
+    /// initialization.
+    owner: AtomicU16,
+    /// The value. Only initialized once `state` is [`COMPLETE`].
+    data: UnsafeCell<MaybeUninit<T>>,
+    /// `true` if the cell can *not* be initialized inside of interrupts.
+    pub preemtable: bool,
+    /// Act like we own access to the core's interrupt state.
+    _interrupt_state: PhantomData<I>,
+}
+
+unsafe impl<T: Send, I: InterruptState> Send for SpinOnce<T, I> {}
+unsafe impl<T: Send + Sync, I: InterruptState> Sync for SpinOnce<T, I> {}
+
+impl<T, I> SpinOnce<T, I> {
+    /// Creates a new uninitialized [`SpinOnce`].
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+            owner: AtomicU16::new(!0),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+            preemtable: true,
+            _interrupt_state: PhantomData,
+        }
+    }
+
+    /// Creates a new uninitialized __non-preemtable__ [`SpinOnce`].
+    ///
+    /// This assumes that it is safe to disable interrupts while the value is
+    /// being initialized.
+    pub const fn new_non_preemtable() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+            owner: AtomicU16::new(!0),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+            preemtable: false,
+            _interrupt_state: PhantomData,
+        }
+    }
+
+    /// Returns `true` once the value is initialized.
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Returns the value, or [`None`] if it is not initialized yet.
+    pub fn get(&self) -> Option<&T> {
+        if self.is_completed() {
+            // Safety: The value is initialized and never changes again.
+            Some(unsafe { (*self.data.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the value, or [`None`] if it is not
+    /// initialized yet.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if *self.state.get_mut() == COMPLETE {
+            // Safety: The value is initialized, and we have exclusive access.
+            Some(unsafe { self.data.get_mut().assume_init_mut() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T, I: InterruptState> SpinOnce<T, I> {
+    /// Returns the value, initializing it with `f` if it is not initialized yet.
+    ///
+    /// If another core is initializing the value, this spins until it is done.
+    /// `f` runs inside of a critical section, so it must not block.
+    ///
+    /// # Panics
+    /// Panics if `f` tries to access this cell, or if a preemtable cell has to be
+    /// initialized inside of an interrupt.
+    #[track_caller]
+    pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+
+        self.initialize(f);
+        self.get().expect("SpinOnce was just initialized")
+    }
+
+    /// Initialize the cell with `value`.
+    ///
+    /// Returns the value back if the cell was already initialized.
+    #[track_caller]
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.is_completed() {
+            return Err(value);
+        }
+
+        let mut value = Some(value);
+        self.initialize(|| value.take().expect("SpinOnce initialized twice"));
+        match value {
+            Some(value) => Err(value),
+            None => Ok(()),
+        }
+    }
+
+    /// Run `f` and store its result if the cell is not initialized yet, or wait
+    /// for another core to finish initializing it.
+    #[track_caller]
+    fn initialize(&self, f: impl FnOnce() -> T) {
+        assert!(
+            !self.preemtable || !I::in_interrupt(),
+            "cannot initialize preemtable SpinOnce in interrupt"
+        );
+
+        // Safety: disabling interrupts is ok for non-preemtable cells.
+        unsafe {
+            I::enter_critical_section(!self.preemtable);
+        }
+
+        loop {
+            match self.state.compare_exchange_weak(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    self.owner.store(I::core_id().0 as u16, Ordering::Relaxed);
+
+                    let value = f();
+                    // Safety: We set `state` to `RUNNING`, so no one else accesses the data.
+                    unsafe {
+                        (*self.data.get()).write(value);
+                    }
+
+                    self.owner.store(!0, Ordering::Relaxed);
+                    self.state.store(COMPLETE, Ordering::Release);
+                    break;
+                }
+                Err(COMPLETE) => break,
+                Err(RUNNING) => {
+                    if self.owner.load(Ordering::Relaxed) == I::core_id().0 as u16 {
+                        panic!("SpinOnce initialized recursively!");
+                    }
+                    spin_loop();
+                }
+                Err(_) => spin_loop(),
+            }
+        }
+
+        // Safety: this restores the interrupt state from when we called
+        // enter_critical_section.
+        unsafe {
+            I::exit_critical_section(!self.preemtable);
+        }
+    }
+}
+
+impl<T, I> Default for SpinOnce<T, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, I> Drop for SpinOnce<T, I> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            // Safety: The value is initialized, and is never accessed again.
+            unsafe { self.data.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+impl<T: fmt::Debug, I> fmt::Debug for SpinOnce<T, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("SpinOnce").field(value).finish(),
+            None => f.write_str("SpinOnce(<uninit>)"),
+        }
+    }
+}
+
+/// A value that is initialized on first access.
+///
+/// - `T` is the type of the value.
+/// - `I` gives access to the core's interrupt state.
+/// - `F` is the function used to initialize the value.
+pub struct Lazy<T, I, F = fn() -> T> {
+    once: SpinOnce<T, I>,
+    /// Taken by the core that initializes `once`.
+    init: Cell<Option<F>>,
+}
+
+// Safety: `init` is only accessed by the single core that initializes `once`.
+unsafe impl<T: Send + Sync, I: InterruptState, F: Send> Sync for Lazy<T, I, F> {}
+
+impl<T, I, F> Lazy<T, I, F> {
+    /// Creates a new [`Lazy`] that is initialized using `f`.
+    pub const fn new(f: F) -> Self {
+        Self {
+            once: SpinOnce::new(),
+            init: Cell::new(Some(f)),
+        }
+    }
+
+    /// Creates a new __non-preemtable__ [`Lazy`] that is initialized using `f`.
+    ///
+    /// This assumes that it is safe to disable interrupts while the value is
+    /// being initialized.
+    pub const fn new_non_preemtable(f: F) -> Self {
+        Self {
+            once: SpinOnce::new_non_preemtable(),
+            init: Cell::new(Some(f)),
+        }
+    }
+
+    /// Returns the value, or [`None`] if it was not initialized yet.
+    pub fn get(this: &Self) -> Option<&T> {
+        this.once.get()
+    }
+}
+
+impl<T, I: InterruptState, F: FnOnce() -> T> Lazy<T, I, F> {
+    /// Initialize the value if that didn't happen yet, and return it.
+    ///
+    /// See [`SpinOnce::call_once`].
+    #[track_caller]
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(|| {
+            let init = this.init.take().expect("Lazy initializer already ran");
+            init()
+        })
+    }
+}
+
+impl<T, I: InterruptState, F: FnOnce() -> T> Deref for Lazy<T, I, F> {
+    type Target = T;
+
+    #[track_caller]
+    fn deref(&self) -> &Self::Target {
+        Lazy::force(self)
+    }
+}
+
+impl<T: fmt::Debug, I, F> fmt::Debug for Lazy<T, I, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.once.get() {
+            Some(value) => f.debug_tuple("Lazy").field(value).finish(),
+            None => f.write_str("Lazy(<uninit>)"),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Data with a separate instance for every core.
+
+use core::{fmt, marker::PhantomData};
+
+use super::InterruptState;
+use crate::types::CoreId;
+
+/// A container holding one `T` for each of up to `N` cores, indexed by [`CoreId`].
+///
+/// - `T` is the type of data stored for each core.
+/// - `I` gives access to the core's interrupt state.
+///
+/// Since interrupts can access the same instance as the code they interrupted,
+/// and other cores can access any instance using [`PerCore::get`], the data is
+/// only ever shared. Use atomics or a lock for mutable state.
+pub struct PerCore<T, I, const N: usize> {
+    values: [T; N],
+    /// Act like we own access to the core's interrupt state.
+    _interrupt_state: PhantomData<I>,
+}
+
+unsafe impl<T: Send, I: InterruptState, const N: usize> Send for PerCore<T, I, N> {}
+unsafe impl<T: Sync, I: InterruptState, const N: usize> Sync for PerCore<T, I, N> {}
+
+impl<T, I, const N: usize> PerCore<T, I, N> {
+    /// Creates a new [`PerCore`] from one value per core.
+    pub const fn new(values: [T; N]) -> Self {
+        Self {
+            values,
+            _interrupt_state: PhantomData,
+        }
+    }
+
+    /// Creates a new [`PerCore`], creating the value of each core using `f`.
+    pub fn from_fn(mut f: impl FnMut(CoreId) -> T) -> Self {
+        Self::new(core::array::from_fn(|idx| f(CoreId(idx as u8))))
+    }
+
+    /// Returns the value of `core`, or [`None`] if there is no value for it.
+    pub fn get(&self, core: CoreId) -> Option<&T> {
+        self.values.get(core.0 as usize)
+    }
+
+    /// Returns a mutable reference to the value of `core`, or [`None`] if there
+    /// is no value for it.
+    ///
+    /// Since this takes `self` mutably, no synchronization is needed.
+    pub fn get_mut(&mut self, core: CoreId) -> Option<&mut T> {
+        self.values.get_mut(core.0 as usize)
+    }
+
+    /// Iterate over the values of all cores.
+    pub fn iter(&self) -> impl Iterator<Item = (CoreId, &T)> {
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| (CoreId(idx as u8), value))
+    }
+}
+
+impl<T, I: InterruptState, const N: usize> PerCore<T, I, N> {
+    /// Run `f` with the value of the current core.
+    ///
+    /// `f` runs inside of a critical section, so the current thread can't be
+    /// moved to another core while it runs. It must not block.
+    ///
+    /// # Panics
+    /// Panics if there is no value for the current core.
+    #[track_caller]
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        // Safety: interrupts stay enabled.
+        unsafe {
+            I::enter_critical_section(false);
+        }
+
+        let core = I::core_id();
+        let value = self
+            .get(core)
+            .unwrap_or_else(|| panic!("PerCore has no value for core {core}"));
+        let result = f(value);
+
+        // Safety: this restores the state from when we called
+        // enter_critical_section.
+        unsafe {
+            I::exit_critical_section(false);
+        }
+
+        result
+    }
+}
+
+impl<T: Default, I, const N: usize> Default for PerCore<T, I, N> {
+    fn default() -> Self {
+        Self::new(core::array::from_fn(|_| T::default()))
+    }
+}
+
+impl<T: fmt::Debug, I, const N: usize> fmt::Debug for PerCore<T, I, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,207 @@
+//! A counting semaphore that suspends waiting threads instead of spinning.
+
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use super::{
+    wait_queue::{WaitQueue, Waiter},
+    Parker,
+};
+
+/// A counting semaphore.
+///
+/// Permits are granted in FIFO order: when a permit is released while someone
+/// is waiting, it is handed directly to the first waiter.
+pub struct Semaphore<I: Parker> {
+    /// The number of available permits. Always 0 while someone is waiting.
+    permits: AtomicUsize,
+    /// Threads and tasks waiting for a permit.
+    waiters: WaitQueue<I>,
+}
+
+impl<I: Parker> Semaphore<I> {
+    /// Creates a new [`Semaphore`] with `permits` available permits.
+    #[track_caller]
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Returns the number of currently available permits.
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Acquire)
+    }
+
+    /// Take a permit if one is available, without looking at the wait queue.
+    ///
+    /// This can't jump the queue, since permits are only ever made available
+    /// while no one is waiting.
+    fn try_take(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+        false
+    }
+
+    /// Block the current thread until a permit is available, and take it.
+    ///
+    /// The permit is released again once the returned guard is dropped.
+    #[track_caller]
+    pub fn acquire(&self) -> SemaphoreGuard<'_, I> {
+        assert!(
+            !I::in_interrupt(),
+            "cannot acquire a Semaphore in interrupt"
+        );
+
+        if !self.try_take() {
+            let waiter = Waiter::for_current_thread();
+            if self.waiters.push_unless(waiter.clone(), || self.try_take()) {
+                // Once notified, a permit was handed to us.
+                waiter.wait();
+            }
+        }
+
+        SemaphoreGuard { semaphore: self }
+    }
+
+    /// Take a permit if one is available right now.
+    ///
+    /// This can be used from within interrupts.
+    pub fn try_acquire(&self) -> Option<SemaphoreGuard<'_, I>> {
+        if self.try_take() {
+            Some(SemaphoreGuard { semaphore: self })
+        } else {
+            None
+        }
+    }
+
+    /// Asynchronously take a permit.
+    ///
+    /// Dropping the returned future before it resolves gives up its place in the queue.
+    pub fn acquire_async(&self) -> SemaphoreAcquireFuture<'_, I> {
+        SemaphoreAcquireFuture {
+            semaphore: self,
+            waiter: None,
+        }
+    }
+
+    /// Add a permit, handing it to the first waiter if there is one.
+    ///
+    /// This can be used from within interrupts, e.g. to signal that some event
+    /// happened.
+    pub fn release(&self) {
+        self.waiters
+            .notify_one_or_else(|| _ = self.permits.fetch_add(1, Ordering::Release));
+    }
+}
+
+impl<I: Parker> fmt::Debug for Semaphore<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A permit taken from a [`Semaphore`]. It is released again when dropped.
+#[must_use = "if unused the permit is released immediately"]
+pub struct SemaphoreGuard<'s, I: Parker> {
+    semaphore: &'s Semaphore<I>,
+}
+
+impl<I: Parker> SemaphoreGuard<'_, I> {
+    /// Consume the permit without releasing it.
+    ///
+    /// This is useful when a [`Semaphore`] is used to count events, rather than
+    /// to limit access to a resource.
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl<I: Parker> Drop for SemaphoreGuard<'_, I> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+impl<I: Parker> fmt::Debug for SemaphoreGuard<'_, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SemaphoreGuard").finish_non_exhaustive()
+    }
+}
+
+/// Future returned by [`Semaphore::acquire_async`].
+#[must_use = "futures do nothing unless polled"]
+pub struct SemaphoreAcquireFuture<'s, I: Parker> {
+    semaphore: &'s Semaphore<I>,
+    /// Set once this future is in the semaphore's wait queue.
+    waiter: Option<Arc<Waiter<I>>>,
+}
+
+impl<'s, I: Parker> Future for SemaphoreAcquireFuture<'s, I> {
+    type Output = SemaphoreGuard<'s, I>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+
+        match &self.waiter {
+            Some(waiter) => {
+                if waiter.poll_notified(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+            None => {
+                if semaphore.try_take() {
+                    return Poll::Ready(SemaphoreGuard { semaphore });
+                }
+
+                let waiter = Waiter::for_task();
+                if !semaphore
+                    .waiters
+                    .push_unless(waiter.clone(), || semaphore.try_take())
+                {
+                    return Poll::Ready(SemaphoreGuard { semaphore });
+                }
+
+                let pending = waiter.poll_notified(cx).is_pending();
+                self.waiter = Some(waiter);
+                if pending {
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        // A permit was handed to us.
+        self.waiter = None;
+        Poll::Ready(SemaphoreGuard { semaphore })
+    }
+}
+
+impl<I: Parker> Drop for SemaphoreAcquireFuture<'_, I> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            // If we were already handed a permit, pass it on.
+            if !self.semaphore.waiters.remove(&waiter) {
+                self.semaphore.release();
+            }
+        }
+    }
+}
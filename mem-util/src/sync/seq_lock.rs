@@ -0,0 +1,187 @@
+//! A [sequence lock](https://en.wikipedia.org/wiki/Seqlock), for data that is
+//! read a lot more often than it is written, like clocks.
+//!
+//! Readers never block writers. Instead, they copy the data and retry if a write
+//! happened while they were reading.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    hint::spin_loop,
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{fence, AtomicU16, AtomicU64, Ordering},
+};
+
+use super::InterruptState;
+
+/// A sequence lock.
+///
+/// - `T` is the type of data stored in the lock. It is copied out on every read,
+///   so it should be small.
+/// - `I` gives access to the core's interrupt state.
+///
+/// A reader that interrupts a write on the same core would spin forever, so a
+/// preemtable [`SeqLock`] can't be used inside of interrupts at all. A
+/// non-preemtable one disables interrupts while writing.
+pub struct SeqLock<T, I> {
+    /// Odd while a write is in progress. Incremented at the start and end of
+    /// every write.
+    seq: AtomicU64,
+    /// The data held by the lock.
+    data: UnsafeCell<T>,
+    /// The core that is currently writing, used to detect deadlocks.
+    writer: AtomicU16,
+    /// `true` if the lock is *not* usable in interrupts.
+    pub preemtable: bool,
+    /// Act like we own access to the core's interrupt state.
+    _interrupt_state: PhantomData<I>,
+}
+
+unsafe impl<T: Copy + Send, I: InterruptState> Send for SeqLock<T, I> {}
+unsafe impl<T: Copy + Send, I: InterruptState> Sync for SeqLock<T, I> {}
+
+impl<T: Copy, I> SeqLock<T, I> {
+    /// Creates a new [`SeqLock`].
+    pub const fn new(data: T) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            data: UnsafeCell::new(data),
+            writer: AtomicU16::new(!0),
+            preemtable: true,
+            _interrupt_state: PhantomData,
+        }
+    }
+
+    /// Creates a new __non-preemtable__ [`SeqLock`].
+    ///
+    /// This assumes that it is safe to disable interrupts while writing.
+    pub const fn new_non_preemtable(data: T) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            data: UnsafeCell::new(data),
+            writer: AtomicU16::new(!0),
+            preemtable: false,
+            _interrupt_state: PhantomData,
+        }
+    }
+
+    /// Returns a mutable reference to the data.
+    ///
+    /// Since this takes `self` mutably, no locking is needed.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Copy, I: InterruptState> SeqLock<T, I> {
+    /// Returns a copy of the data.
+    ///
+    /// Spins while a write is in progress.
+    #[track_caller]
+    pub fn read(&self) -> T {
+        assert!(
+            !self.preemtable || !I::in_interrupt(),
+            "cannot use preemtable SeqLock in interrupt"
+        );
+
+        loop {
+            let start = self.seq.load(Ordering::Acquire);
+            if start % 2 == 1 {
+                self.check_deadlock();
+                spin_loop();
+                continue;
+            }
+
+            // Safety: This might race with a writer, in which case the copy is
+            // discarded below. `T: Copy`, so a torn copy never needs to be dropped.
+            let data = unsafe { ptr::read_volatile(self.data.get()) };
+
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == start {
+                return data;
+            }
+            spin_loop();
+        }
+    }
+
+    /// Modify the data using `f`.
+    ///
+    /// Writers are serialized, and `f` runs inside of a critical section, so it
+    /// must not block.
+    #[track_caller]
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        assert!(
+            !self.preemtable || !I::in_interrupt(),
+            "cannot use preemtable SeqLock in interrupt"
+        );
+
+        // Safety: disabling interrupts is ok for non-preemtable locks.
+        unsafe {
+            I::enter_critical_section(!self.preemtable);
+        }
+
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq % 2 == 1 {
+                self.check_deadlock();
+                spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+            match self
+                .seq
+                .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+        self.writer.store(I::core_id().0 as u16, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        // Safety: The sequence number is odd, so no one else writes the data,
+        // and readers discard what they read.
+        let result = f(unsafe { &mut *self.data.get() });
+
+        self.writer.store(!0, Ordering::Relaxed);
+        self.seq.store(seq + 2, Ordering::Release);
+
+        // Safety: this restores the interrupt state from when we called
+        // enter_critical_section.
+        unsafe {
+            I::exit_critical_section(!self.preemtable);
+        }
+
+        result
+    }
+
+    /// Replace the data with `value`.
+    #[track_caller]
+    pub fn set(&self, value: T) {
+        self.write(|data| *data = value);
+    }
+
+    /// Panic if the current core is the one that is writing, since waiting for
+    /// the write to finish would never end.
+    #[track_caller]
+    fn check_deadlock(&self) {
+        if self.writer.load(Ordering::Relaxed) == I::core_id().0 as u16 {
+            panic!("SeqLock deadlock detected!");
+        }
+    }
+}
+
+impl<T: Copy + Default, I> Default for SeqLock<T, I> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: Copy + fmt::Debug, I: InterruptState> fmt::Debug for SeqLock<T, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeqLock")
+            .field("seq", &self.seq.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
@@ -5,6 +5,9 @@
 //! not depending on how it is created ([`TicketLock::new`] vs
 //! [`TicketLock::new_non_preemtable`]).
 //!
+//! [`RwTicketLock`] is a [`RwLockCell`] implementation, whose fairness between
+//! readers and writers can be configured using a [`RwLockPolicy`].
+//!
 //! [`UnwrapLock`] is a [`LockCell`] wrapper that allows accessing a
 //! `UnwrapLock<MaybeUninit<T>>` as if it is an `LockCell<T>`.
 
@@ -12,15 +15,22 @@ use core::{
     cell::UnsafeCell,
     hint::spin_loop,
     marker::PhantomData,
-    sync::atomic::{AtomicI64, AtomicU16, AtomicU64, Ordering},
+    sync::atomic::{AtomicBool, AtomicI64, AtomicU16, AtomicU64, Ordering},
 };
 
+#[cfg(feature = "lock-debug")]
+use super::lock_debug::{LockDebugInfo, LockStats};
+#[cfg(feature = "lockdep")]
+use super::lockdep::LockdepMap;
 use super::{
     lock_cell::{
         LockCell, LockCellGuard, LockCellInternal, ReadCellGuard, RwCellInternal, RwLockCell,
+        UpgradableReadCellGuard,
     },
     InterruptState,
 };
+#[cfg(feature = "lock-debug")]
+use crate::types::CoreId;
 
 /// A [ticket lock](https://en.wikipedia.org/wiki/Ticket_lock) implementation
 /// for [`LockCell`].
@@ -40,6 +50,12 @@ pub struct TicketLock<T, I> {
     owner: AtomicU16,
     /// `true` if the lock is *not* usable in interrupts.
     pub preemtable: bool,
+    /// Contention statistics and the location the lock was last taken at.
+    #[cfg(feature = "lock-debug")]
+    debug: LockDebugInfo,
+    /// Lock ordering validator state.
+    #[cfg(feature = "lockdep")]
+    lockdep: LockdepMap,
     /// Act like we own access to the core's interrupt state.
     _interrupt_state: PhantomData<I>,
 }
@@ -49,6 +65,7 @@ unsafe impl<T: Send, I: InterruptState> Sync for TicketLock<T, I> {}
 
 impl<T, I> TicketLock<T, I> {
     /// Creates a new [`TicketLock`].
+    #[track_caller]
     pub const fn new(data: T) -> Self {
         Self {
             current_ticket: AtomicU64::new(0),
@@ -56,6 +73,10 @@ impl<T, I> TicketLock<T, I> {
             data: UnsafeCell::new(data),
             owner: AtomicU16::new(!0),
             preemtable: true,
+            #[cfg(feature = "lock-debug")]
+            debug: LockDebugInfo::new(),
+            #[cfg(feature = "lockdep")]
+            lockdep: LockdepMap::new(),
             _interrupt_state: PhantomData,
         }
     }
@@ -63,6 +84,7 @@ impl<T, I> TicketLock<T, I> {
     /// Creates a new __non-preemtable__ [`TicketLock`].
     ///
     /// This assumes that it is safe to disable interrupts while the lock is held.
+    #[track_caller]
     pub const fn new_non_preemtable(data: T) -> Self {
         Self {
             current_ticket: AtomicU64::new(0),
@@ -70,6 +92,10 @@ impl<T, I> TicketLock<T, I> {
             data: UnsafeCell::new(data),
             owner: AtomicU16::new(!0),
             preemtable: false,
+            #[cfg(feature = "lock-debug")]
+            debug: LockDebugInfo::new(),
+            #[cfg(feature = "lockdep")]
+            lockdep: LockdepMap::new(),
             _interrupt_state: PhantomData,
         }
     }
@@ -82,7 +108,29 @@ impl<T, I> TicketLock<T, I> {
         let current = self.current_ticket.load(Ordering::Relaxed);
         let next = self.next_ticket.load(Ordering::Relaxed);
         let owner = self.owner.load(Ordering::Relaxed);
-        write!(writer, "[TicketLock(c: {current}, n: {next}, o: {owner})]")
+        write!(writer, "[TicketLock(c: {current}, n: {next}, o: {owner})]")?;
+
+        #[cfg(feature = "lock-debug")]
+        {
+            if let Some(location) = self.debug.location() {
+                write!(writer, " last taken at {location}")?;
+            }
+            write!(writer, " {}", self.debug.stats())?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the contention statistics of this lock.
+    #[cfg(feature = "lock-debug")]
+    pub fn stats(&self) -> LockStats {
+        self.debug.stats()
+    }
+
+    /// Returns where this lock was last taken, or [`None`] if it was never taken.
+    #[cfg(feature = "lock-debug")]
+    pub fn last_location(&self) -> Option<&'static core::panic::Location<'static>> {
+        self.debug.location()
     }
 }
 
@@ -101,16 +149,51 @@ impl<T: Send, I: InterruptState> LockCell<T> for TicketLock<T, I> {
 
         let ticket = self.next_ticket.fetch_add(1, Ordering::SeqCst);
 
+        // Only recorded once we can't be moved to another core, but before
+        // waiting, so that lockdep reports a deadlock instead of spinning.
+        #[cfg(feature = "lockdep")]
+        self.lockdep.acquire(
+            self as *const Self as usize,
+            core::panic::Location::caller(),
+            I::core_id(),
+            self.preemtable,
+            I::in_interrupt(),
+        );
+
+        #[cfg(feature = "lock-debug")]
+        let mut spins = 0u64;
+
         while self.current_ticket.load(Ordering::SeqCst) != ticket {
             let owner = self.owner.load(Ordering::Acquire);
             if owner != !0 && owner == I::core_id().0 as u16 {
+                #[cfg(feature = "lock-debug")]
+                if let Some(location) = self.debug.location() {
+                    panic!("TicketLock deadlock detected! The lock was taken at {location}")
+                }
                 panic!("TicketLock deadlock detected!")
             }
             spin_loop();
+
+            #[cfg(feature = "lock-debug")]
+            {
+                spins += 1;
+                self.debug.on_spin(
+                    "TicketLock",
+                    self as *const Self as usize,
+                    I::core_id(),
+                    core::panic::Location::caller(),
+                    (owner != !0).then_some(CoreId(owner as u8)),
+                    spins,
+                );
+            }
         }
 
         self.owner.store(I::core_id().0 as u16, Ordering::Release);
 
+        #[cfg(feature = "lock-debug")]
+        self.debug
+            .on_acquire(core::panic::Location::caller(), spins);
+
         LockCellGuard {
             lockcell: self,
             _phantom: PhantomData,
@@ -119,11 +202,47 @@ impl<T: Send, I: InterruptState> LockCell<T> for TicketLock<T, I> {
 
     #[track_caller]
     fn try_lock(&self) -> Option<LockCellGuard<'_, T, Self>> {
-        if self.owner.load(Ordering::Acquire) == !0 {
-            Some(self.lock())
-        } else {
-            None
+        assert!(
+            !self.preemtable || !I::in_interrupt(),
+            "cannot use non-preemtable TicketLock in interrupt"
+        );
+
+        unsafe {
+            // Safety: disabling interrupts is ok, for preemtable locks
+            I::enter_critical_section(!self.preemtable);
         }
+
+        // Only take a ticket if it is the one being served, so we never wait.
+        let ticket = self.current_ticket.load(Ordering::SeqCst);
+        if self
+            .next_ticket
+            .compare_exchange(ticket, ticket + 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            // Safety: this restores the interrupt state from when we called
+            // enter_critical_section above
+            unsafe {
+                I::exit_critical_section(!self.preemtable);
+            }
+            return None;
+        }
+
+        self.owner.store(I::core_id().0 as u16, Ordering::Release);
+
+        #[cfg(feature = "lock-debug")]
+        self.debug.on_acquire(core::panic::Location::caller(), 0);
+
+        #[cfg(feature = "lockdep")]
+        self.lockdep.acquire_try(
+            self as *const Self as usize,
+            core::panic::Location::caller(),
+            I::core_id(),
+        );
+
+        Some(LockCellGuard {
+            lockcell: self,
+            _phantom: PhantomData,
+        })
     }
 }
 
@@ -149,6 +268,13 @@ impl<T, I: InterruptState> LockCellInternal<T> for TicketLock<T, I> {
     }
 
     unsafe fn force_unlock(&self) {
+        #[cfg(feature = "lock-debug")]
+        self.debug.on_release();
+
+        #[cfg(feature = "lockdep")]
+        self.lockdep
+            .release(self as *const Self as usize, I::core_id());
+
         self.owner.store(!0, Ordering::Release);
         self.current_ticket.fetch_add(1, Ordering::SeqCst);
 
@@ -169,6 +295,7 @@ impl<T, I: InterruptState> LockCellInternal<T> for TicketLock<T, I> {
 }
 
 impl<T: Default, I> Default for TicketLock<T, I> {
+    #[track_caller]
     fn default() -> Self {
         Self::new(Default::default())
     }
@@ -178,22 +305,69 @@ impl<T: Default, I> TicketLock<T, I> {
     /// Creates a new non-preemtable [`TicketLock`] with `data` initialized to its default value.
     ///
     /// This assumes that it is safe to disable interrupts while the lock is held.
+    #[track_caller]
     pub fn default_non_preemtable() -> Self {
         Self::new_non_preemtable(Default::default())
     }
 }
 
+/// Decides who gets a [`RwTicketLock`] when both readers and writers are waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RwLockPolicy {
+    /// Readers can take the lock whenever no writer holds it.
+    ///
+    /// This has the lowest overhead, but a steady stream of readers can starve
+    /// writers.
+    #[default]
+    ReadPreferring,
+    /// New readers wait while a writer is waiting.
+    ///
+    /// Readers can be starved by a steady stream of writers. A core that takes a
+    /// read lock while already holding one can deadlock, if a writer starts
+    /// waiting in between.
+    WritePreferring,
+    /// Readers and writers get the lock in the order they asked for it.
+    ///
+    /// Readers that queue up one after another still share the lock.
+    Fair,
+}
+
 /// A [`RwLockCell`] implementation using a ticketing system.
+///
+/// Who gets the lock first when there is contention depends on its
+/// [`RwLockPolicy`], see [`RwTicketLock::with_policy`].
 pub struct RwTicketLock<T, I> {
     /// If positive, this is the number of readers that currently hold a guard.
     ///
     /// - If 0, no one holds a guard, neither read nor write.
     /// - If -1, there is a writer with a guard.
     access_count: AtomicI64,
+    /// The number of writers waiting for the lock. New readers wait while this
+    /// isn't 0, except for [`RwLockPolicy::ReadPreferring`] locks.
+    ///
+    /// For [`RwLockPolicy::Fair`] locks, this only counts upgrading readers,
+    /// since other writers wait for their turn instead.
+    waiting_writers: AtomicU64,
+    /// The next ticket to give out. Only used by [`RwLockPolicy::Fair`].
+    next_ticket: AtomicU64,
+    /// The ticket that can take the lock next. Only used by [`RwLockPolicy::Fair`].
+    ///
+    /// Readers move it on as soon as they got the lock, writers once they release it.
+    current_ticket: AtomicU64,
+    /// Set if the writer that holds the lock took a ticket, and has to move
+    /// `current_ticket` on once it's done.
+    writer_has_turn: AtomicBool,
+    /// `true` while someone holds or waits for an upgradable read guard.
+    upgradable: AtomicBool,
     /// The data guarded by this lock
     data: UnsafeCell<T>,
     /// Set if the lock is usable in interrupts.
     pub preemtable: bool,
+    /// Who gets the lock first when there is contention.
+    policy: RwLockPolicy,
+    /// Lock ordering validator state.
+    #[cfg(feature = "lockdep")]
+    lockdep: LockdepMap,
     /// Act like we own access to the core's interrupt state.
     _interrupt_state: PhantomData<I>,
 }
@@ -203,11 +377,20 @@ unsafe impl<T: Send, I: InterruptState> Sync for RwTicketLock<T, I> {}
 
 impl<T, I> RwTicketLock<T, I> {
     /// Creates a new [`RwTicketLock`].
+    #[track_caller]
     pub const fn new(data: T) -> Self {
         Self {
             access_count: AtomicI64::new(0),
+            waiting_writers: AtomicU64::new(0),
+            next_ticket: AtomicU64::new(0),
+            current_ticket: AtomicU64::new(0),
+            writer_has_turn: AtomicBool::new(false),
+            upgradable: AtomicBool::new(false),
             data: UnsafeCell::new(data),
             preemtable: true,
+            policy: RwLockPolicy::ReadPreferring,
+            #[cfg(feature = "lockdep")]
+            lockdep: LockdepMap::new(),
             _interrupt_state: PhantomData,
         }
     }
@@ -215,43 +398,64 @@ impl<T, I> RwTicketLock<T, I> {
     /// creates a new non-preemtable [`RwTicketLock`].
     ///
     /// This assumes that it is safe to disable interrupts while the lock is held.
+    #[track_caller]
     pub const fn new_non_preemtable(data: T) -> Self {
         Self {
             access_count: AtomicI64::new(0),
+            waiting_writers: AtomicU64::new(0),
+            next_ticket: AtomicU64::new(0),
+            current_ticket: AtomicU64::new(0),
+            writer_has_turn: AtomicBool::new(false),
+            upgradable: AtomicBool::new(false),
             data: UnsafeCell::new(data),
             preemtable: false,
+            policy: RwLockPolicy::ReadPreferring,
+            #[cfg(feature = "lockdep")]
+            lockdep: LockdepMap::new(),
             _interrupt_state: PhantomData,
         }
     }
-}
 
-impl<T: Default, I> Default for RwTicketLock<T, I> {
-    fn default() -> Self {
-        Self::new(Default::default())
+    /// Use `policy` to decide who gets the lock when there is contention.
+    ///
+    /// The default is [`RwLockPolicy::ReadPreferring`].
+    pub const fn with_policy(mut self, policy: RwLockPolicy) -> Self {
+        self.policy = policy;
+        self
     }
-}
 
-impl<T: Default, I> RwTicketLock<T, I> {
-    /// Creates a new non-preemtable [`RwTicketLock`] with `data` initialized to its default value.
-    ///
-    /// This assumes that it is safe to disable interrupts while the lock is held.
-    pub fn default_non_preemtable() -> Self {
-        Self::new_non_preemtable(Default::default())
+    /// Returns the [`RwLockPolicy`] of this lock.
+    pub fn policy(&self) -> RwLockPolicy {
+        self.policy
     }
-}
 
-impl<T: Send, I: InterruptState> RwLockCell<T> for RwTicketLock<T, I> {
-    fn read(&self) -> ReadCellGuard<'_, T, Self> {
-        // NOTE: Because there can be multiple readers, RwLock is allowed in
-        // interrupts even if preemtable.
-        // Safety: Disabling interrupts is ok for preemtable locks.
-        unsafe {
-            I::enter_critical_section(false);
+    /// Wait until it's the turn of the caller, if the lock is [`RwLockPolicy::Fair`].
+    fn wait_for_turn(&self) {
+        if self.policy == RwLockPolicy::Fair {
+            let ticket = self.next_ticket.fetch_add(1, Ordering::SeqCst);
+            while self.current_ticket.load(Ordering::SeqCst) != ticket {
+                spin_loop();
+            }
+        }
+    }
+
+    /// Let the next waiter in line have its turn, if the lock is [`RwLockPolicy::Fair`].
+    fn end_turn(&self) {
+        if self.policy == RwLockPolicy::Fair {
+            self.current_ticket.fetch_add(1, Ordering::SeqCst);
         }
+    }
+
+    /// Add a reader, waiting until no writer holds the lock.
+    fn acquire_read(&self) {
+        self.wait_for_turn();
 
         let mut cur_count = self.access_count.load(Ordering::Acquire);
         loop {
-            while cur_count < 0 {
+            while cur_count < 0
+                || (self.policy != RwLockPolicy::ReadPreferring
+                    && self.waiting_writers.load(Ordering::Acquire) > 0)
+            {
                 spin_loop();
                 cur_count = self.access_count.load(Ordering::Acquire);
             }
@@ -272,11 +476,142 @@ impl<T: Send, I: InterruptState> RwLockCell<T> for RwTicketLock<T, I> {
             }
         }
 
+        self.end_turn();
+    }
+
+    /// Replace `expected` readers with a writer, waiting until that's possible.
+    fn acquire_write(&self, expected: i64) {
+        // An upgrading reader already holds the lock, so it can't wait for its
+        // turn behind a writer that is waiting for it.
+        let takes_turn = expected == 0;
+        if takes_turn {
+            self.wait_for_turn();
+        }
+
+        let announce = match self.policy {
+            RwLockPolicy::ReadPreferring => false,
+            RwLockPolicy::WritePreferring => true,
+            RwLockPolicy::Fair => !takes_turn,
+        };
+        if announce {
+            self.waiting_writers.fetch_add(1, Ordering::SeqCst);
+        }
+
+        while self
+            .access_count
+            .compare_exchange(expected, -1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            spin_loop();
+        }
+
+        if announce {
+            self.waiting_writers.fetch_sub(1, Ordering::SeqCst);
+        }
+        self.writer_has_turn.store(takes_turn, Ordering::Relaxed);
+    }
+
+    /// End the turn of the writer that holds the lock, if it took one.
+    fn end_write_turn(&self) {
+        if self.writer_has_turn.swap(false, Ordering::Relaxed) {
+            self.end_turn();
+        }
+    }
+
+    /// Turn the write lock into a read lock.
+    fn write_to_read(&self) {
+        self.end_write_turn();
+        let previous = self.access_count.swap(1, Ordering::SeqCst);
+        assert_eq!(
+            previous, -1,
+            "attempted to downgrade a RwTicketLock that isn't locked for writing"
+        );
+    }
+}
+
+impl<T: Default, I> Default for RwTicketLock<T, I> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: Default, I> RwTicketLock<T, I> {
+    /// Creates a new non-preemtable [`RwTicketLock`] with `data` initialized to its default value.
+    ///
+    /// This assumes that it is safe to disable interrupts while the lock is held.
+    #[track_caller]
+    pub fn default_non_preemtable() -> Self {
+        Self::new_non_preemtable(Default::default())
+    }
+}
+
+impl<T: Send, I: InterruptState> RwLockCell<T> for RwTicketLock<T, I> {
+    #[track_caller]
+    fn read(&self) -> ReadCellGuard<'_, T, Self> {
+        // NOTE: Because there can be multiple readers, RwLock is allowed in
+        // interrupts even if preemtable.
+        // Safety: Disabling interrupts is ok for non-preemtable locks.
+        unsafe {
+            I::enter_critical_section(!self.preemtable);
+        }
+
+        // Reads are allowed in interrupts even if the lock is preemtable, so
+        // lockdep must not report them.
+        #[cfg(feature = "lockdep")]
+        self.lockdep.acquire(
+            self as *const Self as usize,
+            core::panic::Location::caller(),
+            I::core_id(),
+            false,
+            I::in_interrupt(),
+        );
+
+        self.acquire_read();
+
         ReadCellGuard {
             rw_cell: self,
             _phantom: PhantomData,
         }
     }
+
+    #[track_caller]
+    fn upgradable_read(&self) -> UpgradableReadCellGuard<'_, T, Self> {
+        assert!(
+            !self.preemtable || !I::in_interrupt(),
+            "cannot use non-preemtable RwTicketLock in interrupt"
+        );
+
+        // Safety: Disabling interrupts is ok for non-preemtable locks.
+        unsafe {
+            I::enter_critical_section(!self.preemtable);
+        }
+
+        #[cfg(feature = "lockdep")]
+        self.lockdep.acquire(
+            self as *const Self as usize,
+            core::panic::Location::caller(),
+            I::core_id(),
+            self.preemtable,
+            I::in_interrupt(),
+        );
+
+        // Only one upgradable reader at a time, so that two of them never wait
+        // for each other to upgrade.
+        while self
+            .upgradable
+            .compare_exchange_weak(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+        self.acquire_read();
+
+        UpgradableReadCellGuard {
+            rw_cell: self,
+            _phantom: PhantomData,
+        }
+    }
 }
 
 impl<T: Send, I: InterruptState> LockCell<T> for RwTicketLock<T, I> {
@@ -292,21 +627,16 @@ impl<T: Send, I: InterruptState> LockCell<T> for RwTicketLock<T, I> {
             I::enter_critical_section(!self.preemtable);
         }
 
-        loop {
-            match self
-                .access_count
-                .compare_exchange(0, -1, Ordering::SeqCst, Ordering::SeqCst)
-            {
-                Ok(prev) => {
-                    assert_eq!(
-                        prev, 0,
-                        "attempted to lock a RwTicketLock for writing while read locks exist"
-                    );
-                    break;
-                }
-                Err(_) => spin_loop(),
-            }
-        }
+        #[cfg(feature = "lockdep")]
+        self.lockdep.acquire(
+            self as *const Self as usize,
+            core::panic::Location::caller(),
+            I::core_id(),
+            self.preemtable,
+            I::in_interrupt(),
+        );
+
+        self.acquire_write(0);
 
         LockCellGuard {
             lockcell: self,
@@ -316,11 +646,54 @@ impl<T: Send, I: InterruptState> LockCell<T> for RwTicketLock<T, I> {
 
     #[track_caller]
     fn try_lock(&self) -> Option<LockCellGuard<'_, T, Self>> {
-        if self.access_count.load(Ordering::SeqCst) == 0 {
-            Some(self.lock())
-        } else {
-            None
+        assert!(
+            !self.preemtable || !I::in_interrupt(),
+            "cannot use non-preemtable RwTicketLock in interrupt"
+        );
+
+        unsafe {
+            // Safety: For preemtable locks, disabling interrupts is ok.
+            I::enter_critical_section(!self.preemtable);
         }
+
+        // Only take a turn if it is the one being served, and only take the
+        // lock if nobody holds it, so we never wait.
+        let takes_turn = self.policy == RwLockPolicy::Fair;
+        let has_turn = !takes_turn || {
+            let ticket = self.current_ticket.load(Ordering::SeqCst);
+            self.next_ticket
+                .compare_exchange(ticket, ticket + 1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        };
+        let locked = has_turn
+            && self
+                .access_count
+                .compare_exchange(0, -1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok();
+        if !locked {
+            if takes_turn && has_turn {
+                self.end_turn();
+            }
+            // Safety: this restores the interrupt state from when we called
+            // enter_critical_section above
+            unsafe {
+                I::exit_critical_section(!self.preemtable);
+            }
+            return None;
+        }
+        self.writer_has_turn.store(takes_turn, Ordering::Relaxed);
+
+        #[cfg(feature = "lockdep")]
+        self.lockdep.acquire_try(
+            self as *const Self as usize,
+            core::panic::Location::caller(),
+            I::core_id(),
+        );
+
+        Some(LockCellGuard {
+            lockcell: self,
+            _phantom: PhantomData,
+        })
     }
 }
 
@@ -343,6 +716,11 @@ impl<T, I: InterruptState> RwCellInternal<T> for RwTicketLock<T, I> {
             previous_count >= 1,
             "attempted to forcibly release a read lock for a RwTicketLock when no read locks exist"
         );
+
+        #[cfg(feature = "lockdep")]
+        self.lockdep
+            .release(self as *const Self as usize, I::core_id());
+
         // Safety: This will restore the interrupt state from when we called
         // enter_critical_section, so this is safe.
         unsafe {
@@ -353,6 +731,30 @@ impl<T, I: InterruptState> RwCellInternal<T> for RwTicketLock<T, I> {
     fn open_to_read(&self) -> bool {
         self.access_count.load(Ordering::SeqCst) >= 0
     }
+
+    unsafe fn downgrade_write(&self) {
+        self.write_to_read();
+    }
+
+    unsafe fn upgrade_read(&self) {
+        // We are the only upgradable reader, so once all other readers are gone
+        // no one else can take the lock before us.
+        self.acquire_write(1);
+        self.upgradable.store(false, Ordering::SeqCst);
+    }
+
+    unsafe fn downgrade_upgradable(&self) {
+        self.upgradable.store(false, Ordering::SeqCst);
+    }
+
+    unsafe fn release_upgradable(&self) {
+        self.upgradable.store(false, Ordering::SeqCst);
+
+        // Safety: The caller ensures that it holds a read lock.
+        unsafe {
+            self.force_release_read();
+        }
+    }
 }
 
 impl<T, I: InterruptState> LockCellInternal<T> for RwTicketLock<T, I> {
@@ -375,6 +777,11 @@ impl<T, I: InterruptState> LockCellInternal<T> for RwTicketLock<T, I> {
     }
 
     unsafe fn force_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        self.lockdep
+            .release(self as *const Self as usize, I::core_id());
+
+        self.end_write_turn();
         self.access_count.store(0, Ordering::SeqCst);
 
         // Safety: This will restore the interrupt state from when we called
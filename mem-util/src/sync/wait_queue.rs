@@ -0,0 +1,214 @@
+//! A FIFO queue of threads and async tasks waiting for some event.
+//!
+//! This is the building block for the blocking primitives in this module, like
+//! [`Mutex`][super::mutex::Mutex], [`Condvar`][super::condvar::Condvar] and
+//! [`Semaphore`][super::semaphore::Semaphore].
+//!
+//! Waking up waiters never allocates, so [`WaitQueue::notify_one`] and
+//! [`WaitQueue::notify_all`] can be used from within interrupts. Adding a waiter
+//! allocates, so waiting is only possible outside of interrupts.
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use super::{lock_cell::LockCell, ticket_lock::TicketLock, Parker};
+
+/// What to do once a [`Waiter`] is notified.
+enum WakeTarget<T> {
+    /// Unpark a suspended thread.
+    Thread(T),
+    /// Wake an async task.
+    Task(Waker),
+}
+
+/// A single entry of a [`WaitQueue`].
+///
+/// Both the [`WaitQueue`] and the waiting thread or task hold a reference to the
+/// waiter, until it is notified.
+pub struct Waiter<I: Parker> {
+    /// Set once the waiter was removed from the queue by a notification.
+    notified: AtomicBool,
+    /// [`None`] if the waiter spins, or if it's an async task that wasn't polled yet.
+    target: TicketLock<Option<WakeTarget<I::Thread>>, I>,
+}
+
+impl<I: Parker> Waiter<I> {
+    /// Create a waiter for the current thread.
+    ///
+    /// If the current thread can't be suspended (see [`Parker::current_thread`]),
+    /// [`Waiter::wait`] will spin instead.
+    pub fn for_current_thread() -> Arc<Self> {
+        Arc::new(Self {
+            notified: AtomicBool::new(false),
+            target: TicketLock::new_non_preemtable(I::current_thread().map(WakeTarget::Thread)),
+        })
+    }
+
+    /// Create a waiter for an async task. The task's [`Waker`] is registered by
+    /// [`Waiter::poll_notified`].
+    pub fn for_task() -> Arc<Self> {
+        Arc::new(Self {
+            notified: AtomicBool::new(false),
+            target: TicketLock::new_non_preemtable(None),
+        })
+    }
+
+    /// Returns `true` once the waiter was notified.
+    pub fn is_notified(&self) -> bool {
+        self.notified.load(Ordering::Acquire)
+    }
+
+    /// Block the current thread until the waiter is notified.
+    ///
+    /// Must only be called by the thread that created the waiter using
+    /// [`Waiter::for_current_thread`].
+    pub fn wait(&self) {
+        let can_park = matches!(*self.target.lock(), Some(WakeTarget::Thread(_)));
+        while !self.is_notified() {
+            if can_park {
+                I::park();
+            } else {
+                spin_loop();
+            }
+        }
+    }
+
+    /// Register the waker of `cx` and check whether the waiter was notified.
+    pub fn poll_notified(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_notified() {
+            return Poll::Ready(());
+        }
+
+        {
+            let mut target = self.target.lock();
+            match target.as_mut() {
+                Some(WakeTarget::Task(waker)) if waker.will_wake(cx.waker()) => {}
+                _ => *target = Some(WakeTarget::Task(cx.waker().clone())),
+            }
+        }
+
+        // The notification might have happened before the waker was registered.
+        if self.is_notified() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Mark the waiter as notified and wake it up.
+    fn notify(&self) {
+        self.notified.store(true, Ordering::Release);
+
+        match self.target.lock().take() {
+            Some(WakeTarget::Thread(thread)) => I::unpark(&thread),
+            Some(WakeTarget::Task(waker)) => waker.wake(),
+            None => {}
+        }
+    }
+}
+
+/// A FIFO queue of [`Waiter`]s.
+pub struct WaitQueue<I: Parker> {
+    waiters: TicketLock<VecDeque<Arc<Waiter<I>>>, I>,
+}
+
+impl<I: Parker> WaitQueue<I> {
+    /// Create an empty [`WaitQueue`].
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            waiters: TicketLock::new_non_preemtable(VecDeque::new()),
+        }
+    }
+
+    /// Append `waiter` to the queue.
+    pub fn push(&self, waiter: Arc<Waiter<I>>) {
+        assert!(!I::in_interrupt(), "cannot wait inside of an interrupt");
+        self.waiters.lock().push_back(waiter);
+    }
+
+    /// Append `waiter` to the queue, unless `done` returns `true`.
+    ///
+    /// `done` is called while the queue is locked, so it can't race with
+    /// [`WaitQueue::notify_one_or_else`]. Returns `true` if the waiter was added.
+    pub fn push_unless(&self, waiter: Arc<Waiter<I>>, done: impl FnOnce() -> bool) -> bool {
+        assert!(!I::in_interrupt(), "cannot wait inside of an interrupt");
+        let mut waiters = self.waiters.lock();
+        if done() {
+            false
+        } else {
+            waiters.push_back(waiter);
+            true
+        }
+    }
+
+    /// Remove `waiter` from the queue, e.g. because an async task was cancelled.
+    ///
+    /// Returns `false` if the waiter wasn't in the queue, because it was already
+    /// notified.
+    pub fn remove(&self, waiter: &Arc<Waiter<I>>) -> bool {
+        let mut waiters = self.waiters.lock();
+        match waiters.iter().position(|w| Arc::ptr_eq(w, waiter)) {
+            Some(idx) => {
+                waiters.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Notify the first waiter in the queue.
+    ///
+    /// Returns `false` if there is no waiter.
+    pub fn notify_one(&self) -> bool {
+        self.notify_one_or_else(|| {})
+    }
+
+    /// Notify the first waiter in the queue, or call `empty` if there is none.
+    ///
+    /// `empty` is called while the queue is locked.
+    pub fn notify_one_or_else(&self, empty: impl FnOnce()) -> bool {
+        let mut waiters = self.waiters.lock();
+        match waiters.pop_front() {
+            Some(waiter) => {
+                waiter.notify();
+                true
+            }
+            None => {
+                empty();
+                false
+            }
+        }
+    }
+
+    /// Notify all waiters in the queue.
+    ///
+    /// Returns the number of waiters that were notified.
+    pub fn notify_all(&self) -> usize {
+        let mut waiters = self.waiters.lock();
+        let count = waiters.len();
+        while let Some(waiter) = waiters.pop_front() {
+            waiter.notify();
+        }
+        count
+    }
+
+    /// Returns `true` if no one is waiting.
+    ///
+    /// The caller can't rely on this, since waiters could be added or removed
+    /// right after this call.
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+impl<I: Parker> Default for WaitQueue<I> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
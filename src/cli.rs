@@ -1,8 +1,15 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    /// The runner config file. Defaults to `jo12bar-os.toml` in the current
+    /// directory, if it exists.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -11,15 +18,40 @@ impl Cli {
     pub fn command(&self) -> Commands {
         self.command.clone().unwrap_or(Commands::Run {
             boot_mode: BootMode::Uefi,
+            qemu: QemuArgs::default(),
+            kernel: KernelArgs::default(),
+            serial: SerialArgs::default(),
+            expect: ExpectArgs::default(),
+            screenshot: ScreenshotArgs::default(),
+            gdb: GdbArgs::default(),
         })
     }
 }
 
 #[derive(Clone, Debug, Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     Run {
         #[arg(value_enum, default_value_t = BootMode::Uefi)]
         boot_mode: BootMode,
+
+        #[command(flatten)]
+        qemu: QemuArgs,
+
+        #[command(flatten)]
+        kernel: KernelArgs,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        expect: ExpectArgs,
+
+        #[command(flatten)]
+        screenshot: ScreenshotArgs,
+
+        #[command(flatten)]
+        gdb: GdbArgs,
     },
 
     CopyDiskImages,
@@ -30,3 +62,207 @@ pub enum BootMode {
     Uefi,
     Bios,
 }
+
+/// QEMU options. Unset options are taken from the config file.
+#[derive(Clone, Debug, Default, Args)]
+pub struct QemuArgs {
+    /// The amount of memory, e.g. `512M` or `2G`.
+    #[arg(long, value_name = "SIZE")]
+    pub memory: Option<String>,
+
+    /// The number of CPU cores.
+    #[arg(long, value_name = "COUNT")]
+    pub smp: Option<u32>,
+
+    /// The CPU model, e.g. `host` or `qemu64`.
+    #[arg(long, value_name = "MODEL")]
+    pub cpu: Option<String>,
+
+    /// Use KVM acceleration.
+    #[arg(long, overrides_with = "no_kvm")]
+    pub kvm: bool,
+
+    /// Don't use KVM acceleration, even if the config file enables it.
+    #[arg(long, overrides_with = "kvm")]
+    pub no_kvm: bool,
+
+    /// Don't open a display window.
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Where to connect the serial port, e.g. `stdio` or `file:serial.log`.
+    #[arg(long, value_name = "ROUTE")]
+    pub serial_route: Option<String>,
+
+    /// Where to connect the QEMU monitor, e.g. `stdio` or `tcp::4444,server`.
+    #[arg(long, value_name = "ROUTE")]
+    pub monitor: Option<String>,
+
+    /// Additional arguments passed to QEMU as-is.
+    #[arg(last = true, value_name = "QEMU_ARGS")]
+    pub qemu_args: Vec<String>,
+}
+
+impl QemuArgs {
+    /// Returns `Some` if KVM was enabled or disabled on the command line.
+    pub fn kvm(&self) -> Option<bool> {
+        match (self.kvm, self.no_kvm) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Options passed to the kernel. Unset options are taken from the config file.
+#[derive(Clone, Debug, Default, Args)]
+pub struct KernelArgs {
+    /// The kernel command line, e.g. `log=debug fb_log=off demos=none`.
+    #[arg(long, value_name = "STRING")]
+    pub cmdline: Option<String>,
+
+    /// Pack this directory into the initial ramdisk, instead of `initrd/`.
+    #[arg(long, value_name = "DIR")]
+    pub initrd: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Args)]
+pub struct SerialArgs {
+    /// Also write the kernel's serial output to this file.
+    #[arg(long, value_name = "PATH")]
+    pub serial_log: Option<PathBuf>,
+
+    /// Keep ANSI colors in the serial log file.
+    #[arg(long, requires = "serial_log")]
+    pub serial_log_colors: bool,
+
+    /// Whether to keep ANSI colors in the serial output shown in the terminal.
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Keep colors if stdout is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Checks of the serial output, for automated runs.
+#[derive(Clone, Debug, Default, Args)]
+pub struct ExpectArgs {
+    /// Stop QEMU and fail if it runs longer than this, e.g. `30s` or `2m`.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Text the serial output must contain. Once all expected texts were seen,
+    /// QEMU is stopped.
+    #[arg(long, value_name = "TEXT")]
+    pub expect: Vec<String>,
+
+    /// Text the serial output must not contain.
+    #[arg(long, value_name = "TEXT")]
+    pub forbid: Vec<String>,
+}
+
+impl ExpectArgs {
+    /// Returns `true` if the run is checked automatically, in which case QEMU
+    /// is also stopped if the kernel panics.
+    pub fn is_automated(&self) -> bool {
+        self.timeout.is_some() || !self.expect.is_empty() || !self.forbid.is_empty()
+    }
+}
+
+/// Screenshots of the display, and graphics regression tests.
+#[derive(Clone, Debug, Default, Args)]
+pub struct ScreenshotArgs {
+    /// Expose QEMU's QMP server on this local TCP port. A free port is used
+    /// if screenshots are taken without it.
+    #[arg(long, value_name = "PORT")]
+    pub qmp_port: Option<u16>,
+
+    /// Save a screenshot of the display to this PNG file.
+    #[arg(long, value_name = "PATH", requires = "screenshot_on")]
+    pub screenshot: Option<PathBuf>,
+
+    /// Take the screenshot once the serial output contains this text.
+    #[arg(long, value_name = "TEXT", requires = "screenshot")]
+    pub screenshot_on: Option<String>,
+
+    /// How long to wait after the text was seen before taking the screenshot.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "500ms"
+    )]
+    pub screenshot_delay: Duration,
+
+    /// Compare the screenshot with this PNG file, and fail if they differ.
+    #[arg(long, value_name = "PATH", requires = "screenshot")]
+    pub golden: Option<PathBuf>,
+
+    /// The percentage of pixels that may differ from the golden image.
+    #[arg(long, value_name = "PERCENT", default_value_t = 0.0)]
+    pub golden_tolerance: f64,
+
+    /// Replace the golden image with the screenshot instead of comparing them.
+    #[arg(long, requires = "golden")]
+    pub update_golden: bool,
+}
+
+#[derive(Clone, Debug, Default, Args)]
+pub struct GdbArgs {
+    /// Wait for a debugger on localhost:1234 before booting, and write a
+    /// .gdbinit that connects to it.
+    #[arg(long)]
+    pub gdb: bool,
+
+    /// Start a debugger using the generated .gdbinit.
+    #[arg(
+        long,
+        value_enum,
+        requires = "gdb",
+        conflicts_with_all = ["timeout", "screenshot"]
+    )]
+    pub gdb_client: Option<GdbClient>,
+
+    /// The offset the kernel is loaded at. The kernel logs it while booting as
+    /// `Kernel image offset`, and by default the one logged in the last run is
+    /// used.
+    #[arg(long, value_name = "HEX", value_parser = parse_hex, requires = "gdb")]
+    pub gdb_load_offset: Option<u64>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GdbClient {
+    Gdb,
+    RustGdb,
+}
+
+fn parse_hex(s: &str) -> Result<u64, std::num::ParseIntError> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
+}
+
+/// Parse a duration like `500ms`, `30s` or `2m`. Plain numbers are seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration `{s}`"))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "" | "s" => Ok(Duration::from_secs(value)),
+        "m" => value
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration `{s}` is too long")),
+        _ => Err(format!(
+            "unknown duration unit `{unit}`, expected ms, s or m"
+        )),
+    }
+}
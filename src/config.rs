@@ -0,0 +1,148 @@
+//! The runner's config file.
+//!
+//! Options that are the same for every run can be put into a TOML file instead
+//! of passing them on the command line each time:
+//!
+//! ```toml
+//! [qemu]
+//! memory = "512M"
+//! smp = 2
+//! kvm = true
+//! extra-args = ["-no-reboot"]
+//!
+//! [kernel]
+//! cmdline = "log=info demos=none"
+//! ```
+//!
+//! Command line options take precedence over the config file.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::Context;
+use serde::Deserialize;
+
+use crate::cli::{KernelArgs, QemuArgs};
+
+/// The config file used if `--config` isn't given.
+pub const DEFAULT_CONFIG_FILE: &str = "jo12bar-os.toml";
+
+/// The contents of the config file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The `[qemu]` table.
+    pub qemu: QemuConfig,
+    /// The `[kernel]` table.
+    pub kernel: KernelConfig,
+}
+
+impl Config {
+    /// Load the config file at `path`, or [`DEFAULT_CONFIG_FILE`] if it exists.
+    pub fn load(path: Option<&Path>) -> color_eyre::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_FILE);
+                if !path.exists() {
+                    return Ok(Self::default());
+                }
+                path
+            }
+        };
+
+        let contents = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("couldn't read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("invalid config file {}", path.display()))
+    }
+}
+
+/// How QEMU is set up.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct QemuConfig {
+    /// The amount of memory, using QEMU's `-m` syntax.
+    pub memory: Option<String>,
+    /// The number of CPU cores.
+    pub smp: Option<u32>,
+    /// The CPU model.
+    pub cpu: Option<String>,
+    /// Use KVM acceleration.
+    pub kvm: bool,
+    /// Don't open a display window.
+    pub headless: bool,
+    /// Where to connect the serial port.
+    pub serial: String,
+    /// Where to connect the QEMU monitor, if not QEMU's default.
+    pub monitor: Option<String>,
+    /// Passed to QEMU as-is, before the arguments from the command line.
+    pub extra_args: Vec<String>,
+}
+
+impl Default for QemuConfig {
+    fn default() -> Self {
+        Self {
+            memory: None,
+            smp: None,
+            cpu: None,
+            kvm: false,
+            headless: false,
+            serial: "stdio".to_owned(),
+            monitor: None,
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+impl QemuConfig {
+    /// Override options that were given on the command line.
+    pub fn merge_args(mut self, args: &QemuArgs) -> Self {
+        if let Some(memory) = &args.memory {
+            self.memory = Some(memory.clone());
+        }
+        if let Some(smp) = args.smp {
+            self.smp = Some(smp);
+        }
+        if let Some(cpu) = &args.cpu {
+            self.cpu = Some(cpu.clone());
+        }
+        if let Some(kvm) = args.kvm() {
+            self.kvm = kvm;
+        }
+        self.headless |= args.headless;
+        if let Some(serial) = &args.serial_route {
+            self.serial = serial.clone();
+        }
+        if let Some(monitor) = &args.monitor {
+            self.monitor = Some(monitor.clone());
+        }
+        self.extra_args.extend(args.qemu_args.iter().cloned());
+        self
+    }
+}
+
+/// What is passed to the kernel.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct KernelConfig {
+    /// The kernel command line, see `jo12bar_os_kernel::cmdline`.
+    pub cmdline: Option<String>,
+    /// The directory packed into the initial ramdisk, instead of `initrd/`.
+    pub initrd: Option<PathBuf>,
+}
+
+impl KernelConfig {
+    /// Override options that were given on the command line.
+    pub fn merge_args(mut self, args: &KernelArgs) -> Self {
+        if let Some(cmdline) = &args.cmdline {
+            self.cmdline = Some(cmdline.clone());
+        }
+        if let Some(initrd) = &args.initrd {
+            self.initrd = Some(initrd.clone());
+        }
+        self
+    }
+}
@@ -0,0 +1,89 @@
+//! The disk images QEMU boots from.
+//!
+//! The images built by `build.rs` boot the kernel with the contents of
+//! `initrd/` as its initial ramdisk, and without a command line. If a command
+//! line or another initrd directory is given, new images are built next to the
+//! runner. The command line is passed to the kernel as the file `cmdline` in
+//! the initial ramdisk.
+//!
+//! The initial ramdisk also contains the file `fat.img`, a FAT image with the
+//! contents of `fat/`, which the kernel's FAT demo mounts.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use bootloader::DiskImageBuilder;
+use color_eyre::eyre::{eyre, Context};
+
+use crate::{cli::BootMode, config::KernelConfig, fat_image, initrd};
+
+/// The file in the initial ramdisk containing the kernel command line.
+///
+/// Mirrors `jo12bar_os_kernel::cmdline::CMDLINE_FILE`.
+const CMDLINE_FILE: &str = "cmdline";
+
+/// The file in the initial ramdisk containing the FAT image.
+///
+/// Mirrors `FAT_IMAGE` in the kernel's `main.rs`.
+const FAT_IMAGE_FILE: &str = "fat.img";
+
+/// Returns the disk image for `boot_mode`, building one if `config` differs
+/// from the one `build.rs` used.
+pub fn image(boot_mode: BootMode, config: &KernelConfig) -> color_eyre::Result<PathBuf> {
+    let cmdline = config.cmdline.as_deref().map(str::trim).unwrap_or_default();
+    if cmdline.is_empty() && config.initrd.is_none() {
+        return Ok(PathBuf::from(prebuilt_image(boot_mode)));
+    }
+
+    let current_exe = env::current_exe()?;
+    let initrd_dir = config
+        .initrd
+        .as_deref()
+        .unwrap_or(Path::new(env!("INITRD_DIR")));
+    if !initrd_dir.is_dir() {
+        return Err(eyre!(
+            "initrd directory {} doesn't exist",
+            initrd_dir.display()
+        ));
+    }
+    let fat_dir = Path::new(env!("FAT_DIR"));
+    let fat_image = fat_image::build(fat_dir)
+        .wrap_err_with(|| format!("couldn't pack {} into a FAT image", fat_dir.display()))?;
+    let mut extra_files = vec![(FAT_IMAGE_FILE, fat_image.as_slice())];
+    if !cmdline.is_empty() {
+        extra_files.push((CMDLINE_FILE, cmdline.as_bytes()));
+    }
+    let initrd = current_exe.with_file_name("initrd.cpio");
+    initrd::pack(initrd_dir, &extra_files, &initrd).wrap_err_with(|| {
+        format!(
+            "couldn't pack {} into the initial ramdisk",
+            initrd_dir.display()
+        )
+    })?;
+
+    // set by build.rs, see `CARGO_BIN_FILE_JO12BAR_OS_KERNEL`
+    let mut builder = DiskImageBuilder::new(PathBuf::from(env!("KERNEL_ELF")));
+    builder.set_ramdisk(initrd);
+
+    let image = match boot_mode {
+        BootMode::Uefi => current_exe.with_file_name("uefi-custom.img"),
+        BootMode::Bios => current_exe.with_file_name("bios-custom.img"),
+    };
+    match boot_mode {
+        BootMode::Uefi => builder.create_uefi_image(&image),
+        BootMode::Bios => builder.create_bios_image(&image),
+    }
+    .map_err(|e| eyre!("couldn't create disk image {}: {e:#}", image.display()))?;
+
+    Ok(image)
+}
+
+/// Returns the disk image built by `build.rs`.
+fn prebuilt_image(boot_mode: BootMode) -> &'static Path {
+    match boot_mode {
+        BootMode::Uefi => Path::new(env!("UEFI_IMAGE")),
+        BootMode::Bios => Path::new(env!("BIOS_IMAGE")),
+    }
+}
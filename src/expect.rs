@@ -0,0 +1,92 @@
+//! Checking the serial output for expected and forbidden text.
+
+use crate::cli::{ExpectArgs, ScreenshotArgs};
+
+/// Something in the serial output that ends an automated run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SerialEvent {
+    /// All expected texts were seen.
+    AllExpected,
+    /// A forbidden text was seen.
+    Forbidden(String),
+    /// The kernel panicked.
+    Panicked,
+    /// The text a screenshot should be taken at was seen.
+    ScreenshotMarker,
+}
+
+/// The texts the serial output must and must not contain.
+#[derive(Clone, Debug, Default)]
+pub struct Expectations {
+    /// Each expected text, and whether it was seen.
+    expected: Vec<(String, bool)>,
+    forbidden: Vec<String>,
+    /// The text a screenshot should be taken at, and whether it was seen.
+    screenshot_marker: Option<(String, bool)>,
+}
+
+impl Expectations {
+    pub fn new(args: &ExpectArgs, screenshot: &ScreenshotArgs) -> Self {
+        Self {
+            expected: args
+                .expect
+                .iter()
+                .map(|text| (text.clone(), false))
+                .collect(),
+            forbidden: args.forbid.clone(),
+            screenshot_marker: screenshot
+                .screenshot_on
+                .as_ref()
+                .map(|text| (text.clone(), false)),
+        }
+    }
+
+    /// Check a line of serial output, without ANSI escape sequences.
+    ///
+    /// Returns the events caused by the line, in the order they should be
+    /// handled.
+    pub fn check_line(&mut self, line: &str) -> Vec<SerialEvent> {
+        let mut events = Vec::new();
+
+        if let Some((text, seen)) = &mut self.screenshot_marker {
+            if !*seen && line.contains(text.as_str()) {
+                *seen = true;
+                events.push(SerialEvent::ScreenshotMarker);
+            }
+        }
+
+        if let Some(text) = self
+            .forbidden
+            .iter()
+            .find(|text| line.contains(text.as_str()))
+        {
+            events.push(SerialEvent::Forbidden(text.clone()));
+        }
+
+        let mut newly_seen = false;
+        for (text, seen) in &mut self.expected {
+            if !*seen && line.contains(text.as_str()) {
+                *seen = true;
+                newly_seen = true;
+            }
+        }
+        if newly_seen && self.all_seen() {
+            events.push(SerialEvent::AllExpected);
+        }
+
+        events
+    }
+
+    /// Returns `true` if there are expected texts and all of them were seen.
+    pub fn all_seen(&self) -> bool {
+        !self.expected.is_empty() && self.expected.iter().all(|(_, seen)| *seen)
+    }
+
+    /// Returns the expected texts that weren't seen.
+    pub fn missing(&self) -> impl Iterator<Item = &str> {
+        self.expected
+            .iter()
+            .filter(|(_, seen)| !seen)
+            .map(|(text, _)| text.as_str())
+    }
+}
@@ -0,0 +1,321 @@
+//! Building the FAT image for the kernel's FAT demo.
+//!
+//! The image is a FAT12 volume with the contents of a directory, which the
+//! kernel mounts using `jo12bar_os_kernel::fs::fat`. Names that aren't
+//! uppercase 8.3 names get long file name (LFN) entries, so that the kernel's
+//! LFN support is used as well.
+//!
+//! This module is also used by `build.rs`, so it must only depend on `std`.
+
+use std::{fs, io, path::Path};
+
+const SECTOR_SIZE: usize = 512;
+/// One sector per cluster, so that even small files span several clusters.
+const CLUSTER_SIZE: usize = SECTOR_SIZE;
+/// The size of the volume, 32 KiB, so that the kernel can copy it into its
+/// small heap.
+const TOTAL_SECTORS: usize = 64;
+const RESERVED_SECTORS: usize = 1;
+const FAT_COUNT: usize = 2;
+/// One sector of root directory entries.
+const ROOT_ENTRY_COUNT: usize = 16;
+const ENTRY_LEN: usize = 32;
+/// The media descriptor of fixed disks.
+const MEDIA: u8 = 0xf8;
+/// The cluster value ending a chain.
+const END_OF_CHAIN: u16 = 0xfff;
+
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+/// The UTF-16 code units stored in an LFN entry, and their offsets.
+const LFN_CHARS: usize = 13;
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// A file or directory to put into the image.
+enum Node {
+    File(Vec<u8>),
+    Dir(Vec<(String, Node)>),
+}
+
+/// A FAT12 volume being written.
+struct FatImage {
+    data: Vec<u8>,
+    fat: Vec<u16>,
+    next_cluster: u16,
+}
+
+/// Build an image with the contents of `dir`, or an empty one if `dir`
+/// doesn't exist.
+pub fn build(dir: &Path) -> io::Result<Vec<u8>> {
+    let root = if dir.is_dir() {
+        read_dir(dir)?
+    } else {
+        Vec::new()
+    };
+    let mut image = FatImage::new();
+    image.write_root(&root)?;
+    Ok(image.finish())
+}
+
+/// Read the files and directories in `dir`, sorted by name.
+fn read_dir(dir: &Path) -> io::Result<Vec<(String, Node)>> {
+    let mut nodes = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().map_err(|name| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not valid UTF-8", Path::new(&name).display()),
+            )
+        })?;
+        let node = if entry.file_type()?.is_dir() {
+            Node::Dir(read_dir(&entry.path())?)
+        } else {
+            Node::File(fs::read(entry.path())?)
+        };
+        nodes.push((name, node));
+    }
+    nodes.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(nodes)
+}
+
+impl FatImage {
+    const ROOT_DIR_SECTORS: usize = ROOT_ENTRY_COUNT * ENTRY_LEN / SECTOR_SIZE;
+    /// Large enough for a FAT entry per sector, which is more than there are
+    /// clusters.
+    const FAT_SECTORS: usize = (TOTAL_SECTORS * 3 / 2).div_ceil(SECTOR_SIZE);
+    const ROOT_DIR_OFFSET: usize = (RESERVED_SECTORS + FAT_COUNT * Self::FAT_SECTORS) * SECTOR_SIZE;
+    const DATA_OFFSET: usize = Self::ROOT_DIR_OFFSET + Self::ROOT_DIR_SECTORS * SECTOR_SIZE;
+    const CLUSTER_COUNT: usize = (TOTAL_SECTORS * SECTOR_SIZE - Self::DATA_OFFSET) / CLUSTER_SIZE;
+
+    fn new() -> Self {
+        let mut fat = vec![0; Self::CLUSTER_COUNT + 2];
+        fat[0] = 0xf00 | u16::from(MEDIA);
+        fat[1] = END_OF_CHAIN;
+        Self {
+            data: vec![0; TOTAL_SECTORS * SECTOR_SIZE],
+            fat,
+            next_cluster: 2,
+        }
+    }
+
+    /// Write the fixed root directory and everything in it.
+    fn write_root(&mut self, nodes: &[(String, Node)]) -> io::Result<()> {
+        let entries = self.write_entries(nodes, 0)?;
+        if entries.len() > ROOT_ENTRY_COUNT * ENTRY_LEN {
+            return Err(io::Error::other("too many entries in the root directory"));
+        }
+        self.data[Self::ROOT_DIR_OFFSET..][..entries.len()].copy_from_slice(&entries);
+        Ok(())
+    }
+
+    /// Write the directory `nodes` whose parent starts at `parent`, and
+    /// return its first cluster.
+    fn write_dir(&mut self, nodes: &[(String, Node)], parent: u16) -> io::Result<u16> {
+        // The clusters are allocated first, since the `.` entries of the
+        // subdirectories point to them.
+        let len = 2 * ENTRY_LEN
+            + nodes
+                .iter()
+                .map(|(name, _)| (lfn_entry_count(name) + 1) * ENTRY_LEN)
+                .sum::<usize>();
+        let first = self.allocate(len.div_ceil(CLUSTER_SIZE))?;
+
+        let mut entries = Vec::with_capacity(len);
+        entries.extend(short_entry(b".          ", ATTR_DIRECTORY, first, 0));
+        entries.extend(short_entry(b"..         ", ATTR_DIRECTORY, parent, 0));
+        entries.extend(self.write_entries(nodes, first)?);
+        self.write_chain(first, &entries);
+        Ok(first)
+    }
+
+    /// Write the files and directories `nodes` in the directory starting at
+    /// `dir`, and return their directory entries.
+    fn write_entries(&mut self, nodes: &[(String, Node)], dir: u16) -> io::Result<Vec<u8>> {
+        let mut entries = Vec::new();
+        let mut short_names = Vec::new();
+        for (name, node) in nodes {
+            let (attr, cluster, size) = match node {
+                Node::File(data) if data.is_empty() => (ATTR_ARCHIVE, 0, 0),
+                Node::File(data) => {
+                    let cluster = self.allocate(data.len().div_ceil(CLUSTER_SIZE))?;
+                    self.write_chain(cluster, data);
+                    let size = u32::try_from(data.len())
+                        .map_err(|_| io::Error::other(format!("{name} is too large")))?;
+                    (ATTR_ARCHIVE, cluster, size)
+                }
+                Node::Dir(nodes) => (ATTR_DIRECTORY, self.write_dir(nodes, dir)?, 0),
+            };
+
+            let short_name =
+                exact_short_name(name).unwrap_or_else(|| generate_short_name(name, &short_names));
+            short_names.push(short_name);
+            if exact_short_name(name).is_none() {
+                entries.extend(lfn_entries(name, &short_name));
+            }
+            entries.extend(short_entry(&short_name, attr, cluster, size));
+        }
+        Ok(entries)
+    }
+
+    /// Allocate a chain of `count` clusters, and return the first one.
+    fn allocate(&mut self, count: usize) -> io::Result<u16> {
+        let first = self.next_cluster;
+        if usize::from(first) + count > self.fat.len() {
+            return Err(io::Error::other("the FAT image is full"));
+        }
+        for cluster in first..first + count as u16 {
+            self.fat[usize::from(cluster)] = cluster + 1;
+        }
+        self.fat[usize::from(first) + count - 1] = END_OF_CHAIN;
+        self.next_cluster += count as u16;
+        Ok(first)
+    }
+
+    /// Write `data` to the contiguous chain starting at `first`.
+    fn write_chain(&mut self, first: u16, data: &[u8]) {
+        let offset = Self::DATA_OFFSET + usize::from(first - 2) * CLUSTER_SIZE;
+        self.data[offset..][..data.len()].copy_from_slice(data);
+    }
+
+    /// Write the boot sector and the FATs, and return the image.
+    fn finish(mut self) -> Vec<u8> {
+        let boot = &mut self.data[..SECTOR_SIZE];
+        boot[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        boot[3..11].copy_from_slice(b"JO12BAR ");
+        boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        boot[13] = (CLUSTER_SIZE / SECTOR_SIZE) as u8;
+        boot[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+        boot[16] = FAT_COUNT as u8;
+        boot[17..19].copy_from_slice(&(ROOT_ENTRY_COUNT as u16).to_le_bytes());
+        boot[19..21].copy_from_slice(&(TOTAL_SECTORS as u16).to_le_bytes());
+        boot[21] = MEDIA;
+        boot[22..24].copy_from_slice(&(Self::FAT_SECTORS as u16).to_le_bytes());
+        // drive number, extended boot signature and volume ID
+        boot[36] = 0x80;
+        boot[38] = 0x29;
+        boot[39..43].copy_from_slice(&0x4a31_3262u32.to_le_bytes());
+        boot[43..54].copy_from_slice(b"JO12BAR OS ");
+        boot[54..62].copy_from_slice(b"FAT12   ");
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        let mut fat = vec![0; Self::FAT_SECTORS * SECTOR_SIZE];
+        for (cluster, &value) in self.fat.iter().enumerate() {
+            // Two entries are packed into three bytes.
+            let offset = cluster * 3 / 2;
+            if cluster.is_multiple_of(2) {
+                fat[offset] = value as u8;
+                fat[offset + 1] |= (value >> 8) as u8;
+            } else {
+                fat[offset] |= (value << 4) as u8;
+                fat[offset + 1] = (value >> 4) as u8;
+            }
+        }
+        for index in 0..FAT_COUNT {
+            let offset = (RESERVED_SECTORS + index * Self::FAT_SECTORS) * SECTOR_SIZE;
+            self.data[offset..][..fat.len()].copy_from_slice(&fat);
+        }
+        self.data
+    }
+}
+
+/// Returns `true` if `b` may be part of a short name.
+fn is_short_name_char(b: u8) -> bool {
+    b.is_ascii_uppercase() || b.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&b)
+}
+
+/// Returns the short name `name` is stored as, if it is a valid uppercase 8.3
+/// name.
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    let valid = |part: &str, max: usize| part.len() <= max && part.bytes().all(is_short_name_char);
+    if base.is_empty() || !valid(base, 8) || !valid(ext, 3) {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..][..ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short_name)
+}
+
+/// Returns a short name of the form `BASE~N.EXT` for `name` that isn't in
+/// `existing`.
+fn generate_short_name(name: &str, existing: &[[u8; 11]]) -> [u8; 11] {
+    let convert = |part: &str, max: usize| -> Vec<u8> {
+        part.bytes()
+            .map(|b| b.to_ascii_uppercase())
+            .filter(|&b| b != b' ' && b != b'.')
+            .map(|b| if is_short_name_char(b) { b } else { b'_' })
+            .take(max)
+            .collect()
+    };
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => (convert(base, 8), convert(ext, 3)),
+        _ => (convert(name, 8), Vec::new()),
+    };
+
+    (1..)
+        .map(|n| {
+            let tail = format!("~{n}");
+            let base_len = base.len().min(8 - tail.len());
+            let mut short_name = [b' '; 11];
+            short_name[..base_len].copy_from_slice(&base[..base_len]);
+            short_name[base_len..][..tail.len()].copy_from_slice(tail.as_bytes());
+            short_name[8..][..ext.len()].copy_from_slice(&ext);
+            short_name
+        })
+        .find(|short_name| !existing.contains(short_name))
+        .unwrap()
+}
+
+/// Returns the number of LFN entries stored before the short entry of `name`.
+fn lfn_entry_count(name: &str) -> usize {
+    if exact_short_name(name).is_some() {
+        0
+    } else {
+        name.encode_utf16().count().div_ceil(LFN_CHARS)
+    }
+}
+
+/// Returns the LFN entries of `name`, in the order they are stored.
+fn lfn_entries(name: &str, short_name: &[u8; 11]) -> Vec<u8> {
+    let checksum = short_name
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+
+    // The name is terminated by a null character if it doesn't fill the last
+    // entry, and padded with `0xffff`.
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = chars.len().div_ceil(LFN_CHARS);
+    if chars.len() < count * LFN_CHARS {
+        chars.push(0);
+    }
+    chars.resize(count * LFN_CHARS, 0xffff);
+
+    let mut entries = Vec::with_capacity(count * ENTRY_LEN);
+    for (index, chunk) in chars.chunks(LFN_CHARS).enumerate().rev() {
+        let mut entry = [0; ENTRY_LEN];
+        entry[0] = index as u8 + 1;
+        if index == count - 1 {
+            entry[0] |= 0x40;
+        }
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = checksum;
+        for (&offset, &c) in LFN_CHAR_OFFSETS.iter().zip(chunk) {
+            entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entries.extend(entry);
+    }
+    entries
+}
+
+/// Returns a short directory entry.
+fn short_entry(short_name: &[u8; 11], attr: u8, cluster: u16, size: u32) -> [u8; ENTRY_LEN] {
+    let mut entry = [0; ENTRY_LEN];
+    entry[..11].copy_from_slice(short_name);
+    entry[11] = attr;
+    entry[26..28].copy_from_slice(&cluster.to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
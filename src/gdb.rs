@@ -0,0 +1,115 @@
+//! Debugging the kernel with GDB.
+//!
+//! With `--gdb`, QEMU waits for a debugger on `localhost:1234` before booting.
+//! A `.gdbinit` is written next to the runner, which loads the kernel's symbols
+//! at the address the bootloader loads the kernel to, and connects to QEMU.
+//!
+//! The kernel is position-independent, and the bootloader picks its load
+//! offset, which the kernel logs while booting. Since QEMU waits for the
+//! debugger before booting, the offset logged in an earlier run is used. It is
+//! remembered in a `.kernel-image-offset` file next to the runner.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use object::{Object, ObjectKind};
+
+use crate::cli::{GdbArgs, GdbClient};
+
+/// The address QEMU's GDB server listens on when started with `-s`.
+const GDB_SERVER: &str = "localhost:1234";
+
+/// The file next to the runner that remembers the kernel's load offset.
+const LOAD_OFFSET_FILE: &str = ".kernel-image-offset";
+
+/// Make QEMU wait for a debugger before booting.
+pub fn configure_qemu(qemu: &mut Command, args: &GdbArgs) {
+    qemu.arg("-s").arg("-S");
+
+    if args.gdb_client.is_some() {
+        // The debugger owns the terminal, so QEMU must neither read from it nor
+        // be stopped by Ctrl+C.
+        qemu.stdin(Stdio::null());
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(qemu, 0);
+    }
+}
+
+/// Returns the offset the kernel at `kernel_elf` is loaded at.
+pub fn load_offset(args: &GdbArgs, kernel_elf: &Path) -> color_eyre::Result<u64> {
+    if let Some(offset) = args.gdb_load_offset {
+        return Ok(offset);
+    }
+
+    let data = fs::read(kernel_elf)
+        .wrap_err_with(|| format!("couldn't read kernel ELF file {}", kernel_elf.display()))?;
+    let elf = object::File::parse(&*data).wrap_err("couldn't parse kernel ELF file")?;
+    match elf.kind() {
+        ObjectKind::Executable => Ok(0),
+        ObjectKind::Dynamic => remembered_load_offset()?.ok_or_else(|| {
+            eyre!(
+                "the kernel's load offset is unknown. Run it once without `--gdb`, so that \
+                the runner sees the `Kernel image offset` it logs, or pass that offset \
+                with `--gdb-load-offset`"
+            )
+        }),
+        kind => Err(eyre!(
+            "the kernel ELF file has an unexpected kind: {kind:?}"
+        )),
+    }
+}
+
+/// Remember the load offset the kernel logged, for later runs with `--gdb`.
+pub fn remember_load_offset(offset: u64) -> color_eyre::Result<()> {
+    let path = env::current_exe()?.with_file_name(LOAD_OFFSET_FILE);
+    fs::write(&path, format!("{offset:#x}\n"))
+        .wrap_err_with(|| format!("couldn't write {}", path.display()))
+}
+
+/// Returns the load offset remembered by [`remember_load_offset`], if any.
+fn remembered_load_offset() -> color_eyre::Result<Option<u64>> {
+    let path = env::current_exe()?.with_file_name(LOAD_OFFSET_FILE);
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    let offset = text
+        .trim()
+        .strip_prefix("0x")
+        .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| eyre!("{} doesn't contain a load offset", path.display()))?;
+    Ok(Some(offset))
+}
+
+/// Write a `.gdbinit` next to the runner, returning its path.
+pub fn write_gdbinit(kernel_elf: &Path, load_offset: u64) -> color_eyre::Result<PathBuf> {
+    let path = env::current_exe()?.with_file_name(".gdbinit");
+    let gdbinit = format!(
+        "# Generated by jo12bar-os-runner\n\
+        set pagination off\n\
+        set confirm off\n\
+        add-symbol-file {} -o {load_offset:#x}\n\
+        target remote {GDB_SERVER}\n",
+        kernel_elf.display(),
+    );
+    fs::write(&path, gdbinit).wrap_err_with(|| format!("couldn't write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Run `client` using the commands in `gdbinit`, and wait for it to exit.
+pub fn run_client(client: GdbClient, gdbinit: &Path) -> color_eyre::Result<ExitStatus> {
+    let program = match client {
+        GdbClient::Gdb => "gdb",
+        GdbClient::RustGdb => "rust-gdb",
+    };
+
+    Command::new(program)
+        .arg("-q")
+        .arg("-x")
+        .arg(gdbinit)
+        .status()
+        .wrap_err_with(|| format!("couldn't start {program}"))
+}
@@ -0,0 +1,152 @@
+//! Packing the kernel's initial ramdisk.
+//!
+//! The initial ramdisk is a `cpio` archive in the "new ASCII" (`newc`) format,
+//! which the kernel reads in place, see `jo12bar_os_kernel::initrd`.
+//!
+//! This module is also used by `build.rs`, so it must only depend on `std`.
+
+use std::{fs, io, path::Path};
+
+/// The mask of the file type bits.
+const S_IFMT: u32 = 0o170000;
+/// The file type bits of a directory.
+const S_IFDIR: u32 = 0o040000;
+/// The file type bits of a regular file.
+const S_IFREG: u32 = 0o100000;
+/// The file type bits of a symbolic link.
+const S_IFLNK: u32 = 0o120000;
+
+/// A `newc` archive being written.
+#[derive(Debug, Default)]
+pub struct CpioWriter {
+    data: Vec<u8>,
+    next_ino: u32,
+}
+
+impl CpioWriter {
+    /// Create an empty archive.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a directory.
+    pub fn add_dir(&mut self, path: &str, permissions: u32) {
+        self.add_entry(path, S_IFDIR | permissions, &[]);
+    }
+
+    /// Add a regular file.
+    pub fn add_file(&mut self, path: &str, permissions: u32, data: &[u8]) {
+        self.add_entry(path, S_IFREG | permissions, data);
+    }
+
+    /// Add a symbolic link to `target`.
+    pub fn add_symlink(&mut self, path: &str, target: &str) {
+        self.add_entry(path, S_IFLNK | 0o777, target.as_bytes());
+    }
+
+    /// Add the files and directories below `dir`, in sorted order.
+    pub fn add_dir_contents(&mut self, dir: &Path) -> io::Result<()> {
+        self.add_dir_contents_at(dir, "")
+    }
+
+    fn add_dir_contents_at(&mut self, dir: &Path, prefix: &str) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = entry.file_name();
+            let name = name.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} isn't UTF-8", entry.path().display()),
+                )
+            })?;
+            let path = format!("{prefix}{name}");
+            let metadata = fs::symlink_metadata(entry.path())?;
+
+            if metadata.is_dir() {
+                self.add_dir(&path, permissions(&metadata, 0o755));
+                self.add_dir_contents_at(&entry.path(), &format!("{path}/"))?;
+            } else if metadata.is_symlink() {
+                let target = fs::read_link(entry.path())?;
+                self.add_symlink(&path, &target.to_string_lossy());
+            } else {
+                let data = fs::read(entry.path())?;
+                self.add_file(&path, permissions(&metadata, 0o644), &data);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_entry(&mut self, path: &str, mode: u32, data: &[u8]) {
+        self.next_ino += 1;
+        let ino = self.next_ino;
+        let nlink = if mode & S_IFMT == S_IFDIR { 2 } else { 1 };
+        self.write_header(ino, mode, nlink, data.len(), path);
+        self.data.extend_from_slice(data);
+        self.pad();
+    }
+
+    fn write_header(&mut self, ino: u32, mode: u32, nlink: u32, len: usize, name: &str) {
+        // magic, ino, mode, uid, gid, nlink, mtime, filesize, devmajor,
+        // devminor, rdevmajor, rdevminor, namesize, check
+        let fields = [
+            ino,
+            mode,
+            0,
+            0,
+            nlink,
+            0,
+            len as u32,
+            0,
+            0,
+            0,
+            0,
+            name.len() as u32 + 1,
+            0,
+        ];
+        self.data.extend_from_slice(b"070701");
+        for field in fields {
+            self.data
+                .extend_from_slice(format!("{field:08x}").as_bytes());
+        }
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.push(0);
+        self.pad();
+    }
+
+    /// Pad to a multiple of 4 bytes.
+    fn pad(&mut self) {
+        let len = self.data.len().next_multiple_of(4);
+        self.data.resize(len, 0);
+    }
+
+    /// Add the trailer, and return the archive.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.write_header(0, 0, 1, 0, "TRAILER!!!");
+        self.data
+    }
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata, _default: u32) -> u32 {
+    std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions(_metadata: &fs::Metadata, default: u32) -> u32 {
+    default
+}
+
+/// Pack the contents of `dir`, if it exists, followed by `extra_files`, and
+/// write the archive to `out`.
+pub fn pack(dir: &Path, extra_files: &[(&str, &[u8])], out: &Path) -> io::Result<()> {
+    let mut cpio = CpioWriter::new();
+    if dir.is_dir() {
+        cpio.add_dir_contents(dir)?;
+    }
+    for (path, data) in extra_files {
+        cpio.add_file(path, 0o644, data);
+    }
+    fs::write(out, cpio.finish())
+}
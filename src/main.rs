@@ -7,54 +7,51 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
 mod cli;
+mod config;
+mod disk_image;
+mod expect;
+mod fat_image;
+mod gdb;
+mod initrd;
+mod qemu;
+mod qmp;
+mod screenshot;
+mod serial;
+mod symbolize;
 
-use std::{
-    env, fs,
-    process::{self, Command},
-};
+use std::{env, fs, process};
 
 use clap::Parser;
 use color_eyre::eyre::Context;
+use config::Config;
 
 fn main() -> color_eyre::Result<()> {
     let cli = cli::Cli::parse();
 
+    let config = Config::load(cli.config.as_deref())?;
+
     match cli.command() {
-        cli::Commands::Run { boot_mode } => match boot_mode {
-            cli::BootMode::Uefi => run_qemu_uefi()?,
-            cli::BootMode::Bios => run_qemu_bios()?,
-        },
+        cli::Commands::Run {
+            boot_mode,
+            qemu: qemu_args,
+            kernel,
+            serial,
+            expect,
+            screenshot,
+            gdb,
+        } => {
+            let qemu_config = config.qemu.merge_args(&qemu_args);
+            let kernel_config = config.kernel.merge_args(&kernel);
+            let image = disk_image::image(boot_mode, &kernel_config)?;
+            let command = qemu::command(boot_mode, &image, &qemu_config);
+            process::exit(qemu::run(command, &serial, &expect, &screenshot, &gdb)?);
+        }
         cli::Commands::CopyDiskImages => copy_disk_images_to_exe_location()?,
     }
 
     Ok(())
 }
 
-fn run_qemu_uefi() -> color_eyre::Result<()> {
-    let mut qemu = Command::new("qemu-system-x86_64");
-    qemu.arg("-drive");
-    qemu.arg(format!("format=raw,file={}", env!("UEFI_IMAGE")));
-    qemu.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
-    qemu.arg("-device");
-    qemu.arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
-    qemu.arg("-serial");
-    qemu.arg("stdio");
-    let exit_status = qemu.status()?;
-    process::exit(exit_status.code().unwrap_or(-1));
-}
-
-fn run_qemu_bios() -> color_eyre::Result<()> {
-    let mut qemu = Command::new("qemu-system-x86_64");
-    qemu.arg("-drive");
-    qemu.arg(format!("format=raw,file={}", env!("BIOS_IMAGE")));
-    qemu.arg("-device");
-    qemu.arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
-    qemu.arg("-serial");
-    qemu.arg("stdio");
-    let exit_status = qemu.status()?;
-    process::exit(exit_status.code().unwrap_or(-1));
-}
-
 fn copy_disk_images_to_exe_location() -> color_eyre::Result<()> {
     let current_exe = env::current_exe()?;
     let uefi_target = current_exe.with_file_name("uefi.img");
@@ -0,0 +1,375 @@
+//! Running the kernel in QEMU.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::Context;
+
+use crate::{
+    cli::{BootMode, ExpectArgs, GdbArgs, ScreenshotArgs, SerialArgs},
+    config::QemuConfig,
+    expect::{Expectations, SerialEvent},
+    gdb, qmp,
+    screenshot::{self, ScreenshotOutcome},
+    serial::SerialMonitor,
+    symbolize::Symbolizer,
+};
+
+/// How long QEMU keeps running after a panic in automated runs, so that the
+/// whole report is written.
+const PANIC_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Exit codes of the runner, besides the ones of [`QemuExitCode`].
+pub mod exit_code {
+    /// A forbidden text was seen in the serial output.
+    pub const FORBIDDEN_OUTPUT: i32 = 2;
+    /// QEMU was stopped because of `--timeout`.
+    pub const TIMEOUT: i32 = 3;
+    /// QEMU exited before all expected texts were seen.
+    pub const MISSING_OUTPUT: i32 = 4;
+    /// The screenshot differs from the golden image.
+    pub const GOLDEN_MISMATCH: i32 = 5;
+    /// The screenshot couldn't be taken or compared.
+    pub const SCREENSHOT_FAILED: i32 = 6;
+}
+
+/// Exit codes written to QEMU's `isa-debug-exit` device.
+///
+/// Mirrors `jo12bar_os_kernel::QemuExitCode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failure = 0x11,
+}
+
+impl QemuExitCode {
+    /// Returns the code the kernel wrote, given QEMU's exit status.
+    ///
+    /// QEMU exits with `(code << 1) | 1` when `code` is written to the
+    /// `isa-debug-exit` device.
+    pub fn from_qemu_status(status: i32) -> Option<Self> {
+        if status & 1 == 0 {
+            return None;
+        }
+        match status >> 1 {
+            0x10 => Some(Self::Success),
+            0x11 => Some(Self::Failure),
+            _ => None,
+        }
+    }
+
+    /// Returns the exit code of the runner.
+    pub fn runner_exit_code(self) -> i32 {
+        match self {
+            Self::Success => 0,
+            Self::Failure => 1,
+        }
+    }
+}
+
+/// Why the runner stopped QEMU.
+#[derive(Debug, Clone, PartialEq)]
+enum StopReason {
+    AllExpected,
+    Forbidden(String),
+    Panicked,
+    Timeout(Duration),
+    GoldenMismatch { difference: f64, diff_path: PathBuf },
+    ScreenshotFailed(String),
+}
+
+/// Create the QEMU command booting `image`, a disk image for `boot_mode`.
+pub fn command(boot_mode: BootMode, image: &Path, config: &QemuConfig) -> Command {
+    let mut qemu = Command::new("qemu-system-x86_64");
+
+    qemu.arg("-drive")
+        .arg(format!("format=raw,file={}", image.display()));
+    if boot_mode == BootMode::Uefi {
+        qemu.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
+    }
+
+    // Used by the kernel's `exit_qemu`.
+    qemu.arg("-device")
+        .arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
+
+    if let Some(memory) = &config.memory {
+        qemu.arg("-m").arg(memory);
+    }
+    if let Some(smp) = config.smp {
+        qemu.arg("-smp").arg(smp.to_string());
+    }
+    if let Some(cpu) = &config.cpu {
+        qemu.arg("-cpu").arg(cpu);
+    }
+    if config.kvm {
+        qemu.arg("-enable-kvm");
+    }
+    if config.headless {
+        qemu.arg("-display").arg("none");
+    }
+
+    qemu.arg("-serial").arg(&config.serial);
+    if let Some(monitor) = &config.monitor {
+        qemu.arg("-monitor").arg(monitor);
+    }
+
+    qemu.args(&config.extra_args);
+    qemu
+}
+
+/// Run `qemu`, forwarding its serial output through a [`SerialMonitor`].
+///
+/// Returns the exit code for the runner.
+pub fn run(
+    mut qemu: Command,
+    serial: &SerialArgs,
+    expect: &ExpectArgs,
+    screenshot: &ScreenshotArgs,
+    gdb: &GdbArgs,
+) -> color_eyre::Result<i32> {
+    // set by build.rs, see `CARGO_BIN_FILE_JO12BAR_OS_KERNEL`
+    let kernel_elf = Path::new(env!("KERNEL_ELF"));
+
+    let symbolizer = match Symbolizer::from_elf(kernel_elf) {
+        Ok(symbolizer) => Some(symbolizer),
+        Err(e) => {
+            eprintln!("Serial output won't be symbolized: {e:#}");
+            None
+        }
+    };
+    let (events_sender, events) = mpsc::channel();
+    let mut monitor = SerialMonitor::new(
+        serial,
+        symbolizer,
+        Expectations::new(expect, screenshot),
+        Some(events_sender),
+    )?;
+
+    let qmp_port = match (screenshot.qmp_port, &screenshot.screenshot) {
+        (Some(port), _) => Some(port),
+        (None, Some(_)) => Some(qmp::free_port()?),
+        (None, None) => None,
+    };
+    if let Some(port) = qmp_port {
+        qemu.arg("-qmp").arg(qmp::qemu_arg(port));
+    }
+
+    let (gdbinit, load_offset) = if gdb.gdb {
+        gdb::configure_qemu(&mut qemu, gdb);
+        let load_offset = gdb::load_offset(gdb, kernel_elf)?;
+        let gdbinit = gdb::write_gdbinit(kernel_elf, load_offset)?;
+        eprintln!(
+            "QEMU is waiting for a debugger, run `gdb -x {}` to attach",
+            gdbinit.display()
+        );
+        (Some(gdbinit), Some(load_offset))
+    } else {
+        (None, None)
+    };
+
+    let mut child = qemu
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err("couldn't start QEMU")?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    let mut client_result = None;
+    let mut stop_reason = None;
+    let status = thread::scope(|scope| {
+        let forward = scope.spawn(|| monitor.forward(stdout));
+
+        // Errors are only returned once QEMU is stopped. Until then, the
+        // forwarding thread is blocked reading its output, and the scope would
+        // wait for it forever.
+        let waited = (|| {
+            match (gdb.gdb_client, &gdbinit) {
+                (Some(client), Some(gdbinit)) => {
+                    // Ctrl+C is meant for the debugger.
+                    ctrlc::set_handler(|| {}).wrap_err("couldn't ignore Ctrl+C")?;
+                    client_result = Some(gdb::run_client(client, gdbinit));
+                }
+                _ => stop_reason = wait_for_stop(&events, expect, screenshot, qmp_port),
+            }
+            Ok::<_, color_eyre::Report>(())
+        })();
+
+        // Without a debugger, QEMU would keep waiting for one.
+        let qemu_exited = matches!(child.try_wait(), Ok(Some(_)));
+        let killed = if qemu_exited { Ok(()) } else { child.kill() };
+        let forwarded = forward.join().expect("forwarding serial output panicked");
+        let status = child.wait()?;
+        waited?;
+        killed?;
+        forwarded?;
+
+        if let (false, Some(client_result)) = (qemu_exited, client_result.take()) {
+            // The debugger's exit status is the interesting one.
+            let client_status = client_result?;
+            return Ok::<_, color_eyre::Report>(Err(client_status));
+        }
+        Ok(Ok(status))
+    })?;
+
+    if let Some(image_offset) = monitor.image_offset() {
+        if let Err(e) = gdb::remember_load_offset(image_offset) {
+            eprintln!("The kernel's load offset won't be available to `--gdb`: {e:#}");
+        }
+        if let Some(load_offset) = load_offset.filter(|&offset| offset != image_offset) {
+            eprintln!(
+                "The kernel was loaded at {image_offset:#x}, but GDB loaded its symbols \
+                at {load_offset:#x}. The next run with `--gdb` uses the new offset."
+            );
+        }
+    }
+
+    if let Some(report) = monitor.crash_report() {
+        eprintln!();
+        eprintln!("========================= kernel crash report ==========================");
+        for line in report {
+            eprintln!("{line}");
+        }
+        eprintln!("========================================================================");
+    }
+
+    let status = match status {
+        Ok(status) => status,
+        Err(client_status) => return Ok(client_status.code().unwrap_or(-1)),
+    };
+    Ok(exit_code(
+        status,
+        stop_reason,
+        monitor.expectations(),
+        monitor.crash_report().is_some(),
+    ))
+}
+
+/// Wait until QEMU should be stopped, or until its serial output ends.
+fn wait_for_stop(
+    events: &Receiver<SerialEvent>,
+    expect: &ExpectArgs,
+    screenshot: &ScreenshotArgs,
+    qmp_port: Option<u16>,
+) -> Option<StopReason> {
+    let deadline = expect
+        .timeout
+        .map(|timeout| (Instant::now() + timeout, timeout));
+
+    loop {
+        let event = match deadline {
+            Some((deadline, timeout)) => {
+                match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => return Some(StopReason::Timeout(timeout)),
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                }
+            }
+            None => events.recv().ok()?,
+        };
+
+        match event {
+            SerialEvent::AllExpected => return Some(StopReason::AllExpected),
+            SerialEvent::Forbidden(text) => return Some(StopReason::Forbidden(text)),
+            SerialEvent::Panicked if expect.is_automated() => {
+                thread::sleep(PANIC_GRACE_PERIOD);
+                return Some(StopReason::Panicked);
+            }
+            // Keep the panic screen open.
+            SerialEvent::Panicked => {}
+            SerialEvent::ScreenshotMarker => {
+                let port = qmp_port.expect("QMP is enabled for screenshots");
+                match screenshot::take(screenshot, port) {
+                    Ok(ScreenshotOutcome::Saved) => {}
+                    Ok(ScreenshotOutcome::Mismatch {
+                        difference,
+                        diff_path,
+                    }) => {
+                        return Some(StopReason::GoldenMismatch {
+                            difference,
+                            diff_path,
+                        })
+                    }
+                    Err(e) => return Some(StopReason::ScreenshotFailed(format!("{e:#}"))),
+                }
+            }
+        }
+    }
+}
+
+/// Returns the runner's exit code, and explains it.
+fn exit_code(
+    status: ExitStatus,
+    stop_reason: Option<StopReason>,
+    expectations: &Expectations,
+    panicked: bool,
+) -> i32 {
+    let report_missing = || {
+        for text in expectations.missing() {
+            eprintln!("Expected output wasn't seen: {text:?}");
+        }
+    };
+
+    match stop_reason {
+        Some(StopReason::AllExpected) => {
+            eprintln!("All expected output was seen");
+            return 0;
+        }
+        Some(StopReason::Forbidden(text)) => {
+            eprintln!("Forbidden output was seen: {text:?}");
+            return exit_code::FORBIDDEN_OUTPUT;
+        }
+        Some(StopReason::Panicked) => return QemuExitCode::Failure.runner_exit_code(),
+        Some(StopReason::GoldenMismatch {
+            difference,
+            diff_path,
+        }) => {
+            eprintln!(
+                "Screenshot differs from the golden image in {:.3}% of pixels, see {}",
+                difference * 100.0,
+                diff_path.display()
+            );
+            return exit_code::GOLDEN_MISMATCH;
+        }
+        Some(StopReason::ScreenshotFailed(error)) => {
+            eprintln!("Taking the screenshot failed: {error}");
+            return exit_code::SCREENSHOT_FAILED;
+        }
+        Some(StopReason::Timeout(timeout)) => {
+            eprintln!("QEMU timed out after {timeout:?}");
+            report_missing();
+            return exit_code::TIMEOUT;
+        }
+        None => {}
+    }
+
+    let Some(code) = status.code() else {
+        eprintln!("QEMU was terminated by a signal");
+        return -1;
+    };
+    let missing_output = expectations.missing().next().is_some();
+    match QemuExitCode::from_qemu_status(code) {
+        Some(QemuExitCode::Success) if missing_output => {
+            eprintln!("QEMU exited with QemuExitCode::Success (0x10)");
+            report_missing();
+            exit_code::MISSING_OUTPUT
+        }
+        Some(qemu_exit_code) => {
+            eprintln!(
+                "QEMU exited with QemuExitCode::{qemu_exit_code:?} ({:#x})",
+                qemu_exit_code as u32
+            );
+            qemu_exit_code.runner_exit_code()
+        }
+        None if panicked => QemuExitCode::Failure.runner_exit_code(),
+        None if missing_output => {
+            report_missing();
+            exit_code::MISSING_OUTPUT
+        }
+        None => code,
+    }
+}
@@ -0,0 +1,98 @@
+//! A minimal client for the QEMU Machine Protocol.
+//!
+//! QMP is a line-based JSON protocol. QEMU greets new connections, expects
+//! `qmp_capabilities` as the first command, and then answers each command with
+//! a `return` or `error` object. Asynchronous events can arrive in between.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, eyre, Context};
+use serde_json::{json, Value};
+
+/// How long to wait for QEMU to accept connections.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns a free local port for QEMU's QMP server.
+pub fn free_port() -> color_eyre::Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .wrap_err("couldn't find a free port for QMP")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Returns the QEMU `-qmp` argument for a server on `port`.
+pub fn qemu_arg(port: u16) -> String {
+    format!("tcp:localhost:{port},server=on,wait=off")
+}
+
+/// A connection to QEMU's QMP server.
+pub struct Qmp {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Qmp {
+    /// Connect to the QMP server on `port`, retrying while QEMU starts.
+    pub fn connect(port: u16) -> color_eyre::Result<Self> {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        let stream = loop {
+            match TcpStream::connect(addr) {
+                Ok(stream) => break stream,
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+                Err(e) => return Err(e).wrap_err("couldn't connect to QMP"),
+            }
+        };
+
+        let mut qmp = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        let greeting = qmp.read_message()?;
+        if greeting.get("QMP").is_none() {
+            bail!("unexpected QMP greeting: {greeting}");
+        }
+        qmp.execute("qmp_capabilities", json!({}))?;
+        Ok(qmp)
+    }
+
+    /// Execute `command`, returning its result.
+    pub fn execute(&mut self, command: &str, arguments: Value) -> color_eyre::Result<Value> {
+        let request = json!({ "execute": command, "arguments": arguments });
+        writeln!(self.writer, "{request}")?;
+        self.writer.flush()?;
+
+        loop {
+            let mut response = self.read_message()?;
+            if let Some(result) = response.get_mut("return") {
+                return Ok(result.take());
+            }
+            if let Some(error) = response.get("error") {
+                bail!("QMP command {command} failed: {error}");
+            }
+            // Anything else is an event.
+        }
+    }
+
+    /// Save the display's content to `path`, as a PPM image.
+    pub fn screendump(&mut self, path: &Path) -> color_eyre::Result<()> {
+        let filename = path
+            .to_str()
+            .ok_or_else(|| eyre!("screenshot path {} isn't UTF-8", path.display()))?;
+        self.execute("screendump", json!({ "filename": filename }))?;
+        Ok(())
+    }
+
+    fn read_message(&mut self) -> color_eyre::Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("QMP connection closed");
+        }
+        serde_json::from_str(&line).wrap_err("invalid QMP message")
+    }
+}
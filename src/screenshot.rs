@@ -0,0 +1,217 @@
+//! Screenshots of the kernel's display, and comparing them with golden images.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use color_eyre::eyre::{bail, eyre, Context};
+
+use crate::{cli::ScreenshotArgs, qmp::Qmp};
+
+/// An RGB image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// Row-major RGB pixels, 3 bytes each.
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Parse a binary PPM (`P6`) image with 8-bit channels, as written by QEMU's
+    /// `screendump`.
+    pub fn from_ppm(data: &[u8]) -> color_eyre::Result<Self> {
+        // The header is made up of 4 whitespace-separated fields, followed by a
+        // single whitespace character.
+        let mut fields = Vec::with_capacity(4);
+        let mut pos = 0;
+        while fields.len() < 4 {
+            while data.get(pos).is_some_and(u8::is_ascii_whitespace) {
+                pos += 1;
+            }
+            if data.get(pos) == Some(&b'#') {
+                while data.get(pos).is_some_and(|&b| b != b'\n') {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while data.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            if start == pos {
+                bail!("truncated PPM header");
+            }
+            fields.push(std::str::from_utf8(&data[start..pos])?);
+        }
+        let pixels = data.get(pos + 1..).unwrap_or_default();
+
+        if fields[0] != "P6" || fields[3] != "255" {
+            bail!(
+                "unsupported PPM format {} with maximum {}",
+                fields[0],
+                fields[3]
+            );
+        }
+        let width: u32 = fields[1].parse()?;
+        let height: u32 = fields[2].parse()?;
+        let len = width as usize * height as usize * 3;
+        if pixels.len() < len {
+            bail!("truncated PPM image");
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels: pixels[..len].to_vec(),
+        })
+    }
+
+    /// Read a PNG image, dropping its alpha channel.
+    pub fn read_png(path: &Path) -> color_eyre::Result<Self> {
+        let file =
+            File::open(path).wrap_err_with(|| format!("couldn't open {}", path.display()))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let buf = &buf[..info.buffer_size()];
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => bail!("indexed PNG wasn't expanded"),
+        };
+        let pixels = buf
+            .chunks_exact(channels)
+            .flat_map(|pixel| match channels {
+                1 | 2 => [pixel[0]; 3],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            })
+            .collect();
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    /// Write the image to `path` as a PNG.
+    pub fn write_png(&self, path: &Path) -> color_eyre::Result<()> {
+        let file =
+            File::create(path).wrap_err_with(|| format!("couldn't create {}", path.display()))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+
+    /// Compare with `golden`, returning the fraction of pixels that differ and
+    /// an image highlighting them in red.
+    ///
+    /// Images of different sizes are an error.
+    pub fn compare(&self, golden: &Image) -> color_eyre::Result<(f64, Image)> {
+        if (self.width, self.height) != (golden.width, golden.height) {
+            bail!(
+                "screenshot is {}x{}, but the golden image is {}x{}",
+                self.width,
+                self.height,
+                golden.width,
+                golden.height
+            );
+        }
+
+        let mut differing = 0;
+        let mut diff = self.clone();
+        for (pixel, golden) in diff
+            .pixels
+            .as_chunks_mut::<3>()
+            .0
+            .iter_mut()
+            .zip(golden.pixels.as_chunks::<3>().0)
+        {
+            if pixel != golden {
+                differing += 1;
+                *pixel = [0xff, 0, 0];
+            } else {
+                // Dim matching pixels, so that the differences stand out.
+                pixel.iter_mut().for_each(|c| *c /= 4);
+            }
+        }
+
+        let total = self.width as f64 * self.height as f64;
+        Ok((differing as f64 / total.max(1.0), diff))
+    }
+}
+
+/// The result of taking a screenshot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScreenshotOutcome {
+    /// The screenshot was saved, and matched the golden image if there is one.
+    Saved,
+    /// The screenshot differs from the golden image.
+    Mismatch {
+        /// The fraction of pixels that differ.
+        difference: f64,
+        /// An image highlighting the differences.
+        diff_path: PathBuf,
+    },
+}
+
+/// Take a screenshot as configured by `args`, using the QMP server on
+/// `qmp_port`.
+pub fn take(args: &ScreenshotArgs, qmp_port: u16) -> color_eyre::Result<ScreenshotOutcome> {
+    let path = args
+        .screenshot
+        .as_deref()
+        .ok_or_else(|| eyre!("no screenshot path"))?;
+
+    // Give the framebuffer logger time to draw the line that triggered this.
+    thread::sleep(args.screenshot_delay);
+
+    let ppm_path =
+        env::temp_dir().join(format!("jo12bar-os-screendump-{}.ppm", std::process::id()));
+    Qmp::connect(qmp_port)?.screendump(&ppm_path)?;
+    let ppm = fs::read(&ppm_path).wrap_err("couldn't read QEMU's screendump")?;
+    let _ = fs::remove_file(&ppm_path);
+
+    let image = Image::from_ppm(&ppm)?;
+    image.write_png(path)?;
+    eprintln!("Saved screenshot to {}", path.display());
+
+    let Some(golden_path) = &args.golden else {
+        return Ok(ScreenshotOutcome::Saved);
+    };
+    if args.update_golden {
+        image.write_png(golden_path)?;
+        eprintln!("Updated golden image {}", golden_path.display());
+        return Ok(ScreenshotOutcome::Saved);
+    }
+
+    let golden = Image::read_png(golden_path)?;
+    let (difference, diff) = image.compare(&golden)?;
+    if difference * 100.0 <= args.golden_tolerance {
+        eprintln!(
+            "Screenshot matches golden image {} ({:.3}% different)",
+            golden_path.display(),
+            difference * 100.0
+        );
+        return Ok(ScreenshotOutcome::Saved);
+    }
+
+    let diff_path = path.with_extension("diff.png");
+    diff.write_png(&diff_path)?;
+    Ok(ScreenshotOutcome::Mismatch {
+        difference,
+        diff_path,
+    })
+}
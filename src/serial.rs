@@ -0,0 +1,243 @@
+//! Processing of the kernel's serial output.
+//!
+//! QEMU's serial port is connected to stdout, which [`SerialMonitor`] copies to
+//! the terminal and optionally to a log file. On the way, addresses are
+//! symbolized, reports of kernel panics are collected so that they can be
+//! shown again once QEMU exits, and lines are checked against the
+//! [`Expectations`] of automated runs.
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufWriter, IsTerminal, Read, Write},
+    sync::mpsc::Sender,
+};
+
+use color_eyre::eyre::Context;
+
+use crate::{
+    cli::{ColorChoice, SerialArgs},
+    expect::{Expectations, SerialEvent},
+    symbolize::Symbolizer,
+};
+
+/// Lines containing one of these start a panic report.
+const PANIC_MARKERS: &[&str] = &["kernel panic on core", "nested kernel panic"];
+
+/// The maximum number of lines kept per crash report.
+const MAX_CRASH_REPORT_LINES: usize = 200;
+
+/// Copies the kernel's serial output to the terminal and a log file.
+pub struct SerialMonitor {
+    symbolizer: Option<Symbolizer>,
+    terminal_colors: bool,
+    log: Option<BufWriter<File>>,
+    log_colors: bool,
+    /// The part of the current line that was already written.
+    line: String,
+    crash_report: Vec<String>,
+    /// `true` while the lines of a panic report are being collected.
+    in_crash_report: bool,
+    expectations: Expectations,
+    events: Option<Sender<SerialEvent>>,
+}
+
+impl SerialMonitor {
+    /// Create a new monitor, creating the log file if one is configured.
+    ///
+    /// Panics, and lines matching `expectations`, are reported to `events`.
+    pub fn new(
+        args: &SerialArgs,
+        symbolizer: Option<Symbolizer>,
+        expectations: Expectations,
+        events: Option<Sender<SerialEvent>>,
+    ) -> color_eyre::Result<Self> {
+        let log = args
+            .serial_log
+            .as_ref()
+            .map(|path| {
+                File::create(path)
+                    .map(BufWriter::new)
+                    .wrap_err_with(|| format!("couldn't create serial log {}", path.display()))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            symbolizer,
+            terminal_colors: match args.color {
+                ColorChoice::Auto => io::stdout().is_terminal(),
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+            },
+            log,
+            log_colors: args.serial_log_colors,
+            line: String::new(),
+            crash_report: Vec::new(),
+            in_crash_report: false,
+            expectations,
+            events,
+        })
+    }
+
+    /// Copy `serial` until it is closed, i.e. QEMU exited.
+    ///
+    /// The events sender is dropped once this returns.
+    ///
+    /// Complete lines are symbolized. Partial lines, like the dots written by
+    /// the timer interrupt, are passed through right away unless they might
+    /// contain an address.
+    pub fn forward(&mut self, serial: impl Read) -> io::Result<()> {
+        let result = self.forward_until_closed(serial);
+
+        // Let the receiver know that there won't be any more events.
+        self.events = None;
+        result
+    }
+
+    fn forward_until_closed(&mut self, mut serial: impl Read) -> io::Result<()> {
+        let mut pending = Vec::new();
+        let mut buf = [0; 4096];
+
+        loop {
+            let len = serial.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            pending.extend_from_slice(&buf[..len]);
+
+            let lines_end = pending
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |idx| idx + 1);
+            let partial = &pending[lines_end..];
+            let end = if partial.windows(2).any(|w| w == b"0x") || partial.ends_with(b"0") {
+                lines_end
+            } else {
+                pending.len()
+            };
+
+            let text = String::from_utf8_lossy(&pending[..end]).into_owned();
+            self.write(&text)?;
+            pending.drain(..end);
+        }
+
+        let text = String::from_utf8_lossy(&pending).into_owned();
+        self.write(&text)?;
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.scan_line(&line);
+        }
+        if let Some(log) = &mut self.log {
+            log.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the lines of the last panic report, if the kernel panicked.
+    pub fn crash_report(&self) -> Option<&[String]> {
+        (!self.crash_report.is_empty()).then_some(&self.crash_report[..])
+    }
+
+    /// Returns the offset the kernel logged it was loaded at, if it did.
+    pub fn image_offset(&self) -> Option<u64> {
+        self.symbolizer.as_ref()?.image_offset()
+    }
+
+    /// Returns which expected texts were seen.
+    pub fn expectations(&self) -> &Expectations {
+        &self.expectations
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let text = match &mut self.symbolizer {
+            Some(symbolizer) => Cow::Owned(symbolizer.symbolize(text)),
+            None => Cow::Borrowed(text),
+        };
+
+        let mut stdout = io::stdout().lock();
+        if self.terminal_colors {
+            stdout.write_all(text.as_bytes())?;
+        } else {
+            stdout.write_all(strip_ansi(&text).as_bytes())?;
+        }
+        stdout.flush()?;
+
+        if let Some(log) = &mut self.log {
+            if self.log_colors {
+                log.write_all(text.as_bytes())?;
+            } else {
+                log.write_all(strip_ansi(&text).as_bytes())?;
+            }
+        }
+
+        for part in text.split_inclusive('\n') {
+            self.line.push_str(part);
+            if part.ends_with('\n') {
+                let line = std::mem::take(&mut self.line);
+                self.scan_line(&line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect `line` if it is part of a panic report.
+    fn scan_line(&mut self, line: &str) {
+        let line = strip_ansi(line);
+        let line = line.trim_end();
+
+        for event in self.expectations.check_line(line) {
+            self.send(event);
+        }
+
+        if let Some(start) = PANIC_MARKERS.iter().find_map(|marker| line.find(marker)) {
+            self.crash_report.clear();
+            self.crash_report.push(line[start..].to_owned());
+            self.in_crash_report = true;
+            self.send(SerialEvent::Panicked);
+        } else if self.in_crash_report {
+            // The report is logged as a single record, so the next record ends it.
+            let line_start = line.trim_start_matches('.');
+            if line_start.starts_with('[') || self.crash_report.len() == MAX_CRASH_REPORT_LINES {
+                self.in_crash_report = false;
+            } else {
+                self.crash_report.push(line.to_owned());
+            }
+        }
+    }
+
+    fn send(&self, event: SerialEvent) {
+        if let Some(events) = &self.events {
+            // Nobody listening anymore is fine, QEMU is being stopped anyways.
+            let _ = events.send(event);
+        }
+    }
+}
+
+/// Remove ANSI escape sequences from `text`.
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        // CSI sequences end with a byte in `@`..=`~`, other escapes after one
+        // more character.
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    Cow::Owned(out)
+}
@@ -0,0 +1,117 @@
+//! Symbolization of kernel addresses in QEMU's serial output.
+//!
+//! The kernel symbolizes its backtraces itself, but that only works once its
+//! symbol table is loaded. Any address that isn't already followed by a
+//! `<function+offset>` is resolved here using the kernel's ELF file.
+
+use std::{fs, path::Path};
+
+use color_eyre::eyre::Context;
+use object::{Object, ObjectSymbol, SymbolKind};
+
+/// The line the kernel logs its image offset in, followed by the offset.
+const IMAGE_OFFSET_MARKER: &str = "image offset: 0x";
+
+/// The number of hex digits in a printed address.
+const ADDRESS_DIGITS: usize = 16;
+
+/// The function symbols of the kernel.
+pub struct Symbolizer {
+    /// Start address, size and demangled name of each function, sorted by address.
+    functions: Vec<(u64, u64, String)>,
+    /// The offset the kernel was loaded at, once the kernel reported it.
+    image_offset: Option<u64>,
+}
+
+impl Symbolizer {
+    /// Read the function symbols from the kernel's ELF file.
+    pub fn from_elf(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path)
+            .wrap_err_with(|| format!("couldn't read kernel ELF file {}", path.display()))?;
+        let elf = object::File::parse(&*data).wrap_err("couldn't parse kernel ELF file")?;
+
+        let mut functions: Vec<_> = elf
+            .symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text)
+            .filter_map(|symbol| {
+                let name = rustc_demangle::demangle(symbol.name().ok()?);
+                Some((symbol.address(), symbol.size(), format!("{name:#}")))
+            })
+            .collect();
+        functions.sort_unstable_by_key(|&(address, _, _)| address);
+
+        Ok(Self {
+            functions,
+            image_offset: None,
+        })
+    }
+
+    /// Returns the offset the kernel was loaded at, if it was logged already.
+    pub fn image_offset(&self) -> Option<u64> {
+        self.image_offset
+    }
+
+    /// Returns `function+offset` for the function containing `addr`.
+    pub fn resolve(&self, addr: u64) -> Option<String> {
+        let addr = addr.checked_sub(self.image_offset.unwrap_or(0))?;
+        let idx = self
+            .functions
+            .partition_point(|&(start, _, _)| start <= addr)
+            .checked_sub(1)?;
+        let (start, size, name) = &self.functions[idx];
+
+        let offset = addr - start;
+        (offset < (*size).max(1)).then(|| format!("{name}+{offset:#x}"))
+    }
+
+    /// Append the function to each unresolved address in `text`.
+    ///
+    /// Return addresses in backtraces resolve to the calling function, unless
+    /// the call was its last instruction.
+    pub fn symbolize(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for line in text.split_inclusive('\n') {
+            if let Some(idx) = line.find(IMAGE_OFFSET_MARKER) {
+                let digits = &line[idx + IMAGE_OFFSET_MARKER.len()..];
+                let end = digits
+                    .find(|c: char| !c.is_ascii_hexdigit())
+                    .unwrap_or(digits.len());
+                if let Ok(offset) = u64::from_str_radix(&digits[..end], 16) {
+                    self.image_offset = Some(offset);
+                }
+            }
+
+            self.symbolize_line(line, &mut out);
+        }
+        out
+    }
+
+    fn symbolize_line(&self, line: &str, out: &mut String) {
+        let mut rest = line;
+        while let Some(idx) = rest.find("0x") {
+            let (before, address) = rest.split_at(idx + 2);
+            out.push_str(before);
+
+            let digits = address
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(address.len());
+            let (digits, after) = address.split_at(digits);
+            out.push_str(digits);
+            rest = after;
+
+            if digits.len() != ADDRESS_DIGITS || after.starts_with(" <") {
+                continue;
+            }
+            let symbol = u64::from_str_radix(digits, 16)
+                .ok()
+                .and_then(|addr| self.resolve(addr));
+            if let Some(symbol) = symbol {
+                out.push_str(" <");
+                out.push_str(&symbol);
+                out.push('>');
+            }
+        }
+        out.push_str(rest);
+    }
+}